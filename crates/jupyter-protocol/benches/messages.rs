@@ -0,0 +1,108 @@
+//! Per-message-type deserialization benchmarks, and what counting them costs.
+//!
+//! Run with:
+//!
+//! ```sh
+//! cargo bench -p jupyter-protocol --features perf-instrumentation --bench messages
+//! ```
+//!
+//! Each message type's content is parsed the way connections parse it, with
+//! `JupyterMessageContent::from_type_and_content`. The `counted` group does
+//! the same and records it in `MessageCounters`, the overhead an embedder
+//! pays for protocol stats.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jupyter_protocol::instrumentation::{Instrumentation, MessageCounters};
+use jupyter_protocol::{Channel, JupyterMessageContent};
+use serde_json::{json, Value};
+
+/// Typical contents, from the messages a Python kernel sends most.
+fn contents() -> Vec<(&'static str, Value)> {
+    vec![
+        ("status", json!({ "execution_state": "busy" })),
+        (
+            "execute_request",
+            json!({
+                "code": "import numpy as np\nnp.arange(10)",
+                "silent": false,
+                "store_history": true,
+                "user_expressions": {},
+                "allow_stdin": true,
+                "stop_on_error": true,
+            }),
+        ),
+        (
+            "stream",
+            json!({ "name": "stdout", "text": "hello world\n".repeat(100) }),
+        ),
+        (
+            "execute_result",
+            json!({
+                "execution_count": 3,
+                "data": {
+                    "text/plain": "array([0, 1, 2, 3, 4, 5, 6, 7, 8, 9])",
+                    "text/html": "<pre>array([0, 1, 2, 3, 4, 5, 6, 7, 8, 9])</pre>",
+                },
+                "metadata": {},
+            }),
+        ),
+        (
+            "complete_reply",
+            json!({
+                "status": "ok",
+                "matches": (0..200).map(|i| format!("np.attribute_{i}")).collect::<Vec<_>>(),
+                "cursor_start": 0,
+                "cursor_end": 3,
+                "metadata": {},
+            }),
+        ),
+    ]
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message_parse");
+    for (msg_type, content) in contents() {
+        group.throughput(Throughput::Bytes(content.to_string().len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(msg_type),
+            &content,
+            |b, content| {
+                b.iter(|| {
+                    JupyterMessageContent::from_type_and_content(
+                        msg_type,
+                        black_box(content.clone()),
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn counted(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message_parse_counted");
+    let counters = MessageCounters::new();
+    for (msg_type, content) in contents() {
+        let bytes = content.to_string().len();
+        group.throughput(Throughput::Bytes(bytes as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(msg_type),
+            &content,
+            |b, content| {
+                b.iter(|| {
+                    let parsed = JupyterMessageContent::from_type_and_content(
+                        msg_type,
+                        black_box(content.clone()),
+                    )
+                    .unwrap();
+                    counters.message_parsed(Channel::IOPub, parsed.message_type(), bytes);
+                    parsed
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, counted);
+criterion_main!(benches);
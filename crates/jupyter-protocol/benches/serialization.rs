@@ -0,0 +1,140 @@
+//! Benchmarks for serializing and deserializing Jupyter messages.
+//!
+//! Run with:
+//!
+//! ```sh
+//! cargo bench -p jupyter-protocol --bench serialization
+//! ```
+//!
+//! To compare a change against `main`, save a baseline first and then compare:
+//!
+//! ```sh
+//! git checkout main
+//! cargo bench -p jupyter-protocol --bench serialization -- --save-baseline main
+//! git checkout -
+//! cargo bench -p jupyter-protocol --bench serialization -- --baseline main
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jupyter_protocol::{
+    DisplayData, ExecuteRequest, JupyterMessage, Media, MediaType, StreamContent,
+};
+use serde_json::{json, Value};
+
+/// A base64-ish payload of roughly `len` bytes, split into 76 character lines like
+/// many kernels emit for images.
+fn fake_base64(len: usize) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut payload = String::with_capacity(len + len / 76);
+    for i in 0..len {
+        payload.push(ALPHABET[(i * 31 + 7) % ALPHABET.len()] as char);
+        if i % 76 == 75 {
+            payload.push('\n');
+        }
+    }
+    payload
+}
+
+fn image_bundle(len: usize) -> Value {
+    json!({
+        "image/png": fake_base64(len),
+        "text/plain": "<Figure size 640x480 with 1 Axes>",
+    })
+}
+
+fn table_bundle(rows: usize) -> Value {
+    let data: Vec<Value> = (0..rows)
+        .map(|i| json!({ "index": i, "name": format!("row-{i}"), "value": i as f64 * 0.5, "flag": i % 2 == 0 }))
+        .collect();
+
+    json!({
+        "application/vnd.dataresource+json": {
+            "data": data,
+            "schema": {
+                "fields": [
+                    {"name": "index", "type": "integer"},
+                    {"name": "name", "type": "string"},
+                    {"name": "value", "type": "number"},
+                    {"name": "flag", "type": "boolean"}
+                ],
+                "primaryKey": ["index"]
+            }
+        },
+        "text/plain": format!("<DataFrame with {rows} rows>"),
+    })
+}
+
+fn media_deserialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("media_deserialize");
+
+    for len in [64 * 1024, 1024 * 1024, 4 * 1024 * 1024] {
+        let raw = serde_json::to_string(&image_bundle(len)).unwrap();
+        group.throughput(Throughput::Bytes(raw.len() as u64));
+        group.bench_with_input(BenchmarkId::new("image_png", len), &raw, |b, raw| {
+            b.iter(|| serde_json::from_str::<Media>(black_box(raw)).unwrap())
+        });
+    }
+
+    for rows in [100, 10_000] {
+        let raw = serde_json::to_string(&table_bundle(rows)).unwrap();
+        group.throughput(Throughput::Bytes(raw.len() as u64));
+        group.bench_with_input(BenchmarkId::new("datatable", rows), &raw, |b, raw| {
+            b.iter(|| serde_json::from_str::<Media>(black_box(raw)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn media_serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("media_serialize");
+
+    let image: Media = serde_json::from_value(image_bundle(1024 * 1024)).unwrap();
+    group.bench_function("image_png/1048576", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&image)).unwrap())
+    });
+
+    let table: Media = serde_json::from_value(table_bundle(10_000)).unwrap();
+    group.bench_function("datatable/10000", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&table)).unwrap())
+    });
+
+    group.finish();
+}
+
+fn round_trip(message: &JupyterMessage) -> JupyterMessage {
+    let bytes = serde_json::to_vec(message).unwrap();
+    let value: Value = serde_json::from_slice(&bytes).unwrap();
+    JupyterMessage::from_value(value).unwrap()
+}
+
+fn message_round_trips(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message_round_trip");
+
+    let execute_request: JupyterMessage =
+        ExecuteRequest::new("import numpy as np\nnp.arange(10)".to_string()).into();
+    group.bench_function("execute_request", |b| {
+        b.iter(|| round_trip(black_box(&execute_request)))
+    });
+
+    let stream: JupyterMessage = StreamContent::stdout(&"hello world\n".repeat(100)).into();
+    group.bench_function("stream", |b| b.iter(|| round_trip(black_box(&stream))));
+
+    let display_data: JupyterMessage = DisplayData::from(vec![
+        MediaType::Plain("<Figure size 640x480 with 1 Axes>".to_string()),
+        MediaType::Png(fake_base64(256 * 1024)),
+    ])
+    .into();
+    group.bench_function("display_data_png", |b| {
+        b.iter(|| round_trip(black_box(&display_data)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    media_deserialization,
+    media_serialization,
+    message_round_trips
+);
+criterion_main!(benches);
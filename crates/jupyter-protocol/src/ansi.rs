@@ -0,0 +1,294 @@
+//! Converting ANSI-colored terminal text, like error tracebacks, to plain text or HTML.
+//!
+//! Kernels color tracebacks with ANSI escape sequences. [`strip_ansi`] removes
+//! them, and [`ansi_to_html`] turns SGR styling into `<span>`s using the same
+//! class names as nbconvert and JupyterLab (`ansi-red-fg`, `ansi-bold`, ...), so
+//! existing notebook stylesheets apply. 256-color and truecolor sequences become
+//! inline `rgb()` styles. All other escape sequences are dropped.
+//!
+//! ```rust
+//! use jupyter_protocol::ansi::{ansi_to_html, strip_ansi};
+//!
+//! let text = "\x1b[0;31mValueError\x1b[0m: bad <input>";
+//! assert_eq!(strip_ansi(text), "ValueError: bad <input>");
+//! assert_eq!(
+//!     ansi_to_html(text),
+//!     r#"<span class="ansi-red-fg">ValueError</span>: bad &lt;input&gt;"#
+//! );
+//! ```
+use vte::{Params, Parser, Perform};
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Named { index: u8, intense: bool },
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// A color from the 256-color palette.
+    fn from_palette(index: u16) -> Option<Color> {
+        let index = u8::try_from(index).ok()?;
+        Some(match index {
+            0..=7 => Color::Named {
+                index,
+                intense: false,
+            },
+            8..=15 => Color::Named {
+                index: index - 8,
+                intense: true,
+            },
+            16..=231 => {
+                let cube = index - 16;
+                let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+                Color::Rgb(level(cube / 36), level(cube / 6 % 6), level(cube % 6))
+            }
+            232..=255 => {
+                let gray = 8 + (index - 232) * 10;
+                Color::Rgb(gray, gray, gray)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    fn apply_sgr(&mut self, params: &Params) {
+        // `38;5;n` and `38:5:n` are both in use, so flatten subparameters
+        let codes: Vec<u16> = params.iter().flatten().copied().collect();
+        if codes.is_empty() {
+            *self = Style::default();
+            return;
+        }
+
+        let mut codes = codes.into_iter();
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(named(code - 30, false)),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(named(code - 40, false)),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(named(code - 90, true)),
+                100..=107 => self.bg = Some(named(code - 100, true)),
+                38 => self.fg = extended_color(&mut codes).or(self.fg),
+                48 => self.bg = extended_color(&mut codes).or(self.bg),
+                _ => {}
+            }
+        }
+    }
+
+    fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    fn open_tag(&self) -> String {
+        let mut classes = Vec::new();
+        let mut styles = Vec::new();
+
+        for (color, suffix, property) in [
+            (self.fg, "fg", "color"),
+            (self.bg, "bg", "background-color"),
+        ] {
+            match color {
+                Some(Color::Named { index, intense }) => classes.push(format!(
+                    "ansi-{}{}-{}",
+                    COLOR_NAMES[index as usize],
+                    if intense { "-intense" } else { "" },
+                    suffix
+                )),
+                Some(Color::Rgb(r, g, b)) => styles.push(format!("{property}: rgb({r},{g},{b})")),
+                None => {}
+            }
+        }
+        if self.bold {
+            classes.push("ansi-bold".to_string());
+        }
+        if self.italic {
+            classes.push("ansi-italic".to_string());
+        }
+        if self.underline {
+            classes.push("ansi-underline".to_string());
+        }
+
+        let mut tag = String::from("<span");
+        if !classes.is_empty() {
+            tag.push_str(&format!(r#" class="{}""#, classes.join(" ")));
+        }
+        if !styles.is_empty() {
+            tag.push_str(&format!(r#" style="{}""#, styles.join("; ")));
+        }
+        tag.push('>');
+        tag
+    }
+}
+
+fn named(index: u16, intense: bool) -> Color {
+    Color::Named {
+        index: index as u8,
+        intense,
+    }
+}
+
+/// Parse the rest of a `38;5;n` or `38;2;r;g;b` sequence.
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    match codes.next()? {
+        5 => Color::from_palette(codes.next()?),
+        2 => {
+            let mut channel = || codes.next().and_then(|value| u8::try_from(value).ok());
+            Some(Color::Rgb(channel()?, channel()?, channel()?))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Renderer {
+    html: bool,
+    output: String,
+    style: Style,
+    /// The style of the currently open `<span>`, if any
+    open: Option<Style>,
+}
+
+impl Renderer {
+    fn push(&mut self, c: char) {
+        if !self.html {
+            self.output.push(c);
+            return;
+        }
+
+        // Open spans lazily so that style changes without text don't leave empty spans
+        let wanted = (!self.style.is_plain()).then_some(self.style);
+        if self.open != wanted {
+            self.close();
+            if let Some(style) = wanted {
+                self.output.push_str(&style.open_tag());
+                self.open = Some(style);
+            }
+        }
+
+        match c {
+            '&' => self.output.push_str("&amp;"),
+            '<' => self.output.push_str("&lt;"),
+            '>' => self.output.push_str("&gt;"),
+            '"' => self.output.push_str("&quot;"),
+            '\'' => self.output.push_str("&#39;"),
+            c => self.output.push(c),
+        }
+    }
+
+    fn close(&mut self) {
+        if self.open.take().is_some() {
+            self.output.push_str("</span>");
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.close();
+        self.output
+    }
+}
+
+impl Perform for Renderer {
+    fn print(&mut self, c: char) {
+        self.push(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' | b'\t' => self.push(byte as char),
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], ignore: bool, action: char) {
+        if action == 'm' && !ignore {
+            self.style.apply_sgr(params);
+        }
+    }
+}
+
+fn render(text: &str, html: bool) -> String {
+    let mut renderer = Renderer {
+        html,
+        output: String::with_capacity(text.len()),
+        ..Default::default()
+    };
+    let mut parser = Parser::new();
+    for byte in text.bytes() {
+        parser.advance(&mut renderer, byte);
+    }
+    renderer.finish()
+}
+
+/// Remove all ANSI escape sequences from `text`.
+pub fn strip_ansi(text: &str) -> String {
+    render(text, false)
+}
+
+/// Convert ANSI styled `text` to HTML-escaped text with styled `<span>`s.
+pub fn ansi_to_html(text: &str) -> String {
+    render(text, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi_keeps_text_and_newlines() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31mTraceback\x1b[0m\n\x1b[2K  File \x1b[32m\"x.py\"\x1b[39m"),
+            "Traceback\n  File \"x.py\""
+        );
+    }
+
+    #[test]
+    fn test_ansi_to_html_classes() {
+        assert_eq!(
+            ansi_to_html("\x1b[1;92mok\x1b[22m then\x1b[0m done"),
+            concat!(
+                r#"<span class="ansi-green-intense-fg ansi-bold">ok</span>"#,
+                r#"<span class="ansi-green-intense-fg"> then</span> done"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_ansi_to_html_extended_colors() {
+        assert_eq!(
+            ansi_to_html("\x1b[38;5;196mred\x1b[48;2;0;0;255m on blue\x1b[m"),
+            concat!(
+                r#"<span style="color: rgb(255,0,0)">red</span>"#,
+                r#"<span style="color: rgb(255,0,0); background-color: rgb(0,0,255)"> on blue</span>"#
+            )
+        );
+        assert_eq!(
+            ansi_to_html("\x1b[38:5:1mred"),
+            r#"<span class="ansi-red-fg">red</span>"#
+        );
+    }
+
+    #[test]
+    fn test_ansi_to_html_skips_empty_spans() {
+        assert_eq!(ansi_to_html("\x1b[31m\x1b[0mplain"), "plain");
+    }
+}
@@ -0,0 +1,274 @@
+//! Binary buffers in comm messages.
+//!
+//! Widgets send binary data like images and arrays as message `buffers` instead
+//! of inside the JSON. The comm `data` says where each buffer belongs with
+//! `buffer_paths`, relative to `data.state` when there is one:
+//!
+//! ```json
+//! {"method": "update", "state": {"value": null}, "buffer_paths": [["value"]]}
+//! ```
+//!
+//! [`resolve_buffers`] puts the buffers back into the JSON, each encoded as
+//! `{"encoding": "base64", "data": "..."}` like the widget state stored in
+//! notebooks, and [`extract_buffers`] pulls them out again before sending.
+//!
+//! ```rust
+//! use bytes::Bytes;
+//! use jupyter_protocol::buffers::{decode_buffer, extract_buffers, resolve_buffers};
+//! use serde_json::json;
+//!
+//! let data = json!({
+//!     "method": "update",
+//!     "state": {"value": null},
+//!     "buffer_paths": [["value"]],
+//! });
+//! let data = data.as_object().unwrap();
+//! let buffers = vec![Bytes::from_static(b"\x89PNG")];
+//!
+//! let resolved = resolve_buffers(data, &buffers).unwrap();
+//! assert_eq!(decode_buffer(&resolved["state"]["value"]), Some(buffers[0].clone()));
+//!
+//! let (extracted, extracted_buffers) = extract_buffers(&resolved);
+//! assert_eq!(extracted_buffers, buffers);
+//! assert_eq!(extracted["buffer_paths"], json!([["value"]]));
+//! ```
+use base64::prelude::*;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::JupyterError;
+
+/// One step of a buffer path: an object key or an array index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+pub type BufferPath = Vec<PathSegment>;
+
+/// The JSON a buffer is replaced with when it's put back into the state.
+pub fn encode_buffer(buffer: &[u8]) -> Value {
+    json!({
+        "encoding": "base64",
+        "data": BASE64_STANDARD.encode(buffer),
+    })
+}
+
+/// The buffer in `value`, if it was encoded by [`encode_buffer`].
+pub fn decode_buffer(value: &Value) -> Option<Bytes> {
+    let object = value.as_object()?;
+    if object.len() != 2 || object.get("encoding")?.as_str()? != "base64" {
+        return None;
+    }
+    BASE64_STANDARD
+        .decode(object.get("data")?.as_str()?)
+        .ok()
+        .map(Bytes::from)
+}
+
+/// Put `buffers` into `state` at the matching `buffer_paths`.
+///
+/// Every segment but the last has to exist already. A missing final key is
+/// added, as senders usually remove the key when they pull the buffer out.
+pub fn put_buffers(
+    state: &mut Value,
+    buffer_paths: &[BufferPath],
+    buffers: &[Bytes],
+) -> Result<(), JupyterError> {
+    for (index, path) in buffer_paths.iter().enumerate() {
+        let buffer = buffers
+            .get(index)
+            .ok_or_else(|| JupyterError::InvalidBufferPath {
+                path: path.clone(),
+                reason: "there's no buffer for it",
+            })?;
+        let slot = slot_mut(state, path).ok_or_else(|| JupyterError::InvalidBufferPath {
+            path: path.clone(),
+            reason: "it doesn't exist in the state",
+        })?;
+        *slot = encode_buffer(buffer);
+    }
+    Ok(())
+}
+
+fn slot_mut<'a>(state: &'a mut Value, path: &[PathSegment]) -> Option<&'a mut Value> {
+    let (last, parents) = path.split_last()?;
+    let mut value = state;
+    for segment in parents {
+        value = match (segment, value) {
+            (PathSegment::Key(key), Value::Object(object)) => object.get_mut(key)?,
+            (PathSegment::Index(index), Value::Array(array)) => array.get_mut(*index)?,
+            _ => return None,
+        };
+    }
+    match (last, value) {
+        (PathSegment::Key(key), Value::Object(object)) => {
+            Some(object.entry(key.clone()).or_insert(Value::Null))
+        }
+        (PathSegment::Index(index), Value::Array(array)) => array.get_mut(*index),
+        _ => None,
+    }
+}
+
+/// Take every encoded buffer out of `state`, returning their paths and bytes.
+///
+/// Like ipywidgets, buffers are removed from objects and replaced with `null`
+/// in arrays so that indices stay put.
+pub fn remove_buffers(state: &mut Value) -> (Vec<BufferPath>, Vec<Bytes>) {
+    let mut buffer_paths = Vec::new();
+    let mut buffers = Vec::new();
+    remove_buffers_at(state, &mut Vec::new(), &mut buffer_paths, &mut buffers);
+    (buffer_paths, buffers)
+}
+
+fn remove_buffers_at(
+    value: &mut Value,
+    path: &mut BufferPath,
+    buffer_paths: &mut Vec<BufferPath>,
+    buffers: &mut Vec<Bytes>,
+) {
+    match value {
+        Value::Object(object) => {
+            let keys: Vec<String> = object.keys().cloned().collect();
+            for key in keys {
+                path.push(PathSegment::Key(key.clone()));
+                if let Some(buffer) = decode_buffer(&object[&key]) {
+                    object.remove(&key);
+                    buffer_paths.push(path.clone());
+                    buffers.push(buffer);
+                } else if let Some(child) = object.get_mut(&key) {
+                    remove_buffers_at(child, path, buffer_paths, buffers);
+                }
+                path.pop();
+            }
+        }
+        Value::Array(array) => {
+            for (index, item) in array.iter_mut().enumerate() {
+                path.push(PathSegment::Index(index));
+                if let Some(buffer) = decode_buffer(item) {
+                    *item = Value::Null;
+                    buffer_paths.push(path.clone());
+                    buffers.push(buffer);
+                } else {
+                    remove_buffers_at(item, path, buffer_paths, buffers);
+                }
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Comm `data` with the message `buffers` substituted in at its `buffer_paths`.
+pub fn resolve_buffers(
+    data: &Map<String, Value>,
+    buffers: &[Bytes],
+) -> Result<Map<String, Value>, JupyterError> {
+    let mut data = data.clone();
+    let buffer_paths: Vec<BufferPath> = match data.remove("buffer_paths") {
+        Some(buffer_paths) => {
+            serde_json::from_value(buffer_paths).map_err(|_| JupyterError::InvalidBufferPath {
+                path: Vec::new(),
+                reason: "buffer_paths must be a list of lists of keys and indices",
+            })?
+        }
+        None => Vec::new(),
+    };
+
+    let mut data = Value::Object(data);
+    let state = if data.get("state").is_some() {
+        &mut data["state"]
+    } else {
+        &mut data
+    };
+    put_buffers(state, &buffer_paths, buffers)?;
+
+    match data {
+        Value::Object(data) => Ok(data),
+        _ => unreachable!("data is still an object"),
+    }
+}
+
+/// The inverse of [`resolve_buffers`]: comm `data` with encoded buffers taken
+/// out and listed in `buffer_paths`, plus the buffers to send with it.
+pub fn extract_buffers(data: &Map<String, Value>) -> (Map<String, Value>, Vec<Bytes>) {
+    let mut data = data.clone();
+    let (buffer_paths, buffers) = match data.get_mut("state") {
+        Some(state) => remove_buffers(state),
+        None => {
+            let mut state = Value::Object(std::mem::take(&mut data));
+            let removed = remove_buffers(&mut state);
+            if let Value::Object(state) = state {
+                data = state;
+            }
+            removed
+        }
+    };
+
+    if !buffer_paths.is_empty() {
+        data.insert("buffer_paths".to_string(), json!(buffer_paths));
+    }
+    (data, buffers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_paths_round_trip() {
+        let data = json!({
+            "state": {
+                "data": [null, {"x": 1}],
+                "layout": "IPY_MODEL_1",
+            },
+            "buffer_paths": [["data", 0], ["data", 1, "bytes"]],
+        });
+        let buffers = vec![Bytes::from_static(b"first"), Bytes::from_static(b"second")];
+
+        let resolved = resolve_buffers(data.as_object().unwrap(), &buffers).unwrap();
+        assert!(!resolved.contains_key("buffer_paths"));
+        assert_eq!(
+            decode_buffer(&resolved["state"]["data"][1]["bytes"]),
+            Some(Bytes::from_static(b"second"))
+        );
+        assert_eq!(resolved["state"]["data"][1]["x"], 1);
+
+        let (extracted, extracted_buffers) = extract_buffers(&resolved);
+        assert_eq!(Value::Object(extracted), data);
+        assert_eq!(extracted_buffers, buffers);
+    }
+
+    #[test]
+    fn test_paths_without_state() {
+        let data = json!({"buffer_paths": [["image"]], "width": 10});
+        let buffers = vec![Bytes::from_static(b"png")];
+
+        let resolved = resolve_buffers(data.as_object().unwrap(), &buffers).unwrap();
+        assert_eq!(decode_buffer(&resolved["image"]), Some(buffers[0].clone()));
+
+        let (extracted, _) = extract_buffers(&resolved);
+        assert_eq!(Value::Object(extracted), data);
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        let data = json!({"state": {}, "buffer_paths": [["missing", "value"]]});
+        assert!(matches!(
+            resolve_buffers(data.as_object().unwrap(), &[Bytes::from_static(b"x")]),
+            Err(JupyterError::InvalidBufferPath { .. })
+        ));
+
+        let data = json!({"state": {}, "buffer_paths": [["value"]]});
+        assert_eq!(
+            resolve_buffers(data.as_object().unwrap(), &[]),
+            Err(JupyterError::InvalidBufferPath {
+                path: vec![PathSegment::Key("value".to_string())],
+                reason: "there's no buffer for it",
+            })
+        );
+    }
+}
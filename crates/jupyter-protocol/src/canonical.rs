@@ -0,0 +1,171 @@
+//! Deterministic JSON for message parts.
+//!
+//! Signatures are computed over the exact bytes of each message part, so two
+//! implementations only agree on an HMAC if they agree on the bytes. serde's
+//! output depends on struct field order (or map insertion order), which makes
+//! byte-level comparisons with other implementations brittle. The canonical
+//! form here is what Python produces with
+//! `json.dumps(obj, sort_keys=True, separators=(",", ":"), ensure_ascii=False)`:
+//!
+//! - object keys sorted by code point
+//! - no whitespace
+//! - non-ASCII text left as UTF-8
+//! - floats written like Python's `repr`, e.g. `1e-05`, `1e+16` and `3.0`
+//!
+//! ```rust
+//! use jupyter_protocol::canonical::to_canonical_vec;
+//! use serde_json::json;
+//!
+//! let value = json!({"b": 1e-5, "a": [3.0, "é"]});
+//! assert_eq!(to_canonical_vec(&value).unwrap(), r#"{"a":[3.0,"é"],"b":1e-05}"#.as_bytes());
+//! ```
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// Serialize `value` in the canonical form.
+pub fn to_canonical_vec<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<Vec<u8>> {
+    let value = serde_json::to_value(value)?;
+    let mut out = Vec::new();
+    write_value(&value, &mut out)?;
+    Ok(out)
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) -> serde_json::Result<()> {
+    match value {
+        Value::Null => out.extend_from_slice(b"null"),
+        Value::Bool(true) => out.extend_from_slice(b"true"),
+        Value::Bool(false) => out.extend_from_slice(b"false"),
+        Value::Number(number) => out.extend_from_slice(format_number(number).as_bytes()),
+        // serde_json escapes the same characters as Python with ensure_ascii=False
+        Value::String(string) => serde_json::to_writer(&mut *out, string)?,
+        Value::Array(items) => {
+            out.push(b'[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                write_value(item, out)?;
+            }
+            out.push(b']');
+        }
+        Value::Object(object) => {
+            // Sort here too, as `preserve_order` may be enabled for serde_json
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+
+            out.push(b'{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key)?;
+                out.push(b':');
+                write_value(value, out)?;
+            }
+            out.push(b'}');
+        }
+    }
+    Ok(())
+}
+
+fn format_number(number: &Number) -> String {
+    match number.as_f64() {
+        Some(float) if number.is_f64() => format_float(float),
+        _ => number.to_string(),
+    }
+}
+
+/// Format a float like Python's `repr`: the shortest digits that round trip,
+/// in scientific notation when the exponent is below -4 or above 15.
+pub fn format_float(float: f64) -> String {
+    let sign = if float.is_sign_negative() { "-" } else { "" };
+    // `{:e}` gives the shortest round trip digits, e.g. `1.5e-5`
+    let scientific = format!("{:e}", float.abs());
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("{:e} always has an exponent");
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().expect("{:e} exponent is an integer");
+    // Where the decimal point goes relative to the start of `digits`
+    let point = exponent + 1;
+
+    if point <= -4 || point > 16 {
+        let (first, rest) = digits.split_at(1);
+        let fraction = if rest.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", rest)
+        };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}{}e{}{:02}",
+            sign,
+            first,
+            fraction,
+            exponent_sign,
+            exponent.abs()
+        )
+    } else if point <= 0 {
+        format!(
+            "{}0.{}{}",
+            sign,
+            "0".repeat(point.unsigned_abs() as usize),
+            digits
+        )
+    } else if point as usize >= digits.len() {
+        format!(
+            "{}{}{}.0",
+            sign,
+            digits,
+            "0".repeat(point as usize - digits.len())
+        )
+    } else {
+        let (whole, fraction) = digits.split_at(point as usize);
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_floats_match_python_repr() {
+        // Expected values from Python's repr()
+        let cases = [
+            (0.0, "0.0"),
+            (-0.0, "-0.0"),
+            (1.0, "1.0"),
+            (0.1, "0.1"),
+            (-2.5, "-2.5"),
+            (100.0, "100.0"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (1.5e-7, "1.5e-07"),
+            (1e15, "1000000000000000.0"),
+            (1e16, "1e+16"),
+            (1.2345e100, "1.2345e+100"),
+            (123456.789, "123456.789"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (5e-324, "5e-324"),
+        ];
+        for (float, expected) in cases {
+            assert_eq!(format_float(float), expected, "formatting {:?}", float);
+        }
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let value = json!({
+            "z": {"b": [1, -2, 3.5], "a": null},
+            "a": "line\nbreak \"quoted\" \u{1} ☃",
+            "m": true,
+        });
+        assert_eq!(
+            String::from_utf8(to_canonical_vec(&value).unwrap()).unwrap(),
+            r#"{"a":"line\nbreak \"quoted\" \u0001 ☃","m":true,"z":{"a":null,"b":[1,-2,3.5]}}"#
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! What a kernel's language and implementation support, from its `kernel_info_reply`.
+//!
+//! Exporters and REPLs keep needing the same few facts about a kernel: which
+//! language to tag a Markdown code fence with, how to write a comment, what
+//! extension a script should get. [`KernelCapabilities`] derives them from the
+//! reply instead of guessing from the kernel name.
+//!
+//! ```rust
+//! use jupyter_protocol::{KernelCapabilities, KernelInfoReply};
+//!
+//! let reply: KernelInfoReply = serde_json::from_value(serde_json::json!({
+//!     "status": "ok",
+//!     "protocol_version": "5.3",
+//!     "implementation": "ipython",
+//!     "implementation_version": "8.24.0",
+//!     "language_info": {
+//!         "name": "python",
+//!         "version": "3.12.2",
+//!         "mimetype": "text/x-python",
+//!         "file_extension": ".py",
+//!         "pygments_lexer": "ipython3",
+//!         "codemirror_mode": {"name": "ipython", "version": 3},
+//!         "nbconvert_exporter": "python"
+//!     },
+//!     "banner": "",
+//!     "help_links": [],
+//!     "debugger": true
+//! }))
+//! .unwrap();
+//!
+//! let capabilities = KernelCapabilities::from(&reply);
+//! assert_eq!(capabilities.code_fence_language(), "python");
+//! assert_eq!(capabilities.comment_prefix(), Some("#"));
+//! assert!(capabilities.supports_debugger);
+//! ```
+use serde::{Deserialize, Serialize};
+
+use crate::KernelInfoReply;
+
+/// Facts about a kernel that tools commonly need, taken from its `kernel_info_reply`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KernelCapabilities {
+    /// The language name as the kernel reports it, e.g. `python` or `C++17`.
+    pub language: String,
+    pub language_version: String,
+    /// The pygments lexer for highlighting, if the kernel names one.
+    pub pygments_lexer: Option<String>,
+    /// Extension for scripts in this language, including the leading dot.
+    pub file_extension: String,
+    pub mimetype: String,
+    /// Whether the kernel handles `debug_request` on control.
+    pub supports_debugger: bool,
+    pub implementation: String,
+    pub protocol_version: String,
+}
+
+impl From<&KernelInfoReply> for KernelCapabilities {
+    fn from(reply: &KernelInfoReply) -> Self {
+        let language_info = &reply.language_info;
+        let file_extension = match language_info.file_extension.as_str() {
+            "" => String::new(),
+            extension if extension.starts_with('.') => extension.to_string(),
+            extension => format!(".{}", extension),
+        };
+        Self {
+            language: language_info.name.clone(),
+            language_version: language_info.version.clone(),
+            pygments_lexer: language_info
+                .pygments_lexer
+                .clone()
+                .filter(|lexer| !lexer.is_empty()),
+            file_extension,
+            mimetype: language_info.mimetype.clone(),
+            supports_debugger: reply.debugger,
+            implementation: reply.implementation.clone(),
+            protocol_version: reply.protocol_version.clone(),
+        }
+    }
+}
+
+impl KernelCapabilities {
+    /// The language to tag Markdown code fences with, as GitHub and most
+    /// highlighters name it.
+    ///
+    /// Kernel lexer names like `ipython3` and language names like `C++17`
+    /// are mapped to the common name (`python`, `cpp`).
+    pub fn code_fence_language(&self) -> String {
+        let name = self
+            .pygments_lexer
+            .as_deref()
+            .unwrap_or(&self.language)
+            .to_lowercase();
+        canonical_language(&name)
+            .or_else(|| canonical_language(&self.language.to_lowercase()))
+            .map(String::from)
+            .unwrap_or(name)
+    }
+
+    /// The line comment prefix for the kernel's language, if it's known.
+    pub fn comment_prefix(&self) -> Option<&'static str> {
+        let language = self.code_fence_language();
+        let prefix = match language.as_str() {
+            "python" | "r" | "julia" | "ruby" | "perl" | "bash" | "sh" | "powershell" | "nim"
+            | "elixir" | "tcl" | "coffeescript" | "raku" => "#",
+            "rust" | "c" | "cpp" | "csharp" | "fsharp" | "java" | "javascript" | "typescript"
+            | "scala" | "kotlin" | "go" | "swift" | "dart" | "groovy" | "php" | "zig" => "//",
+            "sql" | "haskell" | "lua" | "ada" | "elm" => "--",
+            "matlab" | "octave" | "erlang" | "prolog" | "tex" => "%",
+            "clojure" | "scheme" | "racket" | "lisp" | "commonlisp" => ";",
+            "fortran" => "!",
+            _ => return None,
+        };
+        Some(prefix)
+    }
+}
+
+/// The common name for a language or lexer that goes by several.
+fn canonical_language(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "ipython" | "ipython2" | "ipython3" | "python2" | "python3" => "python",
+        "c++" | "c++11" | "c++14" | "c++17" | "c++20" | "c++23" | "cpp" | "xeus-cling-cpp" => "cpp",
+        "c#" | "csharp" => "csharp",
+        "f#" | "fsharp" => "fsharp",
+        "js" | "javascript" | "node" | "nodejs" => "javascript",
+        "ts" | "typescript" => "typescript",
+        "rust" => "rust",
+        "r" => "r",
+        "julia" => "julia",
+        "bash" | "sh" | "shell" | "zsh" => "bash",
+        "golang" | "go" => "go",
+        "octave" => "octave",
+        "matlab" => "matlab",
+        "common-lisp" | "commonlisp" | "lisp" | "sbcl" => "commonlisp",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CodeMirrorMode, LanguageInfo, ReplyStatus};
+
+    fn capabilities(name: &str, lexer: &str, file_extension: &str) -> KernelCapabilities {
+        KernelCapabilities::from(&KernelInfoReply {
+            status: ReplyStatus::Ok,
+            protocol_version: "5.3".to_string(),
+            implementation: "test".to_string(),
+            implementation_version: "1.0".to_string(),
+            language_info: LanguageInfo {
+                name: name.to_string(),
+                version: "1".to_string(),
+                mimetype: "text/plain".to_string(),
+                file_extension: file_extension.to_string(),
+                pygments_lexer: Some(lexer.to_string()),
+                codemirror_mode: Some(CodeMirrorMode::Simple(name.to_string())),
+                nbconvert_exporter: Some("script".to_string()),
+            },
+            banner: String::new(),
+            help_links: Vec::new(),
+            debugger: false,
+            error: None,
+        })
+    }
+
+    #[test]
+    fn test_code_fence_language() {
+        assert_eq!(
+            capabilities("python", "ipython3", ".py").code_fence_language(),
+            "python"
+        );
+        assert_eq!(
+            capabilities("C++17", "", ".cpp").code_fence_language(),
+            "cpp"
+        );
+        assert_eq!(
+            capabilities("typescript", "typescript", ".ts").code_fence_language(),
+            "typescript"
+        );
+        assert_eq!(capabilities("R", "r", ".r").code_fence_language(), "r");
+        // Unknown languages go by their lexer
+        assert_eq!(
+            capabilities("Wolfram Language", "mathematica", ".wl").code_fence_language(),
+            "mathematica"
+        );
+    }
+
+    #[test]
+    fn test_comment_prefix() {
+        assert_eq!(
+            capabilities("python", "ipython3", ".py").comment_prefix(),
+            Some("#")
+        );
+        assert_eq!(
+            capabilities("rust", "rust", ".rs").comment_prefix(),
+            Some("//")
+        );
+        assert_eq!(capabilities("sql", "", ".sql").comment_prefix(), Some("--"));
+        assert_eq!(capabilities("brainfuck", "", ".bf").comment_prefix(), None);
+    }
+
+    #[test]
+    fn test_file_extension_has_dot() {
+        assert_eq!(capabilities("julia", "julia", "jl").file_extension, ".jl");
+        assert_eq!(capabilities("julia", "julia", ".jl").file_extension, ".jl");
+    }
+}
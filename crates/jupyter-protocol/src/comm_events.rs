@@ -0,0 +1,441 @@
+//! Comm lifecycle events, for observers that don't handle comms themselves.
+//!
+//! Recorders and UIs want to know when comms open, what they say and when
+//! they close, without being the ones answering them. [`CommObserver`] reads
+//! the comm messages going by and turns them into [`CommEvent`]s, each with
+//! the target name of its comm, and [`comm_event_stream`] does the same for a
+//! stream of messages. It keeps track of what's open in a [`CommRegistry`],
+//! like a kernel does to answer `comm_info_request`.
+//!
+//! An observer that subscribes to iopub late, or reads it slower than shell,
+//! can see a `comm_msg` before the `comm_open` it belongs to. Those messages
+//! are held back until the open arrives and then come out right after it. If
+//! it never arrives, they're dropped as [`CommEvent::GarbageCollected`] once
+//! they're older than the pending timeout, by their header dates, or once too
+//! many of them have piled up.
+//!
+//! ```rust
+//! use jupyter_protocol::comm_events::{CommEvent, CommObserver};
+//! use jupyter_protocol::{CommId, CommMsg, CommOpen, JupyterMessage};
+//!
+//! let comm_id = CommId("slider".to_string());
+//! let update: JupyterMessage = CommMsg {
+//!     comm_id: comm_id.clone(),
+//!     data: Default::default(),
+//! }
+//! .into();
+//! let open: JupyterMessage = CommOpen {
+//!     comm_id: comm_id.clone(),
+//!     target_name: "jupyter.widget".to_string(),
+//!     data: Default::default(),
+//! }
+//! .into();
+//!
+//! let mut observer = CommObserver::new();
+//! // Held back until the comm opens
+//! assert!(observer.observe(&update).is_empty());
+//!
+//! let events = observer.observe(&open);
+//! assert_eq!(events.len(), 2);
+//! assert!(matches!(
+//!     &events[0],
+//!     CommEvent::Opened { target_name, .. } if target_name == "jupyter.widget"
+//! ));
+//! assert!(matches!(&events[1], CommEvent::Message { .. }));
+//! assert_eq!(observer.target_name(&comm_id), Some("jupyter.widget"));
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+
+use crate::{CommId, CommRegistry, JupyterMessage, JupyterMessageContent};
+
+/// How long messages for a comm that hasn't opened are held, by header dates.
+pub const DEFAULT_PENDING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many messages are held for one comm that hasn't opened.
+pub const DEFAULT_MAX_PENDING_MESSAGES: usize = 256;
+
+/// Something that happened to a comm. `message` is the message it came from.
+#[derive(Debug, Clone)]
+pub enum CommEvent {
+    /// A `comm_open`.
+    Opened {
+        comm_id: CommId,
+        target_name: String,
+        message: JupyterMessage,
+    },
+    /// A `comm_msg` for an open comm.
+    Message {
+        comm_id: CommId,
+        target_name: String,
+        message: JupyterMessage,
+    },
+    /// A `comm_close`. The target name is `None` if the comm was never seen
+    /// opening.
+    Closed {
+        comm_id: CommId,
+        target_name: Option<String>,
+        message: JupyterMessage,
+    },
+    /// Messages held for a comm that never opened, now dropped.
+    GarbageCollected {
+        comm_id: CommId,
+        messages: Vec<JupyterMessage>,
+    },
+}
+
+impl CommEvent {
+    pub fn comm_id(&self) -> &CommId {
+        match self {
+            CommEvent::Opened { comm_id, .. }
+            | CommEvent::Message { comm_id, .. }
+            | CommEvent::Closed { comm_id, .. }
+            | CommEvent::GarbageCollected { comm_id, .. } => comm_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    since: DateTime<Utc>,
+    messages: Vec<JupyterMessage>,
+}
+
+/// Follows comm messages in both directions and derives [`CommEvent`]s.
+///
+/// Feed it the comm messages a client sends on shell as well as the ones
+/// the kernel publishes on iopub: comms can be opened from either side.
+#[derive(Debug, Clone)]
+pub struct CommObserver {
+    comms: CommRegistry,
+    pending: HashMap<CommId, Pending>,
+    pending_timeout: Duration,
+    max_pending_messages: usize,
+}
+
+impl Default for CommObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommObserver {
+    pub fn new() -> Self {
+        Self {
+            comms: CommRegistry::new(),
+            pending: HashMap::new(),
+            pending_timeout: DEFAULT_PENDING_TIMEOUT,
+            max_pending_messages: DEFAULT_MAX_PENDING_MESSAGES,
+        }
+    }
+
+    /// Set how long messages for a comm that hasn't opened are held.
+    pub fn with_pending_timeout(mut self, pending_timeout: Duration) -> Self {
+        self.pending_timeout = pending_timeout;
+        self
+    }
+
+    /// Set how many messages are held for one comm that hasn't opened.
+    pub fn with_max_pending_messages(mut self, max_pending_messages: usize) -> Self {
+        self.max_pending_messages = max_pending_messages.max(1);
+        self
+    }
+
+    pub fn target_name(&self, comm_id: &CommId) -> Option<&str> {
+        self.comms.target_name(comm_id)
+    }
+
+    /// The comms seen opening and not yet closed.
+    pub fn comms(&self) -> &CommRegistry {
+        &self.comms
+    }
+
+    /// The number of messages held for comms that haven't opened.
+    pub fn pending_messages(&self) -> usize {
+        self.pending
+            .values()
+            .map(|pending| pending.messages.len())
+            .sum()
+    }
+
+    /// The events `message` makes, in order. Messages that aren't comm
+    /// messages make none, other than collecting garbage.
+    pub fn observe(&mut self, message: &JupyterMessage) -> Vec<CommEvent> {
+        let mut events = self.collect_garbage(message.header.date);
+
+        match &message.content {
+            JupyterMessageContent::CommOpen(open) => {
+                self.comms.track(&message.content);
+                events.push(CommEvent::Opened {
+                    comm_id: open.comm_id.clone(),
+                    target_name: open.target_name.clone(),
+                    message: message.clone(),
+                });
+                if let Some(pending) = self.pending.remove(&open.comm_id) {
+                    events.extend(
+                        pending
+                            .messages
+                            .into_iter()
+                            .map(|message| CommEvent::Message {
+                                comm_id: open.comm_id.clone(),
+                                target_name: open.target_name.clone(),
+                                message,
+                            }),
+                    );
+                }
+            }
+            JupyterMessageContent::CommMsg(msg) => match self.comms.target_name(&msg.comm_id) {
+                Some(target_name) => events.push(CommEvent::Message {
+                    comm_id: msg.comm_id.clone(),
+                    target_name: target_name.to_string(),
+                    message: message.clone(),
+                }),
+                None => {
+                    let pending =
+                        self.pending
+                            .entry(msg.comm_id.clone())
+                            .or_insert_with(|| Pending {
+                                since: message.header.date,
+                                messages: Vec::new(),
+                            });
+                    pending.messages.push(message.clone());
+                    if pending.messages.len() > self.max_pending_messages {
+                        events.extend(self.drop_pending(&msg.comm_id));
+                    }
+                }
+            },
+            JupyterMessageContent::CommClose(close) => {
+                // Whatever was held for it will never be delivered now
+                events.extend(self.drop_pending(&close.comm_id));
+                events.push(CommEvent::Closed {
+                    comm_id: close.comm_id.clone(),
+                    target_name: self.comms.close(&close.comm_id),
+                    message: message.clone(),
+                });
+            }
+            _ => {}
+        }
+        events
+    }
+
+    /// Drop the messages held longer than the pending timeout as of `now`.
+    pub fn collect_garbage(&mut self, now: DateTime<Utc>) -> Vec<CommEvent> {
+        let timeout =
+            chrono::Duration::from_std(self.pending_timeout).unwrap_or(chrono::Duration::MAX);
+        let mut expired: Vec<CommId> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.signed_duration_since(pending.since) > timeout)
+            .map(|(comm_id, _)| comm_id.clone())
+            .collect();
+        expired.sort_by(|a, b| a.0.cmp(&b.0));
+        expired
+            .iter()
+            .filter_map(|comm_id| self.drop_pending(comm_id))
+            .collect()
+    }
+
+    /// Drop every message still held, e.g. when the connection closes.
+    pub fn flush(&mut self) -> Vec<CommEvent> {
+        let mut comm_ids: Vec<CommId> = self.pending.keys().cloned().collect();
+        comm_ids.sort_by(|a, b| a.0.cmp(&b.0));
+        comm_ids
+            .iter()
+            .filter_map(|comm_id| self.drop_pending(comm_id))
+            .collect()
+    }
+
+    fn drop_pending(&mut self, comm_id: &CommId) -> Option<CommEvent> {
+        self.pending
+            .remove(comm_id)
+            .map(|pending| CommEvent::GarbageCollected {
+                comm_id: comm_id.clone(),
+                messages: pending.messages,
+            })
+    }
+}
+
+/// Turn a stream of messages into a stream of [`CommEvent`]s, as `observer`
+/// derives them. Messages still held when `messages` ends come out as
+/// [`CommEvent::GarbageCollected`].
+pub fn comm_event_stream<S>(messages: S, observer: CommObserver) -> impl Stream<Item = CommEvent>
+where
+    S: Stream<Item = JupyterMessage>,
+{
+    let state = (Box::pin(messages), observer, VecDeque::new(), false);
+    futures::stream::unfold(
+        state,
+        |(mut messages, mut observer, mut queued, mut ended)| async move {
+            loop {
+                if let Some(event) = queued.pop_front() {
+                    return Some((event, (messages, observer, queued, ended)));
+                }
+                if ended {
+                    return None;
+                }
+                match messages.next().await {
+                    Some(message) => queued.extend(observer.observe(&message)),
+                    None => {
+                        queued.extend(observer.flush());
+                        ended = true;
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommClose, CommMsg, CommOpen, FixedClock};
+    use futures::executor::block_on;
+
+    fn at(seconds: i64) -> FixedClock {
+        FixedClock(DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap())
+    }
+
+    fn open(comm_id: &str, seconds: i64) -> JupyterMessage {
+        let open = CommOpen {
+            comm_id: CommId(comm_id.to_string()),
+            target_name: "jupyter.widget".to_string(),
+            data: Default::default(),
+        };
+        JupyterMessage::new_with_clock(open, None, &at(seconds))
+    }
+
+    fn msg(comm_id: &str, seconds: i64) -> JupyterMessage {
+        let msg = CommMsg {
+            comm_id: CommId(comm_id.to_string()),
+            data: Default::default(),
+        };
+        JupyterMessage::new_with_clock(msg, None, &at(seconds))
+    }
+
+    fn close(comm_id: &str, seconds: i64) -> JupyterMessage {
+        let close = CommClose {
+            comm_id: CommId(comm_id.to_string()),
+            data: Default::default(),
+        };
+        JupyterMessage::new_with_clock(close, None, &at(seconds))
+    }
+
+    fn kinds(events: &[CommEvent]) -> Vec<(&'static str, &str)> {
+        events
+            .iter()
+            .map(|event| {
+                let kind = match event {
+                    CommEvent::Opened { .. } => "opened",
+                    CommEvent::Message { .. } => "message",
+                    CommEvent::Closed { .. } => "closed",
+                    CommEvent::GarbageCollected { .. } => "garbage_collected",
+                };
+                (kind, event.comm_id().0.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut observer = CommObserver::new();
+        assert_eq!(kinds(&observer.observe(&open("a", 0))), [("opened", "a")]);
+        assert_eq!(
+            observer.comms().for_target("jupyter.widget"),
+            vec![&CommId("a".to_string())]
+        );
+        let events = observer.observe(&msg("a", 1));
+        assert!(matches!(
+            &events[..],
+            [CommEvent::Message { target_name, .. }] if target_name == "jupyter.widget"
+        ));
+
+        let events = observer.observe(&close("a", 2));
+        assert!(matches!(
+            &events[..],
+            [CommEvent::Closed { target_name: Some(target_name), .. }] if target_name == "jupyter.widget"
+        ));
+        assert_eq!(observer.target_name(&CommId("a".to_string())), None);
+        assert!(observer.comms().is_empty());
+
+        // Closing a comm that was never seen opening
+        let events = observer.observe(&close("b", 3));
+        assert!(matches!(
+            &events[..],
+            [CommEvent::Closed {
+                target_name: None,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_early_messages_follow_their_open() {
+        let mut observer = CommObserver::new();
+        let first = msg("a", 0);
+        let second = msg("a", 1);
+        assert!(observer.observe(&first).is_empty());
+        assert!(observer.observe(&second).is_empty());
+        assert_eq!(observer.pending_messages(), 2);
+
+        let events = observer.observe(&open("a", 2));
+        assert_eq!(
+            kinds(&events),
+            [("opened", "a"), ("message", "a"), ("message", "a")]
+        );
+        let CommEvent::Message { message, .. } = &events[1] else {
+            unreachable!()
+        };
+        assert_eq!(message.header.msg_id, first.header.msg_id);
+        assert_eq!(observer.pending_messages(), 0);
+    }
+
+    #[test]
+    fn test_unopened_comms_are_collected() {
+        let mut observer = CommObserver::new()
+            .with_pending_timeout(Duration::from_secs(10))
+            .with_max_pending_messages(2);
+        observer.observe(&msg("late", 0));
+        observer.observe(&msg("busy", 5));
+        observer.observe(&msg("busy", 6));
+
+        // A third message for the same comm is one too many
+        let events = observer.observe(&msg("busy", 7));
+        assert!(matches!(
+            &events[..],
+            [CommEvent::GarbageCollected { messages, .. }] if messages.len() == 3
+        ));
+
+        // Any message at all moves the clock past the timeout
+        let status = JupyterMessage::new_with_clock(crate::Status::idle(), None, &at(11));
+        assert_eq!(
+            kinds(&observer.observe(&status)),
+            [("garbage_collected", "late")]
+        );
+        assert_eq!(observer.pending_messages(), 0);
+
+        // Closing drops what's held for the comm before reporting the close
+        observer.observe(&msg("closed", 12));
+        assert_eq!(
+            kinds(&observer.observe(&close("closed", 13))),
+            [("garbage_collected", "closed"), ("closed", "closed")]
+        );
+    }
+
+    #[test]
+    fn test_stream_flushes_at_the_end() {
+        let messages = futures::stream::iter([msg("a", 0), open("b", 1), msg("b", 2)]);
+        let events: Vec<CommEvent> =
+            block_on(comm_event_stream(messages, CommObserver::new()).collect());
+        assert_eq!(
+            kinds(&events),
+            [
+                ("opened", "b"),
+                ("message", "b"),
+                ("garbage_collected", "a")
+            ]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Tracking which comms are open, to answer `comm_info_request`s.
+//!
+//! A [`CommRegistry`] follows `comm_open` and `comm_close` messages in either
+//! direction. Feed it every comm message the kernel receives on shell and
+//! every one it publishes on iopub, then answer `comm_info_request` with
+//! [`CommRegistry::reply`]. [`CommObserver`](crate::comm_events::CommObserver)
+//! keeps one too, for observers that want to know what's open.
+//!
+//! ```rust
+//! use jupyter_protocol::{CommClose, CommId, CommInfoRequest, CommOpen, CommRegistry};
+//!
+//! let mut comms = CommRegistry::new();
+//! comms.track(&CommOpen {
+//!     comm_id: CommId("slider".into()),
+//!     target_name: "jupyter.widget".into(),
+//!     data: Default::default(),
+//! }.into());
+//! comms.track(&CommOpen {
+//!     comm_id: CommId("logger".into()),
+//!     target_name: "my_comm".into(),
+//!     data: Default::default(),
+//! }.into());
+//!
+//! let reply = comms.reply(&CommInfoRequest::for_target("jupyter.widget"));
+//! assert_eq!(reply.comm_ids_for("jupyter.widget"), vec![&CommId("slider".into())]);
+//! assert_eq!(reply.comms.len(), 1);
+//!
+//! comms.track(&CommClose { comm_id: CommId("slider".into()), data: Default::default() }.into());
+//! assert!(comms.for_target("jupyter.widget").is_empty());
+//! ```
+use std::collections::HashMap;
+
+use crate::{CommId, CommInfoReply, CommInfoRequest, JupyterMessageContent};
+
+/// The comms open on a kernel, with their target names.
+#[derive(Debug, Clone, Default)]
+pub struct CommRegistry {
+    comms: HashMap<CommId, String>,
+}
+
+impl CommRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(&mut self, comm_id: CommId, target_name: impl Into<String>) {
+        self.comms.insert(comm_id, target_name.into());
+    }
+
+    /// Forget a comm, returning its target name if it was open.
+    pub fn close(&mut self, comm_id: &CommId) -> Option<String> {
+        self.comms.remove(comm_id)
+    }
+
+    /// Open or close comms as `content` says. Other messages are ignored.
+    pub fn track(&mut self, content: &JupyterMessageContent) {
+        match content {
+            JupyterMessageContent::CommOpen(open) => {
+                self.open(open.comm_id.clone(), open.target_name.clone())
+            }
+            JupyterMessageContent::CommClose(close) => {
+                self.close(&close.comm_id);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn target_name(&self, comm_id: &CommId) -> Option<&str> {
+        self.comms.get(comm_id).map(String::as_str)
+    }
+
+    /// The comms open with `target_name`, sorted by id.
+    pub fn for_target(&self, target_name: &str) -> Vec<&CommId> {
+        let mut comm_ids: Vec<&CommId> = self
+            .comms
+            .iter()
+            .filter(|(_, target)| *target == target_name)
+            .map(|(comm_id, _)| comm_id)
+            .collect();
+        comm_ids.sort_by(|a, b| a.0.cmp(&b.0));
+        comm_ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.comms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.comms.is_empty()
+    }
+
+    /// Answer a `comm_info_request`.
+    pub fn reply(&self, request: &CommInfoRequest) -> CommInfoReply {
+        CommInfoReply::for_request(
+            request,
+            self.comms
+                .iter()
+                .map(|(comm_id, target_name)| (comm_id.clone(), target_name.as_str())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommInfo, CommOpen};
+
+    fn open(comm_id: &str, target_name: &str) -> JupyterMessageContent {
+        CommOpen {
+            comm_id: CommId(comm_id.to_string()),
+            target_name: target_name.to_string(),
+            data: Default::default(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_empty_target_lists_every_comm() {
+        let mut comms = CommRegistry::new();
+        comms.track(&open("a", "jupyter.widget"));
+        comms.track(&open("b", "my_comm"));
+
+        let reply = comms.reply(&CommInfoRequest::default());
+        assert_eq!(reply.comms.len(), 2);
+        assert_eq!(
+            reply.comms.get(&CommId("b".to_string())),
+            Some(&CommInfo::new("my_comm"))
+        );
+        assert_eq!(
+            serde_json::to_value(comms.reply(&CommInfoRequest::for_target("my_comm"))).unwrap(),
+            serde_json::json!({
+                "status": "ok",
+                "comms": {"b": {"target_name": "my_comm"}}
+            })
+        );
+    }
+
+    #[test]
+    fn test_reopening_replaces_target() {
+        let mut comms = CommRegistry::new();
+        comms.track(&open("a", "jupyter.widget"));
+        comms.track(&open("a", "my_comm"));
+        assert_eq!(comms.len(), 1);
+        assert_eq!(comms.target_name(&CommId("a".to_string())), Some("my_comm"));
+
+        assert_eq!(
+            comms.close(&CommId("a".to_string())),
+            Some("my_comm".to_string())
+        );
+        assert!(comms.is_empty());
+        assert_eq!(comms.close(&CommId("a".to_string())), None);
+    }
+}
@@ -97,7 +97,11 @@ impl std::fmt::Display for Transport {
 ///
 /// assert_eq!(info.shell_url(), "tcp://127.0.0.1:6767");
 /// ```
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+///
+/// The `key` is a secret: anyone holding it can run code on the kernel. The
+/// `Debug` and `Display` output leave it out, and [`to_public`](Self::to_public)
+/// gives the rest to show or log.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct ConnectionInfo {
     pub ip: String,
     pub transport: Transport,
@@ -113,6 +117,64 @@ pub struct ConnectionInfo {
     pub kernel_name: Option<String>,
 }
 
+impl std::fmt::Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionInfo")
+            .field("ip", &self.ip)
+            .field("transport", &self.transport)
+            .field("shell_port", &self.shell_port)
+            .field("iopub_port", &self.iopub_port)
+            .field("stdin_port", &self.stdin_port)
+            .field("control_port", &self.control_port)
+            .field("hb_port", &self.hb_port)
+            .field("key", &crate::input::REDACTED)
+            .field("signature_scheme", &self.signature_scheme)
+            .field("kernel_name", &self.kernel_name)
+            .finish()
+    }
+}
+
+/// `python3 at tcp://127.0.0.1 (shell 6767, iopub 6768, stdin 6769, control 6770, hb 6771)`
+impl std::fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_public().fmt(f)
+    }
+}
+
+/// Where a kernel listens, without the key needed to talk to it. Safe to
+/// print, log or send to someone who shouldn't be able to run code.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PublicConnectionInfo {
+    pub ip: String,
+    pub transport: Transport,
+    pub shell_port: u16,
+    pub iopub_port: u16,
+    pub stdin_port: u16,
+    pub control_port: u16,
+    pub hb_port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel_name: Option<String>,
+}
+
+impl std::fmt::Display for PublicConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(kernel_name) = &self.kernel_name {
+            write!(f, "{} at ", kernel_name)?;
+        }
+        write!(
+            f,
+            "{}://{} (shell {}, iopub {}, stdin {}, control {}, hb {})",
+            self.transport,
+            self.ip,
+            self.shell_port,
+            self.iopub_port,
+            self.stdin_port,
+            self.control_port,
+            self.hb_port
+        )
+    }
+}
+
 /// Constructs a URL string from the given transport, IP address, and port.
 ///
 /// This is a helper function used internally to create formatted URL strings
@@ -133,6 +195,20 @@ fn form_url(transport: &Transport, ip: &str, port: u16) -> String {
 
 /// Provides methods to generate formatted URLs for various Jupyter communication channels.
 impl ConnectionInfo {
+    /// Everything but the `key` and `signature_scheme`.
+    pub fn to_public(&self) -> PublicConnectionInfo {
+        PublicConnectionInfo {
+            ip: self.ip.clone(),
+            transport: self.transport.clone(),
+            shell_port: self.shell_port,
+            iopub_port: self.iopub_port,
+            stdin_port: self.stdin_port,
+            control_port: self.control_port,
+            hb_port: self.hb_port,
+            kernel_name: self.kernel_name.clone(),
+        }
+    }
+
     /// Formats the URL for the IOPub channel.
     ///
     /// # Returns
@@ -251,4 +327,33 @@ mod test {
         assert_eq!(connection_info.signature_scheme, "hmac-sha256");
         assert_eq!(connection_info.kernel_name, Some("anaconda".to_string()));
     }
+
+    #[test]
+    fn test_key_stays_out_of_output() {
+        let connection_info = ConnectionInfo {
+            ip: "127.0.0.1".to_string(),
+            transport: Transport::TCP,
+            shell_port: 6767,
+            iopub_port: 6768,
+            stdin_port: 6769,
+            control_port: 6770,
+            hb_port: 6771,
+            key: "e733b584-1d43845bc7d8d11a60df6363".to_string(),
+            signature_scheme: "hmac-sha256".to_string(),
+            kernel_name: Some("python3".to_string()),
+        };
+
+        let debug = format!("{:?}", connection_info);
+        assert!(!debug.contains(&connection_info.key));
+        assert!(debug.contains("key: \"<redacted>\""));
+        assert_eq!(
+            connection_info.to_string(),
+            "python3 at tcp://127.0.0.1 (shell 6767, iopub 6768, stdin 6769, control 6770, hb 6771)"
+        );
+
+        let public = serde_json::to_value(connection_info.to_public()).unwrap();
+        assert_eq!(public.get("key"), None);
+        assert_eq!(public.get("signature_scheme"), None);
+        assert_eq!(public["shell_port"], 6767);
+    }
 }
@@ -0,0 +1,190 @@
+//! Cursor positions in code sent to and from kernels.
+//!
+//! Since protocol 5.2, `cursor_pos` in complete and inspect requests and
+//! `cursor_start`/`cursor_end` in complete replies count Unicode code points.
+//! Rust strings are indexed by bytes and Javascript editors by UTF-16 code
+//! units, so passing offsets through unconverted works until someone types
+//! `é` or an emoji before the cursor. [`convert_offset`] translates between
+//! the three, and [`CompleteReply::apply`] inserts a selected match.
+//!
+//! ```rust
+//! use jupyter_protocol::cursor::{convert_offset, CursorUnit};
+//! use jupyter_protocol::CompleteReply;
+//!
+//! // A browser editor reports the cursor after `pri`, in UTF-16 code units
+//! let code = "s = '🦀'; pri";
+//! let cursor_pos = convert_offset(code, 13, CursorUnit::Utf16, CursorUnit::Chars).unwrap();
+//! assert_eq!(cursor_pos, 12);
+//!
+//! let reply = CompleteReply {
+//!     matches: vec!["print".to_string()],
+//!     cursor_start: 9,
+//!     cursor_end: 12,
+//!     ..Default::default()
+//! };
+//! assert_eq!(reply.replace_range(code, CursorUnit::Utf16), Some(10..13));
+//! assert_eq!(
+//!     reply.apply(code, &reply.matches[0]),
+//!     Some(("s = '🦀'; print".to_string(), 14))
+//! );
+//! ```
+use std::ops::Range;
+
+use crate::CompleteReply;
+
+/// What an offset into a string counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CursorUnit {
+    /// UTF-8 bytes, for indexing Rust strings.
+    Bytes,
+    /// Unicode code points, as the Jupyter protocol counts them.
+    Chars,
+    /// UTF-16 code units, as Javascript strings and browser editors count them.
+    Utf16,
+}
+
+impl CursorUnit {
+    fn len(&self, c: char) -> usize {
+        match self {
+            CursorUnit::Bytes => c.len_utf8(),
+            CursorUnit::Chars => 1,
+            CursorUnit::Utf16 => c.len_utf16(),
+        }
+    }
+}
+
+/// `offset` into `source`, counted in `from`, converted to `to`. `None` if
+/// it's past the end of `source` or lands inside a character, like the middle
+/// of a UTF-8 sequence or surrogate pair.
+pub fn convert_offset(
+    source: &str,
+    offset: usize,
+    from: CursorUnit,
+    to: CursorUnit,
+) -> Option<usize> {
+    let mut counted = 0;
+    let mut converted = 0;
+    for c in source.chars() {
+        if counted >= offset {
+            break;
+        }
+        counted += from.len(c);
+        converted += to.len(c);
+    }
+    (counted == offset).then_some(converted)
+}
+
+impl CompleteReply {
+    /// The part of `code` the matches replace, counted in `unit`. `None` if
+    /// the reply's cursor doesn't fit `code`, e.g. because the code changed
+    /// since the request was sent.
+    pub fn replace_range(&self, code: &str, unit: CursorUnit) -> Option<Range<usize>> {
+        if self.cursor_start > self.cursor_end {
+            return None;
+        }
+        let start = convert_offset(code, self.cursor_start, CursorUnit::Chars, unit)?;
+        let end = convert_offset(code, self.cursor_end, CursorUnit::Chars, unit)?;
+        Some(start..end)
+    }
+
+    /// `code` with the replaced part swapped for `selected`, usually one of
+    /// the [`matches`](Self::matches), and the cursor position just after it
+    /// in code points.
+    pub fn apply(&self, code: &str, selected: &str) -> Option<(String, usize)> {
+        let range = self.replace_range(code, CursorUnit::Bytes)?;
+        let mut completed = String::with_capacity(code.len() + selected.len());
+        completed.push_str(&code[..range.start]);
+        completed.push_str(selected);
+        completed.push_str(&code[range.end..]);
+        Some((completed, self.cursor_start + selected.chars().count()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_offset() {
+        // é is 2 bytes and 1 UTF-16 unit, 🦀 is 4 bytes and 2 UTF-16 units
+        let source = "é🦀x";
+        let positions = [(0, 0, 0), (2, 1, 1), (6, 2, 3), (7, 3, 4)];
+        for (bytes, chars, utf16) in positions {
+            for (from, from_offset) in [
+                (CursorUnit::Bytes, bytes),
+                (CursorUnit::Chars, chars),
+                (CursorUnit::Utf16, utf16),
+            ] {
+                assert_eq!(
+                    convert_offset(source, from_offset, from, CursorUnit::Bytes),
+                    Some(bytes)
+                );
+                assert_eq!(
+                    convert_offset(source, from_offset, from, CursorUnit::Chars),
+                    Some(chars)
+                );
+                assert_eq!(
+                    convert_offset(source, from_offset, from, CursorUnit::Utf16),
+                    Some(utf16)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_offset_inside_a_character() {
+        let source = "é🦀x";
+        assert_eq!(
+            convert_offset(source, 1, CursorUnit::Bytes, CursorUnit::Chars),
+            None
+        );
+        // Between the halves of the crab's surrogate pair
+        assert_eq!(
+            convert_offset(source, 2, CursorUnit::Utf16, CursorUnit::Chars),
+            None
+        );
+        assert_eq!(
+            convert_offset(source, 4, CursorUnit::Chars, CursorUnit::Bytes),
+            None
+        );
+        assert_eq!(
+            convert_offset("", 0, CursorUnit::Chars, CursorUnit::Utf16),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_apply_keeps_text_after_the_cursor() {
+        // Completing `ma` inside `f(ma, 1)` with the cursor after `ma`
+        let code = "naïve = f(ma, 1)";
+        let reply = CompleteReply {
+            matches: vec!["math".to_string(), "max".to_string()],
+            cursor_start: 10,
+            cursor_end: 12,
+            ..Default::default()
+        };
+        assert_eq!(reply.replace_range(code, CursorUnit::Bytes), Some(11..13));
+        assert_eq!(
+            reply.apply(code, "max"),
+            Some(("naïve = f(max, 1)".to_string(), 13))
+        );
+    }
+
+    #[test]
+    fn test_stale_reply_is_rejected() {
+        let reply = CompleteReply {
+            matches: vec!["print".to_string()],
+            cursor_start: 0,
+            cursor_end: 10,
+            ..Default::default()
+        };
+        assert_eq!(reply.apply("pri", "print"), None);
+
+        let backwards = CompleteReply {
+            cursor_start: 2,
+            cursor_end: 1,
+            ..Default::default()
+        };
+        assert_eq!(backwards.replace_range("pri", CursorUnit::Chars), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Display handles, for kernels that show an output and then change it.
+//!
+//! Mirrors IPython's `display(..., display_id=True)`: [`DisplayHandle::display`]
+//! creates a `display_data` tagged with the handle's `display_id`, and each
+//! [`DisplayHandle::update`] replaces that output in place with an
+//! `update_display_data`. Streaming kernels can show progress and partial
+//! results this way without sending `clear_output` before every change.
+//!
+//! ```rust
+//! use jupyter_protocol::display::DisplayHandle;
+//! use jupyter_protocol::MediaType;
+//!
+//! let handle = DisplayHandle::new();
+//! let display = handle.display(MediaType::Markdown("_thinking_".into()));
+//! assert_eq!(
+//!     display.transient.as_ref().and_then(|transient| transient.display_id.as_deref()),
+//!     Some(handle.display_id())
+//! );
+//!
+//! let update = handle.update(MediaType::Markdown("Hello".into()));
+//! assert_eq!(update.transient.display_id.as_deref(), Some(handle.display_id()));
+//! ```
+use crate::{
+    DisplayData, JupyterMessage, JupyterMessageContent, Media, Transient, UpdateDisplayData,
+};
+
+/// Refers to one output so it can be updated after it's displayed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DisplayHandle {
+    display_id: String,
+}
+
+impl Default for DisplayHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayHandle {
+    /// A handle with a new, unique display id.
+    pub fn new() -> Self {
+        Self::with_id(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// A handle for an existing display id. Every output displayed with the
+    /// same id is updated together.
+    pub fn with_id(display_id: impl Into<String>) -> Self {
+        Self {
+            display_id: display_id.into(),
+        }
+    }
+
+    pub fn display_id(&self) -> &str {
+        &self.display_id
+    }
+
+    /// Show `data` as a new output that later updates replace.
+    pub fn display(&self, data: impl Into<Media>) -> DisplayData {
+        DisplayData {
+            transient: Some(Transient::new(&self.display_id)),
+            ..DisplayData::new(data.into())
+        }
+    }
+
+    /// Replace what every output with this handle's display id shows.
+    pub fn update(&self, data: impl Into<Media>) -> UpdateDisplayData {
+        UpdateDisplayData::new(data.into(), &self.display_id)
+    }
+
+    /// Whether `message` displays or updates this handle's output.
+    pub fn is_for(&self, message: &JupyterMessage) -> bool {
+        let transient = match &message.content {
+            JupyterMessageContent::DisplayData(display_data) => display_data.transient.as_ref(),
+            JupyterMessageContent::ExecuteResult(execute_result) => {
+                execute_result.transient.as_ref()
+            }
+            JupyterMessageContent::UpdateDisplayData(update) => Some(&update.transient),
+            _ => None,
+        };
+        transient.and_then(|transient| transient.display_id.as_deref())
+            == Some(self.display_id.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MediaType, StreamContent};
+
+    #[test]
+    fn test_handles_have_unique_ids() {
+        assert_ne!(DisplayHandle::new(), DisplayHandle::new());
+        assert_eq!(DisplayHandle::with_id("plot").display_id(), "plot");
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let handle = DisplayHandle::with_id("progress");
+        let display = serde_json::to_value(handle.display(MediaType::Plain("0%".into()))).unwrap();
+        assert_eq!(
+            display,
+            serde_json::json!({
+                "data": {"text/plain": "0%"},
+                "metadata": {},
+                "transient": {"display_id": "progress"}
+            })
+        );
+
+        let update = serde_json::to_value(handle.update(MediaType::Plain("50%".into()))).unwrap();
+        assert_eq!(
+            update,
+            serde_json::json!({
+                "data": {"text/plain": "50%"},
+                "metadata": {},
+                "transient": {"display_id": "progress"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_is_for() {
+        let handle = DisplayHandle::new();
+        let other = DisplayHandle::new();
+
+        let update = JupyterMessage::new(handle.update(MediaType::Plain("1".into())), None);
+        assert!(handle.is_for(&update));
+        assert!(!other.is_for(&update));
+
+        let display = JupyterMessage::new(handle.display(MediaType::Plain("1".into())), None);
+        assert!(handle.is_for(&display));
+
+        let untagged = JupyterMessage::new(StreamContent::stdout("1"), None);
+        assert!(!handle.is_for(&untagged));
+    }
+}
@@ -0,0 +1,46 @@
+//! Errors with a meaning callers may want to act on, as opposed to plain
+//! `anyhow` failures. Functions that return `anyhow::Error` can still carry
+//! these; use `error.downcast_ref::<JupyterError>()` to find them.
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum JupyterError {
+    /// Part of a message is bigger than the configured [`MessageLimits`](crate::MessageLimits).
+    #[error("Message {part} is {size} bytes, over the limit of {limit} bytes")]
+    MessageTooLarge {
+        /// Which part of the message was too big: `content` or `buffers`.
+        part: &'static str,
+        size: usize,
+        limit: usize,
+    },
+    /// A comm message's `buffer_paths` don't line up with its state or buffers.
+    #[error("Invalid buffer path {path:?}: {reason}")]
+    InvalidBufferPath {
+        path: crate::buffers::BufferPath,
+        reason: &'static str,
+    },
+    /// A message arrived on a channel the protocol doesn't carry it on, like
+    /// an `execute_request` on control. See [`routing`](crate::routing).
+    #[error(
+        "{msg_type} from the {origin} is not allowed on {channel}, expected {}",
+        display_channels(expected)
+    )]
+    UnexpectedChannel {
+        msg_type: String,
+        channel: crate::Channel,
+        origin: crate::routing::Origin,
+        expected: Vec<crate::Channel>,
+    },
+    /// A message marked for one channel was sent on another channel's socket.
+    #[error("{msg_type} is marked for {channel} but was sent on {socket}")]
+    ChannelMismatch {
+        msg_type: String,
+        channel: crate::Channel,
+        socket: crate::Channel,
+    },
+}
+
+fn display_channels(channels: &[crate::Channel]) -> String {
+    let names: Vec<String> = channels.iter().map(ToString::to_string).collect();
+    names.join(" or ")
+}
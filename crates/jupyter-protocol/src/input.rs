@@ -0,0 +1,266 @@
+//! Answering `input_request`s without leaking passwords.
+//!
+//! When a kernel asks for a password (`getpass()` in Python), the reply travels
+//! back over stdin like any other input. Anything that logs or records messages
+//! has to know not to keep that value. Replies built with [`InputReply::secret`]
+//! are redacted from `Debug` output and [`JupyterMessage::redacted`]. Replies
+//! read off the wire carry no such mark, so [`InputRedactor`] watches the
+//! requests and redacts the replies to password prompts by `parent_header`.
+//!
+//! Clients can answer password prompts from a [`SecretProvider`] (a keyring,
+//! environment variables, a vault) instead of asking the user and echoing the
+//! value into history.
+//!
+//! ```rust
+//! use jupyter_protocol::input::{answer_input_request, InputRedactor, REDACTED};
+//! use jupyter_protocol::{InputRequest, JupyterMessage, JupyterMessageContent};
+//!
+//! let request: JupyterMessage = InputRequest {
+//!     prompt: "Password: ".to_string(),
+//!     password: true,
+//! }
+//! .into();
+//!
+//! let secrets = |_: &InputRequest| Some("hunter2".to_string());
+//! let JupyterMessageContent::InputRequest(input_request) = &request.content else {
+//!     unreachable!()
+//! };
+//! let reply = answer_input_request(input_request, Some(&secrets), |_| String::new());
+//! assert!(reply.is_secret());
+//!
+//! // The reply as a recorder would see it after it crossed the wire
+//! let mut on_the_wire = reply.clone();
+//! on_the_wire.secret = false;
+//! let reply_message = on_the_wire.as_child_of(&request);
+//!
+//! let mut redactor = InputRedactor::new();
+//! redactor.observe(&request);
+//! let JupyterMessageContent::InputReply(recorded) = redactor.redact(&reply_message).content else {
+//!     unreachable!()
+//! };
+//! assert_eq!(recorded.value, REDACTED);
+//! ```
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{InputReply, InputRequest, JupyterMessage, JupyterMessageContent};
+
+/// What redacted input values are replaced with.
+pub const REDACTED: &str = "<redacted>";
+
+/// How long [`InputRedactor`] waits for the reply to a password prompt unless
+/// [`with_max_age`](InputRedactor::with_max_age) says otherwise.
+pub const DEFAULT_MAX_PENDING_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// A source of answers for password prompts.
+pub trait SecretProvider: Send + Sync {
+    /// The secret for this prompt, or `None` to fall back to asking the user.
+    fn secret(&self, request: &InputRequest) -> Option<String>;
+}
+
+impl<F> SecretProvider for F
+where
+    F: Fn(&InputRequest) -> Option<String> + Send + Sync,
+{
+    fn secret(&self, request: &InputRequest) -> Option<String> {
+        self(request)
+    }
+}
+
+/// Build the reply to `request`.
+///
+/// Password prompts are answered from `secrets` when it has a value, and
+/// otherwise by `prompt_user`. Either way the reply to a password prompt is
+/// marked secret.
+pub fn answer_input_request(
+    request: &InputRequest,
+    secrets: Option<&dyn SecretProvider>,
+    prompt_user: impl FnOnce(&InputRequest) -> String,
+) -> InputReply {
+    if !request.password {
+        return InputReply::new(prompt_user(request));
+    }
+
+    let value = secrets
+        .and_then(|secrets| secrets.secret(request))
+        .unwrap_or_else(|| prompt_user(request));
+    InputReply::secret(value)
+}
+
+/// Redacts replies to password prompts from a stream of messages.
+///
+/// Feed every message through [`observe`](InputRedactor::observe) (or
+/// [`redact`](InputRedactor::redact), which also observes) in the order they
+/// were sent or received.
+///
+/// Password prompts that go unanswered, because the kernel was interrupted or
+/// the reply was never seen, are forgotten after a while so a long running
+/// redactor doesn't grow without bound. A reply arriving later than that is
+/// only redacted if it is marked [secret](InputReply::secret).
+#[derive(Debug)]
+pub struct InputRedactor {
+    /// Password `input_request` ids, and when they were seen
+    pending: HashMap<String, Instant>,
+    max_age: Duration,
+}
+
+impl Default for InputRedactor {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+            max_age: DEFAULT_MAX_PENDING_AGE,
+        }
+    }
+}
+
+impl InputRedactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget password prompts that have waited `max_age` for a reply.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Remember `message` if it is a password `input_request`.
+    pub fn observe(&mut self, message: &JupyterMessage) {
+        let max_age = self.max_age;
+        self.pending.retain(|_, seen| seen.elapsed() < max_age);
+
+        if let JupyterMessageContent::InputRequest(request) = &message.content {
+            if request.password {
+                self.pending
+                    .insert(message.header.msg_id.clone(), Instant::now());
+            }
+        }
+    }
+
+    /// How many password prompts are waiting for a reply.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// A copy of `message` that is safe to log or record.
+    pub fn redact(&mut self, message: &JupyterMessage) -> JupyterMessage {
+        self.observe(message);
+
+        let JupyterMessageContent::InputReply(reply) = &message.content else {
+            return message.clone();
+        };
+
+        let answers_password = message
+            .parent_header
+            .as_ref()
+            .is_some_and(|parent| self.pending.remove(&parent.msg_id).is_some());
+        if !answers_password && !reply.is_secret() {
+            return message.clone();
+        }
+
+        let mut message = message.clone();
+        message.content = JupyterMessageContent::InputReply(InputReply {
+            value: REDACTED.to_string(),
+            ..reply.clone()
+        });
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(password: bool) -> InputRequest {
+        InputRequest {
+            prompt: "> ".to_string(),
+            password,
+        }
+    }
+
+    #[test]
+    fn test_plain_prompts_ignore_secrets() {
+        let secrets = |_: &InputRequest| Some("hunter2".to_string());
+        let reply = answer_input_request(&request(false), Some(&secrets), |_| "42".to_string());
+        assert_eq!(reply.value, "42");
+        assert!(!reply.is_secret());
+    }
+
+    #[test]
+    fn test_password_falls_back_to_prompt() {
+        let secrets = |_: &InputRequest| None;
+        let reply = answer_input_request(&request(true), Some(&secrets), |_| "typed".to_string());
+        assert_eq!(reply.value, "typed");
+        assert!(reply.is_secret());
+        assert!(!format!("{:?}", reply).contains("typed"));
+    }
+
+    #[test]
+    fn test_secret_replies_are_redacted_but_sent() {
+        let request: JupyterMessage = request(true).into();
+        let reply = InputReply::secret("hunter2").as_child_of(&request);
+
+        assert_eq!(
+            serde_json::to_value(&reply.content).unwrap()["value"],
+            "hunter2"
+        );
+        assert!(!format!("{:?}", reply).contains("hunter2"));
+        let JupyterMessageContent::InputReply(redacted) = reply.redacted().content else {
+            panic!("expected an input_reply");
+        };
+        assert_eq!(redacted.value, REDACTED);
+    }
+
+    #[test]
+    fn test_redactor_matches_replies_by_parent() {
+        let mut redactor = InputRedactor::new();
+        let password: JupyterMessage = request(true).into();
+        let plain: JupyterMessage = request(false).into();
+        redactor.observe(&password);
+        redactor.observe(&plain);
+
+        let value = |message: JupyterMessage| match message.content {
+            JupyterMessageContent::InputReply(reply) => reply.value,
+            _ => panic!("expected an input_reply"),
+        };
+        assert_eq!(
+            value(redactor.redact(&InputReply::new("42").as_child_of(&plain))),
+            "42"
+        );
+        assert_eq!(
+            value(redactor.redact(&InputReply::new("hunter2").as_child_of(&password))),
+            REDACTED
+        );
+    }
+
+    #[test]
+    fn test_redactor_forgets_answered_prompts() {
+        let mut redactor = InputRedactor::new();
+        let password: JupyterMessage = request(true).into();
+        redactor.observe(&password);
+        assert_eq!(redactor.pending(), 1);
+
+        redactor.redact(&InputReply::new("hunter2").as_child_of(&password));
+        assert_eq!(redactor.pending(), 0);
+    }
+
+    #[test]
+    fn test_redactor_evicts_old_prompts() {
+        let mut redactor = InputRedactor::new().with_max_age(Duration::ZERO);
+        redactor.observe(&request(true).into());
+        redactor.observe(&request(true).into());
+        // Everything already seen has waited long enough
+        assert_eq!(redactor.pending(), 1);
+
+        // A reply marked secret is still redacted after its prompt is gone
+        let password: JupyterMessage = request(true).into();
+        let mut redactor = InputRedactor::new().with_max_age(Duration::ZERO);
+        redactor.observe(&password);
+        let reply = redactor.redact(&InputReply::secret("hunter2").as_child_of(&password));
+        assert_eq!(redactor.pending(), 0);
+        let JupyterMessageContent::InputReply(reply) = reply.content else {
+            panic!("expected an input_reply");
+        };
+        assert_eq!(reply.value, REDACTED);
+    }
+}
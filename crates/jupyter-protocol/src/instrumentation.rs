@@ -0,0 +1,147 @@
+//! Protocol-level statistics, behind the `perf-instrumentation` feature.
+//!
+//! Connections that support it (runtimelib's, with its own
+//! `perf-instrumentation` feature) call an [`Instrumentation`] hook for every
+//! message they send or parse, so embedders can show how much traffic a
+//! kernel produces and of what kind without wrapping every call site.
+//! [`MessageCounters`] is a ready-made hook that counts messages and bytes
+//! per `msg_type`.
+//!
+//! ```rust
+//! use jupyter_protocol::instrumentation::{Instrumentation, MessageCounters};
+//! use jupyter_protocol::Channel;
+//!
+//! let counters = MessageCounters::new();
+//! counters.message_parsed(Channel::IOPub, "stream", 120);
+//! counters.message_parsed(Channel::IOPub, "stream", 80);
+//! counters.parse_failed(Channel::Shell, &"Missing delimiter");
+//!
+//! let stats = counters.snapshot();
+//! assert_eq!(stats.parsed["stream"].count, 2);
+//! assert_eq!(stats.parsed["stream"].bytes, 200);
+//! assert_eq!(stats.parse_failures, 1);
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::Channel;
+
+/// Called by connections as messages go over the wire. Calls come from
+/// whichever task is sending or reading, so keep them cheap.
+pub trait Instrumentation: Send + Sync {
+    /// A message was read and parsed. `bytes` is its size on the wire.
+    fn message_parsed(&self, _channel: Channel, _msg_type: &str, _bytes: usize) {}
+
+    /// A message was serialized and sent. `bytes` is its size on the wire.
+    fn message_sent(&self, _channel: Channel, _msg_type: &str, _bytes: usize) {}
+
+    /// A message arrived but couldn't be parsed, or was over a size limit.
+    fn parse_failed(&self, _channel: Channel, _error: &dyn Display) {}
+}
+
+/// How many messages of one type went by, and their total size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MessageTypeStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// What [`MessageCounters`] has counted so far, keyed by `msg_type`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MessageStats {
+    pub parsed: BTreeMap<String, MessageTypeStats>,
+    pub sent: BTreeMap<String, MessageTypeStats>,
+    pub parse_failures: u64,
+}
+
+/// Counts messages and bytes per `msg_type`, across every connection it's
+/// attached to.
+#[derive(Debug, Default)]
+pub struct MessageCounters {
+    stats: Mutex<MessageStats>,
+}
+
+impl MessageCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counts so far.
+    pub fn snapshot(&self) -> MessageStats {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The counts so far, starting again from zero.
+    pub fn take(&self) -> MessageStats {
+        std::mem::take(&mut *self.stats.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn record(&self, sent: bool, msg_type: &str, bytes: usize) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        let by_type = if sent {
+            &mut stats.sent
+        } else {
+            &mut stats.parsed
+        };
+        // Avoid allocating the key for types already seen
+        let entry = match by_type.get_mut(msg_type) {
+            Some(entry) => entry,
+            None => by_type.entry(msg_type.to_string()).or_default(),
+        };
+        entry.count += 1;
+        entry.bytes += bytes as u64;
+    }
+}
+
+impl Instrumentation for MessageCounters {
+    fn message_parsed(&self, _channel: Channel, msg_type: &str, bytes: usize) {
+        self.record(false, msg_type, bytes);
+    }
+
+    fn message_sent(&self, _channel: Channel, msg_type: &str, bytes: usize) {
+        self.record(true, msg_type, bytes);
+    }
+
+    fn parse_failed(&self, _channel: Channel, _error: &dyn Display) {
+        self.stats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .parse_failures += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_by_direction_and_type() {
+        let counters = MessageCounters::new();
+        counters.message_sent(Channel::Shell, "execute_request", 300);
+        counters.message_parsed(Channel::Shell, "execute_reply", 200);
+        counters.message_parsed(Channel::IOPub, "status", 150);
+        counters.message_parsed(Channel::IOPub, "status", 150);
+
+        let stats = counters.take();
+        assert_eq!(
+            stats.sent["execute_request"],
+            MessageTypeStats {
+                count: 1,
+                bytes: 300
+            }
+        );
+        assert_eq!(stats.parsed.len(), 2);
+        assert_eq!(
+            stats.parsed["status"],
+            MessageTypeStats {
+                count: 2,
+                bytes: 300
+            }
+        );
+
+        assert_eq!(counters.snapshot(), MessageStats::default());
+    }
+}
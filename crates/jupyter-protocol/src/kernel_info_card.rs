@@ -0,0 +1,259 @@
+//! A kernel's identity, shown the same way by every frontend.
+//!
+//! A `kernel_info_reply` says which kernel is running, in which language,
+//! with a banner and links to its documentation. [`KernelInfoCard`] keeps the
+//! parts worth showing, cleaned up, and renders them as Markdown for
+//! terminals or as an HTML fragment for pages.
+//!
+//! ```rust
+//! use jupyter_protocol::{HelpLink, KernelInfoCard};
+//!
+//! let card = KernelInfoCard {
+//!     implementation: "ipykernel".to_string(),
+//!     implementation_version: "6.29.0".to_string(),
+//!     language: "python".to_string(),
+//!     language_version: "3.12.1".to_string(),
+//!     banner: "IPython 8.20.0 -- An enhanced Interactive Python.".to_string(),
+//!     help_links: vec![HelpLink {
+//!         text: "Python Reference".to_string(),
+//!         url: "https://docs.python.org/3.12".to_string(),
+//!     }],
+//! };
+//! assert_eq!(
+//!     card.to_markdown(),
+//!     "**ipykernel 6.29.0** · python 3.12.1\n\
+//!      \n    IPython 8.20.0 -- An enhanced Interactive Python.\n\
+//!      \n- [Python Reference](<https://docs.python.org/3.12>)\n"
+//! );
+//! ```
+use serde::{Deserialize, Serialize};
+
+use crate::ansi::{ansi_to_html, strip_ansi};
+use crate::{HelpLink, KernelInfoReply};
+
+/// What to show about a kernel. Empty fields are left out when rendering.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelInfoCard {
+    /// Like `ipykernel`
+    pub implementation: String,
+    pub implementation_version: String,
+    /// Like `python`
+    pub language: String,
+    pub language_version: String,
+    /// Without ANSI escapes, trailing whitespace or blank lines at either end
+    pub banner: String,
+    /// Only links to web pages, each once
+    pub help_links: Vec<HelpLink>,
+}
+
+impl From<&KernelInfoReply> for KernelInfoCard {
+    fn from(reply: &KernelInfoReply) -> Self {
+        let banner = strip_ansi(&reply.banner)
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_matches('\n')
+            .to_string();
+
+        let mut help_links: Vec<HelpLink> = Vec::new();
+        for link in &reply.help_links {
+            let url = link.url.trim();
+            if !(url.starts_with("https://") || url.starts_with("http://"))
+                || help_links.iter().any(|seen| seen.url == url)
+            {
+                continue;
+            }
+            let text = link.text.trim();
+            help_links.push(HelpLink {
+                text: if text.is_empty() { url } else { text }.to_string(),
+                url: url.to_string(),
+            });
+        }
+
+        Self {
+            implementation: reply.implementation.clone(),
+            implementation_version: reply.implementation_version.clone(),
+            language: reply.language_info.name.clone(),
+            language_version: reply.language_info.version.clone(),
+            banner,
+            help_links,
+        }
+    }
+}
+
+impl KernelInfoCard {
+    /// The implementation and its version, like `ipykernel 6.29.0`.
+    pub fn title(&self) -> String {
+        join_nonempty(&self.implementation, &self.implementation_version)
+    }
+
+    /// The language and its version, like `python 3.12.1`.
+    pub fn language_title(&self) -> String {
+        join_nonempty(&self.language, &self.language_version)
+    }
+
+    /// The card as Markdown: the title line, the banner as a code block and
+    /// the help links as a list.
+    pub fn to_markdown(&self) -> String {
+        let mut blocks = Vec::new();
+        let heading = match (self.title(), self.language_title()) {
+            (title, language) if title.is_empty() => language,
+            (title, language) if language.is_empty() => format!("**{}**", title),
+            (title, language) => format!("**{}** · {}", title, language),
+        };
+        if !heading.is_empty() {
+            blocks.push(heading);
+        }
+        if !self.banner.is_empty() {
+            // Indented rather than fenced, so backticks in the banner can't end it
+            blocks.push(
+                self.banner
+                    .lines()
+                    .map(|line| format!("    {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+        if !self.help_links.is_empty() {
+            blocks.push(
+                self.help_links
+                    .iter()
+                    .map(|link| {
+                        format!(
+                            "- [{}](<{}>)",
+                            link.text.replace('[', "\\[").replace(']', "\\]"),
+                            link.url.replace('>', "%3E").replace(' ', "%20")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+
+        let mut markdown = blocks.join("\n\n");
+        markdown.push('\n');
+        markdown
+    }
+
+    /// The card as an HTML fragment, a `<div class="kernel-info">` for pages
+    /// to style.
+    pub fn to_html(&self) -> String {
+        let mut html = String::from("<div class=\"kernel-info\">");
+        let (title, language) = (self.title(), self.language_title());
+        if !title.is_empty() || !language.is_empty() {
+            html.push_str("<div class=\"kernel-info-title\">");
+            if !title.is_empty() {
+                html.push_str(&format!("<strong>{}</strong>", ansi_to_html(&title)));
+            }
+            if !title.is_empty() && !language.is_empty() {
+                html.push_str(" · ");
+            }
+            html.push_str(&ansi_to_html(&language));
+            html.push_str("</div>");
+        }
+        if !self.banner.is_empty() {
+            html.push_str(&format!(
+                "<pre class=\"kernel-info-banner\">{}</pre>",
+                ansi_to_html(&self.banner)
+            ));
+        }
+        if !self.help_links.is_empty() {
+            html.push_str("<ul class=\"kernel-info-links\">");
+            for link in &self.help_links {
+                html.push_str(&format!(
+                    "<li><a href=\"{}\" target=\"_blank\" rel=\"noopener\">{}</a></li>",
+                    ansi_to_html(&link.url),
+                    ansi_to_html(&link.text)
+                ));
+            }
+            html.push_str("</ul>");
+        }
+        html.push_str("</div>");
+        html
+    }
+}
+
+fn join_nonempty(name: &str, version: &str) -> String {
+    format!("{} {}", name.trim(), version.trim())
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn reply() -> KernelInfoReply {
+        serde_json::from_value(json!({
+            "status": "ok",
+            "protocol_version": "5.3",
+            "implementation": "irkernel",
+            "implementation_version": "1.3.2",
+            "language_info": {
+                "name": "R",
+                "version": "4.3.2",
+                "mimetype": "text/x-r-source",
+                "file_extension": ".r"
+            },
+            "banner": "\n\x1b[1mR version 4.3.2\x1b[0m  \nType 'demo()' <for> demos.\n\n",
+            "help_links": [
+                {"text": "R documentation", "url": "https://www.r-project.org/"},
+                {"text": "Again", "url": "https://www.r-project.org/"},
+                {"text": "Local", "url": "file:///usr/share/R/doc"},
+                {"text": " ", "url": "https://cran.r-project.org/"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_card_from_reply() {
+        let card = KernelInfoCard::from(&reply());
+        assert_eq!(card.title(), "irkernel 1.3.2");
+        assert_eq!(card.language_title(), "R 4.3.2");
+        assert_eq!(card.banner, "R version 4.3.2\nType 'demo()' <for> demos.");
+        assert_eq!(
+            card.help_links,
+            vec![
+                HelpLink {
+                    text: "R documentation".to_string(),
+                    url: "https://www.r-project.org/".to_string()
+                },
+                HelpLink {
+                    text: "https://cran.r-project.org/".to_string(),
+                    url: "https://cran.r-project.org/".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_card_as_html() {
+        let html = KernelInfoCard::from(&reply()).to_html();
+        assert_eq!(
+            html,
+            "<div class=\"kernel-info\">\
+             <div class=\"kernel-info-title\"><strong>irkernel 1.3.2</strong> · R 4.3.2</div>\
+             <pre class=\"kernel-info-banner\">R version 4.3.2\nType &#39;demo()&#39; &lt;for&gt; demos.</pre>\
+             <ul class=\"kernel-info-links\">\
+             <li><a href=\"https://www.r-project.org/\" target=\"_blank\" rel=\"noopener\">R documentation</a></li>\
+             <li><a href=\"https://cran.r-project.org/\" target=\"_blank\" rel=\"noopener\">https://cran.r-project.org/</a></li>\
+             </ul></div>"
+        );
+    }
+
+    #[test]
+    fn test_empty_parts_are_left_out() {
+        let card = KernelInfoCard {
+            language: "python".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(card.to_markdown(), "python\n");
+        assert_eq!(
+            card.to_html(),
+            "<div class=\"kernel-info\"><div class=\"kernel-info-title\">python</div></div>"
+        );
+    }
+}
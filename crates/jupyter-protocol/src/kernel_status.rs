@@ -0,0 +1,578 @@
+//! A single derived kernel status for frontends.
+//!
+//! Kernels report `busy` and `idle` on iopub, servers relay lifecycle states
+//! like `restarting`, and liveness is only known from the heartbeat channel.
+//! [`KernelStatusTracker`] folds all of these into one [`KernelStatus`], and
+//! [`kernel_status_stream`] turns a stream of [`StatusEvent`]s into a debounced
+//! `Stream<Item = KernelStatus>` that only yields changes.
+//!
+//! When several requests are in flight, their `busy` and `idle` messages
+//! interleave, and a kernel can report `idle` for one request while it's still
+//! busy with another. [`BusyRequests`] counts busy requests by parent
+//! `msg_id` and only reports `idle` once none are left.
+//!
+//! ```rust
+//! use futures::{executor::block_on, stream, StreamExt};
+//! use jupyter_protocol::{kernel_status_stream, ExecutionState, KernelStatus, StatusEvent};
+//! use std::time::Duration;
+//!
+//! let events = stream::iter([
+//!     StatusEvent::Execution(ExecutionState::Idle),
+//!     StatusEvent::Execution(ExecutionState::Busy),
+//!     StatusEvent::Execution(ExecutionState::Idle),
+//! ]);
+//!
+//! let statuses: Vec<_> = block_on(kernel_status_stream(events, Duration::ZERO).collect());
+//! assert_eq!(
+//!     statuses,
+//!     vec![KernelStatus::Starting, KernelStatus::Idle, KernelStatus::Busy, KernelStatus::Idle]
+//! );
+//! ```
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use futures::{Stream, StreamExt};
+use futures_timer::Delay;
+use serde::{Deserialize, Serialize};
+
+use crate::{ExecutionState, JupyterMessage, JupyterMessageContent};
+
+/// Consecutive missed heartbeats before a kernel is considered dead.
+pub const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// The status of a kernel as a frontend should present it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KernelStatus {
+    Starting,
+    Idle,
+    Busy,
+    Restarting,
+    Dead,
+}
+
+/// An input to the [`KernelStatusTracker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusEvent {
+    /// The `execution_state` of a `status` message from iopub.
+    Execution(ExecutionState),
+    /// The kernel answered a heartbeat.
+    HeartbeatOk,
+    /// A heartbeat went unanswered.
+    HeartbeatMissed,
+}
+
+impl StatusEvent {
+    /// The event for an iopub message, if it is a `status` message.
+    pub fn from_message(message: &JupyterMessage) -> Option<Self> {
+        match &message.content {
+            JupyterMessageContent::Status(status) => {
+                Some(StatusEvent::Execution(status.execution_state))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<ExecutionState> for StatusEvent {
+    fn from(execution_state: ExecutionState) -> Self {
+        StatusEvent::Execution(execution_state)
+    }
+}
+
+/// Derives a [`KernelStatus`] from status messages and heartbeat results.
+///
+/// Transitions:
+///
+/// - Every kernel starts out `Starting`.
+/// - `busy` and `idle` move between `Busy` and `Idle`.
+/// - `restarting` and `autorestarting` move to `Restarting`, and `starting` back to `Starting`.
+/// - `terminating`, `dead`, or too many missed heartbeats in a row move to `Dead`.
+///   A dead kernel stays dead until it reports `starting` or `restarting`, so late
+///   `busy`/`idle` messages can't revive it.
+#[derive(Debug, Clone)]
+pub struct KernelStatusTracker {
+    status: KernelStatus,
+    missed_heartbeats: u32,
+    max_missed_heartbeats: u32,
+}
+
+impl Default for KernelStatusTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KernelStatusTracker {
+    pub fn new() -> Self {
+        Self {
+            status: KernelStatus::Starting,
+            missed_heartbeats: 0,
+            max_missed_heartbeats: DEFAULT_MAX_MISSED_HEARTBEATS,
+        }
+    }
+
+    /// Set how many consecutive missed heartbeats mark the kernel as dead.
+    pub fn with_max_missed_heartbeats(mut self, max_missed_heartbeats: u32) -> Self {
+        self.max_missed_heartbeats = max_missed_heartbeats.max(1);
+        self
+    }
+
+    pub fn status(&self) -> KernelStatus {
+        self.status
+    }
+
+    /// Apply an event, returning the new status if it changed.
+    pub fn apply(&mut self, event: StatusEvent) -> Option<KernelStatus> {
+        let next = match event {
+            StatusEvent::HeartbeatOk => {
+                self.missed_heartbeats = 0;
+                return None;
+            }
+            StatusEvent::HeartbeatMissed => {
+                self.missed_heartbeats += 1;
+                if self.missed_heartbeats < self.max_missed_heartbeats {
+                    return None;
+                }
+                KernelStatus::Dead
+            }
+            StatusEvent::Execution(execution_state) => {
+                self.missed_heartbeats = 0;
+                match (self.status, execution_state) {
+                    (_, ExecutionState::Starting) => KernelStatus::Starting,
+                    (_, ExecutionState::Restarting | ExecutionState::Autorestarting) => {
+                        KernelStatus::Restarting
+                    }
+                    (_, ExecutionState::Terminating | ExecutionState::Dead) => KernelStatus::Dead,
+                    (KernelStatus::Dead, ExecutionState::Busy | ExecutionState::Idle) => {
+                        KernelStatus::Dead
+                    }
+                    (_, ExecutionState::Busy) => KernelStatus::Busy,
+                    (_, ExecutionState::Idle) => KernelStatus::Idle,
+                }
+            }
+        };
+
+        if next == self.status {
+            None
+        } else {
+            self.status = next;
+            Some(next)
+        }
+    }
+}
+
+/// Tracks which requests a kernel is busy with, by the `parent_header` of
+/// its `status` messages, to derive one busy/idle state for the kernel.
+///
+/// Each `busy` adds a reference to its parent request and each `idle` drops
+/// one, so a request that reports busy twice (e.g. a comm message handled
+/// while its cell runs) needs two idles. Status messages without a parent
+/// count as one anonymous request. Lifecycle states like `restarting` and
+/// `dead` forget every request, since their idles will never come.
+///
+/// ```rust
+/// use jupyter_protocol::{BusyRequests, ExecuteRequest, ExecutionState, JupyterMessage, Status};
+///
+/// let first: JupyterMessage = ExecuteRequest::new("a".to_string()).into();
+/// let second: JupyterMessage = ExecuteRequest::new("b".to_string()).into();
+/// let mut busy = BusyRequests::new();
+///
+/// let busy_event = busy.apply(&Status::busy().as_child_of(&first));
+/// assert_eq!(busy_event, Some(ExecutionState::Busy.into()));
+/// assert_eq!(busy.apply(&Status::busy().as_child_of(&second)), None);
+///
+/// // Still running `second`
+/// assert_eq!(busy.apply(&Status::idle().as_child_of(&first)), None);
+/// let idle_event = busy.apply(&Status::idle().as_child_of(&second));
+/// assert_eq!(idle_event, Some(ExecutionState::Idle.into()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BusyRequests {
+    /// Outstanding busy reports by parent `msg_id`, `""` for no parent
+    busy: HashMap<String, u32>,
+}
+
+impl BusyRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !self.busy.is_empty()
+    }
+
+    /// The `msg_id`s of the requests the kernel is busy with.
+    pub fn in_flight(&self) -> impl Iterator<Item = &str> {
+        self.busy
+            .keys()
+            .filter(|msg_id| !msg_id.is_empty())
+            .map(String::as_str)
+    }
+
+    /// Account for an iopub message, returning the event to pass on to a
+    /// [`KernelStatusTracker`] or [`kernel_status_stream`], if any.
+    ///
+    /// `busy` and `idle` only come through when the kernel as a whole goes
+    /// busy or idle. Other execution states always come through.
+    pub fn apply(&mut self, message: &JupyterMessage) -> Option<StatusEvent> {
+        let JupyterMessageContent::Status(status) = &message.content else {
+            return None;
+        };
+        let parent = message
+            .parent_header
+            .as_ref()
+            .map_or("", |parent| parent.msg_id.as_str());
+
+        match status.execution_state {
+            ExecutionState::Busy => {
+                let was_busy = self.is_busy();
+                *self.busy.entry(parent.to_string()).or_default() += 1;
+                (!was_busy).then_some(StatusEvent::Execution(ExecutionState::Busy))
+            }
+            ExecutionState::Idle => {
+                if !self.busy.contains_key(parent) && self.is_busy() {
+                    // An idle for a request we never saw go busy, e.g. one that
+                    // started before we connected. Only trust it if nothing
+                    // else is running.
+                    return None;
+                }
+                if let Some(count) = self.busy.get_mut(parent) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.busy.remove(parent);
+                    }
+                }
+                (!self.is_busy()).then_some(StatusEvent::Execution(ExecutionState::Idle))
+            }
+            execution_state => {
+                self.busy.clear();
+                Some(StatusEvent::Execution(execution_state))
+            }
+        }
+    }
+}
+
+struct StreamState<S> {
+    events: Pin<Box<S>>,
+    tracker: KernelStatusTracker,
+    debounce: Duration,
+    emitted: Option<KernelStatus>,
+    pending_idle: Option<Delay>,
+}
+
+impl<S> StreamState<S> {
+    fn emit(&mut self, status: KernelStatus) -> Option<KernelStatus> {
+        if self.emitted == Some(status) {
+            None
+        } else {
+            self.emitted = Some(status);
+            Some(status)
+        }
+    }
+}
+
+/// Turn status events into a stream of [`KernelStatus`] changes.
+///
+/// The stream first yields the initial `Starting` status, then each change.
+/// Transitions to `Idle` are held back for `debounce`; if the kernel goes busy
+/// again within that window the idle is never emitted, so quick
+/// busy → idle → busy bursts show up as a single `Busy`. All other transitions
+/// are emitted right away. Pass [`Duration::ZERO`] to disable debouncing.
+pub fn kernel_status_stream<S>(events: S, debounce: Duration) -> impl Stream<Item = KernelStatus>
+where
+    S: Stream<Item = StatusEvent>,
+{
+    let state = StreamState {
+        events: Box::pin(events),
+        tracker: KernelStatusTracker::new(),
+        debounce,
+        emitted: None,
+        pending_idle: None,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        if state.emitted.is_none() {
+            let status = state.tracker.status();
+            state.emitted = Some(status);
+            return Some((status, state));
+        }
+
+        loop {
+            let event = match state.pending_idle.as_mut() {
+                Some(delay) => match select(state.events.next(), delay).await {
+                    Either::Left((event, _)) => Some(event),
+                    Either::Right(_) => None,
+                },
+                None => Some(state.events.next().await),
+            };
+
+            let event = match event {
+                Some(event) => event,
+                // The debounce window elapsed without the kernel going busy again
+                None => {
+                    state.pending_idle = None;
+                    match state.emit(KernelStatus::Idle) {
+                        Some(status) => return Some((status, state)),
+                        None => continue,
+                    }
+                }
+            };
+
+            let Some(event) = event else {
+                // Flush a pending idle before ending
+                state.pending_idle.take()?;
+                let status = state.emit(KernelStatus::Idle);
+                return status.map(|status| (status, state));
+            };
+
+            let Some(status) = state.tracker.apply(event) else {
+                continue;
+            };
+
+            if status == KernelStatus::Idle && !state.debounce.is_zero() {
+                state.pending_idle = Some(Delay::new(state.debounce));
+                continue;
+            }
+
+            state.pending_idle = None;
+            if let Some(status) = state.emit(status) {
+                return Some((status, state));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::stream;
+
+    fn collect(events: Vec<StatusEvent>, debounce: Duration) -> Vec<KernelStatus> {
+        block_on(kernel_status_stream(stream::iter(events), debounce).collect())
+    }
+
+    #[test]
+    fn test_missed_heartbeats_mark_kernel_dead() {
+        let mut tracker = KernelStatusTracker::new().with_max_missed_heartbeats(2);
+        assert_eq!(
+            tracker.apply(ExecutionState::Idle.into()),
+            Some(KernelStatus::Idle)
+        );
+        assert_eq!(tracker.apply(StatusEvent::HeartbeatMissed), None);
+        assert_eq!(tracker.apply(StatusEvent::HeartbeatOk), None);
+        assert_eq!(tracker.apply(StatusEvent::HeartbeatMissed), None);
+        assert_eq!(
+            tracker.apply(StatusEvent::HeartbeatMissed),
+            Some(KernelStatus::Dead)
+        );
+    }
+
+    #[test]
+    fn test_dead_kernel_stays_dead_until_restart() {
+        let mut tracker = KernelStatusTracker::new();
+        tracker.apply(ExecutionState::Dead.into());
+        assert_eq!(tracker.apply(ExecutionState::Idle.into()), None);
+        assert_eq!(tracker.status(), KernelStatus::Dead);
+
+        assert_eq!(
+            tracker.apply(ExecutionState::Autorestarting.into()),
+            Some(KernelStatus::Restarting)
+        );
+        assert_eq!(
+            tracker.apply(ExecutionState::Starting.into()),
+            Some(KernelStatus::Starting)
+        );
+        assert_eq!(
+            tracker.apply(ExecutionState::Idle.into()),
+            Some(KernelStatus::Idle)
+        );
+    }
+
+    #[test]
+    fn test_stream_deduplicates_statuses() {
+        let statuses = collect(
+            vec![
+                ExecutionState::Busy.into(),
+                ExecutionState::Busy.into(),
+                StatusEvent::HeartbeatOk,
+                ExecutionState::Idle.into(),
+            ],
+            Duration::ZERO,
+        );
+        assert_eq!(
+            statuses,
+            vec![
+                KernelStatus::Starting,
+                KernelStatus::Busy,
+                KernelStatus::Idle
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stream_debounces_idle_flicker() {
+        let statuses = collect(
+            vec![
+                ExecutionState::Busy.into(),
+                ExecutionState::Idle.into(),
+                ExecutionState::Busy.into(),
+                ExecutionState::Idle.into(),
+            ],
+            Duration::from_secs(60),
+        );
+        assert_eq!(
+            statuses,
+            vec![
+                KernelStatus::Starting,
+                KernelStatus::Busy,
+                KernelStatus::Idle
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stream_emits_idle_after_debounce_window() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut statuses = Box::pin(kernel_status_stream(rx, Duration::from_millis(10)));
+
+        block_on(async {
+            assert_eq!(statuses.next().await, Some(KernelStatus::Starting));
+            tx.unbounded_send(ExecutionState::Busy.into()).unwrap();
+            assert_eq!(statuses.next().await, Some(KernelStatus::Busy));
+            tx.unbounded_send(ExecutionState::Idle.into()).unwrap();
+            // The sender is still open, so only the timer can release the idle
+            assert_eq!(statuses.next().await, Some(KernelStatus::Idle));
+            tx.close_channel();
+            assert_eq!(statuses.next().await, None);
+        });
+    }
+
+    fn status(execution_state: ExecutionState, parent: Option<&JupyterMessage>) -> JupyterMessage {
+        JupyterMessage::new(crate::Status { execution_state }, parent)
+    }
+
+    #[test]
+    fn test_busy_requests_count_references() {
+        let first: JupyterMessage = crate::ExecuteRequest::new("a".to_string()).into();
+        let second: JupyterMessage = crate::CommInfoRequest::default().into();
+        let mut busy = BusyRequests::new();
+
+        let events: Vec<Option<StatusEvent>> = [
+            status(ExecutionState::Busy, Some(&first)),
+            // A nested busy for the same request
+            status(ExecutionState::Busy, Some(&first)),
+            status(ExecutionState::Busy, Some(&second)),
+            status(ExecutionState::Idle, Some(&second)),
+            status(ExecutionState::Idle, Some(&first)),
+            status(ExecutionState::Idle, Some(&first)),
+        ]
+        .iter()
+        .map(|message| busy.apply(message))
+        .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                Some(ExecutionState::Busy.into()),
+                None,
+                None,
+                None,
+                None,
+                Some(ExecutionState::Idle.into()),
+            ]
+        );
+        assert!(!busy.is_busy());
+    }
+
+    #[test]
+    fn test_busy_requests_in_flight() {
+        let request: JupyterMessage = crate::ExecuteRequest::new("a".to_string()).into();
+        let mut busy = BusyRequests::new();
+        busy.apply(&status(ExecutionState::Busy, None));
+        busy.apply(&status(ExecutionState::Busy, Some(&request)));
+        assert_eq!(
+            busy.in_flight().collect::<Vec<_>>(),
+            vec![request.header.msg_id.as_str()]
+        );
+
+        // Parentless messages are one request of their own
+        assert_eq!(busy.apply(&status(ExecutionState::Idle, None)), None);
+        assert!(busy.is_busy());
+    }
+
+    #[test]
+    fn test_busy_requests_unknown_idle() {
+        let running: JupyterMessage = crate::ExecuteRequest::new("a".to_string()).into();
+        let unknown: JupyterMessage = crate::ExecuteRequest::new("b".to_string()).into();
+        let mut busy = BusyRequests::new();
+
+        assert_eq!(
+            busy.apply(&status(ExecutionState::Idle, Some(&unknown))),
+            Some(ExecutionState::Idle.into())
+        );
+        busy.apply(&status(ExecutionState::Busy, Some(&running)));
+        assert_eq!(
+            busy.apply(&status(ExecutionState::Idle, Some(&unknown))),
+            None
+        );
+        assert!(busy.is_busy());
+    }
+
+    #[test]
+    fn test_busy_requests_reset_on_restart() {
+        let request: JupyterMessage = crate::ExecuteRequest::new("a".to_string()).into();
+        let mut busy = BusyRequests::new();
+        busy.apply(&status(ExecutionState::Busy, Some(&request)));
+
+        assert_eq!(
+            busy.apply(&status(ExecutionState::Restarting, None)),
+            Some(ExecutionState::Restarting.into())
+        );
+        assert!(!busy.is_busy());
+        assert_eq!(
+            busy.apply(&status(ExecutionState::Busy, None)),
+            Some(ExecutionState::Busy.into())
+        );
+    }
+
+    #[test]
+    fn test_busy_requests_feed_status_stream() {
+        let first: JupyterMessage = crate::ExecuteRequest::new("a".to_string()).into();
+        let second: JupyterMessage = crate::ExecuteRequest::new("b".to_string()).into();
+        let mut busy = BusyRequests::new();
+        let events: Vec<StatusEvent> = [
+            status(ExecutionState::Busy, Some(&first)),
+            status(ExecutionState::Busy, Some(&second)),
+            status(ExecutionState::Idle, Some(&first)),
+            status(ExecutionState::Idle, Some(&second)),
+        ]
+        .iter()
+        .filter_map(|message| busy.apply(message))
+        .collect();
+
+        assert_eq!(
+            collect(events, Duration::ZERO),
+            vec![
+                KernelStatus::Starting,
+                KernelStatus::Busy,
+                KernelStatus::Idle
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_event_from_message() {
+        let message: JupyterMessage = crate::Status::starting().into();
+        assert_eq!(
+            StatusEvent::from_message(&message),
+            Some(StatusEvent::Execution(ExecutionState::Starting))
+        );
+
+        let message: JupyterMessage = crate::ExecuteRequest::new("1".to_string()).into();
+        assert_eq!(StatusEvent::from_message(&message), None);
+    }
+}
@@ -42,10 +42,54 @@
 pub mod messaging;
 pub use messaging::*;
 
+pub mod error;
+pub use error::JupyterError;
+
+pub mod limits;
+pub use limits::{truncate_stream_text, MessageLimits};
+
 pub mod connection_info;
-pub use connection_info::{ConnectionInfo, Transport};
+pub use connection_info::{ConnectionInfo, PublicConnectionInfo, Transport};
+
+pub mod ansi;
+
+pub mod buffers;
+
+pub mod comm_events;
+
+pub mod comm_registry;
+pub use comm_registry::CommRegistry;
+
+pub mod canonical;
+
+pub mod cursor;
+
+pub mod display;
+pub use display::DisplayHandle;
+
+pub mod capabilities;
+pub use capabilities::KernelCapabilities;
+
+pub mod kernel_info_card;
+pub use kernel_info_card::KernelInfoCard;
+
+pub mod input;
+
+#[cfg(feature = "perf-instrumentation")]
+pub mod instrumentation;
+
+pub mod routing;
+
+pub mod kernel_status;
+pub use kernel_status::{
+    kernel_status_stream, BusyRequests, KernelStatus, KernelStatusTracker, StatusEvent,
+};
+
+pub mod session;
+pub use session::Session;
 
-mod time;
+pub mod time;
+pub use time::{Clock, ExecutionTimings, FixedClock, SystemClock};
 
 mod execution_count;
 pub use execution_count::*;
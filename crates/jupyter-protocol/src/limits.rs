@@ -0,0 +1,139 @@
+//! Size limits for incoming messages.
+//!
+//! A buggy or hostile kernel can send arbitrarily large messages. Deserializing
+//! a huge `content` allocates even more, so connections check sizes against
+//! [`MessageLimits`] first and reject anything too big with
+//! [`JupyterError::MessageTooLarge`]. Oversized `stream` output, the usual
+//! culprit, can instead be truncated with a note saying how much was dropped.
+//!
+//! ```rust
+//! use jupyter_protocol::{truncate_stream_text, MessageLimits, StreamContent};
+//!
+//! let limits = MessageLimits::default().with_max_content_bytes(Some(1024));
+//! assert!(limits.check_content(2048).is_err());
+//!
+//! let mut stream = StreamContent::stdout("abcdef");
+//! assert!(truncate_stream_text(&mut stream, 3));
+//! assert_eq!(stream.text, "abc\n[output truncated: 3 bytes omitted]\n");
+//! ```
+use bytes::Bytes;
+
+use crate::{JupyterError, StreamContent};
+
+/// Default limit on the serialized `content` of a message.
+pub const DEFAULT_MAX_CONTENT_BYTES: usize = 64 * 1024 * 1024;
+/// Default limit on the combined size of a message's binary buffers.
+pub const DEFAULT_MAX_BUFFER_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Largest serialized `content` accepted, or `None` for no limit.
+    pub max_content_bytes: Option<usize>,
+    /// Largest combined size of the buffers accepted, or `None` for no limit.
+    pub max_buffer_bytes: Option<usize>,
+    /// Truncate the text of oversized `stream` messages instead of rejecting them.
+    pub truncate_streams: bool,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_content_bytes: Some(DEFAULT_MAX_CONTENT_BYTES),
+            max_buffer_bytes: Some(DEFAULT_MAX_BUFFER_BYTES),
+            truncate_streams: false,
+        }
+    }
+}
+
+impl MessageLimits {
+    /// No limits at all.
+    pub fn unlimited() -> Self {
+        Self {
+            max_content_bytes: None,
+            max_buffer_bytes: None,
+            truncate_streams: false,
+        }
+    }
+
+    pub fn with_max_content_bytes(mut self, max_content_bytes: Option<usize>) -> Self {
+        self.max_content_bytes = max_content_bytes;
+        self
+    }
+
+    pub fn with_max_buffer_bytes(mut self, max_buffer_bytes: Option<usize>) -> Self {
+        self.max_buffer_bytes = max_buffer_bytes;
+        self
+    }
+
+    pub fn with_stream_truncation(mut self, truncate_streams: bool) -> Self {
+        self.truncate_streams = truncate_streams;
+        self
+    }
+
+    pub fn check_content(&self, size: usize) -> Result<(), JupyterError> {
+        check("content", size, self.max_content_bytes)
+    }
+
+    pub fn check_buffers(&self, buffers: &[Bytes]) -> Result<(), JupyterError> {
+        let size = buffers.iter().map(Bytes::len).sum();
+        check("buffers", size, self.max_buffer_bytes)
+    }
+}
+
+fn check(part: &'static str, size: usize, limit: Option<usize>) -> Result<(), JupyterError> {
+    match limit {
+        Some(limit) if size > limit => Err(JupyterError::MessageTooLarge { part, size, limit }),
+        _ => Ok(()),
+    }
+}
+
+/// Cut `stream.text` down to at most `max_bytes` (on a character boundary) and
+/// note how much was omitted. Returns whether anything was cut.
+pub fn truncate_stream_text(stream: &mut StreamContent, max_bytes: usize) -> bool {
+    if stream.text.len() <= max_bytes {
+        return false;
+    }
+
+    let mut end = max_bytes;
+    while !stream.text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let omitted = stream.text.len() - end;
+    stream.text.truncate(end);
+    stream.text.push_str(&format!(
+        "\n[output truncated: {} bytes omitted]\n",
+        omitted
+    ));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_buffers_sums_sizes() {
+        let limits = MessageLimits::default().with_max_buffer_bytes(Some(4));
+        let buffers = vec![Bytes::from_static(b"abc"), Bytes::from_static(b"de")];
+        assert_eq!(
+            limits.check_buffers(&buffers),
+            Err(JupyterError::MessageTooLarge {
+                part: "buffers",
+                size: 5,
+                limit: 4
+            })
+        );
+        assert!(MessageLimits::unlimited().check_buffers(&buffers).is_ok());
+    }
+
+    #[test]
+    fn test_truncate_respects_char_boundaries() {
+        let mut stream = StreamContent::stdout("héllo");
+        assert!(truncate_stream_text(&mut stream, 2));
+        assert_eq!(stream.text, "h\n[output truncated: 5 bytes omitted]\n");
+
+        let mut stream = StreamContent::stdout("short");
+        assert!(!truncate_stream_text(&mut stream, 10));
+        assert_eq!(stream.text, "short");
+    }
+}
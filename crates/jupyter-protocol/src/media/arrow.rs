@@ -0,0 +1,426 @@
+//! Conversion between [`TabularDataResource`] tables and Arrow record batches,
+//! for frontends that hand tables to Arrow based data tools.
+//!
+//! Each schema field becomes a nullable column: integers as `Int64`, numbers
+//! as `Float64`, booleans, dates as `Date32` and datetimes as millisecond
+//! timestamps in the field's time zone. Other field types are kept as strings,
+//! with values that aren't strings written as JSON. The primary key, which
+//! pandas uses for its index, is kept in the Arrow schema's `primaryKey`
+//! metadata as a JSON list of field names.
+//!
+//! ```rust
+//! use jupyter_protocol::media::TabularDataResource;
+//!
+//! let table: TabularDataResource = serde_json::from_value(serde_json::json!({
+//!     "schema": {
+//!         "fields": [{"name": "city", "type": "string"}, {"name": "people", "type": "integer"}]
+//!     },
+//!     "data": [{"city": "Oslo", "people": 709037}, {"city": "Bergen", "people": null}]
+//! }))
+//! .unwrap();
+//!
+//! let batch = table.to_record_batch().unwrap();
+//! assert_eq!(batch.num_rows(), 2);
+//! assert_eq!(batch.column(1).null_count(), 1);
+//! assert_eq!(TabularDataResource::from_record_batch(&batch).unwrap().data, table.data);
+//! ```
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BooleanBuilder, Date32Builder, Float64Builder, Int64Builder, StringBuilder,
+    TimestampMillisecondBuilder,
+};
+use arrow_array::cast::AsArray;
+use arrow_array::types::*;
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde_json::{Map, Value};
+
+use super::datatable::{FieldType, PrimaryKey, TableSchema, TableSchemaField, TabularDataResource};
+
+/// The Arrow schema metadata key the primary key is kept under.
+pub const PRIMARY_KEY_METADATA: &str = "primaryKey";
+
+impl TableSchema {
+    /// The Arrow schema [`TabularDataResource::to_record_batch`] produces.
+    pub fn to_arrow_schema(&self) -> Schema {
+        let fields: Vec<Field> = self
+            .fields
+            .iter()
+            .map(|field| Field::new(&field.name, arrow_type(field), true))
+            .collect();
+        let mut metadata = HashMap::new();
+        if self.primary_key.is_some() {
+            metadata.insert(
+                PRIMARY_KEY_METADATA.to_string(),
+                Value::from(self.primary_key_fields()).to_string(),
+            );
+        }
+        Schema::new_with_metadata(fields, metadata)
+    }
+
+    /// The table schema for an Arrow schema, failing on column types that have
+    /// no table schema equivalent.
+    pub fn from_arrow_schema(schema: &Schema) -> Result<Self, ArrowError> {
+        let fields = schema
+            .fields()
+            .iter()
+            .map(|field| table_field(field))
+            .collect::<Result<_, _>>()?;
+        let primary_key = match schema.metadata().get(PRIMARY_KEY_METADATA) {
+            Some(names) => Some(PrimaryKey::Multiple(
+                serde_json::from_str(names)
+                    .map_err(|error| ArrowError::JsonError(error.to_string()))?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            fields,
+            primary_key,
+            ..Default::default()
+        })
+    }
+}
+
+impl TabularDataResource {
+    /// The table's inline `data` as a record batch, with a column for every
+    /// schema field. Rows without a field's value are null in its column.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let rows = self.data.as_deref().unwrap_or_default();
+        let columns = self
+            .schema
+            .fields
+            .iter()
+            .map(|field| {
+                let values = rows.iter().map(|row| match row.get(&field.name) {
+                    None | Some(Value::Null) => None,
+                    Some(value) => Some(value),
+                });
+                build_column(field, values)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        RecordBatch::try_new(Arc::new(self.schema.to_arrow_schema()), columns)
+    }
+
+    /// A table with `batch`'s rows as inline `data`.
+    pub fn from_record_batch(batch: &RecordBatch) -> Result<Self, ArrowError> {
+        let schema = TableSchema::from_arrow_schema(&batch.schema())?;
+        let mut rows = vec![Map::new(); batch.num_rows()];
+        for (field, column) in schema.fields.iter().zip(batch.columns()) {
+            for (row, value) in rows.iter_mut().zip(column_values(column)?) {
+                row.insert(field.name.clone(), value);
+            }
+        }
+        Ok(Self {
+            data: Some(rows.into_iter().map(Value::Object).collect()),
+            schema,
+            ..Default::default()
+        })
+    }
+}
+
+fn arrow_type(field: &TableSchemaField) -> DataType {
+    match field.field_type {
+        FieldType::Integer => DataType::Int64,
+        FieldType::Number => DataType::Float64,
+        FieldType::Boolean => DataType::Boolean,
+        FieldType::Date => DataType::Date32,
+        FieldType::Datetime => {
+            DataType::Timestamp(TimeUnit::Millisecond, field.tz.as_deref().map(Arc::from))
+        }
+        _ => DataType::Utf8,
+    }
+}
+
+fn table_field(field: &Field) -> Result<TableSchemaField, ArrowError> {
+    let (field_type, tz) = match field.data_type() {
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => (FieldType::Integer, None),
+        DataType::Float32 | DataType::Float64 => (FieldType::Number, None),
+        DataType::Boolean => (FieldType::Boolean, None),
+        DataType::Utf8 | DataType::LargeUtf8 => (FieldType::String, None),
+        DataType::Date32 => (FieldType::Date, None),
+        DataType::Timestamp(_, tz) => (FieldType::Datetime, tz.as_deref().map(String::from)),
+        other => {
+            return Err(ArrowError::NotYetImplemented(format!(
+                "{} columns in table schemas",
+                other
+            )))
+        }
+    };
+    Ok(TableSchemaField {
+        name: field.name().clone(),
+        field_type,
+        tz,
+        ..Default::default()
+    })
+}
+
+fn invalid(field: &TableSchemaField, value: &Value) -> ArrowError {
+    ArrowError::ParseError(format!(
+        "{} isn't a valid {:?} for column {}",
+        value, field.field_type, field.name
+    ))
+}
+
+fn build_column<'a>(
+    field: &TableSchemaField,
+    values: impl Iterator<Item = Option<&'a Value>>,
+) -> Result<ArrayRef, ArrowError> {
+    macro_rules! build {
+        ($builder:expr, $convert:expr) => {{
+            let mut builder = $builder;
+            for value in values {
+                match value {
+                    Some(value) => {
+                        builder.append_value($convert(value).ok_or_else(|| invalid(field, value))?)
+                    }
+                    None => builder.append_null(),
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }};
+    }
+
+    match arrow_type(field) {
+        DataType::Int64 => build!(Int64Builder::new(), Value::as_i64),
+        DataType::Float64 => build!(Float64Builder::new(), Value::as_f64),
+        DataType::Boolean => build!(BooleanBuilder::new(), Value::as_bool),
+        DataType::Date32 => build!(Date32Builder::new(), parse_date),
+        DataType::Timestamp(_, tz) => build!(
+            TimestampMillisecondBuilder::new().with_timezone_opt(tz),
+            parse_datetime
+        ),
+        _ => build!(StringBuilder::new(), |value: &Value| Some(match value {
+            Value::String(string) => string.clone(),
+            other => other.to_string(),
+        })),
+    }
+}
+
+/// Days since the epoch of a `YYYY-MM-DD` date, or of a datetime's date.
+fn parse_date(value: &Value) -> Option<i32> {
+    let date = value.as_str()?.get(..10)?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some((date - DateTime::UNIX_EPOCH.date_naive()).num_days() as i32)
+}
+
+/// Milliseconds since the epoch of an ISO 8601 datetime like pandas writes,
+/// or of epoch milliseconds from `date_format="epoch"`.
+fn parse_datetime(value: &Value) -> Option<i64> {
+    let Some(string) = value.as_str() else {
+        return value.as_i64();
+    };
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(string) {
+        return Some(datetime.timestamp_millis());
+    }
+    let datetime = NaiveDateTime::parse_from_str(string, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    Some(datetime.and_utc().timestamp_millis())
+}
+
+/// A column's values as JSON, nulls included.
+fn column_values(column: &ArrayRef) -> Result<Vec<Value>, ArrowError> {
+    macro_rules! values {
+        ($array:expr, $convert:expr) => {{
+            let array = $array;
+            (0..array.len())
+                .map(|i| match array.is_null(i) {
+                    true => Value::Null,
+                    false => $convert(array.value(i)),
+                })
+                .collect()
+        }};
+    }
+    macro_rules! timestamps {
+        ($type:ty, $to_datetime:expr, $tz:expr) => {
+            values!(column.as_primitive::<$type>(), |value| {
+                $to_datetime(value).map_or(Value::Null, |datetime: DateTime<Utc>| {
+                    format_datetime(datetime, $tz.is_some())
+                })
+            })
+        };
+    }
+
+    Ok(match column.data_type() {
+        DataType::Int8 => values!(column.as_primitive::<Int8Type>(), Value::from),
+        DataType::Int16 => values!(column.as_primitive::<Int16Type>(), Value::from),
+        DataType::Int32 => values!(column.as_primitive::<Int32Type>(), Value::from),
+        DataType::Int64 => values!(column.as_primitive::<Int64Type>(), Value::from),
+        DataType::UInt8 => values!(column.as_primitive::<UInt8Type>(), Value::from),
+        DataType::UInt16 => values!(column.as_primitive::<UInt16Type>(), Value::from),
+        DataType::UInt32 => values!(column.as_primitive::<UInt32Type>(), Value::from),
+        DataType::UInt64 => values!(column.as_primitive::<UInt64Type>(), Value::from),
+        DataType::Float32 => values!(column.as_primitive::<Float32Type>(), |value: f32| {
+            Value::from(value as f64)
+        }),
+        DataType::Float64 => values!(column.as_primitive::<Float64Type>(), Value::from),
+        DataType::Boolean => values!(column.as_boolean(), Value::from),
+        DataType::Utf8 => values!(column.as_string::<i32>(), Value::from),
+        DataType::LargeUtf8 => values!(column.as_string::<i64>(), Value::from),
+        DataType::Date32 => values!(column.as_primitive::<Date32Type>(), |days: i32| {
+            let date = DateTime::UNIX_EPOCH.date_naive() + chrono::Duration::days(days.into());
+            Value::from(date.format("%Y-%m-%d").to_string())
+        }),
+        DataType::Timestamp(TimeUnit::Second, tz) => {
+            timestamps!(
+                TimestampSecondType,
+                |secs| DateTime::from_timestamp(secs, 0),
+                tz
+            )
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, tz) => {
+            timestamps!(
+                TimestampMillisecondType,
+                DateTime::from_timestamp_millis,
+                tz
+            )
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, tz) => {
+            timestamps!(
+                TimestampMicrosecondType,
+                DateTime::from_timestamp_micros,
+                tz
+            )
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, tz) => timestamps!(
+            TimestampNanosecondType,
+            |nanos| Some(DateTime::from_timestamp_nanos(nanos)),
+            tz
+        ),
+        other => {
+            return Err(ArrowError::NotYetImplemented(format!(
+                "{} columns in table schemas",
+                other
+            )))
+        }
+    })
+}
+
+/// A datetime the way pandas' `to_json(orient="table")` writes it, in UTC
+/// with a `Z` when the column has a time zone.
+fn format_datetime(datetime: DateTime<Utc>, zoned: bool) -> Value {
+    let format = if zoned {
+        "%Y-%m-%dT%H:%M:%S%.3fZ"
+    } else {
+        "%Y-%m-%dT%H:%M:%S%.3f"
+    };
+    Value::from(datetime.format(format).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pandas_table() -> TabularDataResource {
+        // df.set_index("id").to_json(orient="table"), with a UTC datetime column
+        serde_json::from_value(json!({
+            "schema": {
+                "fields": [
+                    {"name": "id", "type": "integer"},
+                    {"name": "score", "type": "number"},
+                    {"name": "done", "type": "boolean"},
+                    {"name": "when", "type": "datetime", "tz": "UTC"},
+                    {"name": "day", "type": "date"},
+                    {"name": "tags", "type": "any"}
+                ],
+                "primaryKey": ["id"],
+                "pandas_version": "1.4.0"
+            },
+            "data": [
+                {"id": 1, "score": 0.5, "done": true, "when": "2024-01-01T12:00:00.000Z", "day": "2024-01-01", "tags": "a"},
+                {"id": 2, "score": null, "done": false, "when": null, "day": "2024-02-29", "tags": ["b", "c"]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_to_record_batch() {
+        let batch = pandas_table().to_record_batch().unwrap();
+        let schema = batch.schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(
+            schema.field(3).data_type(),
+            &DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+        );
+        assert_eq!(schema.metadata()[PRIMARY_KEY_METADATA], r#"["id"]"#);
+
+        assert_eq!(batch.column(1).as_primitive::<Float64Type>().value(0), 0.5);
+        assert!(batch.column(1).is_null(1));
+        assert_eq!(
+            batch
+                .column(3)
+                .as_primitive::<TimestampMillisecondType>()
+                .value(0),
+            1_704_110_400_000
+        );
+        assert_eq!(batch.column(4).as_primitive::<Date32Type>().value(1), 19782);
+        assert_eq!(batch.column(5).as_string::<i32>().value(1), r#"["b","c"]"#);
+    }
+
+    #[test]
+    fn test_record_batch_round_trip() {
+        let table = pandas_table();
+        let back =
+            TabularDataResource::from_record_batch(&table.to_record_batch().unwrap()).unwrap();
+        assert_eq!(back.schema.primary_key_fields(), vec!["id"]);
+        assert_eq!(
+            back.schema.field("when").unwrap().tz.as_deref(),
+            Some("UTC")
+        );
+        assert_eq!(
+            back.schema.field("tags").unwrap().field_type,
+            FieldType::String
+        );
+
+        let rows = back.data.unwrap();
+        assert_eq!(rows[0], {
+            let mut row = table.data.as_ref().unwrap()[0].clone();
+            row["tags"] = "a".into();
+            row
+        });
+        assert_eq!(rows[1]["when"], Value::Null);
+        assert_eq!(rows[1]["tags"], r#"["b","c"]"#);
+    }
+
+    #[test]
+    fn test_naive_datetimes_and_missing_values() {
+        let table: TabularDataResource = serde_json::from_value(json!({
+            "schema": {"fields": [{"name": "when", "type": "datetime"}]},
+            "data": [{"when": "2024-01-01T12:00:00.000"}, {}]
+        }))
+        .unwrap();
+        let batch = table.to_record_batch().unwrap();
+        assert_eq!(
+            batch.schema().field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Millisecond, None)
+        );
+        assert!(batch.column(0).is_null(1));
+
+        let rows = TabularDataResource::from_record_batch(&batch)
+            .unwrap()
+            .data
+            .unwrap();
+        assert_eq!(rows[0]["when"], "2024-01-01T12:00:00.000");
+    }
+
+    #[test]
+    fn test_invalid_values_fail() {
+        let table: TabularDataResource = serde_json::from_value(json!({
+            "schema": {"fields": [{"name": "n", "type": "integer"}]},
+            "data": [{"n": "three"}]
+        }))
+        .unwrap();
+        let error = table.to_record_batch().unwrap_err().to_string();
+        assert!(error.contains("column n"), "{error}");
+    }
+}
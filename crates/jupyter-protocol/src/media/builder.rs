@@ -0,0 +1,142 @@
+//! Building a bundle together with its per-mimetype metadata.
+//!
+//! Sizes and other display hints live in the message's `metadata`, keyed by
+//! the mimetype they apply to, rather than next to the data in the bundle.
+//! [`MediaBuilder`] keeps the two in step, so a kernel can't size an image
+//! under the wrong mimetype:
+//!
+//! ```rust
+//! use jupyter_protocol::media::{DisplayMetadata, MediaBuilder, MediaMetadata, MediaType};
+//!
+//! let display = MediaBuilder::new()
+//!     .with_metadata(
+//!         MediaType::Png("iVBORw0KGgo=".to_string()),
+//!         MediaMetadata::new().with_size(640, 480),
+//!     )
+//!     .with(MediaType::Plain("<Figure size 640x480>".to_string()))
+//!     .display_data();
+//!
+//! assert_eq!(display.metadata["image/png"]["width"], 640);
+//! assert_eq!(display.media_metadata("text/plain").width, None);
+//! ```
+use serde_json::Value;
+
+use super::{JsonObject, Media, MediaMetadata, MediaType};
+use crate::{DisplayData, ExecuteResult, ExecutionCount};
+
+/// A [`Media`] bundle and the `metadata` that goes with it.
+#[derive(Debug, Clone, Default)]
+pub struct MediaBuilder {
+    content: Vec<MediaType>,
+    metadata: JsonObject,
+}
+
+impl MediaBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `media_type` to the bundle, replacing any data of the same mimetype.
+    pub fn with(mut self, media_type: MediaType) -> Self {
+        let mime_type = media_type.mime_type().to_string();
+        self.content
+            .retain(|existing| existing.mime_type() != mime_type);
+        self.metadata.remove(&mime_type);
+        self.content.push(media_type);
+        self
+    }
+
+    /// Add `media_type` to the bundle, with `metadata` under its mimetype.
+    /// Empty metadata is left out.
+    pub fn with_metadata(self, media_type: MediaType, metadata: MediaMetadata) -> Self {
+        let mime_type = media_type.mime_type().to_string();
+        let mut builder = self.with(media_type);
+        let metadata = metadata.to_object();
+        if !metadata.is_empty() {
+            builder.metadata.insert(mime_type, Value::Object(metadata));
+        }
+        builder
+    }
+
+    /// The bundle and its `metadata`.
+    pub fn build(self) -> (Media, JsonObject) {
+        (Media::new(self.content), self.metadata)
+    }
+
+    pub fn display_data(self) -> DisplayData {
+        let (data, metadata) = self.build();
+        DisplayData {
+            metadata,
+            ..DisplayData::new(data)
+        }
+    }
+
+    pub fn execute_result(self, execution_count: ExecutionCount) -> ExecuteResult {
+        let (data, metadata) = self.build();
+        ExecuteResult {
+            metadata,
+            ..ExecuteResult::new(execution_count, data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::DisplayMetadata;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_is_nested_by_mimetype() {
+        let result = MediaBuilder::new()
+            .with_metadata(
+                MediaType::Png("iVBORw0KGgo=".to_string()),
+                MediaMetadata::new().with_size(320, 200),
+            )
+            .with_metadata(
+                MediaType::Jpeg("/9j/4AAQ".to_string()),
+                MediaMetadata::new().with_unconfined(true),
+            )
+            .with_metadata(
+                MediaType::Plain("<Figure>".to_string()),
+                MediaMetadata::new(),
+            )
+            .execute_result(ExecutionCount::new(3));
+
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            json!({
+                "execution_count": 3,
+                "data": {
+                    "image/png": "iVBORw0KGgo=",
+                    "image/jpeg": "/9j/4AAQ",
+                    "text/plain": "<Figure>"
+                },
+                "metadata": {
+                    "image/png": {"width": 320, "height": 200},
+                    "image/jpeg": {"unconfined": true}
+                }
+            })
+        );
+        // The png's size doesn't leak onto the jpeg
+        let jpeg = result.media_metadata("image/jpeg");
+        assert_eq!((jpeg.width, jpeg.height), (None, None));
+    }
+
+    #[test]
+    fn test_replacing_data_replaces_its_metadata() {
+        let display = MediaBuilder::new()
+            .with_metadata(
+                MediaType::Png("old".to_string()),
+                MediaMetadata::new().with_size(10, 10),
+            )
+            .with(MediaType::Png("new".to_string()))
+            .display_data();
+
+        assert_eq!(
+            display.data.content,
+            vec![MediaType::Png("new".to_string())]
+        );
+        assert!(display.metadata.is_empty());
+    }
+}
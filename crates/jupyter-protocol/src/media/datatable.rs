@@ -1,3 +1,32 @@
+//! The `application/vnd.dataresource+json` table schema, as written by
+//! `pandas.DataFrame.to_json(orient="table")` and other data libraries.
+//!
+//! pandas puts its index in the schema's `primaryKey`, so the index columns
+//! can be told apart from the data columns:
+//!
+//! ```rust
+//! use jupyter_protocol::media::TabularDataResource;
+//!
+//! let table: TabularDataResource = serde_json::from_value(serde_json::json!({
+//!     "schema": {
+//!         "fields": [
+//!             {"name": "index", "type": "integer"},
+//!             {"name": "city", "type": "string"},
+//!             {"name": "when", "type": "datetime", "tz": "UTC"}
+//!         ],
+//!         "primaryKey": ["index"],
+//!         "pandas_version": "1.4.0"
+//!     },
+//!     "data": [{"index": 0, "city": "Oslo", "when": "2024-01-01T00:00:00.000Z"}]
+//! }))
+//! .unwrap();
+//!
+//! assert!(table.schema.is_pandas());
+//! assert_eq!(table.schema.primary_key_fields(), vec!["index"]);
+//! let columns: Vec<&str> = table.schema.value_fields().map(|f| f.name.as_str()).collect();
+//! assert_eq!(columns, vec!["city", "when"]);
+//! assert_eq!(table.schema.field("when").unwrap().tz.as_deref(), Some("UTC"));
+//! ```
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -51,6 +80,54 @@ pub struct TableSchema {
     pub foreign_keys: Option<Vec<ForeignKey>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub missing_values: Option<Vec<String>>,
+    /// Set by pandas, which writes its index as the primary key.
+    #[serde(rename = "pandas_version", skip_serializing_if = "Option::is_none")]
+    pub pandas_version: Option<String>,
+}
+
+impl TableSchema {
+    pub fn field(&self, name: &str) -> Option<&TableSchemaField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// The names of the primary key fields, empty if there's no primary key.
+    pub fn primary_key_fields(&self) -> Vec<&str> {
+        self.primary_key
+            .as_ref()
+            .map(PrimaryKey::fields)
+            .unwrap_or_default()
+    }
+
+    /// Whether the table was written by pandas, with the index as its primary key.
+    pub fn is_pandas(&self) -> bool {
+        self.pandas_version.is_some()
+    }
+
+    /// The fields making up the index, in index level order. For tables from
+    /// pandas these are the index levels; otherwise the primary key.
+    pub fn index_fields(&self) -> Vec<&TableSchemaField> {
+        self.primary_key_fields()
+            .into_iter()
+            .filter_map(|name| self.field(name))
+            .collect()
+    }
+
+    /// The fields that aren't part of the index, in column order.
+    pub fn value_fields(&self) -> impl Iterator<Item = &TableSchemaField> {
+        let index = self.primary_key_fields();
+        self.fields
+            .iter()
+            .filter(move |field| !index.contains(&field.name.as_str()))
+    }
+}
+
+impl PrimaryKey {
+    pub fn fields(&self) -> Vec<&str> {
+        match self {
+            PrimaryKey::Single(field) => vec![field.as_str()],
+            PrimaryKey::Multiple(fields) => fields.iter().map(String::as_str).collect(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -99,6 +176,18 @@ pub struct TableSchemaField {
     pub constraints: Option<HashMap<String, serde_json::Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rdf_type: Option<String>,
+    /// Whether a pandas categorical's `enum` constraint is ordered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ordered: Option<bool>,
+    /// Time zone of a pandas timezone aware datetime column.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+    /// Frequency of a pandas period column, like `M`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freq: Option<String>,
+    /// Name of a pandas extension dtype, like `Int64` or `string`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext_dtype: Option<String>,
 }
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -180,3 +269,59 @@ pub struct Dialect {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub case_sensitive_header: Option<bool>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pandas_multi_index_round_trips() {
+        // df.set_index(["region", "year"]).to_json(orient="table")
+        let value = json!({
+            "schema": {
+                "fields": [
+                    {"name": "region", "type": "any", "constraints": {"enum": ["north", "south"]}, "ordered": false},
+                    {"name": "year", "type": "integer"},
+                    {"name": "sales", "type": "integer", "extDtype": "Int64"}
+                ],
+                "primaryKey": ["region", "year"],
+                "pandas_version": "1.4.0"
+            },
+            "data": [{"region": "north", "year": 2023, "sales": 10}]
+        });
+        let table: TabularDataResource = serde_json::from_value(value.clone()).unwrap();
+
+        let index: Vec<&str> = table
+            .schema
+            .index_fields()
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect();
+        assert_eq!(index, vec!["region", "year"]);
+        assert_eq!(table.schema.field("region").unwrap().ordered, Some(false));
+        assert_eq!(
+            table
+                .schema
+                .value_fields()
+                .next()
+                .unwrap()
+                .ext_dtype
+                .as_deref(),
+            Some("Int64")
+        );
+        assert_eq!(serde_json::to_value(&table).unwrap(), value);
+    }
+
+    #[test]
+    fn test_single_primary_key() {
+        let schema: TableSchema = serde_json::from_value(json!({
+            "fields": [{"name": "id", "type": "integer"}, {"name": "name", "type": "string"}],
+            "primaryKey": "id"
+        }))
+        .unwrap();
+        assert!(!schema.is_pandas());
+        assert_eq!(schema.primary_key_fields(), vec!["id"]);
+        assert_eq!(schema.value_fields().count(), 1);
+    }
+}
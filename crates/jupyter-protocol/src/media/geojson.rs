@@ -0,0 +1,266 @@
+//! Typed GeoJSON ([RFC 7946](https://datatracker.ietf.org/doc/html/rfc7946)) for
+//! `application/geo+json` outputs.
+//!
+//! [`MediaType::GeoJson`] keeps the raw object so unusual documents still
+//! round trip. [`GeoJson`] parses it into geometries and features:
+//!
+//! ```rust
+//! use jupyter_protocol::media::geojson::{GeoJson, Geometry};
+//! use jupyter_protocol::media::MediaType;
+//!
+//! let media: MediaType = serde_json::from_value(serde_json::json!({
+//!     "type": "application/geo+json",
+//!     "data": {
+//!         "type": "Feature",
+//!         "geometry": {"type": "Point", "coordinates": [-118.4, 34.1]},
+//!         "properties": {"name": "Los Angeles"}
+//!     }
+//! }))
+//! .unwrap();
+//!
+//! let MediaType::GeoJson(object) = media else { unreachable!() };
+//! let GeoJson::Feature(feature) = GeoJson::try_from(&object).unwrap() else {
+//!     unreachable!()
+//! };
+//! assert_eq!(
+//!     feature.geometry,
+//!     Some(Geometry::Point { coordinates: vec![-118.4, 34.1] })
+//! );
+//! ```
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{JsonObject, MediaType};
+
+/// Longitude, latitude and optionally altitude, in that order.
+pub type Position = Vec<f64>;
+
+/// Any GeoJSON document.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum GeoJson {
+    Feature(Feature),
+    FeatureCollection(FeatureCollection),
+    #[serde(untagged)]
+    Geometry(Geometry),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point {
+        coordinates: Position,
+    },
+    MultiPoint {
+        coordinates: Vec<Position>,
+    },
+    LineString {
+        coordinates: Vec<Position>,
+    },
+    MultiLineString {
+        coordinates: Vec<Vec<Position>>,
+    },
+    /// A linear ring for the exterior, followed by any holes.
+    Polygon {
+        coordinates: Vec<Vec<Position>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Position>>>,
+    },
+    GeometryCollection {
+        geometries: Vec<Geometry>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Feature {
+    /// `null` for features without a location.
+    pub geometry: Option<Geometry>,
+    pub properties: Option<JsonObject>,
+    /// A string or number identifying the feature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<Vec<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<Vec<f64>>,
+}
+
+/// A 2D bounding box as `[west, south, east, north]`.
+pub type BoundingBox = [f64; 4];
+
+impl Geometry {
+    /// Every position in the geometry, in document order.
+    pub fn positions(&self) -> Vec<&Position> {
+        let mut positions = Vec::new();
+        self.collect_positions(&mut positions);
+        positions
+    }
+
+    fn collect_positions<'a>(&'a self, positions: &mut Vec<&'a Position>) {
+        match self {
+            Geometry::Point { coordinates } => positions.push(coordinates),
+            Geometry::MultiPoint { coordinates } | Geometry::LineString { coordinates } => {
+                positions.extend(coordinates)
+            }
+            Geometry::MultiLineString { coordinates } | Geometry::Polygon { coordinates } => {
+                positions.extend(coordinates.iter().flatten())
+            }
+            Geometry::MultiPolygon { coordinates } => {
+                positions.extend(coordinates.iter().flatten().flatten())
+            }
+            Geometry::GeometryCollection { geometries } => {
+                for geometry in geometries {
+                    geometry.collect_positions(positions);
+                }
+            }
+        }
+    }
+}
+
+impl GeoJson {
+    /// The geometries in the document, skipping features without one.
+    pub fn geometries(&self) -> Vec<&Geometry> {
+        match self {
+            GeoJson::Geometry(geometry) => vec![geometry],
+            GeoJson::Feature(feature) => feature.geometry.iter().collect(),
+            GeoJson::FeatureCollection(collection) => collection
+                .features
+                .iter()
+                .filter_map(|feature| feature.geometry.as_ref())
+                .collect(),
+        }
+    }
+
+    /// The bounds of all positions, for fitting a map to the data. Uses the
+    /// document's own `bbox` when it has one.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let declared = match self {
+            GeoJson::Feature(feature) => feature.bbox.as_ref(),
+            GeoJson::FeatureCollection(collection) => collection.bbox.as_ref(),
+            GeoJson::Geometry(_) => None,
+        };
+        // A 3D bbox is [west, south, low, east, north, high]
+        match declared.map(Vec::as_slice) {
+            Some(&[west, south, east, north]) | Some(&[west, south, _, east, north, _]) => {
+                return Some([west, south, east, north])
+            }
+            _ => {}
+        }
+
+        let mut bounds: Option<BoundingBox> = None;
+        for position in self.geometries().into_iter().flat_map(Geometry::positions) {
+            let (Some(&x), Some(&y)) = (position.first(), position.get(1)) else {
+                continue;
+            };
+            bounds = Some(match bounds {
+                None => [x, y, x, y],
+                Some([west, south, east, north]) => {
+                    [west.min(x), south.min(y), east.max(x), north.max(y)]
+                }
+            });
+        }
+        bounds
+    }
+}
+
+impl TryFrom<&JsonObject> for GeoJson {
+    type Error = serde_json::Error;
+
+    fn try_from(object: &JsonObject) -> Result<Self, Self::Error> {
+        serde_json::from_value(Value::Object(object.clone()))
+    }
+}
+
+impl From<GeoJson> for MediaType {
+    fn from(geojson: GeoJson) -> Self {
+        match serde_json::to_value(geojson) {
+            Ok(Value::Object(object)) => MediaType::GeoJson(object),
+            _ => MediaType::GeoJson(JsonObject::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_feature_collection() {
+        let geojson: GeoJson = serde_json::from_value(json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "id": 1,
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[0.0, 0.0], [4.0, 0.0], [4.0, 3.0], [0.0, 0.0]]]
+                    },
+                    "properties": {"name": "triangle"}
+                },
+                {"type": "Feature", "geometry": null, "properties": null}
+            ]
+        }))
+        .unwrap();
+
+        let GeoJson::FeatureCollection(collection) = &geojson else {
+            panic!("Expected a feature collection, got {:?}", geojson);
+        };
+        assert_eq!(collection.features.len(), 2);
+        assert_eq!(collection.features[0].id, Some(json!(1)));
+        assert_eq!(geojson.geometries().len(), 1);
+        assert_eq!(geojson.bounding_box(), Some([0.0, 0.0, 4.0, 3.0]));
+    }
+
+    #[test]
+    fn test_bare_geometry_round_trips() {
+        let value = json!({
+            "type": "GeometryCollection",
+            "geometries": [
+                {"type": "Point", "coordinates": [1.0, 2.0]},
+                {"type": "LineString", "coordinates": [[-1.0, 5.0, 10.0], [3.0, -2.0, 12.0]]}
+            ]
+        });
+        let geojson: GeoJson = serde_json::from_value(value.clone()).unwrap();
+        assert!(matches!(
+            geojson,
+            GeoJson::Geometry(Geometry::GeometryCollection { .. })
+        ));
+        assert_eq!(geojson.bounding_box(), Some([-1.0, -2.0, 3.0, 5.0]));
+        assert_eq!(serde_json::to_value(&geojson).unwrap(), value);
+    }
+
+    #[test]
+    fn test_declared_bbox_wins() {
+        let geojson: GeoJson = serde_json::from_value(json!({
+            "type": "Feature",
+            "bbox": [-10.0, -10.0, 0.0, 10.0, 10.0, 100.0],
+            "geometry": {"type": "Point", "coordinates": [1.0, 1.0]},
+            "properties": {}
+        }))
+        .unwrap();
+        assert_eq!(geojson.bounding_box(), Some([-10.0, -10.0, 10.0, 10.0]));
+
+        let MediaType::GeoJson(object) = MediaType::from(geojson.clone()) else {
+            panic!("Expected GeoJSON media");
+        };
+        assert_eq!(object["type"], "Feature");
+        assert_eq!(GeoJson::try_from(&object).unwrap(), geojson);
+    }
+
+    #[test]
+    fn test_unknown_type_is_an_error() {
+        let object = json!({"type": "Circle", "radius": 3});
+        let Value::Object(object) = object else {
+            unreachable!()
+        };
+        assert!(GeoJson::try_from(&object).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Rendering Markdown outputs to sanitized HTML.
+//!
+//! Kernels like the Ollama kernel answer in `text/markdown`, and each consumer
+//! showing it as HTML would otherwise pick its own renderer and get different
+//! tables, math and escaping. [`render_markdown`] parses CommonMark with
+//! tables, strikethrough and footnotes using
+//! [pulldown-cmark](https://docs.rs/pulldown-cmark), then
+//! [sanitizes](super::sanitize) the result, since Markdown can embed raw HTML.
+//!
+//! `$inline$` and `$$display$$` math is left as TeX, wrapped the way pandoc's
+//! `--katex` mode does it, so KaTeX's auto-render extension typesets it in the
+//! browser:
+//!
+//! ```rust
+//! use jupyter_protocol::media::markdown::render_markdown;
+//! use jupyter_protocol::media::sanitize::SanitizePolicy;
+//!
+//! let html = render_markdown("Euler: $e^{i\\pi} + 1 = 0$", SanitizePolicy::Strict);
+//! assert_eq!(
+//!     html,
+//!     "<p>Euler: <span class=\"math inline\">\\(e^{i\\pi} + 1 = 0\\)</span></p>\n"
+//! );
+//! ```
+use pulldown_cmark::{html, CowStr, Event, Options, Parser};
+
+use super::sanitize::SanitizePolicy;
+use super::{Media, MediaType};
+
+const MATH_CLASSES: &[&str] = &["math", "inline", "display"];
+
+/// Render `markdown` as an HTML fragment, sanitized with `policy`.
+pub fn render_markdown(markdown: &str, policy: SanitizePolicy) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_MATH;
+    let events = Parser::new_ext(markdown, options).flat_map(|event| match event {
+        Event::InlineMath(tex) => math("inline", "\\(", tex, "\\)"),
+        Event::DisplayMath(tex) => math("display", "\\[", tex, "\\]"),
+        event => vec![event],
+    });
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, events);
+
+    let mut builder = policy.builder();
+    // `AllowStyles` keeps every class already
+    if policy == SanitizePolicy::Strict {
+        builder.add_allowed_classes("span", MATH_CLASSES);
+    }
+    builder.clean(&unsafe_html).to_string()
+}
+
+/// TeX wrapped in KaTeX's default delimiters. It goes out as text, so it's
+/// escaped like any other.
+fn math<'a>(class: &str, open: &str, tex: CowStr<'a>, close: &str) -> Vec<Event<'a>> {
+    vec![
+        Event::InlineHtml(format!("<span class=\"math {}\">", class).into()),
+        Event::Text(format!("{}{}{}", open, tex, close).into()),
+        Event::InlineHtml("</span>".into()),
+    ]
+}
+
+impl MediaType {
+    /// Markdown rendered as sanitized `text/html`, or `None` for any other
+    /// type.
+    pub fn rendered_markdown(&self, policy: SanitizePolicy) -> Option<MediaType> {
+        match self {
+            MediaType::Markdown(markdown) => {
+                Some(MediaType::Html(render_markdown(markdown, policy)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Media {
+    /// Add `text/html` rendered from the bundle's `text/markdown`, for
+    /// frontends that only show HTML. Bundles that already have HTML, or
+    /// don't have Markdown, are left alone.
+    pub fn render_markdown(&mut self, policy: SanitizePolicy) {
+        if self
+            .content
+            .iter()
+            .any(|media_type| matches!(media_type, MediaType::Html(_)))
+        {
+            return;
+        }
+        if let Some(html) = self
+            .content
+            .iter()
+            .find_map(|media_type| media_type.rendered_markdown(policy))
+        {
+            self.content.push(html);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_math_is_left_for_katex() {
+        let html = render_markdown(
+            "Where $a_1 < b_2$ and\n\n$$\\sum_{i=1}^n x_i$$",
+            SanitizePolicy::Strict,
+        );
+        // Underscores in math aren't emphasis, and `<` is escaped
+        assert_eq!(
+            html,
+            "<p>Where <span class=\"math inline\">\\(a_1 &lt; b_2\\)</span> and</p>\n\
+             <p><span class=\"math display\">\\[\\sum_{i=1}^n x_i\\]</span></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_raw_html_is_sanitized() {
+        let markdown = "# Title\n\n<img src=\"x.png\" onerror=\"steal()\"><script>steal()</script>\n\n<span class=\"math evil\" style=\"color: red\">styled</span>";
+        let strict = render_markdown(markdown, SanitizePolicy::Strict);
+        assert!(strict.contains("<h1>Title</h1>"));
+        assert!(strict.contains("<img src=\"x.png\">"));
+        assert!(!strict.contains("script"));
+        assert!(!strict.contains("evil"));
+        assert!(!strict.contains("style="));
+
+        let styled = render_markdown(markdown, SanitizePolicy::AllowStyles);
+        assert!(styled.contains("<span class=\"math evil\" style=\"color: red\">"));
+        assert!(!styled.contains("script"));
+    }
+
+    #[test]
+    fn test_tables() {
+        let html = render_markdown(
+            "| a | b |\n|---|---|\n| 1 | ~~2~~ |",
+            SanitizePolicy::Strict,
+        );
+        assert!(html.contains("<th>a</th>"));
+        assert!(html.contains("<td><del>2</del></td>"));
+    }
+
+    #[test]
+    fn test_media_gains_html_from_markdown() {
+        let mut media = Media::new(vec![
+            MediaType::Plain("**hi**".into()),
+            MediaType::Markdown("**hi**".into()),
+        ]);
+        media.render_markdown(SanitizePolicy::Strict);
+        assert_eq!(
+            media.content.last(),
+            Some(&MediaType::Html("<p><strong>hi</strong></p>\n".into()))
+        );
+
+        // Kernels' own HTML wins
+        let mut media = Media::new(vec![
+            MediaType::Markdown("**hi**".into()),
+            MediaType::Html("<b>hi</b>".into()),
+        ]);
+        media.render_markdown(SanitizePolicy::Strict);
+        assert_eq!(media.content.len(), 2);
+    }
+}
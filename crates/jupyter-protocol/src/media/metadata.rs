@@ -0,0 +1,244 @@
+//! Typed access to the well known per-mimetype display metadata.
+//!
+//! The `metadata` of `display_data`, `execute_result` and `update_display_data`
+//! is keyed by mimetype, and frontends look for a few standard fields there:
+//!
+//! ```json
+//! {
+//!   "image/png": {"width": 640, "height": 480},
+//!   "text/html": {"isolated": true},
+//!   "application/json": {"expanded": false, "root": "data"}
+//! }
+//! ```
+//!
+//! [`DisplayMetadata`] reads and writes those as a [`MediaMetadata`]:
+//!
+//! ```rust
+//! use jupyter_protocol::media::{DisplayMetadata, MediaMetadata, MediaType};
+//! use jupyter_protocol::DisplayData;
+//!
+//! let display = DisplayData::from(MediaType::Png("iVBORw0KGgo=".to_string()))
+//!     .with_media_metadata("image/png", MediaMetadata::new().with_size(640, 480));
+//!
+//! let metadata = display.media_metadata("image/png");
+//! assert_eq!((metadata.width, metadata.height), (Some(640), Some(480)));
+//! assert_eq!(display.media_metadata("text/html"), MediaMetadata::default());
+//! ```
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::JsonObject;
+use crate::{DisplayData, ExecuteResult, UpdateDisplayData};
+
+/// The metadata for one mimetype in a bundle.
+///
+/// Unrecognized fields are kept in `additional`. Fields with values of the
+/// wrong type are kept there too, rather than failing the whole read.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct MediaMetadata {
+    /// Display width in pixels, for images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Display height in pixels, for images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Render `text/html` or `image/svg+xml` in an iframe, isolated from the page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isolated: Option<bool>,
+    /// Show `application/json` fully expanded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expanded: Option<bool>,
+    /// The label of the top level node of `application/json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// Let images grow past the width of the output area.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unconfined: Option<bool>,
+    #[serde(flatten)]
+    pub additional: JsonObject,
+}
+
+impl MediaMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.width = Some(width);
+        self.height = Some(height);
+        self
+    }
+
+    pub fn with_isolated(mut self, isolated: bool) -> Self {
+        self.isolated = Some(isolated);
+        self
+    }
+
+    pub fn with_expanded(mut self, expanded: bool) -> Self {
+        self.expanded = Some(expanded);
+        self
+    }
+
+    pub fn with_root(mut self, root: impl Into<String>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    pub fn with_unconfined(mut self, unconfined: bool) -> Self {
+        self.unconfined = Some(unconfined);
+        self
+    }
+
+    /// Read the fields out of a mimetype's metadata object.
+    pub fn from_object(object: &JsonObject) -> Self {
+        let mut additional = object.clone();
+        let mut take = |key: &str, parse: fn(&Value) -> bool| match additional.get(key) {
+            Some(value) if parse(value) => additional.remove(key),
+            _ => None,
+        };
+
+        let width = take("width", |value| as_pixels(value).is_some());
+        let height = take("height", |value| as_pixels(value).is_some());
+        let isolated = take("isolated", Value::is_boolean);
+        let expanded = take("expanded", Value::is_boolean);
+        let root = take("root", Value::is_string);
+        let unconfined = take("unconfined", Value::is_boolean);
+
+        Self {
+            width: width.as_ref().and_then(as_pixels),
+            height: height.as_ref().and_then(as_pixels),
+            isolated: isolated.and_then(|value| value.as_bool()),
+            expanded: expanded.and_then(|value| value.as_bool()),
+            root: root.and_then(|value| value.as_str().map(String::from)),
+            unconfined: unconfined.and_then(|value| value.as_bool()),
+            additional,
+        }
+    }
+
+    pub fn to_object(&self) -> JsonObject {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(object)) => object,
+            _ => Map::new(),
+        }
+    }
+}
+
+/// Sizes are sometimes floats, e.g. from `IPython.display.Image(width=320.0)`.
+fn as_pixels(value: &Value) -> Option<u32> {
+    match value {
+        Value::Number(number) => number
+            .as_u64()
+            .or_else(|| {
+                number
+                    .as_f64()
+                    .filter(|f| *f >= 0.0)
+                    .map(|f| f.round() as u64)
+            })
+            .and_then(|pixels| u32::try_from(pixels).ok()),
+        _ => None,
+    }
+}
+
+/// Messages with a mimetype keyed `metadata` map.
+pub trait DisplayMetadata {
+    fn metadata_map(&self) -> &JsonObject;
+    fn metadata_map_mut(&mut self) -> &mut JsonObject;
+
+    /// The metadata for `mime_type`, empty if there's none.
+    fn media_metadata(&self, mime_type: &str) -> MediaMetadata {
+        match self.metadata_map().get(mime_type) {
+            Some(Value::Object(object)) => MediaMetadata::from_object(object),
+            _ => MediaMetadata::default(),
+        }
+    }
+
+    /// Replace the metadata for `mime_type`, removing it if it's empty.
+    fn set_media_metadata(&mut self, mime_type: &str, metadata: MediaMetadata) {
+        let object = metadata.to_object();
+        if object.is_empty() {
+            self.metadata_map_mut().remove(mime_type);
+        } else {
+            self.metadata_map_mut()
+                .insert(mime_type.to_string(), Value::Object(object));
+        }
+    }
+
+    fn with_media_metadata(mut self, mime_type: &str, metadata: MediaMetadata) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_media_metadata(mime_type, metadata);
+        self
+    }
+}
+
+macro_rules! impl_display_metadata {
+    ($($message:ty),*) => {
+        $(
+            impl DisplayMetadata for $message {
+                fn metadata_map(&self) -> &JsonObject {
+                    &self.metadata
+                }
+
+                fn metadata_map_mut(&mut self) -> &mut JsonObject {
+                    &mut self.metadata
+                }
+            }
+        )*
+    };
+}
+
+impl_display_metadata!(DisplayData, ExecuteResult, UpdateDisplayData);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_read_media_metadata() {
+        let display: DisplayData = serde_json::from_value(json!({
+            "data": {"text/plain": "x"},
+            "metadata": {
+                "image/png": {"width": 320.0, "height": 200, "needs_background": "light"},
+                "text/html": {"isolated": true},
+                "application/json": {"expanded": false, "root": "data"},
+                "image/jpeg": {"width": "wide"},
+            },
+        }))
+        .unwrap();
+
+        let png = display.media_metadata("image/png");
+        assert_eq!((png.width, png.height), (Some(320), Some(200)));
+        assert_eq!(png.additional["needs_background"], "light");
+        assert_eq!(display.media_metadata("text/html").isolated, Some(true));
+
+        let json = display.media_metadata("application/json");
+        assert_eq!(json.expanded, Some(false));
+        assert_eq!(json.root.as_deref(), Some("data"));
+
+        let jpeg = display.media_metadata("image/jpeg");
+        assert_eq!(jpeg.width, None);
+        assert_eq!(jpeg.additional["width"], "wide");
+    }
+
+    #[test]
+    fn test_set_media_metadata() {
+        let mut result = ExecuteResult::default()
+            .with_media_metadata("text/html", MediaMetadata::new().with_isolated(true))
+            .with_media_metadata(
+                "application/json",
+                MediaMetadata::new().with_expanded(true).with_root("root"),
+            );
+        assert_eq!(
+            serde_json::to_value(&result.metadata).unwrap(),
+            json!({
+                "text/html": {"isolated": true},
+                "application/json": {"expanded": true, "root": "root"},
+            })
+        );
+
+        result.set_media_metadata("text/html", MediaMetadata::default());
+        assert!(!result.metadata.contains_key("text/html"));
+    }
+}
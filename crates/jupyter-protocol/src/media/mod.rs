@@ -46,9 +46,23 @@ use serde::{de, Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod builder;
 pub mod datatable;
-
+pub mod geojson;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+pub mod metadata;
+pub mod rank;
+#[cfg(feature = "sanitize")]
+pub mod sanitize;
+
+pub use builder::MediaBuilder;
 pub use datatable::TabularDataResource;
+pub use geojson::GeoJson;
+pub use metadata::{DisplayMetadata, MediaMetadata};
+pub use rank::{RankProfile, Ranking};
 
 pub type JsonObject = serde_json::Map<String, serde_json::Value>;
 
@@ -144,8 +158,9 @@ pub enum MediaType {
     Other((String, Value)),
 }
 
-impl std::hash::Hash for MediaType {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+impl MediaType {
+    /// The mimetype this media is keyed by in a bundle, like `image/png`.
+    pub fn mime_type(&self) -> &str {
         match &self {
             MediaType::Plain(_) => "text/plain",
             MediaType::Html(_) => "text/html",
@@ -173,7 +188,12 @@ impl std::hash::Hash for MediaType {
             MediaType::Vdom(_) => "application/vdom.v1+json",
             MediaType::Other((key, _)) => key.as_str(),
         }
-        .hash(state)
+    }
+}
+
+impl std::hash::Hash for MediaType {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.mime_type().hash(state)
     }
 }
 
@@ -236,6 +256,7 @@ where
                 "image/png" => MediaType::Png(text),
                 "image/jpeg" => MediaType::Jpeg(text),
                 "image/gif" => MediaType::Gif(text),
+                "image/svg+xml" => MediaType::Svg(text),
                 _ => MediaType::Other((key.clone(), value)),
             };
             content.push(mediatype);
@@ -248,7 +269,6 @@ where
             "text/latex" => MediaType::Latex(text),
             "application/javascript" => MediaType::Javascript(text),
             "text/markdown" => MediaType::Markdown(text),
-            "image/svg+xml" => MediaType::Svg(text),
 
             // Keep unknown mediatypes exactly as they were
             _ => MediaType::Other((key.clone(), value)),
@@ -277,6 +297,16 @@ where
     serialize_media_with_options(&media.content, serializer, true)
 }
 
+/// Text with more than one line as a notebook's list of lines, each keeping
+/// its line ending so that joining them gives back exactly `text`.
+fn split_lines(text: &str) -> Option<Value> {
+    let lines: Vec<Value> = text
+        .split_inclusive('\n')
+        .map(|line| Value::String(line.to_string()))
+        .collect();
+    (lines.len() > 1).then_some(Value::Array(lines))
+}
+
 pub fn serialize_media_with_options<S>(
     content: &Vec<MediaType>,
     serializer: S,
@@ -305,16 +335,9 @@ where
                     _ => unreachable!(),
                 };
                 let value = if with_multiline {
-                    let lines: Vec<&str> = text.lines().collect();
-
-                    if lines.len() > 1 {
-                        let entries = lines
-                            .iter()
-                            .map(|line| Value::String(format!("{}\n", line)));
-
-                        Value::Array(entries.collect())
-                    } else {
-                        Value::Array(vec![Value::String(text.clone())])
+                    match split_lines(text) {
+                        Some(lines) => lines,
+                        None => Value::Array(vec![Value::String(text.clone())]),
                     }
                 } else {
                     Value::String(text.clone())
@@ -336,17 +359,7 @@ where
                     _ => unreachable!(),
                 };
                 let value = if with_multiline {
-                    let lines: Vec<&str> = text.lines().collect();
-
-                    if lines.len() > 1 {
-                        let entries = lines
-                            .iter()
-                            .map(|line| Value::String(format!("{}\n", line)));
-
-                        Value::Array(entries.collect())
-                    } else {
-                        Value::String(text.clone())
-                    }
+                    split_lines(text).unwrap_or_else(|| Value::String(text.clone()))
                 } else {
                     Value::String(text.clone())
                 };
@@ -380,7 +393,8 @@ where
 impl Media {
     /// Find the richest media type in the bundle, based on the provided ranker function.
     /// A rank of 0 indicates that the media type is not supported. Higher numbers indicate
-    /// that the media type is preferred over other media types.
+    /// that the media type is preferred over other media types. [`RankProfile`] has
+    /// rankings shared by frontends of the same kind.
     ///
     /// # Examples
     ///
@@ -410,7 +424,7 @@ impl Media {
     /// );
     ///
     /// ```
-    pub fn richest(&self, ranker: fn(&MediaType) -> usize) -> Option<&MediaType> {
+    pub fn richest(&self, ranker: impl Fn(&MediaType) -> usize) -> Option<&MediaType> {
         self.content
             .iter()
             .filter_map(|mediatype| {
@@ -643,4 +657,65 @@ mod test {
             .content
             .contains(&MediaType::Html("<h1>\n  Hello, world!\n</h1>".to_string())));
     }
+
+    #[derive(Serialize)]
+    struct NotebookMedia<'a>(#[serde(serialize_with = "serialize_media_for_notebook")] &'a Media);
+
+    fn sorted(mut content: Vec<MediaType>) -> Vec<MediaType> {
+        content.sort_by(|a, b| a.mime_type().cmp(b.mime_type()));
+        content
+    }
+
+    fn round_trip_bundle() -> Media {
+        Media::new(vec![
+            MediaType::Plain("no trailing newline\nsecond line".to_string()),
+            MediaType::Html("<p>\n  trailing\n</p>\n".to_string()),
+            MediaType::Markdown("windows\r\nline endings\r\n".to_string()),
+            MediaType::Latex("$x$".to_string()),
+            MediaType::Svg("<svg>\n</svg>".to_string()),
+            MediaType::Png("iVBORw0KGgo=\nAAAA\n".to_string()),
+            MediaType::Json(
+                serde_json::json!({"a": [1, 2]})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ),
+            MediaType::Other((
+                "application/x-custom".to_string(),
+                serde_json::json!(["kept", "as is"]),
+            )),
+        ])
+    }
+
+    #[test]
+    fn wire_round_trip() {
+        let media = round_trip_bundle();
+        let wire = serde_json::to_value(&media).unwrap();
+        assert_eq!(
+            wire["text/plain"],
+            serde_json::json!("no trailing newline\nsecond line")
+        );
+
+        let parsed: Media = serde_json::from_value(wire).unwrap();
+        assert_eq!(sorted(parsed.content), sorted(media.content));
+    }
+
+    #[test]
+    fn notebook_round_trip() {
+        let media = round_trip_bundle();
+        let notebook = serde_json::to_value(NotebookMedia(&media)).unwrap();
+        assert_eq!(
+            notebook["text/plain"],
+            serde_json::json!(["no trailing newline\n", "second line"])
+        );
+        assert_eq!(
+            notebook["text/markdown"],
+            serde_json::json!(["windows\r\n", "line endings\r\n"])
+        );
+        assert_eq!(notebook["text/latex"], serde_json::json!(["$x$"]));
+
+        // Both serializations read back to the same bundle
+        let parsed: Media = serde_json::from_value(notebook).unwrap();
+        assert_eq!(sorted(parsed.content), sorted(media.content));
+    }
 }
@@ -0,0 +1,217 @@
+//! Shared display priorities for [`Media::richest`](super::Media::richest).
+//!
+//! Each kind of frontend wants a different media type out of the same
+//! bundle: a terminal can't show HTML, a static export can't run Javascript
+//! and a browser with widgets prefers them to everything. [`RankProfile`]
+//! has a ranking for each, so frontends of the same kind agree on what to
+//! show. [`Ranking`] starts from a profile and changes the priority of
+//! individual mimetypes.
+//!
+//! ```rust
+//! use jupyter_protocol::media::{Media, MediaType, RankProfile};
+//!
+//! let media = Media::new(vec![
+//!     MediaType::Plain("DataFrame".to_string()),
+//!     MediaType::Html("<table></table>".to_string()),
+//!     MediaType::Markdown("| a |".to_string()),
+//! ]);
+//!
+//! let web = media.richest(|media_type| RankProfile::WebHtml.rank(media_type));
+//! assert!(matches!(web, Some(MediaType::Html(_))));
+//!
+//! // Prefer Markdown to HTML, and never show plain text
+//! let ranking = RankProfile::WebHtml
+//!     .with_priority("text/markdown", 125)
+//!     .with_priority("text/plain", 0);
+//! let richest = media.richest(|media_type| ranking.rank(media_type));
+//! assert!(matches!(richest, Some(MediaType::Markdown(_))));
+//! ```
+use std::collections::HashMap;
+
+use super::MediaType;
+
+/// Built in display priorities. Higher ranks are preferred, and 0 means the
+/// frontend can't show the media type.
+///
+/// Ranks are multiples of 10, so [overrides](RankProfile::with_priority) can
+/// slot a mimetype in between two others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RankProfile {
+    /// Text a terminal can print: inline tables, plain text, Markdown and
+    /// LaTeX source, then JSON. Images depend on the terminal, so they're
+    /// left for the terminal to [add](RankProfile::with_priority).
+    Terminal,
+    /// A browser that runs Javascript, with nteract's order: widgets and
+    /// interactive visualizations first, then HTML, Markdown, LaTeX and
+    /// images, with plain text last.
+    WebHtml,
+    /// A static document, like an HTML or Markdown export: images, then HTML,
+    /// Markdown, LaTeX, JSON and plain text. Nothing that needs Javascript.
+    NotebookExport,
+}
+
+impl RankProfile {
+    /// How much this profile prefers `media_type`.
+    pub fn rank(self, media_type: &MediaType) -> usize {
+        match self {
+            RankProfile::Terminal => match media_type {
+                MediaType::DataTable(table) if table.data.is_some() => 50,
+                MediaType::Plain(_) => 40,
+                MediaType::Markdown(_) => 30,
+                MediaType::Latex(_) => 20,
+                MediaType::Json(_) | MediaType::GeoJson(_) => 10,
+                _ => 0,
+            },
+            RankProfile::WebHtml => match media_type {
+                MediaType::WidgetView(_) => 200,
+                MediaType::Vdom(_) => 190,
+                MediaType::Plotly(_) => 180,
+                MediaType::GeoJson(_) => 170,
+                MediaType::DataTable(_) => 160,
+                // Newer versions first
+                MediaType::VegaLiteV6(_) => 156,
+                MediaType::VegaLiteV5(_) => 155,
+                MediaType::VegaLiteV4(_) => 154,
+                MediaType::VegaLiteV3(_) => 153,
+                MediaType::VegaLiteV2(_) => 152,
+                MediaType::VegaV5(_) => 145,
+                MediaType::VegaV4(_) => 144,
+                MediaType::VegaV3(_) => 143,
+                MediaType::Json(_) => 140,
+                MediaType::Javascript(_) => 130,
+                MediaType::Html(_) => 120,
+                MediaType::Markdown(_) => 110,
+                MediaType::Latex(_) => 100,
+                MediaType::Svg(_) => 90,
+                MediaType::Gif(_) => 80,
+                MediaType::Png(_) => 70,
+                MediaType::Jpeg(_) => 60,
+                MediaType::Plain(_) => 50,
+                _ => 0,
+            },
+            RankProfile::NotebookExport => match media_type {
+                MediaType::Png(_) | MediaType::Jpeg(_) | MediaType::Gif(_) | MediaType::Svg(_) => {
+                    60
+                }
+                MediaType::Html(_) => 50,
+                MediaType::Markdown(_) => 40,
+                MediaType::Latex(_) => 30,
+                MediaType::Json(_) => 20,
+                MediaType::Plain(_) => 10,
+                _ => 0,
+            },
+        }
+    }
+
+    /// A [`Ranking`] like this profile, but ranking `mime_type` at `rank`.
+    pub fn with_priority(self, mime_type: impl Into<String>, rank: usize) -> Ranking {
+        Ranking::new(self).with_priority(mime_type, rank)
+    }
+}
+
+/// A [`RankProfile`] with the priorities of some mimetypes changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ranking {
+    profile: RankProfile,
+    overrides: HashMap<String, usize>,
+}
+
+impl Ranking {
+    pub fn new(profile: RankProfile) -> Self {
+        Self {
+            profile,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Rank `mime_type` at `rank` instead of the profile's rank for it. A rank
+    /// of 0 hides it.
+    pub fn with_priority(mut self, mime_type: impl Into<String>, rank: usize) -> Self {
+        self.overrides.insert(mime_type.into(), rank);
+        self
+    }
+
+    pub fn profile(&self) -> RankProfile {
+        self.profile
+    }
+
+    pub fn rank(&self, media_type: &MediaType) -> usize {
+        self.overrides
+            .get(media_type.mime_type())
+            .copied()
+            .unwrap_or_else(|| self.profile.rank(media_type))
+    }
+}
+
+impl From<RankProfile> for Ranking {
+    fn from(profile: RankProfile) -> Self {
+        Self::new(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::Media;
+    use serde_json::json;
+
+    fn bundle() -> Media {
+        serde_json::from_value(json!({
+            "text/plain": "<Chart>",
+            "text/html": "<div id=\"chart\"></div>",
+            "image/png": "iVBORw0KGgo=",
+            "application/vnd.vegalite.v4+json": {"mark": "bar"},
+            "application/vnd.vegalite.v5+json": {"mark": "bar"},
+            "application/javascript": "render()",
+            "application/vnd.custom+json": {"chart": true}
+        }))
+        .unwrap()
+    }
+
+    fn richest_mime_type(media: &Media, ranking: &Ranking) -> Option<String> {
+        media
+            .richest(|media_type| ranking.rank(media_type))
+            .map(|media_type| media_type.mime_type().to_string())
+    }
+
+    #[test]
+    fn test_profiles_pick_what_they_can_show() {
+        let media = bundle();
+        let richest = |profile: RankProfile| richest_mime_type(&media, &profile.into());
+
+        assert_eq!(
+            richest(RankProfile::WebHtml).as_deref(),
+            Some("application/vnd.vegalite.v5+json")
+        );
+        assert_eq!(
+            richest(RankProfile::NotebookExport).as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            richest(RankProfile::Terminal).as_deref(),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_overrides() {
+        let media = bundle();
+        let ranking = RankProfile::WebHtml
+            .with_priority("application/vnd.custom+json", 500)
+            .with_priority("text/plain", 0);
+        assert_eq!(
+            richest_mime_type(&media, &ranking).as_deref(),
+            Some("application/vnd.custom+json")
+        );
+
+        let text_only = Media::new(vec![MediaType::Plain("x".to_string())]);
+        assert_eq!(richest_mime_type(&text_only, &ranking), None);
+
+        // Terminals that can draw images add them on top
+        let ranking = RankProfile::Terminal.with_priority("image/png", 60);
+        assert_eq!(
+            richest_mime_type(&media, &ranking).as_deref(),
+            Some("image/png")
+        );
+    }
+}
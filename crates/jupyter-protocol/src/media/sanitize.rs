@@ -0,0 +1,245 @@
+//! Sanitizing HTML and SVG outputs from untrusted notebooks.
+//!
+//! A `text/html` or `image/svg+xml` output can carry `<script>` tags, `onload`
+//! handlers and `javascript:` links that run as soon as the output is shown.
+//! [`MediaType::sanitized`] strips them with [ammonia](https://docs.rs/ammonia)
+//! so that renderers and exporters showing outputs from notebooks nobody
+//! vouched for don't each need their own sanitizer.
+//!
+//! ```rust
+//! use jupyter_protocol::media::sanitize::SanitizePolicy;
+//! use jupyter_protocol::media::MediaType;
+//!
+//! let html = MediaType::Html(r#"<b onclick="steal()">bold</b><script>steal()</script>"#.into());
+//! assert_eq!(
+//!     html.sanitized(SanitizePolicy::Strict),
+//!     Some(MediaType::Html("<b>bold</b>".into()))
+//! );
+//!
+//! // Javascript can't be made safe, so it's dropped
+//! let javascript = MediaType::Javascript("steal()".into());
+//! assert_eq!(javascript.sanitized(SanitizePolicy::Strict), None);
+//! ```
+//!
+//! Markdown can embed raw HTML too. It's left as is here: render it with
+//! `media::markdown`, behind the `markdown` feature, which sanitizes what it
+//! produces, or sanitize another renderer's HTML with
+//! [`SanitizePolicy::clean_html`].
+use std::collections::HashSet;
+
+use ammonia::Builder;
+
+use super::{Media, MediaType};
+
+/// How much markup survives sanitizing. Either way, scripts, event handlers,
+/// `javascript:` URLs, iframes, forms and embedded objects are removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SanitizePolicy {
+    /// Text formatting, links, lists, tables and images from `http(s)` URLs.
+    /// Styles and classes are removed.
+    #[default]
+    Strict,
+    /// Also keeps `<style>` blocks, `style` and `class` attributes and `data:`
+    /// images, which pandas tables and most rich reprs rely on to look right.
+    /// CSS can still restyle the page around the output.
+    AllowStyles,
+}
+
+const SVG_TAGS: &[&str] = &[
+    "svg",
+    "g",
+    "path",
+    "rect",
+    "circle",
+    "ellipse",
+    "line",
+    "polyline",
+    "polygon",
+    "text",
+    "tspan",
+    "title",
+    "desc",
+    "defs",
+    "clipPath",
+    "linearGradient",
+    "radialGradient",
+    "stop",
+];
+
+const SVG_ATTRIBUTES: &[&str] = &[
+    "xmlns",
+    "version",
+    "viewBox",
+    "preserveAspectRatio",
+    "width",
+    "height",
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "r",
+    "rx",
+    "ry",
+    "d",
+    "points",
+    "transform",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "stroke",
+    "stroke-width",
+    "stroke-opacity",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-dasharray",
+    "opacity",
+    "clip-path",
+    "clip-rule",
+    "font-family",
+    "font-size",
+    "font-weight",
+    "text-anchor",
+    "dominant-baseline",
+    "offset",
+    "stop-color",
+    "stop-opacity",
+    "gradientUnits",
+    "id",
+];
+
+impl SanitizePolicy {
+    pub(super) fn builder(&self) -> Builder<'static> {
+        let mut builder = Builder::default();
+        if *self == SanitizePolicy::AllowStyles {
+            builder
+                .rm_clean_content_tags(["style"])
+                .add_tags(["style"])
+                .add_generic_attributes(["style", "class"])
+                .add_url_schemes(["data"]);
+        }
+        builder
+    }
+
+    /// Sanitize an HTML fragment.
+    pub fn clean_html(&self, html: &str) -> String {
+        self.builder().clean(html).to_string()
+    }
+
+    /// Sanitize an SVG image, keeping shapes, text and gradients.
+    pub fn clean_svg(&self, svg: &str) -> String {
+        let mut builder = self.builder();
+        builder
+            .tags(SVG_TAGS.iter().copied().collect::<HashSet<_>>())
+            .add_generic_attributes(SVG_ATTRIBUTES);
+        if *self == SanitizePolicy::AllowStyles {
+            builder.add_tags(["style"]);
+        }
+        builder.clean(svg).to_string()
+    }
+}
+
+impl MediaType {
+    /// This media with anything that could run code removed, or `None` if
+    /// it's code itself, like `application/javascript`. Types that aren't
+    /// markup come back unchanged.
+    pub fn sanitized(&self, policy: SanitizePolicy) -> Option<MediaType> {
+        match self {
+            MediaType::Html(html) => Some(MediaType::Html(policy.clean_html(html))),
+            MediaType::Svg(svg) => Some(MediaType::Svg(policy.clean_svg(svg))),
+            MediaType::Javascript(_) => None,
+            MediaType::Other((mime_type, _)) if is_executable(mime_type) => None,
+            other => Some(other.clone()),
+        }
+    }
+}
+
+impl Media {
+    /// The bundle with every media type [sanitized](MediaType::sanitized),
+    /// dropping those that can't be.
+    pub fn sanitized(&self, policy: SanitizePolicy) -> Media {
+        Media::new(
+            self.content
+                .iter()
+                .filter_map(|media_type| media_type.sanitized(policy))
+                .collect(),
+        )
+    }
+}
+
+fn is_executable(mime_type: &str) -> bool {
+    matches!(
+        mime_type,
+        "text/javascript"
+            | "application/ecmascript"
+            | "application/x-javascript"
+            | "text/ecmascript"
+            | "application/vnd.jupyter.javascript"
+    ) || mime_type.starts_with("application/vnd.bokehjs_exec")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strict_html() {
+        let html = r#"<div style="color: red" class="x"><a href="javascript:alert(1)">link</a><img src="x.png" onerror="alert(1)"><iframe src="https://example.com"></iframe><style>body { display: none }</style></div>"#;
+        assert_eq!(
+            SanitizePolicy::Strict.clean_html(html),
+            r#"<div><a rel="noopener noreferrer">link</a><img src="x.png"></div>"#
+        );
+    }
+
+    #[test]
+    fn test_allow_styles_keeps_dataframe_markup() {
+        let html = r#"<style scoped>.dataframe tbody tr th { vertical-align: top; }</style><table class="dataframe" border="1"><tr><th style="text-align: right">a</th><td onmouseover="x()">1</td></tr></table><img src="data:image/png;base64,AAAA">"#;
+        let cleaned = SanitizePolicy::AllowStyles.clean_html(html);
+        assert!(cleaned.contains("<style>.dataframe tbody tr th { vertical-align: top; }</style>"));
+        assert!(cleaned.contains(r#"<table class="dataframe">"#));
+        assert!(cleaned.contains(r#"<th style="text-align: right">"#));
+        assert!(cleaned.contains("<td>1</td>"));
+        assert!(cleaned.contains(r#"<img src="data:image/png;base64,AAAA">"#));
+
+        // Strict drops the data URL and styles
+        let strict = SanitizePolicy::Strict.clean_html(html);
+        assert!(!strict.contains("style"));
+        assert!(!strict.contains("data:"));
+    }
+
+    #[test]
+    fn test_svg() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10" onload="alert(1)"><script>alert(1)</script><clipPath id="c"><rect width="10" height="10"></rect></clipPath><path d="M0 0L10 10" stroke="black" clip-path="url(#c)"></path><foreignObject><div>html</div></foreignObject></svg>"#;
+        let cleaned = SanitizePolicy::Strict.clean_svg(svg);
+        assert!(!cleaned.contains("script"));
+        assert!(!cleaned.contains("onload"));
+        assert!(!cleaned.contains("foreignObject"));
+        assert!(cleaned.contains(r#"viewBox="0 0 10 10""#));
+        assert!(
+            cleaned.contains(r#"<clipPath id="c"><rect width="10" height="10"></rect></clipPath>"#)
+        );
+        assert!(
+            cleaned.contains(r#"<path d="M0 0L10 10" stroke="black" clip-path="url(#c)"></path>"#)
+        );
+    }
+
+    #[test]
+    fn test_media_drops_executable_types() {
+        let media = Media::new(vec![
+            MediaType::Plain("<script>fine as text</script>".into()),
+            MediaType::Html("<p>hi</p><script>alert(1)</script>".into()),
+            MediaType::Javascript("alert(1)".into()),
+            MediaType::Other(("text/javascript".into(), "alert(1)".into())),
+        ]);
+        assert_eq!(
+            media.sanitized(SanitizePolicy::Strict).content,
+            vec![
+                MediaType::Plain("<script>fine as text</script>".into()),
+                MediaType::Html("<p>hi</p>".into()),
+            ]
+        );
+    }
+}
@@ -47,7 +47,7 @@
 //!     }
 //! }
 //! ```
-use crate::time;
+use crate::time::{Clock, SystemClock};
 
 pub use crate::{
     media::{Media, MediaType},
@@ -58,7 +58,7 @@ use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{collections::HashMap, fmt};
+use std::{borrow::Cow, collections::HashMap, fmt};
 use uuid::Uuid;
 
 /// Represents the different channels in the Jupyter messaging protocol.
@@ -85,7 +85,7 @@ use uuid::Uuid;
 ///     _ => println!("Using another channel"),
 /// }
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Channel {
     /// Used for request/reply-style messages.
@@ -100,9 +100,23 @@ pub enum Channel {
     Heartbeat,
 }
 
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Channel::Shell => "shell",
+            Channel::Control => "control",
+            Channel::Stdin => "stdin",
+            Channel::IOPub => "iopub",
+            Channel::Heartbeat => "heartbeat",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct UnknownJupyterMessage {
     pub header: Header,
+    #[serde(default, deserialize_with = "deserialize_parent_header")]
     pub parent_header: Option<Header>,
     pub metadata: Value,
     pub content: Value,
@@ -168,6 +182,25 @@ where
     }
 }
 
+/// Deserializes the `parent_header` of a `JupyterMessage`.
+///
+/// The inverse of `serialize_parent_header`: an empty object (or `null`) becomes `None`.
+/// Messages without a parent are sent with `"parent_header": {}`, which isn't a
+/// valid `Header`, so without this they couldn't be read back.
+fn deserialize_parent_header<'de, D>(deserializer: D) -> Result<Option<Header>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<Value>::deserialize(deserializer)?;
+    match value {
+        None => Ok(None),
+        Some(Value::Object(map)) if map.is_empty() => Ok(None),
+        Some(value) => Header::deserialize(value)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
 /// A message in the Jupyter protocol format.
 ///
 /// A Jupyter message consists of several parts:
@@ -177,7 +210,8 @@ where
 /// - `metadata`: Additional metadata as JSON
 /// - `content`: The main message content
 /// - `buffers`: Binary buffers for messages that need them (not serialized)
-/// - `channel`: The communication channel this message belongs to
+/// - `channel`: The communication channel this message belongs to. ZeroMQ
+///   sockets imply it, but the WebSocket protocol sends it with each message.
 ///
 /// # Example
 ///
@@ -215,29 +249,111 @@ where
 ///     Some(&parent),
 /// );
 /// ```
-
-#[derive(Deserialize, Serialize, Clone)]
+///
+/// Serialized messages are read back by their header's `msg_type`, so
+/// [`UnknownMessage`]s and messages whose content could pass for another type
+/// survive dumps and replays. The `channel` is kept as recorded, even one the
+/// message type never travels on; connections check it against
+/// [`routing`](crate::routing), so dumps of misrouted traffic still load.
+#[derive(Clone)]
 pub struct JupyterMessage {
-    #[serde(skip_serializing, skip_deserializing)]
     pub zmq_identities: Vec<Bytes>,
     pub header: Header,
-    #[serde(serialize_with = "serialize_parent_header")]
     pub parent_header: Option<Header>,
     pub metadata: Value,
     pub content: JupyterMessageContent,
-    #[serde(skip_serializing, skip_deserializing)]
     pub buffers: Vec<Bytes>,
     pub channel: Option<Channel>,
 }
 
+/// How a [`JupyterMessage`] is serialized, without the ZeroMQ identities and
+/// buffers.
+#[derive(Serialize)]
+struct SerializedMessage<'a> {
+    header: &'a Header,
+    #[serde(serialize_with = "serialize_parent_header")]
+    parent_header: &'a Option<Header>,
+    metadata: &'a Value,
+    content: &'a JupyterMessageContent,
+    channel: &'a Option<Channel>,
+}
+
+/// A serialized [`JupyterMessage`] before its content is parsed by type.
+#[derive(Deserialize)]
+struct DeserializedMessage {
+    header: Header,
+    #[serde(default, deserialize_with = "deserialize_parent_header")]
+    parent_header: Option<Header>,
+    metadata: Value,
+    content: Value,
+    #[serde(default)]
+    channel: Option<Channel>,
+}
+
+impl Serialize for JupyterMessage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let header = match self.unknown_msg_type() {
+            Some(msg_type) => Cow::Owned(Header {
+                msg_type: msg_type.to_string(),
+                ..self.header.clone()
+            }),
+            None => Cow::Borrowed(&self.header),
+        };
+        SerializedMessage {
+            header: &header,
+            parent_header: &self.parent_header,
+            metadata: &self.metadata,
+            content: &self.content,
+            channel: &self.channel,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for JupyterMessage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let message = DeserializedMessage::deserialize(deserializer)?;
+        let content =
+            JupyterMessageContent::from_type_and_content(&message.header.msg_type, message.content)
+                .map_err(serde::de::Error::custom)?;
+        Ok(JupyterMessage {
+            zmq_identities: Vec::new(),
+            header: message.header,
+            parent_header: message.parent_header,
+            metadata: message.metadata,
+            content,
+            buffers: Vec::new(),
+            channel: message.channel,
+        })
+    }
+}
+
 impl JupyterMessage {
     pub fn new(
         content: impl Into<JupyterMessageContent>,
         parent: Option<&JupyterMessage>,
     ) -> JupyterMessage {
-        // Normally a session ID is per client. A higher level wrapper on this API
-        // should probably create messages based on a `Session` struct that is stateful.
-        // For now, a user can create a message and then set the session ID directly.
+        Self::new_with_clock(content, parent, &SystemClock)
+    }
+
+    /// Create a new message, taking the header's `date` from `clock` rather than the
+    /// system time.
+    ///
+    /// This is useful for producing deterministic headers in tests and for replaying
+    /// captured messages with their original timestamps.
+    pub fn new_with_clock(
+        content: impl Into<JupyterMessageContent>,
+        parent: Option<&JupyterMessage>,
+        clock: &dyn Clock,
+    ) -> JupyterMessage {
+        // Without a `Session`, replies join their parent's session and anything else
+        // gets a fresh one. `Session::message` and `Session::reply_to` set it properly.
         let session = match parent {
             Some(parent) => parent.header.session.clone(),
             None => Uuid::new_v4().to_string(),
@@ -249,7 +365,7 @@ impl JupyterMessage {
             msg_id: Uuid::new_v4().to_string(),
             username: "runtimelib".to_string(),
             session,
-            date: time::utc_now(),
+            date: clock.now(),
             msg_type: content.message_type().to_owned(),
             version: "5.3".to_string(),
         };
@@ -282,6 +398,11 @@ impl JupyterMessage {
         self
     }
 
+    pub fn with_channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
     pub fn with_zmq_identities(mut self, zmq_identities: Vec<Bytes>) -> Self {
         self.zmq_identities = zmq_identities;
         self
@@ -296,6 +417,44 @@ impl JupyterMessage {
         self.content.message_type()
     }
 
+    /// The `msg_type` of [`UnknownMessage`] content, if the header disagrees.
+    /// Content swapped in after the message was created, like an
+    /// [`UnknownMessage::reply`], knows its type better than the header does.
+    fn unknown_msg_type(&self) -> Option<&str> {
+        match &self.content {
+            JupyterMessageContent::UnknownMessage(unknown)
+                if !unknown.msg_type.is_empty() && unknown.msg_type != self.header.msg_type =>
+            {
+                Some(&unknown.msg_type)
+            }
+            _ => None,
+        }
+    }
+
+    /// Make the header's `msg_type` match [`UnknownMessage`] content, as it's
+    /// serialized. Called when a [`Session`](crate::Session) stamps a message
+    /// for sending.
+    pub(crate) fn sync_msg_type(&mut self) {
+        if let Some(msg_type) = self.unknown_msg_type() {
+            self.header.msg_type = msg_type.to_string();
+        }
+    }
+
+    /// A copy of this message that is safe to log or record: secret
+    /// [`InputReply`] values are replaced with [`REDACTED`](crate::input::REDACTED).
+    ///
+    /// Replies received off the wire aren't marked secret; use an
+    /// [`InputRedactor`](crate::input::InputRedactor) to catch those.
+    pub fn redacted(&self) -> JupyterMessage {
+        let mut message = self.clone();
+        if let JupyterMessageContent::InputReply(reply) = &message.content {
+            if reply.secret {
+                message.content = JupyterMessageContent::InputReply(reply.redacted());
+            }
+        }
+        message
+    }
+
     pub fn from_value(message: Value) -> Result<JupyterMessage, anyhow::Error> {
         let message = serde_json::from_value::<UnknownJupyterMessage>(message)?;
 
@@ -348,10 +507,16 @@ impl fmt::Debug for JupyterMessage {
             "Metadata: {}",
             serde_json::to_string_pretty(&self.metadata).unwrap()
         )?;
+        let content = match &self.content {
+            JupyterMessageContent::InputReply(reply) if reply.secret => {
+                JupyterMessageContent::InputReply(reply.redacted())
+            }
+            _ => self.content.clone(),
+        };
         writeln!(
             f,
             "Content: {}\n",
-            serde_json::to_string_pretty(&self.content).unwrap()
+            serde_json::to_string_pretty(&content).unwrap()
         )?;
         Ok(())
     }
@@ -384,6 +549,7 @@ pub enum JupyterMessageContent {
     InspectRequest(InspectRequest),
     InterruptReply(InterruptReply),
     InterruptRequest(InterruptRequest),
+    IopubWelcome(IopubWelcome),
     IsCompleteReply(IsCompleteReply),
     IsCompleteRequest(IsCompleteRequest),
     // This field is much larger than the most frequent ones
@@ -425,6 +591,7 @@ impl JupyterMessageContent {
             JupyterMessageContent::InspectRequest(_) => "inspect_request",
             JupyterMessageContent::InterruptReply(_) => "interrupt_reply",
             JupyterMessageContent::InterruptRequest(_) => "interrupt_request",
+            JupyterMessageContent::IopubWelcome(_) => "iopub_welcome",
             JupyterMessageContent::IsCompleteReply(_) => "is_complete_reply",
             JupyterMessageContent::IsCompleteRequest(_) => "is_complete_request",
             JupyterMessageContent::KernelInfoReply(_) => "kernel_info_reply",
@@ -527,6 +694,10 @@ impl JupyterMessageContent {
                 serde_json::from_value(content)?,
             )),
 
+            "iopub_welcome" => Ok(JupyterMessageContent::IopubWelcome(serde_json::from_value(
+                content,
+            )?)),
+
             "is_complete_reply" => Ok(JupyterMessageContent::IsCompleteReply(
                 serde_json::from_value(content)?,
             )),
@@ -587,9 +758,7 @@ macro_rules! impl_message_traits {
                 ///   content: serde_json::json!({ "key": "value" }),
                 /// }, None);
                 ///
-                #[doc = concat!("let child_message = ", stringify!($name), "{\n")]
-                ///   ..Default::default()
-                /// }.as_child_of(&parent_message);
+                #[doc = concat!("let child_message = ", stringify!($name), "::default().as_child_of(&parent_message);\n")]
                 ///
                 /// // Next you would send the `child_message` over the connection
                 ///
@@ -605,7 +774,6 @@ macro_rules! impl_message_traits {
                 #[doc = concat!("Create a new `JupyterMessage` for a `", stringify!($name), "`.\n\n")]
                 /// ⚠️ If you use this method with `runtimelib`, you must set the zmq identities yourself. If you
                 /// have a message that "caused" your message to be sent, use that message with `as_child_of` instead.
-                #[must_use]
                 fn from(content: $name) -> Self {
                     JupyterMessage::new(content, None)
                 }
@@ -613,7 +781,6 @@ macro_rules! impl_message_traits {
 
             impl From<$name> for JupyterMessageContent {
                 #[doc = concat!("Create a new `JupyterMessageContent` for a `", stringify!($name), "`.\n\n")]
-                #[must_use]
                 fn from(content: $name) -> Self {
                     JupyterMessageContent::$name(content)
                 }
@@ -653,6 +820,7 @@ impl_message_traits!(
     InspectRequest,
     InterruptReply,
     InterruptRequest,
+    IopubWelcome,
     IsCompleteReply,
     IsCompleteRequest,
     // KernelInfoReply, // special case due to boxing
@@ -727,7 +895,6 @@ impl From<HistoryRequest> for JupyterMessage {
     /// Create a new `JupyterMessage` for a `HistoryRequest`.
     /// ⚠️ If you use this method with `runtimelib`, you must set the zmq identities yourself. If you
     /// have a message that "caused" your message to be sent, use that message with `as_child_of` instead.
-    #[must_use]
     fn from(content: HistoryRequest) -> Self {
         JupyterMessage::new(content, None)
     }
@@ -735,7 +902,6 @@ impl From<HistoryRequest> for JupyterMessage {
 
 impl From<HistoryRequest> for JupyterMessageContent {
     /// Create a new `JupyterMessageContent` for a `HistoryRequest`.
-    #[must_use]
     fn from(content: HistoryRequest) -> Self {
         JupyterMessageContent::HistoryRequest(content)
     }
@@ -800,6 +966,65 @@ pub struct ReplyError {
     pub traceback: Vec<String>,
 }
 
+impl ReplyError {
+    pub fn new(
+        ename: impl Into<String>,
+        evalue: impl Into<String>,
+        traceback: Vec<String>,
+    ) -> Self {
+        Self {
+            ename: ename.into(),
+            evalue: evalue.into(),
+            traceback,
+        }
+    }
+}
+
+/// Adds an `error` constructor to replies, which flatten their [`ReplyError`]
+/// into the content next to an `error` status.
+macro_rules! impl_error_reply {
+    ($($name:ident),*) => {
+        $(
+            impl $name {
+                #[doc = concat!("An `error` status `", stringify!($name), "`, with `ename`, `evalue` and `traceback` in the content and the other fields empty.")]
+                ///
+                /// ```rust
+                #[doc = concat!("use jupyter_protocol::", stringify!($name), ";\n")]
+                ///
+                #[doc = concat!("let reply = ", stringify!($name), "::error(\"NameError\", \"name 'x' is not defined\", vec![]);\n")]
+                /// let content = serde_json::to_value(&reply).unwrap();
+                /// assert_eq!(content["status"], "error");
+                /// assert_eq!(content["ename"], "NameError");
+                /// ```
+                // Some replies have nothing besides `status` and `error`
+                #[allow(clippy::needless_update)]
+                pub fn error(
+                    ename: impl Into<String>,
+                    evalue: impl Into<String>,
+                    traceback: Vec<String>,
+                ) -> Self {
+                    Self {
+                        status: ReplyStatus::Error.into(),
+                        error: Some(Box::new(ReplyError::new(ename, evalue, traceback))),
+                        ..Default::default()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_error_reply!(
+    CommInfoReply,
+    CompleteReply,
+    ExecuteReply,
+    HistoryReply,
+    InputReply,
+    InspectReply,
+    InterruptReply,
+    ShutdownReply
+);
+
 /// Clear output of a single cell / output area.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct ClearOutput {
@@ -856,6 +1081,23 @@ impl ExecuteRequest {
             ..Default::default()
         }
     }
+
+    /// A request that only evaluates `user_expressions`, for asking the kernel
+    /// about its state (the working directory, `sys.path`, package versions)
+    /// without running code the user would see.
+    ///
+    /// It's silent and skips the history, so it publishes no output and
+    /// doesn't bump the execution count.
+    pub fn probe(user_expressions: HashMap<String, String>) -> Self {
+        Self {
+            code: String::new(),
+            silent: true,
+            store_history: false,
+            user_expressions: Some(user_expressions),
+            allow_stdin: false,
+            stop_on_error: false,
+        }
+    }
 }
 
 impl Default for ExecuteRequest {
@@ -885,7 +1127,8 @@ pub struct ExecuteReply {
 
     #[serde(default)]
     pub payload: Vec<Payload>,
-    pub user_expressions: Option<HashMap<String, String>>,
+    /// The results of the request's `user_expressions`, by name.
+    pub user_expressions: Option<HashMap<String, ExpressionResult>>,
 
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub error: Option<Box<ReplyError>>,
@@ -902,6 +1145,77 @@ impl Default for ExecuteReply {
     }
 }
 
+impl ExecuteReply {
+    /// The result of the user expression called `name`, if the kernel evaluated it.
+    pub fn user_expression(&self, name: &str) -> Option<&ExpressionResult> {
+        self.user_expressions.as_ref()?.get(name)
+    }
+}
+
+/// The result of evaluating one of an `execute_request`'s `user_expressions`.
+///
+/// Kernels evaluate each expression after the code runs and reply with a
+/// MIME bundle, like an `execute_result`, or the error it raised.
+///
+/// ```rust
+/// use jupyter_protocol::{ExecuteReply, ExpressionResult};
+///
+/// let reply: ExecuteReply = serde_json::from_value(serde_json::json!({
+///     "status": "ok",
+///     "execution_count": 3,
+///     "user_expressions": {
+///         "cwd": {"status": "ok", "data": {"text/plain": "'/home/user'"}, "metadata": {}},
+///         "oops": {"status": "error", "ename": "NameError", "evalue": "name 'x' is not defined", "traceback": []}
+///     }
+/// }))
+/// .unwrap();
+///
+/// assert_eq!(reply.user_expression("cwd").unwrap().plain_text(), Some("'/home/user'"));
+/// assert!(matches!(reply.user_expression("oops"), Some(ExpressionResult::Error(_))));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ExpressionResult {
+    Ok {
+        data: Media,
+        #[serde(default)]
+        metadata: serde_json::Map<String, Value>,
+    },
+    Error(ErrorOutput),
+}
+
+impl ExpressionResult {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, ExpressionResult::Ok { .. })
+    }
+
+    /// The `text/plain` representation of a successful result, e.g. Python's `repr`.
+    pub fn plain_text(&self) -> Option<&str> {
+        let ExpressionResult::Ok { data, .. } = self else {
+            return None;
+        };
+        data.content.iter().find_map(|media_type| match media_type {
+            MediaType::Plain(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl From<Media> for ExpressionResult {
+    fn from(data: Media) -> Self {
+        ExpressionResult::Ok {
+            data,
+            metadata: Default::default(),
+        }
+    }
+}
+
+impl From<ErrorOutput> for ExpressionResult {
+    fn from(error: ErrorOutput) -> Self {
+        ExpressionResult::Error(error)
+    }
+}
+
 /// Payloads are a way to trigger frontend actions from the kernel.
 /// They are stated as deprecated, however they are in regular use via `?` in IPython
 ///
@@ -945,6 +1259,7 @@ pub struct KernelInfoReply {
     pub implementation_version: String,
     pub language_info: LanguageInfo,
     pub banner: String,
+    #[serde(default)]
     pub help_links: Vec<HelpLink>,
     #[serde(default = "default_debugger")]
     pub debugger: bool,
@@ -986,18 +1301,29 @@ impl CodeMirrorMode {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The kernel's language, from its `kernel_info_reply`.
+///
+/// Only the name, version, mimetype and file extension are required by the
+/// protocol. Kernels like IRkernel, IJulia, evcxr and xeus-cling leave out
+/// some or all of the rest.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct LanguageInfo {
     pub name: String,
     pub version: String,
     pub mimetype: String,
     pub file_extension: String,
-    pub pygments_lexer: String,
-    pub codemirror_mode: CodeMirrorMode,
-    pub nbconvert_exporter: String,
+    /// Pygments lexer for highlighting, when it differs from `name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pygments_lexer: Option<String>,
+    /// CodeMirror mode for editing, when it differs from `name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codemirror_mode: Option<CodeMirrorMode>,
+    /// nbconvert exporter for notebooks in this language, if not the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbconvert_exporter: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct HelpLink {
     pub text: String,
     pub url: String,
@@ -1089,6 +1415,15 @@ pub struct Transient {
     pub display_id: Option<String>,
 }
 
+impl Transient {
+    /// Transient data naming the output's display id, so it can be updated later.
+    pub fn new(display_id: impl Into<String>) -> Self {
+        Self {
+            display_id: Some(display_id.into()),
+        }
+    }
+}
+
 /// A `display_data` message on the `iopub` channel.
 ///
 /// See [Display Data](https://jupyter-client.readthedocs.io/en/latest/messaging.html#display-data).
@@ -1172,9 +1507,7 @@ impl UpdateDisplayData {
         Self {
             data,
             metadata: Default::default(),
-            transient: Transient {
-                display_id: Some(display_id.to_string()),
-            },
+            transient: Transient::new(display_id),
         }
     }
 }
@@ -1226,6 +1559,7 @@ pub struct ExecuteResult {
     pub execution_count: ExecutionCount,
     pub data: Media,
     pub metadata: serde_json::Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub transient: Option<Transient>,
 }
 impl Default for ExecuteResult {
@@ -1274,6 +1608,22 @@ pub struct ErrorOutput {
     pub traceback: Vec<String>,
 }
 
+impl ErrorOutput {
+    /// The traceback with ANSI escape codes removed, one frame per line.
+    pub fn traceback_plain(&self) -> String {
+        crate::ansi::strip_ansi(&self.traceback.join("\n"))
+    }
+
+    /// The traceback as an HTML `<pre>` block, with ANSI colors converted to
+    /// nbconvert-compatible `ansi-*` classes.
+    pub fn traceback_html(&self) -> String {
+        format!(
+            "<pre>{}</pre>",
+            crate::ansi::ansi_to_html(&self.traceback.join("\n"))
+        )
+    }
+}
+
 /// A `comm_open` message on the `iopub` channel.
 ///
 /// See [Comm Open](https://jupyter-client.readthedocs.io/en/latest/messaging.html#opening-a-comm).
@@ -1351,9 +1701,29 @@ impl Default for CommMsg {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CommInfoRequest {
+    /// Optional in the protocol, and empty when a client leaves it out
+    #[serde(default)]
     pub target_name: String,
 }
 
+impl CommInfoRequest {
+    /// Ask for the comms open with `target_name`.
+    ///
+    /// Kernels treat an empty target name differently: some list every comm,
+    /// ipykernel lists none. Name the target to get consistent replies.
+    pub fn for_target(target_name: impl Into<String>) -> Self {
+        Self {
+            target_name: target_name.into(),
+        }
+    }
+
+    /// Whether a comm with `target_name` belongs in the reply. An empty
+    /// request target matches every comm.
+    pub fn matches(&self, target_name: &str) -> bool {
+        self.target_name.is_empty() || self.target_name == target_name
+    }
+}
+
 #[derive(Eq, Hash, PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct CommId(pub String);
 
@@ -1369,11 +1739,19 @@ impl From<String> for CommId {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct CommInfo {
     pub target_name: String,
 }
 
+impl CommInfo {
+    pub fn new(target_name: impl Into<String>) -> Self {
+        Self {
+            target_name: target_name.into(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CommInfoReply {
     pub status: ReplyStatus,
@@ -1392,6 +1770,51 @@ impl Default for CommInfoReply {
     }
 }
 
+impl CommInfoReply {
+    /// The reply to `request` from the `(comm_id, target_name)` pairs of the
+    /// comms a kernel has open, keeping the ones the request asks for.
+    ///
+    /// ```rust
+    /// use jupyter_protocol::{CommId, CommInfoReply, CommInfoRequest};
+    ///
+    /// let open = vec![
+    ///     (CommId("a".into()), "jupyter.widget".to_string()),
+    ///     (CommId("b".into()), "my_comm".to_string()),
+    /// ];
+    /// let reply = CommInfoReply::for_request(&CommInfoRequest::for_target("my_comm"), open);
+    /// assert_eq!(reply.comm_ids_for("my_comm"), vec![&CommId("b".into())]);
+    /// assert!(reply.comm_ids_for("jupyter.widget").is_empty());
+    /// ```
+    pub fn for_request<T: Into<String>>(
+        request: &CommInfoRequest,
+        comms: impl IntoIterator<Item = (CommId, T)>,
+    ) -> Self {
+        let comms = comms
+            .into_iter()
+            .map(|(comm_id, target_name)| (comm_id, CommInfo::new(target_name)))
+            .filter(|(_, info)| request.matches(&info.target_name))
+            .collect();
+        Self {
+            comms,
+            ..Default::default()
+        }
+    }
+
+    /// The comms in the reply open with `target_name`, sorted by id. Filters
+    /// again on the client side, since not every kernel honors the request's
+    /// target.
+    pub fn comm_ids_for(&self, target_name: &str) -> Vec<&CommId> {
+        let mut comm_ids: Vec<&CommId> = self
+            .comms
+            .iter()
+            .filter(|(_, info)| info.target_name == target_name)
+            .map(|(comm_id, _)| comm_id)
+            .collect();
+        comm_ids.sort_by(|a, b| a.0.cmp(&b.0));
+        comm_ids
+    }
+}
+
 /// A `comm_close` message on the `iopub` channel.
 ///
 /// Since comms live on both sides, when a comm is destroyed the other side must
@@ -1437,9 +1860,14 @@ pub struct InterruptRequest {}
 /// This message is sent by the kernel in response to an `InterruptRequest`.
 /// It indicates whether the interrupt was successful.
 ///
+/// Kernels implementing protocol 5.5 and later always include `status`, older
+/// ones may reply with an empty dict. Use [`InterruptReply::status_for_version`]
+/// to tell an acknowledgement from an old kernel apart from a malformed reply.
+///
 /// See <https://jupyter-client.readthedocs.io/en/latest/messaging.html#kernel-interrupt>
 pub struct InterruptReply {
-    pub status: ReplyStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ReplyStatus>,
 
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub error: Option<Box<ReplyError>>,
@@ -1454,10 +1882,42 @@ impl Default for InterruptReply {
 impl InterruptReply {
     pub fn new() -> Self {
         Self {
-            status: ReplyStatus::Ok,
+            status: Some(ReplyStatus::Ok),
             error: None,
         }
     }
+
+    /// Whether kernels speaking `protocol_version` must include `status`.
+    pub fn expects_status(protocol_version: &str) -> bool {
+        let mut parts = protocol_version
+            .split('.')
+            .map(|part| part.trim().parse::<u32>().unwrap_or(0));
+        let major = parts.next().unwrap_or(0);
+        let minor = parts.next().unwrap_or(0);
+        (major, minor) >= (5, 5)
+    }
+
+    /// The status of a reply from a kernel speaking `protocol_version`, which
+    /// is the `version` in the reply's header.
+    ///
+    /// A reply without `status` from a kernel older than 5.5 is an `ok`, as
+    /// that's all those kernels could send. From a newer kernel it's `None`,
+    /// as the kernel didn't say whether the interrupt worked.
+    ///
+    /// ```rust
+    /// use jupyter_protocol::{InterruptReply, ReplyStatus};
+    ///
+    /// let reply: InterruptReply = serde_json::from_str("{}").unwrap();
+    /// assert_eq!(reply.status_for_version("5.3"), Some(ReplyStatus::Ok));
+    /// assert_eq!(reply.status_for_version("5.5"), None);
+    /// ```
+    pub fn status_for_version(&self, protocol_version: &str) -> Option<ReplyStatus> {
+        match &self.status {
+            Some(status) => Some(status.clone()),
+            None if Self::expects_status(protocol_version) => None,
+            None => Some(ReplyStatus::Ok),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -1469,6 +1929,8 @@ impl InterruptReply {
 /// See <https://jupyter-client.readthedocs.io/en/latest/messaging.html#kernel-shutdown>
 pub struct ShutdownReply {
     pub restart: bool,
+    /// IRkernel replies without a status
+    #[serde(default)]
     pub status: ReplyStatus,
 
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
@@ -1504,19 +1966,32 @@ impl Default for InputRequest {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 /// Reply to an input request.
 ///
 /// This message is sent by the frontend in response to an `InputRequest`.
 /// It contains the user's input.
 ///
+/// Replies to password prompts should be marked as [`secret`](InputReply::secret)
+/// so that `Debug` output and [`JupyterMessage::redacted`] never include the value.
+///
+/// Build replies with [`InputReply::new`] or [`InputReply::secret`]; more local-only
+/// fields may be added.
+///
 /// See <https://jupyter-client.readthedocs.io/en/latest/messaging.html#messages-on-the-stdin-router-dealer-channel>
+#[non_exhaustive]
 pub struct InputReply {
     pub value: String,
 
+    /// `jupyter_client` sends only the value, so a missing status is `ok`
+    #[serde(default)]
     pub status: ReplyStatus,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub error: Option<Box<ReplyError>>,
+
+    /// Whether `value` answers a password prompt. Local only, never sent on the wire.
+    #[serde(skip)]
+    pub secret: bool,
 }
 impl Default for InputReply {
     fn default() -> Self {
@@ -1524,10 +1999,61 @@ impl Default for InputReply {
             value: String::new(),
             status: ReplyStatus::Ok,
             error: None,
+            secret: false,
         }
     }
 }
 
+impl InputReply {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A reply to a password prompt, which is redacted from logs and recordings.
+    pub fn secret(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            secret: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.secret
+    }
+
+    /// A copy with the value replaced by [`REDACTED`](crate::input::REDACTED) if it is secret.
+    pub fn redacted(&self) -> Self {
+        if self.secret {
+            Self {
+                value: crate::input::REDACTED.to_string(),
+                ..self.clone()
+            }
+        } else {
+            self.clone()
+        }
+    }
+}
+
+impl fmt::Debug for InputReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = if self.secret {
+            crate::input::REDACTED
+        } else {
+            self.value.as_str()
+        };
+        f.debug_struct("InputReply")
+            .field("value", &value)
+            .field("status", &self.status)
+            .field("error", &self.error)
+            .field("secret", &self.secret)
+            .finish()
+    }
+}
+
 /// A `inspect_request` message on the `shell` channel.
 ///
 /// Code can be inspected to show useful information to the user.
@@ -1665,7 +2191,8 @@ pub struct IsCompleteReply {
     /// If status is 'incomplete', indent should contain the characters to use
     /// to indent the next line. This is only a hint: frontends may ignore it
     /// and use their own autoindentation rules. For other statuses, this
-    /// field does not exist.
+    /// field does not exist, and IRkernel leaves it out even then.
+    #[serde(default)]
     pub indent: String,
 }
 impl Default for IsCompleteReply {
@@ -1782,11 +2309,21 @@ pub struct IsCompleteRequest {
     pub code: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// The `execution_state` of a [`Status`] message.
+///
+/// Kernels send `starting`, `busy`, and `idle`. Jupyter servers also relay the
+/// lifecycle states (`restarting`, `autorestarting`, `terminating`, `dead`) to
+/// websocket clients over the same message.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ExecutionState {
     Busy,
     Idle,
+    Starting,
+    Restarting,
+    Autorestarting,
+    Terminating,
+    Dead,
 }
 
 impl ExecutionState {
@@ -1794,6 +2331,11 @@ impl ExecutionState {
         match self {
             ExecutionState::Busy => "busy",
             ExecutionState::Idle => "idle",
+            ExecutionState::Starting => "starting",
+            ExecutionState::Restarting => "restarting",
+            ExecutionState::Autorestarting => "autorestarting",
+            ExecutionState::Terminating => "terminating",
+            ExecutionState::Dead => "dead",
         }
     }
 }
@@ -1825,6 +2367,24 @@ impl Status {
             execution_state: ExecutionState::Idle,
         }
     }
+
+    pub fn starting() -> Self {
+        Self {
+            execution_state: ExecutionState::Starting,
+        }
+    }
+}
+
+/// Sent on iopub when a client subscribes, so it knows its subscription is
+/// live and it won't miss replies' side effects. Proposed for protocol 5.4 in
+/// [JEP 65](https://github.com/jupyter/enhancement-proposals/pull/65).
+///
+/// Kernels publishing from a plain `PUB` socket can't see subscriptions and
+/// send it to everyone, so clients should expect welcomes meant for others.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct IopubWelcome {
+    /// The topic prefix subscribed to, empty for everything.
+    pub subscription: String,
 }
 
 #[cfg(test)]
@@ -2017,6 +2577,41 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_error_replies() {
+        let reply = ExecuteReply {
+            execution_count: ExecutionCount::new(4),
+            ..ExecuteReply::error(
+                "ZeroDivisionError",
+                "division by zero",
+                vec!["ZeroDivisionError: division by zero".to_string()],
+            )
+        };
+        assert_eq!(
+            serde_json::to_value(&reply).unwrap(),
+            json!({
+                "status": "error",
+                "execution_count": 4,
+                "payload": [],
+                "user_expressions": null,
+                "ename": "ZeroDivisionError",
+                "evalue": "division by zero",
+                "traceback": ["ZeroDivisionError: division by zero"]
+            })
+        );
+
+        let reply = CompleteReply::error("KeyError", "'x'", vec![]);
+        assert!(reply.matches.is_empty());
+        assert_eq!(reply.status, ReplyStatus::Error);
+
+        // Interrupt replies may leave out their status, but errors say so
+        let reply = InterruptReply::error("RuntimeError", "no", vec![]);
+        assert_eq!(reply.status, Some(ReplyStatus::Error));
+        let round_trip: InterruptReply =
+            serde_json::from_value(serde_json::to_value(&reply).unwrap()).unwrap();
+        assert_eq!(round_trip.error.unwrap().ename, "RuntimeError");
+    }
+
     #[test]
     fn test_enum_variant_sizes() {
         size_of_variant!(ClearOutput);
@@ -2043,6 +2638,7 @@ mod test {
         size_of_variant!(InspectRequest);
         size_of_variant!(InterruptReply);
         size_of_variant!(InterruptRequest);
+        size_of_variant!(IopubWelcome);
         size_of_variant!(IsCompleteReply);
         size_of_variant!(IsCompleteRequest);
         size_of_variant!(Box<KernelInfoReply>);
@@ -2079,6 +2675,81 @@ mod test {
         assert!(parent_header.as_object().unwrap().is_empty());
     }
 
+    #[test]
+    fn test_jupyter_message_without_parent_round_trips() {
+        let message = JupyterMessage::from(ExecuteRequest::new("1 + 1".to_string()));
+
+        let serialized_message = serde_json::to_value(&message).unwrap();
+        let deserialized_message = JupyterMessage::from_value(serialized_message).unwrap();
+
+        assert!(deserialized_message.parent_header.is_none());
+        assert_eq!(deserialized_message.header.msg_id, message.header.msg_id);
+    }
+
+    #[test]
+    fn test_jupyter_message_parent_header_deserialization() {
+        let parent = JupyterMessage::from(ExecuteRequest::new("1 + 1".to_string()));
+        let child = StreamContent::stdout("2").as_child_of(&parent);
+        let serialized = serde_json::to_value(&child).unwrap();
+
+        let with_parent_header = |parent_header: Option<Value>| {
+            let mut value = serialized.clone();
+            match parent_header {
+                Some(parent_header) => value["parent_header"] = parent_header,
+                None => {
+                    value.as_object_mut().unwrap().remove("parent_header");
+                }
+            }
+            value
+        };
+
+        // Both ways of reading a message treat a missing parent the same
+        let parse = |value: Value| -> Vec<Option<String>> {
+            let via_from_value = JupyterMessage::from_value(value.clone())
+                .map(|message| message.parent_header.map(|header| header.msg_id));
+            let via_serde = serde_json::from_value::<JupyterMessage>(value)
+                .map(|message| message.parent_header.map(|header| header.msg_id));
+            vec![via_from_value.unwrap(), via_serde.unwrap()]
+        };
+
+        assert_eq!(
+            parse(serialized.clone()),
+            vec![Some(parent.header.msg_id.clone()); 2]
+        );
+        assert_eq!(parse(with_parent_header(Some(json!({})))), vec![None, None]);
+        assert_eq!(
+            parse(with_parent_header(Some(Value::Null))),
+            vec![None, None]
+        );
+        assert_eq!(parse(with_parent_header(None)), vec![None, None]);
+
+        // A parent header that isn't empty has to be a whole header
+        let partial = with_parent_header(Some(json!({ "msg_id": "abc" })));
+        assert!(JupyterMessage::from_value(partial.clone()).is_err());
+        assert!(serde_json::from_value::<JupyterMessage>(partial).is_err());
+    }
+
+    #[test]
+    fn test_error_output_traceback_conversion() {
+        let error = ErrorOutput {
+            ename: "ZeroDivisionError".to_string(),
+            evalue: "division by zero".to_string(),
+            traceback: vec![
+                "\u{1b}[0;31m---------------------------------------------------------------------------\u{1b}[0m".to_string(),
+                "\u{1b}[0;31mZeroDivisionError\u{1b}[0m: division by zero".to_string(),
+            ],
+        };
+
+        assert_eq!(
+            error.traceback_plain(),
+            "---------------------------------------------------------------------------\nZeroDivisionError: division by zero"
+        );
+        assert_eq!(
+            error.traceback_html(),
+            "<pre><span class=\"ansi-red-fg\">---------------------------------------------------------------------------</span>\n<span class=\"ansi-red-fg\">ZeroDivisionError</span>: division by zero</pre>"
+        );
+    }
+
     #[test]
     fn test_user_expressions_serialization() {
         let request = ExecuteRequest {
@@ -2111,4 +2782,257 @@ mod test {
             request.user_expressions
         );
     }
+
+    #[test]
+    fn test_execute_reply_user_expressions() {
+        let reply = ExecuteReply {
+            user_expressions: Some(HashMap::from([
+                (
+                    "answer".to_string(),
+                    ExpressionResult::from(Media::new(vec![MediaType::Plain("49".to_string())])),
+                ),
+                (
+                    "missing".to_string(),
+                    ExpressionResult::from(ErrorOutput {
+                        ename: "NameError".to_string(),
+                        evalue: "name 'y' is not defined".to_string(),
+                        traceback: vec![],
+                    }),
+                ),
+            ])),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&reply).unwrap();
+        assert_eq!(
+            value["user_expressions"],
+            serde_json::json!({
+                "answer": {"status": "ok", "data": {"text/plain": "49"}, "metadata": {}},
+                "missing": {
+                    "status": "error",
+                    "ename": "NameError",
+                    "evalue": "name 'y' is not defined",
+                    "traceback": []
+                }
+            })
+        );
+
+        let reply: ExecuteReply = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            reply.user_expression("answer").unwrap().plain_text(),
+            Some("49")
+        );
+        assert!(!reply.user_expression("missing").unwrap().is_ok());
+        assert!(reply.user_expression("other").is_none());
+    }
+
+    #[test]
+    fn test_interrupt_reply_status_by_version() {
+        let empty =
+            JupyterMessageContent::from_type_and_content("interrupt_reply", serde_json::json!({}))
+                .unwrap();
+        let JupyterMessageContent::InterruptReply(empty) = empty else {
+            panic!("Expected an interrupt_reply");
+        };
+        assert_eq!(empty.status, None);
+        assert_eq!(serde_json::to_value(&empty).unwrap(), serde_json::json!({}));
+
+        assert!(!InterruptReply::expects_status("5.3"));
+        assert!(!InterruptReply::expects_status("4.1"));
+        assert!(InterruptReply::expects_status("5.5"));
+        assert!(InterruptReply::expects_status("5.10"));
+        assert!(InterruptReply::expects_status("6.0"));
+
+        assert_eq!(empty.status_for_version("5.4"), Some(ReplyStatus::Ok));
+        assert_eq!(empty.status_for_version("5.5"), None);
+
+        let failed: InterruptReply = serde_json::from_value(serde_json::json!({
+            "status": "error",
+            "ename": "RuntimeError",
+            "evalue": "can't interrupt",
+            "traceback": [],
+        }))
+        .unwrap();
+        assert_eq!(failed.status_for_version("5.3"), Some(ReplyStatus::Error));
+        assert_eq!(failed.error.unwrap().ename, "RuntimeError");
+    }
+
+    #[test]
+    fn test_unknown_message_json_round_trip() {
+        let message = JupyterMessage::new(
+            UnknownMessage {
+                msg_type: "custom_request".to_string(),
+                content: json!({ "key": "value" }),
+            },
+            None,
+        );
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["header"]["msg_type"], "custom_request");
+        assert_eq!(value["content"], json!({ "key": "value" }));
+
+        let read: JupyterMessage = serde_json::from_value(value).unwrap();
+        assert_eq!(read.message_type(), "custom_request");
+        let JupyterMessageContent::UnknownMessage(unknown) = read.content else {
+            panic!("Expected an UnknownMessage, got {:?}", read.content);
+        };
+        assert_eq!(unknown.content, json!({ "key": "value" }));
+
+        // Empty content no longer passes for the first message type it fits
+        let interrupt: JupyterMessage = serde_json::from_value(
+            serde_json::to_value(JupyterMessage::new(InterruptRequest {}, None)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(interrupt.message_type(), "interrupt_request");
+    }
+
+    #[test]
+    fn test_swapped_unknown_content_keeps_its_msg_type() {
+        let request = UnknownMessage {
+            msg_type: "custom_request".to_string(),
+            content: json!({}),
+        };
+        let mut message = JupyterMessage::new(request.clone(), None);
+        message.content = request.reply(json!({ "status": "ok" }));
+
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["header"]["msg_type"], "custom_reply");
+
+        let stamped = crate::Session::new().stamp(message);
+        assert_eq!(stamped.header.msg_type, "custom_reply");
+    }
+
+    #[test]
+    fn test_channel_is_serialized_and_kept() {
+        let message = JupyterMessage::new(ExecuteRequest::new("1 + 1".to_string()), None)
+            .with_channel(Channel::Shell);
+        let mut value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["channel"], "shell");
+
+        let read: JupyterMessage = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(read.channel, Some(Channel::Shell));
+
+        // Misrouted messages still load, for connections to check
+        value["channel"] = json!("iopub");
+        let read: JupyterMessage = serde_json::from_value(value).unwrap();
+        assert_eq!(read.channel, Some(Channel::IOPub));
+    }
+
+    /// `kernel_info_reply` contents as real kernels send them.
+    fn kernel_info_fixtures() -> Vec<(&'static str, Value)> {
+        vec![
+            (
+                "ipykernel",
+                json!({
+                    "status": "ok",
+                    "protocol_version": "5.3",
+                    "implementation": "ipython",
+                    "implementation_version": "8.18.1",
+                    "language_info": {
+                        "name": "python",
+                        "version": "3.11.6",
+                        "mimetype": "text/x-python",
+                        "codemirror_mode": {"name": "ipython", "version": 3},
+                        "pygments_lexer": "ipython3",
+                        "nbconvert_exporter": "python",
+                        "file_extension": ".py"
+                    },
+                    "banner": "Python 3.11.6 | IPython 8.18.1 -- An enhanced Interactive Python.",
+                    "help_links": [
+                        {"text": "Python Reference", "url": "https://docs.python.org/3.11"}
+                    ],
+                    "debugger": true
+                }),
+            ),
+            (
+                "IRkernel",
+                json!({
+                    "status": "ok",
+                    "protocol_version": "5.0",
+                    "implementation": "IRkernel",
+                    "implementation_version": "1.3.2",
+                    "language_info": {
+                        "name": "R",
+                        "codemirror_mode": "r",
+                        "pygments_lexer": "r",
+                        "mimetype": "text/x-r-source",
+                        "file_extension": ".r",
+                        "version": "4.3.2"
+                    },
+                    "banner": "R version 4.3.2 (2023-10-31)",
+                    "help_links": []
+                }),
+            ),
+            (
+                "evcxr",
+                json!({
+                    "status": "ok",
+                    "protocol_version": "5.3",
+                    "implementation": "EvCxR",
+                    "implementation_version": "0.17.0",
+                    "language_info": {
+                        "name": "Rust",
+                        "version": "",
+                        "mimetype": "text/rust",
+                        "file_extension": ".rs",
+                        "pygments_lexer": "rust",
+                        "codemirror_mode": "rust"
+                    },
+                    "banner": "EvCxR 0.17.0 - Evaluation Context for Rust",
+                    "help_links": [
+                        {"text": "Rust std docs", "url": "https://doc.rust-lang.org/std/index.html"}
+                    ]
+                }),
+            ),
+            (
+                "xeus-cling",
+                json!({
+                    "status": "ok",
+                    "protocol_version": "5.3",
+                    "implementation": "xeus-cling",
+                    "implementation_version": "0.15.3",
+                    "language_info": {
+                        "name": "C++17",
+                        "version": "17",
+                        "mimetype": "text/x-c++src",
+                        "codemirror_mode": "text/x-c++src",
+                        "file_extension": ".cpp"
+                    },
+                    "banner": "xeus-cling: a Jupyter kernel for C++",
+                    "debugger": false
+                }),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_kernel_info_from_real_kernels() {
+        for (kernel, fixture) in kernel_info_fixtures() {
+            let reply: KernelInfoReply =
+                serde_json::from_value(fixture.clone()).unwrap_or_else(|e| panic!("{kernel}: {e}"));
+            let language_info = &reply.language_info;
+            match kernel {
+                "ipykernel" => {
+                    assert_eq!(language_info.pygments_lexer.as_deref(), Some("ipython3"));
+                    assert!(matches!(
+                        language_info.codemirror_mode,
+                        Some(CodeMirrorMode::CustomMode { version: 3, .. })
+                    ));
+                    assert!(reply.debugger);
+                }
+                "IRkernel" => assert_eq!(language_info.nbconvert_exporter, None),
+                "evcxr" => assert_eq!(reply.help_links.len(), 1),
+                "xeus-cling" => {
+                    assert_eq!(language_info.pygments_lexer, None);
+                    assert!(reply.help_links.is_empty());
+                }
+                _ => unreachable!(),
+            }
+
+            // Missing fields stay missing when the reply is sent on
+            let round_trip = serde_json::to_value(&reply).unwrap();
+            assert_eq!(
+                round_trip["language_info"], fixture["language_info"],
+                "{kernel}"
+            );
+        }
+    }
 }
@@ -0,0 +1,289 @@
+//! Which channels each message type travels on, per the messaging spec.
+//!
+//! Sending a message on the wrong socket usually fails silently: a kernel
+//! ignores an `execute_request` on control, or a client never sees a reply a
+//! kernel published on iopub. That's behind many "works in Jupyter but not
+//! with my client" bugs. Routers and validators can check each message
+//! against this table and flag the mistake instead.
+//!
+//! ```rust
+//! use jupyter_protocol::routing::{self, Origin};
+//! use jupyter_protocol::Channel;
+//!
+//! assert_eq!(
+//!     routing::default_channel("execute_request", Origin::Client),
+//!     Some(Channel::Shell)
+//! );
+//! assert!(routing::check("interrupt_request", Channel::Control, Origin::Client).is_ok());
+//! assert!(routing::check("execute_request", Channel::Control, Origin::Client).is_err());
+//! assert!(routing::check("execute_reply", Channel::IOPub, Origin::Kernel).is_err());
+//!
+//! // Comms go from the client on shell, and from the kernel on iopub
+//! assert_eq!(routing::channels("comm_msg", Origin::Kernel), vec![Channel::IOPub]);
+//! ```
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Channel, JupyterError, JupyterMessage};
+
+/// The side of the connection that sends a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    Client,
+    Kernel,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Client => f.write_str("client"),
+            Origin::Kernel => f.write_str("kernel"),
+        }
+    }
+}
+
+/// A channel a message type may be sent on, and by whom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub channel: Channel,
+    pub origin: Origin,
+}
+
+const fn route(channel: Channel, origin: Origin) -> Route {
+    Route { channel, origin }
+}
+
+const CLIENT_SHELL: &[Route] = &[route(Channel::Shell, Origin::Client)];
+const KERNEL_SHELL: &[Route] = &[route(Channel::Shell, Origin::Kernel)];
+const CLIENT_CONTROL: &[Route] = &[route(Channel::Control, Origin::Client)];
+const KERNEL_CONTROL: &[Route] = &[route(Channel::Control, Origin::Kernel)];
+const KERNEL_IOPUB: &[Route] = &[route(Channel::IOPub, Origin::Kernel)];
+const COMM: &[Route] = &[
+    route(Channel::Shell, Origin::Client),
+    route(Channel::IOPub, Origin::Kernel),
+];
+
+/// Every message type in the spec and where it may go. For message types on
+/// more than one channel, the first is the one to send on.
+pub const ROUTING_TABLE: &[(&str, &[Route])] = &[
+    ("execute_request", CLIENT_SHELL),
+    ("execute_reply", KERNEL_SHELL),
+    ("inspect_request", CLIENT_SHELL),
+    ("inspect_reply", KERNEL_SHELL),
+    ("complete_request", CLIENT_SHELL),
+    ("complete_reply", KERNEL_SHELL),
+    ("history_request", CLIENT_SHELL),
+    ("history_reply", KERNEL_SHELL),
+    ("is_complete_request", CLIENT_SHELL),
+    ("is_complete_reply", KERNEL_SHELL),
+    ("comm_info_request", CLIENT_SHELL),
+    ("comm_info_reply", KERNEL_SHELL),
+    // Clients commonly ask for kernel info on control to check on a busy kernel
+    (
+        "kernel_info_request",
+        &[
+            route(Channel::Shell, Origin::Client),
+            route(Channel::Control, Origin::Client),
+        ],
+    ),
+    (
+        "kernel_info_reply",
+        &[
+            route(Channel::Shell, Origin::Kernel),
+            route(Channel::Control, Origin::Kernel),
+        ],
+    ),
+    // Sending shutdown on shell is deprecated since protocol 5.4, but still accepted
+    (
+        "shutdown_request",
+        &[
+            route(Channel::Control, Origin::Client),
+            route(Channel::Shell, Origin::Client),
+        ],
+    ),
+    // ipykernel also broadcasts the reply on iopub, so every client sees the shutdown
+    (
+        "shutdown_reply",
+        &[
+            route(Channel::Control, Origin::Kernel),
+            route(Channel::Shell, Origin::Kernel),
+            route(Channel::IOPub, Origin::Kernel),
+        ],
+    ),
+    ("interrupt_request", CLIENT_CONTROL),
+    ("interrupt_reply", KERNEL_CONTROL),
+    ("debug_request", CLIENT_CONTROL),
+    ("debug_reply", KERNEL_CONTROL),
+    ("comm_open", COMM),
+    ("comm_msg", COMM),
+    ("comm_close", COMM),
+    ("stream", KERNEL_IOPUB),
+    ("display_data", KERNEL_IOPUB),
+    ("update_display_data", KERNEL_IOPUB),
+    ("execute_input", KERNEL_IOPUB),
+    ("execute_result", KERNEL_IOPUB),
+    ("error", KERNEL_IOPUB),
+    ("status", KERNEL_IOPUB),
+    ("iopub_welcome", KERNEL_IOPUB),
+    ("clear_output", KERNEL_IOPUB),
+    ("debug_event", KERNEL_IOPUB),
+    ("input_request", &[route(Channel::Stdin, Origin::Kernel)]),
+    ("input_reply", &[route(Channel::Stdin, Origin::Client)]),
+];
+
+/// Where a message type may go, or `None` if it isn't in the spec.
+pub fn routes(msg_type: &str) -> Option<&'static [Route]> {
+    ROUTING_TABLE
+        .iter()
+        .find(|(name, _)| *name == msg_type)
+        .map(|(_, routes)| *routes)
+}
+
+/// The channels `origin` may send `msg_type` on, preferred first. Empty for
+/// message types the spec doesn't define or that `origin` never sends.
+pub fn channels(msg_type: &str, origin: Origin) -> Vec<Channel> {
+    routes(msg_type)
+        .unwrap_or_default()
+        .iter()
+        .filter(|route| route.origin == origin)
+        .map(|route| route.channel)
+        .collect()
+}
+
+/// The channel `origin` should send `msg_type` on.
+pub fn default_channel(msg_type: &str, origin: Origin) -> Option<Channel> {
+    channels(msg_type, origin).first().copied()
+}
+
+/// Whether `origin` may send `msg_type` on `channel`. Message types outside
+/// the spec, like custom extensions, are allowed anywhere.
+pub fn is_allowed(msg_type: &str, channel: Channel, origin: Origin) -> bool {
+    match routes(msg_type) {
+        Some(routes) => routes.contains(&route(channel, origin)),
+        None => true,
+    }
+}
+
+/// Whether `msg_type` travels on `channel` in either direction. Message types
+/// outside the spec may go anywhere.
+pub fn is_carried_on(msg_type: &str, channel: Channel) -> bool {
+    match routes(msg_type) {
+        Some(routes) => routes.iter().any(|route| route.channel == channel),
+        None => true,
+    }
+}
+
+/// Check a message type against the table, with an error naming the channels
+/// it belongs on.
+pub fn check(msg_type: &str, channel: Channel, origin: Origin) -> Result<(), JupyterError> {
+    if is_allowed(msg_type, channel, origin) {
+        return Ok(());
+    }
+    Err(JupyterError::UnexpectedChannel {
+        msg_type: msg_type.to_string(),
+        channel,
+        origin,
+        expected: channels(msg_type, origin),
+    })
+}
+
+impl JupyterMessage {
+    /// Check that this message may be sent by `origin` on `channel`.
+    pub fn check_channel(&self, channel: Channel, origin: Origin) -> Result<(), JupyterError> {
+        check(self.message_type(), channel, origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExecuteRequest;
+
+    #[test]
+    fn test_table_is_well_formed() {
+        for (msg_type, routes) in ROUTING_TABLE {
+            assert!(!routes.is_empty(), "{} has no routes", msg_type);
+            assert!(
+                routes
+                    .iter()
+                    .all(|route| route.channel != Channel::Heartbeat),
+                "{} routed to heartbeat",
+                msg_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_replies_follow_requests() {
+        for (msg_type, request_routes) in ROUTING_TABLE {
+            let Some(request) = msg_type.strip_suffix("_request") else {
+                continue;
+            };
+            let reply_type = format!("{}_reply", request);
+            let reply_routes = routes(&reply_type).unwrap();
+            let request_channels: Vec<Channel> =
+                request_routes.iter().map(|route| route.channel).collect();
+            let reply_channels: Vec<Channel> =
+                reply_routes.iter().map(|route| route.channel).collect();
+            // Replies go back the way the request came, and may also be
+            // broadcast on iopub
+            let (direct, broadcast) =
+                reply_channels.split_at(request_channels.len().min(reply_channels.len()));
+            assert_eq!(request_channels, direct, "{}", reply_type);
+            assert!(
+                broadcast.iter().all(|channel| *channel == Channel::IOPub),
+                "{}",
+                reply_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_names_expected_channels() {
+        let message = JupyterMessage::new(ExecuteRequest::new("1 + 1".to_string()), None);
+        assert!(message
+            .check_channel(Channel::Shell, Origin::Client)
+            .is_ok());
+
+        let error = message
+            .check_channel(Channel::Control, Origin::Client)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            JupyterError::UnexpectedChannel {
+                msg_type: "execute_request".to_string(),
+                channel: Channel::Control,
+                origin: Origin::Client,
+                expected: vec![Channel::Shell],
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "execute_request from the client is not allowed on control, expected shell"
+        );
+
+        assert_eq!(
+            check("shutdown_reply", Channel::Stdin, Origin::Kernel)
+                .unwrap_err()
+                .to_string(),
+            "shutdown_reply from the kernel is not allowed on stdin, expected control or shell or iopub"
+        );
+        assert!(check("shutdown_reply", Channel::IOPub, Origin::Kernel).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_types_are_allowed() {
+        assert!(is_allowed(
+            "usage_request",
+            Channel::Control,
+            Origin::Client
+        ));
+        assert_eq!(routes("usage_request"), None);
+        assert!(channels("usage_request", Origin::Client).is_empty());
+        // A known type sent the wrong way
+        assert!(!is_allowed("stream", Channel::IOPub, Origin::Client));
+        assert!(channels("execute_reply", Origin::Client).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! The session that a client's or kernel's messages belong to.
+//!
+//! Every message header carries a `session` id and a `username`, and all the
+//! messages one client sends, on any channel, should share them. A [`Session`]
+//! owns both and creates messages with them, so they don't have to be patched
+//! in after the fact. Clone it to share it between connections.
+//!
+//! ```rust
+//! use jupyter_protocol::{ExecuteReply, ExecuteRequest, Session};
+//!
+//! let client = Session::new().with_username("alice");
+//! let request = client.message(ExecuteRequest::new("1 + 1".to_string()));
+//! assert_eq!(request.header.session, client.id());
+//! assert_eq!(request.header.username, "alice");
+//!
+//! let kernel = Session::new();
+//! let reply = kernel.reply_to(&request, ExecuteReply::default());
+//! assert_eq!(reply.header.session, kernel.id());
+//! assert_eq!(reply.parent_header.unwrap().msg_id, request.header.msg_id);
+//! ```
+use std::fmt;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::{Clock, JupyterMessage, JupyterMessageContent, SystemClock};
+
+const DEFAULT_USERNAME: &str = "runtimelib";
+
+#[derive(Clone)]
+pub struct Session {
+    id: String,
+    username: String,
+    clock: Arc<dyn Clock>,
+}
+
+impl Session {
+    /// A new session with a random id.
+    pub fn new() -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            username: DEFAULT_USERNAME.to_string(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    pub fn with_username(mut self, username: impl Into<String>) -> Self {
+        self.username = username.into();
+        self
+    }
+
+    /// Take header dates from `clock` instead of the system time.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// A new message in this session.
+    pub fn message(&self, content: impl Into<JupyterMessageContent>) -> JupyterMessage {
+        self.stamp(JupyterMessage::new_with_clock(
+            content,
+            None,
+            self.clock.as_ref(),
+        ))
+    }
+
+    /// A reply to `parent` from this session, routed back to the same ZeroMQ identities.
+    pub fn reply_to(
+        &self,
+        parent: &JupyterMessage,
+        content: impl Into<JupyterMessageContent>,
+    ) -> JupyterMessage {
+        self.stamp(JupyterMessage::new_with_clock(
+            content,
+            Some(parent),
+            self.clock.as_ref(),
+        ))
+    }
+
+    /// Claim a message created elsewhere, e.g. with [`JupyterMessage::new`], for this session.
+    ///
+    /// [`UnknownMessage`](crate::UnknownMessage) content keeps its own `msg_type`, even
+    /// if it was swapped in after the header was made.
+    pub fn stamp(&self, mut message: JupyterMessage) -> JupyterMessage {
+        self.id.clone_into(&mut message.header.session);
+        self.username.clone_into(&mut message.header.username);
+        message.sync_msg_type();
+        message
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &self.id)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExecuteRequest, FixedClock, Status};
+
+    #[test]
+    fn test_messages_share_the_session() {
+        let date = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let session = Session::new()
+            .with_id("abc")
+            .with_username("alice")
+            .with_clock(FixedClock(date));
+
+        let first = session.message(ExecuteRequest::new("1".to_string()));
+        let second = session
+            .clone()
+            .message(ExecuteRequest::new("2".to_string()));
+        for message in [&first, &second] {
+            assert_eq!(message.header.session, "abc");
+            assert_eq!(message.header.username, "alice");
+            assert_eq!(message.header.date, date);
+        }
+        assert_ne!(first.header.msg_id, second.header.msg_id);
+    }
+
+    #[test]
+    fn test_reply_to_keeps_routing_but_not_session() {
+        let client = Session::new();
+        let kernel = Session::new().with_username("kernel");
+        let request = client
+            .message(ExecuteRequest::new("1".to_string()))
+            .with_zmq_identities(vec![bytes::Bytes::from_static(b"client-1")]);
+
+        let reply = kernel.reply_to(&request, Status::busy());
+        assert_eq!(reply.header.session, kernel.id());
+        assert_eq!(reply.header.username, "kernel");
+        assert_eq!(reply.parent_header.as_ref().unwrap().session, client.id());
+        assert_eq!(reply.zmq_identities, request.zmq_identities);
+    }
+
+    #[test]
+    fn test_stamp() {
+        let session = Session::new();
+        let message = session.stamp(ExecuteRequest::new("1".to_string()).into());
+        assert_eq!(message.header.session, session.id());
+        assert_eq!(message.header.username, DEFAULT_USERNAME);
+    }
+}
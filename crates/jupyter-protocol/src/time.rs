@@ -1,14 +1,171 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 
+//! Timestamps for message headers.
+//!
+//! Message headers carry the date they were created. By default this is the
+//! current system time, but a [`Clock`] can be supplied to
+//! [`JupyterMessage::new_with_clock`](crate::JupyterMessage::new_with_clock)
+//! so that tests produce deterministic headers and replay tooling can stamp
+//! messages with their original capture times.
+//!
+//! ```rust
+//! use jupyter_protocol::{ExecuteRequest, FixedClock, JupyterMessage};
+//!
+//! let date = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+//! let clock = FixedClock(date);
+//!
+//! let message = JupyterMessage::new_with_clock(ExecuteRequest::new("1 + 1".to_string()), None, &clock);
+//! assert_eq!(message.header.date, date);
+//! ```
+use chrono::{DateTime, Utc};
+
+use crate::{ExecutionState, JupyterMessage, JupyterMessageContent};
+
 /// Identical to chrono::Utc::now() but without the system "clock"
 /// feature flag.
 ///
 /// The "clock" feature flag pulls in the "iana-time-zone" crate
 /// which links to macOS's "CoreFoundation" framework which increases
 /// startup time for the CLI.
-pub(crate) fn utc_now() -> chrono::DateTime<chrono::Utc> {
+pub(crate) fn utc_now() -> DateTime<Utc> {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .expect("system time before Unix epoch");
-    chrono::DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos()).unwrap()
+    DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos()).unwrap()
+}
+
+/// A source of timestamps for message headers.
+pub trait Clock: Send + Sync {
+    /// The current time, according to this clock.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock. This is what [`JupyterMessage::new`](crate::JupyterMessage::new) uses.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        utc_now()
+    }
+}
+
+/// A clock that always reports the same instant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime<Utc> + Send + Sync,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
+}
+
+/// When each step of one execution happened, from the headers of its messages.
+///
+/// These are the timestamps JupyterLab stores in a cell's `execution` metadata
+/// when `record_timing` is on, and that the jupyterlab-execute-time extension
+/// displays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionTimings {
+    /// `iopub.status.busy`: the kernel started handling the request.
+    pub busy: Option<DateTime<Utc>>,
+    /// `iopub.execute_input`: the kernel broadcast the code it's running.
+    pub execute_input: Option<DateTime<Utc>>,
+    /// `shell.execute_reply.started`: from the `started` field ipykernel puts
+    /// in the reply's metadata.
+    pub reply_started: Option<DateTime<Utc>>,
+    /// `shell.execute_reply`: the kernel replied.
+    pub reply: Option<DateTime<Utc>>,
+    /// `iopub.status.idle`: the kernel finished handling the request.
+    pub idle: Option<DateTime<Utc>>,
+}
+
+impl ExecutionTimings {
+    /// Note the time of `message` if it marks a step of the execution. Pass it
+    /// every shell and iopub message that's a child of the `execute_request`.
+    pub fn record(&mut self, message: &JupyterMessage) {
+        let date = message.header.date;
+        match &message.content {
+            JupyterMessageContent::Status(status) => match status.execution_state {
+                ExecutionState::Busy => self.busy = Some(date),
+                ExecutionState::Idle => self.idle = Some(date),
+                _ => {}
+            },
+            JupyterMessageContent::ExecuteInput(_) => self.execute_input = Some(date),
+            JupyterMessageContent::ExecuteReply(_) => {
+                self.reply = Some(date);
+                self.reply_started = message
+                    .metadata
+                    .get("started")
+                    .and_then(|started| started.as_str())
+                    .and_then(|started| DateTime::parse_from_rfc3339(started).ok())
+                    .map(|started| started.with_timezone(&Utc));
+            }
+            _ => {}
+        }
+    }
+
+    /// How long the code ran, from when the kernel started on the request to
+    /// when it replied.
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        let started = self.reply_started.or(self.execute_input).or(self.busy)?;
+        Some(self.reply? - started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExecuteInput, ExecuteReply, ExecutionCount, Status};
+
+    #[test]
+    fn test_record_execution_timings() {
+        let at = |millis: i64| DateTime::from_timestamp_millis(1_700_000_000_000 + millis).unwrap();
+        let request = JupyterMessage::new(crate::ExecuteRequest::new("1".to_string()), None);
+
+        let mut reply = JupyterMessage::new_with_clock(
+            ExecuteReply::default(),
+            Some(&request),
+            &FixedClock(at(30)),
+        );
+        reply.metadata = serde_json::json!({"started": "2023-11-14T22:13:20.005Z"});
+        let messages = [
+            JupyterMessage::new_with_clock(Status::busy(), Some(&request), &FixedClock(at(1))),
+            JupyterMessage::new_with_clock(
+                ExecuteInput {
+                    code: "1".to_string(),
+                    execution_count: ExecutionCount::new(1),
+                },
+                Some(&request),
+                &FixedClock(at(2)),
+            ),
+            reply,
+            JupyterMessage::new_with_clock(Status::idle(), Some(&request), &FixedClock(at(31))),
+        ];
+
+        let mut timings = ExecutionTimings::default();
+        for message in &messages {
+            timings.record(message);
+        }
+        assert_eq!(
+            timings,
+            ExecutionTimings {
+                busy: Some(at(1)),
+                execute_input: Some(at(2)),
+                reply_started: Some(at(5)),
+                reply: Some(at(30)),
+                idle: Some(at(31)),
+            }
+        );
+        assert_eq!(timings.duration(), Some(chrono::Duration::milliseconds(25)));
+    }
 }
@@ -0,0 +1,284 @@
+//! Drawing images inline, on terminals that can.
+//!
+//! iTerm2's and Kitty's protocols take the encoded image as it comes from the
+//! kernel. Sixel takes pixels, so for those terminals the image is decoded,
+//! scaled down to [`SIXEL_MAX_WIDTH`] and mapped onto a 216 color palette.
+
+use base64::prelude::*;
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+/// Escape sequences a terminal understands for showing an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProtocol {
+    /// iTerm2's inline images, also understood by WezTerm. Takes PNG, JPEG
+    /// and GIF
+    Iterm,
+    /// Kitty's graphics protocol, also understood by Ghostty. Takes PNG
+    Kitty,
+    /// DEC sixel graphics, understood by foot, mlterm, contour and recent
+    /// Konsole. Takes PNG, JPEG and GIF, whose first frame is shown
+    Sixel,
+}
+
+/// Kitty takes the base64 payload in chunks of at most this many bytes.
+const KITTY_CHUNK: usize = 4096;
+
+/// Wider images are scaled down to this many pixels before drawing with sixel.
+pub const SIXEL_MAX_WIDTH: u32 = 800;
+
+/// The first Konsole version with sixel support, as `KONSOLE_VERSION` has it.
+const KONSOLE_SIXEL_VERSION: u32 = 220400;
+
+impl ImageProtocol {
+    /// The protocol of the terminal this process runs in, going by the
+    /// variables terminals set in their environment.
+    pub fn detect() -> Option<Self> {
+        Self::detect_from(|name| std::env::var(name).ok())
+    }
+
+    fn detect_from(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        match var("TERM_PROGRAM").as_deref() {
+            Some("iTerm.app") | Some("WezTerm") => return Some(ImageProtocol::Iterm),
+            Some("ghostty") => return Some(ImageProtocol::Kitty),
+            _ => {}
+        }
+        let term = var("TERM").unwrap_or_default();
+        if var("KITTY_WINDOW_ID").is_some() || term == "xterm-kitty" {
+            return Some(ImageProtocol::Kitty);
+        }
+        let konsole = var("KONSOLE_VERSION").and_then(|version| version.parse::<u32>().ok());
+        if ["foot", "mlterm", "contour"]
+            .iter()
+            .any(|name| term.starts_with(name))
+            || term.contains("sixel")
+            || konsole.is_some_and(|version| version >= KONSOLE_SIXEL_VERSION)
+        {
+            return Some(ImageProtocol::Sixel);
+        }
+        None
+    }
+
+    /// The image media types this protocol can draw.
+    pub fn mime_types(self) -> &'static [&'static str] {
+        match self {
+            ImageProtocol::Iterm | ImageProtocol::Sixel => {
+                &["image/png", "image/jpeg", "image/gif"]
+            }
+            ImageProtocol::Kitty => &["image/png"],
+        }
+    }
+
+    /// Escape sequences drawing the base64 encoded image `data` at the cursor,
+    /// or `None` if it has to be decoded and can't be.
+    pub(crate) fn inline_image(self, data: &str) -> Option<String> {
+        // Kernels may wrap the base64 over several lines
+        let data: String = data.split_whitespace().collect();
+        match self {
+            ImageProtocol::Iterm => Some(format!(
+                "\x1b]1337;File=inline=1;preserveAspectRatio=1:{data}\x07"
+            )),
+            ImageProtocol::Sixel => {
+                let bytes = BASE64_STANDARD.decode(data).ok()?;
+                let image = image::load_from_memory(&bytes).ok()?.to_rgba8();
+                Some(sixel(&fit_width(image, SIXEL_MAX_WIDTH)))
+            }
+            ImageProtocol::Kitty => {
+                let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+                let mut escapes = String::with_capacity(data.len() + chunks.len() * 16);
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let more = u8::from(i + 1 < chunks.len());
+                    let control = if i == 0 {
+                        format!("a=T,f=100,m={more}")
+                    } else {
+                        format!("m={more}")
+                    };
+                    // Base64 is ASCII, so every chunk is valid UTF-8
+                    let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+                    escapes.push_str(&format!("\x1b_G{control};{chunk}\x1b\\"));
+                }
+                Some(escapes)
+            }
+        }
+    }
+}
+
+fn fit_width(image: RgbaImage, max_width: u32) -> RgbaImage {
+    if image.width() <= max_width {
+        return image;
+    }
+    let height = (image.height() as u64 * max_width as u64 / image.width() as u64).max(1) as u32;
+    image::imageops::resize(&image, max_width, height, FilterType::Triangle)
+}
+
+/// The palette index of a pixel: 6 levels each of red, green and blue, or
+/// `None` for mostly transparent pixels, which are left undrawn.
+fn palette_index([r, g, b, a]: [u8; 4]) -> Option<usize> {
+    if a < 128 {
+        return None;
+    }
+    let level = |channel: u8| (channel as usize * 5 + 127) / 255;
+    Some(level(r) * 36 + level(g) * 6 + level(b))
+}
+
+/// `image` as a sixel escape sequence. Pixels are drawn six rows at a time,
+/// one pass per color in each band, with runs of the same sixel compressed.
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let indices: Vec<Option<usize>> = image.pixels().map(|pixel| palette_index(pixel.0)).collect();
+
+    // P2=1 leaves undrawn pixels transparent
+    let mut out = format!("\x1bP0;1;0q\"1;1;{width};{height}");
+    let mut used = [false; 216];
+    for index in indices.iter().flatten() {
+        used[*index] = true;
+    }
+    for (index, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        let percent = |level: usize| level * 100 / 5;
+        out.push_str(&format!(
+            "#{index};2;{};{};{}",
+            percent(index / 36),
+            percent(index / 6 % 6),
+            percent(index % 6)
+        ));
+    }
+
+    let width = width as usize;
+    for band in indices.chunks(width * 6) {
+        let rows = band.len() / width;
+        let mut colors: Vec<usize> = band.iter().flatten().copied().collect();
+        colors.sort_unstable();
+        colors.dedup();
+        for (i, color) in colors.iter().enumerate() {
+            if i > 0 {
+                out.push('$');
+            }
+            out.push_str(&format!("#{color}"));
+            let sixels = (0..width).map(|x| {
+                let bits = (0..rows)
+                    .filter(|y| band[y * width + x] == Some(*color))
+                    .fold(0u8, |bits, y| bits | 1 << y);
+                (63 + bits) as char
+            });
+            push_runs(&mut out, sixels);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Append `sixels`, writing runs of more than three as `!<count><sixel>`.
+fn push_runs(out: &mut String, sixels: impl Iterator<Item = char>) {
+    let mut run: Option<(char, usize)> = None;
+    let flush = |out: &mut String, (sixel, count): (char, usize)| {
+        if count > 3 {
+            out.push_str(&format!("!{count}{sixel}"));
+        } else {
+            out.extend(std::iter::repeat_n(sixel, count));
+        }
+    };
+    for sixel in sixels {
+        run = match run {
+            Some((current, count)) if current == sixel => Some((current, count + 1)),
+            Some(previous) => {
+                flush(out, previous);
+                Some((sixel, 1))
+            }
+            None => Some((sixel, 1)),
+        };
+    }
+    if let Some(run) = run {
+        flush(out, run);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn detect(vars: &[(&str, &str)]) -> Option<ImageProtocol> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        ImageProtocol::detect_from(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            detect(&[("TERM_PROGRAM", "iTerm.app")]),
+            Some(ImageProtocol::Iterm)
+        );
+        assert_eq!(
+            detect(&[("TERM", "xterm-kitty")]),
+            Some(ImageProtocol::Kitty)
+        );
+        assert_eq!(detect(&[("TERM", "foot")]), Some(ImageProtocol::Sixel));
+        assert_eq!(
+            detect(&[("KONSOLE_VERSION", "230805"), ("TERM", "xterm-256color")]),
+            Some(ImageProtocol::Sixel)
+        );
+        assert_eq!(detect(&[("KONSOLE_VERSION", "210800")]), None);
+        assert_eq!(
+            detect(&[
+                ("TERM_PROGRAM", "Apple_Terminal"),
+                ("TERM", "xterm-256color")
+            ]),
+            None
+        );
+    }
+
+    fn png(image: &RgbaImage) -> String {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        BASE64_STANDARD.encode(bytes.into_inner())
+    }
+
+    #[test]
+    fn test_sixel_draws_each_color_in_bands_of_six_rows() {
+        // 5 wide and 7 tall: red on top, a blue row below it, and a
+        // transparent pixel in the corner
+        let image = RgbaImage::from_fn(5, 7, |x, y| match (x, y) {
+            (4, 6) => image::Rgba([0, 0, 0, 0]),
+            (_, 6) => image::Rgba([0, 0, 255, 255]),
+            _ => image::Rgba([255, 0, 0, 255]),
+        });
+        let escapes = ImageProtocol::Sixel.inline_image(&png(&image)).unwrap();
+        assert_eq!(
+            escapes,
+            concat!(
+                "\x1bP0;1;0q\"1;1;5;7",
+                "#5;2;0;0;100#180;2;100;0;0",
+                // The first band is all red, the second has four blue sixels
+                "#180!5~-",
+                "#5!4@?-",
+                "\x1b\\"
+            )
+        );
+    }
+
+    #[test]
+    fn test_sixel_scales_wide_images_and_skips_bad_data() {
+        let image = RgbaImage::from_pixel(SIXEL_MAX_WIDTH * 2, 10, image::Rgba([0, 0, 0, 255]));
+        let escapes = ImageProtocol::Sixel.inline_image(&png(&image)).unwrap();
+        assert!(escapes.contains(&format!("\"1;1;{};5", SIXEL_MAX_WIDTH)));
+
+        assert_eq!(ImageProtocol::Sixel.inline_image("not an image"), None);
+    }
+
+    #[test]
+    fn test_kitty_chunks_large_images() {
+        let data = "A".repeat(KITTY_CHUNK + 10);
+        let escapes = ImageProtocol::Kitty
+            .inline_image(&format!("{data}\n"))
+            .unwrap();
+        assert_eq!(
+            escapes,
+            format!(
+                "\x1b_Ga=T,f=100,m=1;{}\x1b\\\x1b_Gm=0;{}\x1b\\",
+                "A".repeat(KITTY_CHUNK),
+                "A".repeat(10)
+            )
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Rendering Jupyter outputs for a terminal, the way `jupyter-console` does.
+//!
+//! A [`Renderer`] turns iopub outputs into text for stdout or stderr: streams
+//! as they are, results as `Out[n]: ...`, and errors as their tracebacks. For
+//! each bundle it picks the richest media type the terminal can show, as
+//! [`TerminalCapabilities`] describes it. Tables from pandas and other data
+//! libraries are laid out as text, and images are drawn inline on terminals
+//! with an image protocol. Markdown is [styled](markdown) on terminals with
+//! color. Everything else falls back to `text/plain`, or to a placeholder
+//! naming what the bundle holds.
+//!
+//! ```rust
+//! use jupyter_protocol::{ExecuteResult, JupyterMessageContent, Media, MediaType};
+//! use jupyter_render_term::{Renderer, Target, TerminalCapabilities};
+//!
+//! let renderer = Renderer::new(TerminalCapabilities::plain());
+//! let result = JupyterMessageContent::ExecuteResult(ExecuteResult::new(
+//!     3.into(),
+//!     Media::new(vec![
+//!         MediaType::Html("<b>42</b>".to_string()),
+//!         MediaType::Plain("42".to_string()),
+//!     ]),
+//! ));
+//!
+//! let rendered = renderer.render_output(&result).unwrap();
+//! assert_eq!(rendered.target, Target::Stdout);
+//! assert_eq!(rendered.text, "Out[3]: 42\n");
+//! ```
+use std::io::{self, Write};
+
+use jupyter_protocol::ansi::strip_ansi;
+use jupyter_protocol::{
+    ErrorOutput, JupyterMessageContent, Media, MediaType, RankProfile, Ranking, Stdio,
+};
+
+mod image;
+pub mod markdown;
+mod table;
+
+pub use image::ImageProtocol;
+
+const RED: &str = "\x1b[31m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// What the terminal being written to can show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TerminalCapabilities {
+    /// Keep ANSI colors in tracebacks, and color prompts and table headers
+    pub color: bool,
+    /// How to draw images inline, if the terminal can
+    pub images: Option<ImageProtocol>,
+}
+
+impl TerminalCapabilities {
+    /// No color and no images, for pipes and files.
+    pub fn plain() -> Self {
+        Self::default()
+    }
+
+    /// The capabilities of the terminal this process runs in, if it's writing
+    /// to one. Color follows the `NO_COLOR` convention and `TERM=dumb`.
+    pub fn detect(is_terminal: bool) -> Self {
+        if !is_terminal {
+            return Self::plain();
+        }
+        let dumb = std::env::var("TERM").is_ok_and(|term| term == "dumb");
+        Self {
+            color: !dumb && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            images: ImageProtocol::detect(),
+        }
+    }
+}
+
+/// Where a rendered output goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Stdout,
+    Stderr,
+}
+
+/// An output as text for the terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub target: Target,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    capabilities: TerminalCapabilities,
+    ranking: Ranking,
+    max_table_rows: usize,
+}
+
+impl Renderer {
+    pub fn new(capabilities: TerminalCapabilities) -> Self {
+        // The terminal profile, with the images the terminal can draw on top
+        let image_types = capabilities
+            .images
+            .map(ImageProtocol::mime_types)
+            .unwrap_or_default();
+        let ranking = image_types
+            .iter()
+            .fold(Ranking::new(RankProfile::Terminal), |ranking, mime_type| {
+                ranking.with_priority(*mime_type, 60)
+            });
+        Self {
+            capabilities,
+            ranking,
+            max_table_rows: 50,
+        }
+    }
+
+    /// Show at most `rows` rows of a table, with a line saying how many more
+    /// there are.
+    pub fn with_max_table_rows(mut self, rows: usize) -> Self {
+        self.max_table_rows = rows;
+        self
+    }
+
+    pub fn capabilities(&self) -> TerminalCapabilities {
+        self.capabilities
+    }
+
+    /// The richest media type in `media` that the terminal can show.
+    pub fn richest<'a>(&self, media: &'a Media) -> Option<&'a MediaType> {
+        media.richest(|media_type| self.ranking.rank(media_type))
+    }
+
+    /// A bundle as text, without a trailing newline, or a placeholder naming
+    /// its media types if the terminal can't show any of them.
+    pub fn render_media(&self, media: &Media) -> String {
+        let rendered = match self.richest(media) {
+            Some(MediaType::Markdown(text)) if self.capabilities.color => {
+                Some(markdown::render_markdown(text))
+            }
+            Some(MediaType::Plain(text))
+            | Some(MediaType::Markdown(text))
+            | Some(MediaType::Latex(text)) => Some(text.trim_end_matches('\n').to_string()),
+            Some(MediaType::Json(json)) | Some(MediaType::GeoJson(json)) => {
+                serde_json::to_string_pretty(json).ok()
+            }
+            Some(MediaType::DataTable(table)) => {
+                table::render(table, self.max_table_rows, self.capabilities.color)
+            }
+            Some(MediaType::Png(data))
+            | Some(MediaType::Jpeg(data))
+            | Some(MediaType::Gif(data)) => self
+                .capabilities
+                .images
+                .and_then(|protocol| protocol.inline_image(data)),
+            _ => None,
+        };
+        rendered.unwrap_or_else(|| {
+            let mime_types: Vec<&str> = media
+                .content
+                .iter()
+                .map(|media_type| media_type.mime_type())
+                .collect();
+            format!("<{}>", mime_types.join(", "))
+        })
+    }
+
+    /// An iopub output as text, or `None` for messages that aren't outputs.
+    pub fn render_output(&self, content: &JupyterMessageContent) -> Option<Rendered> {
+        let (target, text) = match content {
+            JupyterMessageContent::StreamContent(stream) => (
+                match stream.name {
+                    Stdio::Stdout => Target::Stdout,
+                    Stdio::Stderr => Target::Stderr,
+                },
+                stream.text.clone(),
+            ),
+            JupyterMessageContent::ExecuteResult(result) => {
+                let prompt = format!("Out[{}]:", result.execution_count.value());
+                let prompt = if self.capabilities.color {
+                    format!("{RED}{prompt}{RESET}")
+                } else {
+                    prompt
+                };
+                let data = self.render_media(&result.data);
+                // Like jupyter-console, multi-line results start below the prompt
+                let separator = if data.contains('\n') { "\n" } else { " " };
+                (Target::Stdout, format!("{prompt}{separator}{data}\n"))
+            }
+            JupyterMessageContent::DisplayData(display) => (
+                Target::Stdout,
+                format!("{}\n", self.render_media(&display.data)),
+            ),
+            JupyterMessageContent::UpdateDisplayData(update) => (
+                Target::Stdout,
+                format!("{}\n", self.render_media(&update.data)),
+            ),
+            JupyterMessageContent::ErrorOutput(error) => (Target::Stderr, self.render_error(error)),
+            _ => return None,
+        };
+        Some(Rendered { target, text })
+    }
+
+    fn render_error(&self, error: &ErrorOutput) -> String {
+        if error.traceback.is_empty() {
+            return if self.capabilities.color {
+                format!("{BOLD_RED}{}{RESET}: {}\n", error.ename, error.evalue)
+            } else {
+                format!("{}: {}\n", error.ename, error.evalue)
+            };
+        }
+        let traceback = error.traceback.join("\n");
+        if self.capabilities.color {
+            format!("{traceback}\n")
+        } else {
+            format!("{}\n", strip_ansi(&traceback))
+        }
+    }
+
+    /// Render `content` and write it to `stdout` or `stderr`, skipping
+    /// messages that aren't outputs.
+    pub fn write_output(
+        &self,
+        content: &JupyterMessageContent,
+        stdout: &mut impl Write,
+        stderr: &mut impl Write,
+    ) -> io::Result<()> {
+        let Some(rendered) = self.render_output(content) else {
+            return Ok(());
+        };
+        let writer: &mut dyn Write = match rendered.target {
+            Target::Stdout => stdout,
+            Target::Stderr => stderr,
+        };
+        writer.write_all(rendered.text.as_bytes())?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jupyter_protocol::{DisplayData, ExecuteResult, StreamContent};
+
+    fn bundle(media_types: Vec<MediaType>) -> Media {
+        Media::new(media_types)
+    }
+
+    #[test]
+    fn test_richest_depends_on_the_terminal() {
+        let media = bundle(vec![
+            MediaType::Plain("<Figure size 640x480>".to_string()),
+            MediaType::Png("iVBORw0KGgo=".to_string()),
+        ]);
+
+        let plain = Renderer::new(TerminalCapabilities::plain());
+        assert_eq!(plain.render_media(&media), "<Figure size 640x480>");
+
+        let iterm = Renderer::new(TerminalCapabilities {
+            color: true,
+            images: Some(ImageProtocol::Iterm),
+        });
+        assert_eq!(
+            iterm.render_media(&media),
+            "\x1b]1337;File=inline=1;preserveAspectRatio=1:iVBORw0KGgo=\x07"
+        );
+    }
+
+    #[test]
+    fn test_unrenderable_bundle_names_its_types() {
+        let renderer = Renderer::new(TerminalCapabilities::plain());
+        let media = bundle(vec![
+            MediaType::Html("<div></div>".to_string()),
+            MediaType::Javascript("render()".to_string()),
+        ]);
+        assert_eq!(
+            renderer.render_media(&media),
+            "<text/html, application/javascript>"
+        );
+    }
+
+    #[test]
+    fn test_outputs_like_jupyter_console() {
+        let renderer = Renderer::new(TerminalCapabilities::plain());
+
+        let stream = JupyterMessageContent::StreamContent(StreamContent {
+            name: Stdio::Stderr,
+            text: "warning\n".to_string(),
+        });
+        assert_eq!(
+            renderer.render_output(&stream),
+            Some(Rendered {
+                target: Target::Stderr,
+                text: "warning\n".to_string()
+            })
+        );
+
+        let result = JupyterMessageContent::ExecuteResult(ExecuteResult::new(
+            7.into(),
+            bundle(vec![MediaType::Plain("   a\n0  1\n1  2".to_string())]),
+        ));
+        assert_eq!(
+            renderer.render_output(&result).unwrap().text,
+            "Out[7]:\n   a\n0  1\n1  2\n"
+        );
+
+        let display = JupyterMessageContent::DisplayData(DisplayData::new(bundle(vec![
+            MediaType::Markdown("# Title\n".to_string()),
+        ])));
+        assert_eq!(renderer.render_output(&display).unwrap().text, "# Title\n");
+
+        assert_eq!(
+            renderer.render_output(&JupyterMessageContent::StreamContent(
+                StreamContent::default()
+            )),
+            Some(Rendered {
+                target: Target::Stdout,
+                text: String::new()
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_colors_follow_the_terminal() {
+        let error = JupyterMessageContent::ErrorOutput(ErrorOutput {
+            ename: "ValueError".to_string(),
+            evalue: "bad".to_string(),
+            traceback: vec![
+                "\x1b[0;31mTraceback\x1b[0m".to_string(),
+                "\x1b[0;31mValueError\x1b[0m: bad".to_string(),
+            ],
+        });
+
+        let plain = Renderer::new(TerminalCapabilities::plain());
+        let rendered = plain.render_output(&error).unwrap();
+        assert_eq!(rendered.target, Target::Stderr);
+        assert_eq!(rendered.text, "Traceback\nValueError: bad\n");
+
+        let colored = Renderer::new(TerminalCapabilities {
+            color: true,
+            images: None,
+        });
+        assert_eq!(
+            colored.render_output(&error).unwrap().text,
+            "\x1b[0;31mTraceback\x1b[0m\n\x1b[0;31mValueError\x1b[0m: bad\n"
+        );
+
+        let bare = ErrorOutput {
+            ename: "KeyError".to_string(),
+            evalue: "'a'".to_string(),
+            traceback: vec![],
+        };
+        assert_eq!(plain.render_error(&bare), "KeyError: 'a'\n");
+    }
+
+    #[test]
+    fn test_write_output_picks_the_stream() {
+        let renderer = Renderer::new(TerminalCapabilities::plain());
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let error = JupyterMessageContent::ErrorOutput(ErrorOutput {
+            ename: "NameError".to_string(),
+            evalue: "x".to_string(),
+            traceback: vec![],
+        });
+        renderer
+            .write_output(&error, &mut stdout, &mut stderr)
+            .unwrap();
+        assert!(stdout.is_empty());
+        assert_eq!(String::from_utf8(stderr).unwrap(), "NameError: x\n");
+    }
+}
@@ -0,0 +1,389 @@
+//! Markdown styled with ANSI escapes, for terminals with color.
+//!
+//! The Markdown is parsed with [pulldown-cmark](https://docs.rs/pulldown-cmark)
+//! and laid out the way `glow` or `mdcat` would, within what a pager can
+//! show: headings and strong text in bold, emphasis in italics, code in cyan,
+//! block quotes behind a bar, tables in aligned columns and links followed by
+//! their URL.
+//!
+//! ```rust
+//! use jupyter_render_term::markdown::render_markdown;
+//!
+//! assert_eq!(
+//!     render_markdown("# Results\n\n- *all* passed"),
+//!     "\x1b[1m# Results\x1b[0m\n\n• \x1b[3mall\x1b[0m passed"
+//! );
+//! ```
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
+
+use crate::RESET;
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const STRIKETHROUGH: &str = "\x1b[9m";
+const CYAN: &str = "\x1b[36m";
+
+/// `markdown` styled for the terminal, without a trailing newline.
+pub fn render_markdown(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut writer = Writer::default();
+    for event in Parser::new_ext(markdown, options) {
+        writer.event(event);
+    }
+    writer.out.trim_end_matches('\n').to_string()
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    /// Styles in effect, outermost first
+    styles: Vec<&'static str>,
+    /// Written at the start of each line, for block quotes and list items
+    prefixes: Vec<String>,
+    /// The next number of each open list, or `None` for bullets
+    lists: Vec<Option<u64>>,
+    /// The URL of each open link or image, unless it's already the text
+    links: Vec<Option<String>>,
+    /// The rows of the table being read, laid out once it's complete
+    table: Option<Vec<Vec<String>>>,
+    /// Text was written since the last newline. Escapes don't count
+    mid_line: bool,
+    /// A block ended, so the next one starts after a blank line
+    blank_line_pending: bool,
+    /// A list item's marker was just written, and its first block goes next to it
+    after_marker: bool,
+}
+
+impl Writer {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => self.text(&text),
+            Event::Code(code) => {
+                self.push_style(CYAN);
+                self.text(&code);
+                self.pop_style();
+            }
+            Event::SoftBreak if self.table.is_some() => self.text(" "),
+            Event::SoftBreak | Event::HardBreak => self.text("\n"),
+            Event::Rule => {
+                self.start_block();
+                self.text(&"─".repeat(40));
+                self.end_block();
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::FootnoteReference(name) => self.text(&format!("[^{}]", name)),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::HtmlBlock => self.start_block(),
+            Tag::Heading { level, .. } => {
+                self.start_block();
+                self.push_style(BOLD);
+                self.text(&format!("{} ", "#".repeat(level as usize)));
+            }
+            Tag::BlockQuote(_) => {
+                self.start_block();
+                self.prefixes.push("│ ".to_string());
+            }
+            Tag::CodeBlock(_) => {
+                self.start_block();
+                self.prefixes.push("    ".to_string());
+                self.push_style(CYAN);
+            }
+            Tag::List(first) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else if !self.at_line_start() {
+                    self.newline();
+                }
+                self.lists.push(first);
+            }
+            Tag::Item => {
+                if !self.at_line_start() {
+                    self.newline();
+                }
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.write_prefixes();
+                self.out.push_str(&marker);
+                self.mid_line = true;
+                self.prefixes.push(" ".repeat(marker.chars().count()));
+                self.after_marker = true;
+            }
+            Tag::Emphasis => self.push_style(ITALIC),
+            Tag::Strong => self.push_style(BOLD),
+            Tag::Strikethrough => self.push_style(STRIKETHROUGH),
+            Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            } => {
+                self.push_style(UNDERLINE);
+                let shown = matches!(link_type, LinkType::Autolink | LinkType::Email);
+                self.links.push((!shown).then(|| dest_url.to_string()));
+            }
+            Tag::Image { dest_url, .. } => {
+                self.text("[image: ");
+                self.links.push(Some(dest_url.to_string()));
+            }
+            Tag::Table(_) => {
+                self.start_block();
+                self.table = Some(Vec::new());
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(rows) = &mut self.table {
+                    rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => {
+                if let Some(row) = self.table.as_mut().and_then(|rows| rows.last_mut()) {
+                    row.push(String::new());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock => self.end_block(),
+            TagEnd::Heading(_) => {
+                self.pop_style();
+                self.end_block();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.prefixes.pop();
+                self.end_block();
+            }
+            TagEnd::CodeBlock => {
+                self.pop_style();
+                self.prefixes.pop();
+                self.end_block();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_block();
+                }
+            }
+            TagEnd::Item => {
+                self.prefixes.pop();
+                self.after_marker = false;
+                if !self.at_line_start() {
+                    self.newline();
+                }
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => self.pop_style(),
+            TagEnd::Link => {
+                self.pop_style();
+                self.write_url();
+            }
+            TagEnd::Image => {
+                self.text("]");
+                self.write_url();
+            }
+            TagEnd::Table => {
+                let rows = self.table.take().unwrap_or_default();
+                self.write_table(rows);
+                self.end_block();
+            }
+            _ => {}
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        !self.mid_line
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.mid_line = false;
+    }
+
+    fn start_block(&mut self) {
+        if self.after_marker {
+            self.after_marker = false;
+            return;
+        }
+        if !self.at_line_start() {
+            self.newline();
+        }
+        if self.blank_line_pending && !self.out.is_empty() {
+            self.out.push_str(self.prefixes.concat().trim_end());
+            self.newline();
+        }
+        self.blank_line_pending = false;
+    }
+
+    fn end_block(&mut self) {
+        if !self.at_line_start() {
+            self.newline();
+        }
+        self.blank_line_pending = true;
+    }
+
+    /// The line prefixes, written unstyled.
+    fn write_prefixes(&mut self) {
+        if self.prefixes.is_empty() {
+            return;
+        }
+        if !self.styles.is_empty() {
+            self.out.push_str(RESET);
+        }
+        self.out.push_str(&self.prefixes.concat());
+        self.out.push_str(&self.styles.concat());
+        self.mid_line = true;
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some(cell) = self
+            .table
+            .as_mut()
+            .and_then(|rows| rows.last_mut())
+            .and_then(|row| row.last_mut())
+        {
+            cell.push_str(text);
+            return;
+        }
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if self.at_line_start() {
+                self.write_prefixes();
+            }
+            self.out.push_str(line);
+            self.mid_line = true;
+        }
+        self.after_marker = false;
+    }
+
+    /// Styles aren't applied inside tables, where they'd throw off the
+    /// columns. At the start of a prefixed line, they're applied after the
+    /// prefix.
+    fn push_style(&mut self, style: &'static str) {
+        self.styles.push(style);
+        if self.table.is_none() && (self.mid_line || self.prefixes.is_empty()) {
+            self.out.push_str(style);
+        }
+    }
+
+    fn pop_style(&mut self) {
+        self.styles.pop();
+        if self.table.is_none() {
+            self.out.push_str(RESET);
+            self.out.push_str(&self.styles.concat());
+        }
+    }
+
+    fn write_url(&mut self) {
+        if let Some(Some(url)) = self.links.pop() {
+            self.push_style(DIM);
+            self.text(&format!(" ({})", url));
+            self.pop_style();
+        }
+    }
+
+    /// `rows` in columns, with the first row as the header.
+    fn write_table(&mut self, rows: Vec<Vec<String>>) {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                rows.iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        for (index, row) in rows.iter().enumerate() {
+            let line = widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    let cell = row.get(column).map(String::as_str).unwrap_or_default();
+                    format!("{:width$}", cell, width = width)
+                })
+                .collect::<Vec<_>>()
+                .join(" │ ");
+            self.write_prefixes();
+            if index == 0 {
+                self.out.push_str(BOLD);
+                self.out.push_str(line.trim_end());
+                self.out.push_str(RESET);
+                self.newline();
+                let rule: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+                self.write_prefixes();
+                self.out.push_str(&rule.join("─┼─"));
+            } else {
+                self.out.push_str(line.trim_end());
+            }
+            self.newline();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_are_separated() {
+        let rendered = render_markdown(
+            "## Setup\n\nRun `make`:\n\n```sh\nmake\nmake test\n```\n\n> Note\n> again\n\n---",
+        );
+        assert_eq!(
+            rendered,
+            "\x1b[1m## Setup\x1b[0m\n\
+             \n\
+             Run \x1b[36mmake\x1b[0m:\n\
+             \n\x1b[0m    \x1b[36mmake\n\x1b[0m    \x1b[36mmake test\n\x1b[0m\n\
+             │ Note\n│ again\n\
+             \n\
+             ────────────────────────────────────────"
+        );
+    }
+
+    #[test]
+    fn test_lists_and_links() {
+        let rendered = render_markdown(
+            "1. [docs](https://docs.rs)\n2. <https://example.com>\n   - nested\n   - [x] done",
+        );
+        assert_eq!(
+            rendered,
+            "1. \x1b[4mdocs\x1b[0m\x1b[2m (https://docs.rs)\x1b[0m\n\
+             2. \x1b[4mhttps://example.com\x1b[0m\n\
+             \x20  • nested\n\
+             \x20  • [x] done"
+        );
+    }
+
+    #[test]
+    fn test_table_columns_line_up() {
+        let rendered = render_markdown("| name | n |\n|---|---|\n| alpha | **1** |\n| b | 22 |");
+        assert_eq!(
+            rendered,
+            "\x1b[1mname  │ n\x1b[0m\n\
+             ──────┼───\n\
+             alpha │ 1\n\
+             b     │ 22"
+        );
+    }
+}
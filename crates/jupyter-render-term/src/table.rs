@@ -0,0 +1,171 @@
+//! `application/vnd.dataresource+json` tables as aligned text.
+use jupyter_protocol::media::datatable::{FieldType, TableSchemaField};
+use jupyter_protocol::TabularDataResource;
+use serde_json::Value;
+
+/// Longer cells are cut short, so one long string doesn't push every other
+/// column off the screen.
+const MAX_CELL_WIDTH: usize = 40;
+
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The table with its index columns first, or `None` if its data isn't
+/// inline.
+pub(crate) fn render(table: &TabularDataResource, max_rows: usize, color: bool) -> Option<String> {
+    let data = table.data.as_ref()?;
+    let schema = &table.schema;
+    let columns: Vec<&TableSchemaField> = schema
+        .index_fields()
+        .into_iter()
+        .chain(schema.value_fields())
+        .collect();
+
+    let header: Vec<String> = columns.iter().map(|field| cell(&field.name)).collect();
+    let rows: Vec<Vec<String>> = data
+        .iter()
+        .take(max_rows)
+        .map(|row| {
+            columns
+                .iter()
+                .map(|field| {
+                    let value = match row {
+                        Value::Object(row) => row.get(&field.name),
+                        // Rows given as arrays follow the schema's field order
+                        Value::Array(row) => schema
+                            .fields
+                            .iter()
+                            .position(|f| f.name == field.name)
+                            .and_then(|i| row.get(i)),
+                        _ => None,
+                    };
+                    cell(&value.map(format_value).unwrap_or_default())
+                })
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([header[i].chars().count()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let numeric: Vec<bool> = columns
+        .iter()
+        .map(|field| matches!(field.field_type, FieldType::Integer | FieldType::Number))
+        .collect();
+
+    let line = |cells: &[String]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if numeric[i] {
+                    format!("{:>width$}", cell, width = widths[i])
+                } else {
+                    format!("{:<width$}", cell, width = widths[i])
+                }
+            })
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let mut lines = Vec::with_capacity(rows.len() + 3);
+    let header_line = line(&header);
+    lines.push(if color {
+        format!("{BOLD}{header_line}{RESET}")
+    } else {
+        header_line
+    });
+    lines.push(
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("  "),
+    );
+    lines.extend(rows.iter().map(|row| line(row)));
+    if data.len() > max_rows {
+        let more = data.len() - max_rows;
+        lines.push(format!(
+            "... {} more row{}",
+            more,
+            if more == 1 { "" } else { "s" }
+        ));
+    }
+    Some(lines.join("\n"))
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `text` on one line, cut to [`MAX_CELL_WIDTH`] characters.
+fn cell(text: &str) -> String {
+    let text = text.replace('\n', " ");
+    if text.chars().count() <= MAX_CELL_WIDTH {
+        return text;
+    }
+    let mut cut: String = text.chars().take(MAX_CELL_WIDTH - 3).collect();
+    cut.push_str("...");
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table(data: Value) -> TabularDataResource {
+        serde_json::from_value(json!({
+            "schema": {
+                "fields": [
+                    {"name": "city", "type": "string"},
+                    {"name": "index", "type": "integer"},
+                    {"name": "population", "type": "number"}
+                ],
+                "primaryKey": ["index"],
+                "pandas_version": "1.4.0"
+            },
+            "data": data
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_index_first_and_numbers_right_aligned() {
+        let table = table(json!([
+            {"index": 0, "city": "Oslo", "population": 709037},
+            {"index": 1, "city": "Reykjavik", "population": null},
+        ]));
+        assert_eq!(
+            render(&table, 50, false).unwrap(),
+            [
+                "index  city       population",
+                "-----  ---------  ----------",
+                "    0  Oslo           709037",
+                "    1  Reykjavik",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_long_tables_and_cells_are_cut() {
+        let long = "x".repeat(100);
+        let table = table(json!([["a", 0, 1], [long, 1, 2], ["c", 2, 3],]));
+        let rendered = render(&table, 2, false).unwrap();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[2], format!("    0  {:<40}  {:>10}", "a", 1));
+        assert_eq!(lines[3], format!("    1  {}...  {:>10}", "x".repeat(37), 2));
+        assert_eq!(lines[4], "... 1 more row");
+    }
+}
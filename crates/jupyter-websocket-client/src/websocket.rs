@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use async_tungstenite::{async_std::ConnectStream, tungstenite::Message, WebSocketStream};
 use futures::{Sink, SinkExt as _, Stream, StreamExt};
 
-use jupyter_protocol::{JupyterConnection, JupyterMessage};
+use jupyter_protocol::{routing, JupyterConnection, JupyterMessage};
 use std::pin::Pin;
 use std::task::{Context as TaskContext, Poll};
 
@@ -23,7 +23,8 @@ impl Stream for JupyterWebSocket {
                         .and_then(|value| {
                             JupyterMessage::from_value(value)
                                 .context("Failed to create JupyterMessage")
-                        }),
+                        })
+                        .and_then(check_channel),
                 )),
                 _ => Poll::Ready(Some(Err(anyhow::anyhow!("Received non-text message")))),
             },
@@ -34,6 +35,18 @@ impl Stream for JupyterWebSocket {
     }
 }
 
+/// Websocket messages name their channel. One its type never travels on is a
+/// server bug, reported here rather than when deserializing so that recorded
+/// traffic still loads.
+fn check_channel(message: JupyterMessage) -> Result<JupyterMessage> {
+    if let Some(channel) = message.channel {
+        if !routing::is_carried_on(message.message_type(), channel) {
+            anyhow::bail!("{} isn't sent on {}", message.message_type(), channel);
+        }
+    }
+    Ok(message)
+}
+
 impl Sink<JupyterMessage> for JupyterWebSocket {
     type Error = anyhow::Error;
 
@@ -0,0 +1,398 @@
+//! Render an executed notebook as a standalone HTML report.
+//!
+//! The page has no external dependencies: styles are inlined, images are
+//! embedded as `data:` URIs and tracebacks collapse with `<details>`, so the
+//! file can be shared and opened anywhere without a Jupyter installation.
+//!
+//! HTML and SVG outputs can run scripts, so [`render_html`] sanitizes them
+//! with the `sanitize` feature and leaves them out in favor of their text
+//! without it. [`render_html_trusted`] embeds them as they are, for
+//! [trusted](crate::trust) notebooks.
+//!
+//! Markdown cells and outputs show as text, or as sanitized HTML with the
+//! `markdown` feature. Math in them is left for KaTeX, which the page doesn't
+//! load: add its auto-render script to typeset it.
+//!
+//! ```rust
+//! use nbformat::{html::render_html, parse_notebook, Notebook};
+//!
+//! let json = r#"{
+//!     "nbformat": 4, "nbformat_minor": 5, "metadata": {},
+//!     "cells": [{
+//!         "id": "a", "cell_type": "code", "metadata": {}, "execution_count": 1,
+//!         "source": ["print('hi')"],
+//!         "outputs": [{"output_type": "stream", "name": "stdout", "text": ["hi\n"]}]
+//!     }]
+//! }"#;
+//! let Notebook::V4(notebook) = parse_notebook(json).unwrap() else {
+//!     unreachable!()
+//! };
+//! let html = render_html(&notebook);
+//! assert!(html.contains("<pre class=\"stream stdout\">hi\n</pre>"));
+//! ```
+use std::fmt::Write as _;
+
+use chrono::{DateTime, FixedOffset};
+use jupyter_protocol::ansi::ansi_to_html;
+use jupyter_protocol::media::{DisplayMetadata, MediaMetadata, MediaType, RankProfile};
+use jupyter_protocol::KernelInfoCard;
+
+#[cfg(feature = "markdown")]
+use jupyter_protocol::media::markdown::render_markdown;
+
+use crate::v4::{Cell, CellMetadata, ErrorOutput, Metadata, Notebook, Output};
+
+/// Turns Markdown into an HTML fragment.
+type MarkdownRenderer<'a> = &'a dyn Fn(&str) -> String;
+
+const STYLE: &str = r#"
+body { font-family: system-ui, sans-serif; max-width: 960px; margin: 2em auto; padding: 0 1em; color: #1f2328; }
+.cell { margin: 1em 0; }
+.prompt { font-family: monospace; color: #57606a; font-size: 0.85em; }
+.timing { float: right; }
+pre { font-family: ui-monospace, monospace; font-size: 0.9em; white-space: pre-wrap; word-break: break-word; margin: 0.25em 0; }
+.source { background: #f6f8fa; border: 1px solid #d0d7de; border-radius: 4px; padding: 0.5em; }
+.markdown, .raw { white-space: pre-wrap; }
+.markdown.rendered { white-space: normal; }
+.kernel-info { color: #57606a; font-size: 0.9em; border-bottom: 1px solid #d0d7de; padding-bottom: 0.5em; }
+.output { padding: 0.25em 0.5em; overflow-x: auto; }
+.stderr { background: #fff0f0; }
+.error summary { color: #cf222e; font-family: monospace; cursor: pointer; }
+img { max-width: 100%; }
+.ansi-bold { font-weight: bold; }
+.ansi-italic { font-style: italic; }
+.ansi-underline { text-decoration: underline; }
+.ansi-black-fg { color: #3e424d; } .ansi-red-fg { color: #e75c58; } .ansi-green-fg { color: #00a250; }
+.ansi-yellow-fg { color: #ddb62b; } .ansi-blue-fg { color: #208ffb; } .ansi-magenta-fg { color: #d160c4; }
+.ansi-cyan-fg { color: #60c6c8; } .ansi-white-fg { color: #c5c1b4; }
+.ansi-black-intense-fg { color: #282c36; } .ansi-red-intense-fg { color: #b22b31; } .ansi-green-intense-fg { color: #007427; }
+.ansi-yellow-intense-fg { color: #b27d12; } .ansi-blue-intense-fg { color: #0065ca; } .ansi-magenta-intense-fg { color: #a03196; }
+.ansi-cyan-intense-fg { color: #258f8f; } .ansi-white-intense-fg { color: #a1a6b2; }
+.ansi-black-bg { background-color: #3e424d; } .ansi-red-bg { background-color: #e75c58; } .ansi-green-bg { background-color: #00a250; }
+.ansi-yellow-bg { background-color: #ddb62b; } .ansi-blue-bg { background-color: #208ffb; } .ansi-magenta-bg { background-color: #d160c4; }
+.ansi-cyan-bg { background-color: #60c6c8; } .ansi-white-bg { background-color: #c5c1b4; }
+"#;
+
+/// Render `notebook` as a complete HTML document that's safe to open
+/// whatever the notebook holds.
+///
+/// With the `sanitize` feature, HTML and SVG outputs are sanitized like
+/// [`render_html_sanitized`] does with
+/// [`AllowStyles`](jupyter_protocol::media::sanitize::SanitizePolicy::AllowStyles).
+/// Without it, they're left out and the outputs show as text.
+pub fn render_html(notebook: &Notebook) -> String {
+    #[cfg(feature = "sanitize")]
+    return render_html_sanitized(
+        notebook,
+        jupyter_protocol::media::sanitize::SanitizePolicy::AllowStyles,
+    );
+    #[cfg(not(feature = "sanitize"))]
+    render(notebook, None, false)
+}
+
+/// Render `notebook` like [`render_html`], with HTML and SVG outputs embedded
+/// as they are, scripts and all. Only use this for notebooks that are
+/// [trusted](crate::trust), or whose outputs you produced yourself.
+pub fn render_html_trusted(notebook: &Notebook) -> String {
+    // Trusted notebooks keep their styles, like the rest of their HTML
+    #[cfg(feature = "markdown")]
+    let markdown: Option<MarkdownRenderer> = Some(&|markdown| {
+        render_markdown(
+            markdown,
+            jupyter_protocol::media::sanitize::SanitizePolicy::AllowStyles,
+        )
+    });
+    #[cfg(not(feature = "markdown"))]
+    let markdown = None;
+    render(notebook, markdown, true)
+}
+
+/// Markdown renders with `markdown` if given, or as text. HTML and SVG
+/// outputs are only embedded with `markup`.
+fn render(notebook: &Notebook, markdown: Option<MarkdownRenderer>, markup: bool) -> String {
+    let title = notebook
+        .metadata
+        .additional
+        .get("title")
+        .and_then(|title| title.as_str())
+        .unwrap_or("Notebook");
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(html, "<title>{}</title>", escape(title));
+    let _ = writeln!(html, "<style>{}</style>", STYLE);
+    html.push_str("</head>\n<body>\n");
+    if let Some(card) = kernel_info_card(&notebook.metadata) {
+        let _ = writeln!(html, "{}", card.to_html());
+    }
+    for cell in &notebook.cells {
+        render_cell(cell, markdown, markup, &mut html);
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Render `notebook` with scripts, event handlers and Javascript outputs
+/// stripped by `policy`, for notebooks that aren't [trusted](crate::trust).
+#[cfg(feature = "sanitize")]
+pub fn render_html_sanitized(
+    notebook: &Notebook,
+    policy: jupyter_protocol::media::sanitize::SanitizePolicy,
+) -> String {
+    let mut notebook = notebook.clone();
+    for cell in &mut notebook.cells {
+        let Cell::Code { outputs, .. } = cell else {
+            continue;
+        };
+        for output in outputs {
+            match output {
+                Output::DisplayData(display_data) => {
+                    display_data.data = display_data.data.sanitized(policy)
+                }
+                Output::ExecuteResult(execute_result) => {
+                    execute_result.data = execute_result.data.sanitized(policy)
+                }
+                Output::Stream { .. } | Output::Error(_) => {}
+            }
+        }
+    }
+    #[cfg(feature = "markdown")]
+    let markdown: Option<MarkdownRenderer> = Some(&|markdown| render_markdown(markdown, policy));
+    #[cfg(not(feature = "markdown"))]
+    let markdown = None;
+    render(&notebook, markdown, true)
+}
+
+fn render_cell(cell: &Cell, markdown: Option<MarkdownRenderer>, markup: bool, html: &mut String) {
+    match cell {
+        Cell::Markdown { source, .. } => match markdown {
+            Some(markdown) => {
+                let _ = writeln!(
+                    html,
+                    "<div class=\"cell markdown rendered\">{}</div>",
+                    markdown(&source.concat())
+                );
+            }
+            None => {
+                let _ = writeln!(
+                    html,
+                    "<div class=\"cell markdown\">{}</div>",
+                    escape(&source.concat())
+                );
+            }
+        },
+        Cell::Raw { source, .. } => {
+            let _ = writeln!(
+                html,
+                "<div class=\"cell raw\">{}</div>",
+                escape(&source.concat())
+            );
+        }
+        Cell::Code {
+            metadata,
+            execution_count,
+            source,
+            outputs,
+            ..
+        } => {
+            html.push_str("<div class=\"cell code\">\n<div class=\"prompt\">");
+            match execution_count {
+                Some(count) => {
+                    let _ = write!(html, "In [{}]", count);
+                }
+                None => html.push_str("In [ ]"),
+            }
+            if let Some(duration) = execution_duration(metadata) {
+                let _ = write!(
+                    html,
+                    "<span class=\"timing\">{}</span>",
+                    format_duration(duration)
+                );
+            }
+            html.push_str("</div>\n");
+            let _ = writeln!(
+                html,
+                "<pre class=\"source\">{}</pre>",
+                escape(&source.concat())
+            );
+            for output in outputs {
+                render_output(output, markdown, markup, html);
+            }
+            html.push_str("</div>\n");
+        }
+    }
+}
+
+fn render_output(
+    output: &Output,
+    markdown: Option<MarkdownRenderer>,
+    markup: bool,
+    html: &mut String,
+) {
+    let rank = |media_type: &MediaType| match media_type {
+        MediaType::Html(_) | MediaType::Svg(_) if !markup => 0,
+        media_type => rank(media_type),
+    };
+    match output {
+        Output::Stream { name, text } => {
+            let _ = writeln!(
+                html,
+                "<pre class=\"stream {}\">{}</pre>",
+                escape(name),
+                ansi_to_html(&text.0)
+            );
+        }
+        Output::DisplayData(display_data) => {
+            if let Some(media) = display_data.data.richest(rank) {
+                render_media(
+                    media,
+                    &display_data.media_metadata(media.mime_type()),
+                    markdown,
+                    html,
+                );
+            }
+        }
+        Output::ExecuteResult(execute_result) => {
+            if let Some(media) = execute_result.data.richest(rank) {
+                render_media(
+                    media,
+                    &execute_result.media_metadata(media.mime_type()),
+                    markdown,
+                    html,
+                );
+            }
+        }
+        Output::Error(error) => render_error(error, html),
+    }
+}
+
+fn render_error(error: &ErrorOutput, html: &mut String) {
+    let _ = writeln!(
+        html,
+        "<details class=\"output error\"><summary>{}: {}</summary>",
+        escape(&error.ename),
+        ansi_to_html(&error.evalue)
+    );
+    let _ = writeln!(
+        html,
+        "<pre class=\"traceback\">{}</pre>",
+        ansi_to_html(&error.traceback.join("\n"))
+    );
+    html.push_str("</details>\n");
+}
+
+/// Images and HTML render as-is, everything else as text. Javascript and
+/// widgets are skipped since there's no kernel or frontend to run them.
+fn rank(media_type: &MediaType) -> usize {
+    RankProfile::NotebookExport.rank(media_type)
+}
+
+fn render_media(
+    media_type: &MediaType,
+    metadata: &MediaMetadata,
+    markdown: Option<MarkdownRenderer>,
+    html: &mut String,
+) {
+    html.push_str("<div class=\"output\">");
+    match media_type {
+        MediaType::Png(data) | MediaType::Jpeg(data) | MediaType::Gif(data) => {
+            image(media_type.mime_type(), data, metadata, html)
+        }
+        MediaType::Svg(svg) | MediaType::Html(svg) => html.push_str(svg),
+        MediaType::Markdown(text) => match markdown {
+            Some(markdown) => html.push_str(&markdown(text)),
+            None => {
+                let _ = write!(html, "<pre>{}</pre>", ansi_to_html(text));
+            }
+        },
+        MediaType::Latex(text) | MediaType::Plain(text) => {
+            let _ = write!(html, "<pre>{}</pre>", ansi_to_html(text));
+        }
+        MediaType::Json(json) => {
+            let json = serde_json::to_string_pretty(json).unwrap_or_default();
+            let _ = write!(html, "<pre>{}</pre>", escape(&json));
+        }
+        _ => {}
+    }
+    html.push_str("</div>\n");
+}
+
+/// An `<img>` sized by the output's metadata for its mimetype. Unconfined
+/// images are let out of the page's `max-width`.
+pub(crate) fn image(mime_type: &str, data: &str, metadata: &MediaMetadata, html: &mut String) {
+    // Notebooks store images as base64, sometimes split over several lines
+    let data: String = data.split_whitespace().collect();
+    let _ = write!(html, "<img src=\"data:{};base64,{}\"", mime_type, data);
+    if let Some(width) = metadata.width {
+        let _ = write!(html, " width=\"{}\"", width);
+    }
+    if let Some(height) = metadata.height {
+        let _ = write!(html, " height=\"{}\"", height);
+    }
+    if metadata.unconfined == Some(true) {
+        html.push_str(" style=\"max-width: none\"");
+    }
+    html.push('>');
+}
+
+/// The kernel the notebook was run with, as far as its metadata says. There's
+/// no `kernel_info_reply` in a notebook, so no banner or help links.
+fn kernel_info_card(metadata: &Metadata) -> Option<KernelInfoCard> {
+    if metadata.kernelspec.is_none() && metadata.language_info.is_none() {
+        return None;
+    }
+    let language_info = metadata.language_info.as_ref();
+    Some(KernelInfoCard {
+        implementation: metadata
+            .kernelspec
+            .as_ref()
+            .map(|kernelspec| kernelspec.display_name.clone())
+            .unwrap_or_default(),
+        language: language_info
+            .map(|info| info.name.clone())
+            .unwrap_or_default(),
+        language_version: language_info
+            .and_then(|info| info.version.clone())
+            .unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+/// How long the cell took to run, from the timings JupyterLab records with
+/// `record_timing` enabled.
+fn execution_duration(metadata: &CellMetadata) -> Option<chrono::Duration> {
+    let execution = metadata.execution.as_ref()?;
+    let started = execution
+        .shell_execute_reply_started
+        .as_deref()
+        .or(execution.iopub_execute_input.as_deref())?;
+    let finished = execution.shell_execute_reply.as_deref()?;
+    let started = DateTime::<FixedOffset>::parse_from_rfc3339(started).ok()?;
+    let finished = DateTime::<FixedOffset>::parse_from_rfc3339(finished).ok()?;
+    Some(finished - started)
+}
+
+fn format_duration(duration: chrono::Duration) -> String {
+    let millis = duration.num_milliseconds().max(0);
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.2}s", millis as f64 / 1000.0)
+    } else {
+        format!("{}m {}s", millis / 60_000, (millis % 60_000) / 1000)
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,4 +1,11 @@
+pub mod html;
 pub mod legacy;
+pub mod lint;
+pub mod markdown;
+pub mod parameters;
+#[cfg(feature = "trust")]
+pub mod trust;
+pub mod v3;
 pub mod v4;
 
 use serde::Serialize as _;
@@ -12,12 +19,15 @@ pub enum NotebookError {
     JsonError(#[from] serde_json::Error),
     #[error("Validation error: {0}")]
     ValidationError(String),
+    #[error("{0} messages aren't notebook outputs")]
+    NotAnOutput(String),
 }
 
 #[derive(Debug)]
 pub enum Notebook {
     V4(v4::Notebook),
     Legacy(legacy::Notebook),
+    V3(v3::Notebook),
 }
 
 pub fn parse_notebook(json: &str) -> Result<Notebook, NotebookError> {
@@ -30,6 +40,7 @@ pub fn parse_notebook(json: &str) -> Result<Notebook, NotebookError> {
         (4, 1) | (4, 2) | (4, 3) | (4, 4) => Ok(Notebook::Legacy(serde_json::from_value::<
             legacy::Notebook,
         >(value)?)),
+        (3, _) => Ok(Notebook::V3(serde_json::from_value::<v3::Notebook>(value)?)),
         _ => Err(NotebookError::UnsupportedVersion(nbformat, nbformat_minor)),
     }
 }
@@ -55,6 +66,10 @@ pub fn serialize_notebook(notebook: &Notebook) -> Result<String, NotebookError>
             notebook.nbformat,
             notebook.nbformat_minor,
         )),
+        Notebook::V3(notebook) => Err(NotebookError::UnsupportedVersion(
+            notebook.nbformat,
+            notebook.nbformat_minor,
+        )),
     }
 }
 
@@ -114,3 +129,30 @@ pub fn upgrade_legacy_notebook(legacy_notebook: legacy::Notebook) -> anyhow::Res
         nbformat_minor: 5,
     })
 }
+
+/// Upgrade an nbformat 3 notebook to 4.5.
+///
+/// Cells from every worksheet are kept in order, `heading` cells become markdown
+/// headings, `pyout`/`pyerr` become `execute_result`/`error`, output keys become
+/// MIME types, and `prompt_number` becomes `execution_count`.
+pub fn upgrade_v3_notebook(v3_notebook: v3::Notebook) -> anyhow::Result<v4::Notebook> {
+    let mut metadata = v3_notebook.metadata;
+    // Dropped in nbformat 4
+    metadata.remove("name");
+    metadata.remove("signature");
+    let metadata: v4::Metadata = serde_json::from_value(serde_json::Value::Object(metadata))?;
+
+    let cells = v3_notebook
+        .worksheets
+        .into_iter()
+        .flat_map(|worksheet| worksheet.cells)
+        .map(v3::upgrade_cell)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(v4::Notebook {
+        cells,
+        metadata,
+        nbformat: 4,
+        nbformat_minor: 5,
+    })
+}
@@ -0,0 +1,372 @@
+//! Statistics and hygiene checks for notebooks kept in a repository.
+//!
+//! [`analyze`] measures a notebook, with its cells, outputs by mimetype and
+//! gaps in its execution counts, and [`lint`] reports what usually shouldn't
+//! be committed: huge outputs, cells run out of order, duplicate cell ids and
+//! missing kernel metadata.
+//!
+//! [`parse_notebook`](crate::parse_notebook) rejects duplicate cell ids, so
+//! notebooks are read with [`parse_for_lint`] to be able to report them.
+//!
+//! ```rust
+//! use nbformat::lint::{lint, parse_for_lint, LintOptions, LintRule};
+//!
+//! let notebook = parse_for_lint(r#"{
+//!     "nbformat": 4, "nbformat_minor": 5, "metadata": {},
+//!     "cells": [
+//!         {"id": "a", "cell_type": "code", "metadata": {}, "execution_count": 2, "source": [], "outputs": []},
+//!         {"id": "a", "cell_type": "code", "metadata": {}, "execution_count": 1, "source": [], "outputs": []}
+//!     ]
+//! }"#).unwrap();
+//!
+//! let rules: Vec<LintRule> = lint(&notebook, &LintOptions::default())
+//!     .into_iter()
+//!     .map(|lint| lint.rule)
+//!     .collect();
+//! assert_eq!(
+//!     rules,
+//!     vec![
+//!         LintRule::MissingKernelspec,
+//!         LintRule::MissingLanguageInfo,
+//!         LintRule::DuplicateCellId,
+//!         LintRule::OutOfOrderExecution,
+//!     ]
+//! );
+//! ```
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::v4::{Cell, Notebook, Output};
+use crate::{upgrade_legacy_notebook, upgrade_v3_notebook, NotebookError};
+
+/// Cells whose outputs add up to more than this are [huge](LintRule::HugeOutput).
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// What a notebook is made of.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct NotebookStats {
+    pub code_cells: usize,
+    pub markdown_cells: usize,
+    pub raw_cells: usize,
+    /// Code cells without an execution count
+    pub unexecuted_code_cells: usize,
+    pub outputs: usize,
+    /// Bytes of output data by mimetype. Streams count as
+    /// `application/vnd.jupyter.stdout` or `application/vnd.jupyter.stderr`,
+    /// and error tracebacks as `application/vnd.jupyter.stderr`.
+    pub output_bytes: BTreeMap<String, usize>,
+    /// Places where the execution count skips ahead between one executed cell
+    /// and the next, as when cells were run and then deleted.
+    pub execution_count_gaps: usize,
+    pub has_kernelspec: bool,
+    pub has_language_info: bool,
+}
+
+impl NotebookStats {
+    pub fn cells(&self) -> usize {
+        self.code_cells + self.markdown_cells + self.raw_cells
+    }
+
+    pub fn total_output_bytes(&self) -> usize {
+        self.output_bytes.values().sum()
+    }
+}
+
+/// How much a [`Lint`] matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    /// The notebook is broken, and tools may refuse to open it
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintRule {
+    /// A cell's outputs are bigger than [`LintOptions::max_output_bytes`]
+    HugeOutput,
+    /// A cell's execution count isn't above the one before it, so running
+    /// the notebook from the top may not give the saved outputs
+    OutOfOrderExecution,
+    /// Two cells share an id
+    DuplicateCellId,
+    /// No `kernelspec` metadata, so frontends can't tell which kernel to start
+    MissingKernelspec,
+    /// No `language_info` metadata, so code isn't highlighted
+    MissingLanguageInfo,
+}
+
+impl LintRule {
+    pub fn severity(self) -> Severity {
+        match self {
+            LintRule::DuplicateCellId => Severity::Error,
+            LintRule::HugeOutput | LintRule::OutOfOrderExecution | LintRule::MissingKernelspec => {
+                Severity::Warning
+            }
+            LintRule::MissingLanguageInfo => Severity::Info,
+        }
+    }
+
+    /// The rule's name, like `huge-output`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LintRule::HugeOutput => "huge-output",
+            LintRule::OutOfOrderExecution => "out-of-order-execution",
+            LintRule::DuplicateCellId => "duplicate-cell-id",
+            LintRule::MissingKernelspec => "missing-kernelspec",
+            LintRule::MissingLanguageInfo => "missing-language-info",
+        }
+    }
+}
+
+/// A problem [`lint`] found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lint {
+    pub rule: LintRule,
+    pub severity: Severity,
+    /// The position of the cell it's about, if it's about a cell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cell_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cell_id: Option<String>,
+    pub message: String,
+}
+
+impl Lint {
+    fn new(rule: LintRule, message: String) -> Self {
+        Self {
+            rule,
+            severity: rule.severity(),
+            cell_index: None,
+            cell_id: None,
+            message,
+        }
+    }
+
+    fn for_cell(rule: LintRule, index: usize, cell: &Cell, message: String) -> Self {
+        Self {
+            cell_index: Some(index),
+            cell_id: Some(cell.id().to_string()),
+            ..Self::new(rule, message)
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.severity, self.rule.name())?;
+        if let (Some(index), Some(id)) = (self.cell_index, &self.cell_id) {
+            write!(f, " cell {} ({})", index, id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintOptions {
+    /// The most output a cell can have before it's [huge](LintRule::HugeOutput).
+    pub max_output_bytes: usize,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+}
+
+/// Read a notebook of any supported version, upgrading older ones to v4.5.
+///
+/// Unlike [`parse_notebook`](crate::parse_notebook), v4.5 notebooks with
+/// duplicate cell ids are read rather than rejected.
+pub fn parse_for_lint(json: &str) -> anyhow::Result<Notebook> {
+    let mut value: Value = serde_json::from_str(json)?;
+    if value["nbformat"] != 4 || value["nbformat_minor"] != 5 {
+        return Ok(match crate::parse_notebook(json)? {
+            crate::Notebook::V4(notebook) => notebook,
+            crate::Notebook::Legacy(notebook) => upgrade_legacy_notebook(notebook)?,
+            crate::Notebook::V3(notebook) => upgrade_v3_notebook(notebook)?,
+        });
+    }
+
+    // Cells are read one by one, which skips the check for unique ids
+    let cells = match value.get_mut("cells") {
+        Some(cells) => cells.take(),
+        None => return Err(NotebookError::ValidationError("Missing cells".to_string()).into()),
+    };
+    value["cells"] = Value::Array(Vec::new());
+    let mut notebook: Notebook = serde_json::from_value(value)?;
+    notebook.cells = serde_json::from_value::<Vec<Value>>(cells)?
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<Result<_, _>>()?;
+    Ok(notebook)
+}
+
+pub fn analyze(notebook: &Notebook) -> NotebookStats {
+    let mut stats = NotebookStats {
+        has_kernelspec: notebook.metadata.kernelspec.is_some(),
+        has_language_info: notebook.metadata.language_info.is_some(),
+        ..Default::default()
+    };
+
+    let mut last_execution_count = None;
+    for cell in &notebook.cells {
+        let (execution_count, outputs) = match cell {
+            Cell::Markdown { .. } => {
+                stats.markdown_cells += 1;
+                continue;
+            }
+            Cell::Raw { .. } => {
+                stats.raw_cells += 1;
+                continue;
+            }
+            Cell::Code {
+                execution_count,
+                outputs,
+                ..
+            } => (execution_count, outputs),
+        };
+        stats.code_cells += 1;
+        stats.outputs += outputs.len();
+        for output in outputs {
+            for (mime_type, bytes) in output_sizes(output) {
+                *stats.output_bytes.entry(mime_type).or_default() += bytes;
+            }
+        }
+
+        match (last_execution_count, execution_count) {
+            (_, None) => stats.unexecuted_code_cells += 1,
+            (Some(last), Some(count)) if *count > last + 1 => stats.execution_count_gaps += 1,
+            _ => {}
+        }
+        if execution_count.is_some() {
+            last_execution_count = *execution_count;
+        }
+    }
+    stats
+}
+
+/// Check `notebook` against every [`LintRule`], in the order of the cells
+/// they're about, after the notebook-wide ones.
+pub fn lint(notebook: &Notebook, options: &LintOptions) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    if notebook.metadata.kernelspec.is_none() {
+        lints.push(Lint::new(
+            LintRule::MissingKernelspec,
+            "The notebook has no kernelspec metadata".to_string(),
+        ));
+    }
+    if notebook.metadata.language_info.is_none() {
+        lints.push(Lint::new(
+            LintRule::MissingLanguageInfo,
+            "The notebook has no language_info metadata".to_string(),
+        ));
+    }
+
+    let mut ids = HashSet::new();
+    let mut last_execution_count = None;
+    for (index, cell) in notebook.cells.iter().enumerate() {
+        if !ids.insert(cell.id().as_str()) {
+            lints.push(Lint::for_cell(
+                LintRule::DuplicateCellId,
+                index,
+                cell,
+                format!("Another cell already has the id {}", cell.id()),
+            ));
+        }
+
+        let Cell::Code {
+            execution_count,
+            outputs,
+            ..
+        } = cell
+        else {
+            continue;
+        };
+
+        let bytes: usize = outputs
+            .iter()
+            .flat_map(output_sizes)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        if bytes > options.max_output_bytes {
+            lints.push(Lint::for_cell(
+                LintRule::HugeOutput,
+                index,
+                cell,
+                format!(
+                    "Outputs are {} bytes, more than the limit of {}",
+                    bytes, options.max_output_bytes
+                ),
+            ));
+        }
+
+        if let Some(count) = *execution_count {
+            if let Some(last) = last_execution_count.filter(|last| count <= *last) {
+                lints.push(Lint::for_cell(
+                    LintRule::OutOfOrderExecution,
+                    index,
+                    cell,
+                    format!(
+                        "Execution count {} comes after {}; run the notebook from the top",
+                        count, last
+                    ),
+                ));
+            }
+            last_execution_count = Some(count);
+        }
+    }
+    lints
+}
+
+/// The bytes of data in `output` for each of its mimetypes.
+fn output_sizes(output: &Output) -> Vec<(String, usize)> {
+    match output {
+        Output::Stream { name, text } => {
+            let mime_type = match name.as_str() {
+                "stderr" => "application/vnd.jupyter.stderr",
+                _ => "application/vnd.jupyter.stdout",
+            };
+            vec![(mime_type.to_string(), text.0.len())]
+        }
+        Output::DisplayData(display_data) => media_sizes(&display_data.data),
+        Output::ExecuteResult(execute_result) => media_sizes(&execute_result.data),
+        Output::Error(error) => vec![(
+            "application/vnd.jupyter.stderr".to_string(),
+            error.traceback.iter().map(String::len).sum(),
+        )],
+    }
+}
+
+fn media_sizes(media: &jupyter_protocol::media::Media) -> Vec<(String, usize)> {
+    let Ok(Value::Object(bundle)) = serde_json::to_value(media) else {
+        return Vec::new();
+    };
+    bundle
+        .into_iter()
+        .map(|(mime_type, data)| {
+            let bytes = match &data {
+                Value::String(text) => text.len(),
+                data => data.to_string().len(),
+            };
+            (mime_type, bytes)
+        })
+        .collect()
+}
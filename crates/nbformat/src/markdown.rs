@@ -0,0 +1,127 @@
+//! Render an executed notebook as a Markdown document.
+//!
+//! Markdown cells are kept as written and code cells become fenced blocks in
+//! the notebook's language, followed by their outputs. Images are embedded as
+//! `data:` URIs. Markdown can't size an image, so images with a size or
+//! `unconfined` in their metadata are written as `<img>` tags, which most
+//! Markdown renderers pass through.
+//!
+//! ```rust
+//! use nbformat::{markdown::render_markdown, parse_notebook, Notebook};
+//!
+//! let json = r#"{
+//!     "nbformat": 4, "nbformat_minor": 5,
+//!     "metadata": {"language_info": {"name": "python"}},
+//!     "cells": [{
+//!         "id": "a", "cell_type": "code", "metadata": {}, "execution_count": 1,
+//!         "source": ["print('hi')"],
+//!         "outputs": [{"output_type": "stream", "name": "stdout", "text": ["hi\n"]}]
+//!     }]
+//! }"#;
+//! let Notebook::V4(notebook) = parse_notebook(json).unwrap() else {
+//!     unreachable!()
+//! };
+//! assert_eq!(
+//!     render_markdown(&notebook),
+//!     "```python\nprint('hi')\n```\n\n```\nhi\n```\n"
+//! );
+//! ```
+use jupyter_protocol::ansi::strip_ansi;
+use jupyter_protocol::media::{DisplayMetadata, MediaMetadata, MediaType, RankProfile};
+
+use crate::html::image;
+use crate::v4::{Cell, Notebook, Output};
+
+/// Render `notebook` as Markdown, with its blocks separated by blank lines.
+pub fn render_markdown(notebook: &Notebook) -> String {
+    let language = notebook
+        .metadata
+        .language_info
+        .as_ref()
+        .map(|info| info.name.as_str())
+        .unwrap_or_default();
+
+    let mut blocks = Vec::new();
+    for cell in &notebook.cells {
+        match cell {
+            Cell::Markdown { source, .. } | Cell::Raw { source, .. } => {
+                blocks.push(source.concat().trim_end().to_string())
+            }
+            Cell::Code {
+                source, outputs, ..
+            } => {
+                blocks.push(fenced(&source.concat(), language));
+                blocks.extend(outputs.iter().filter_map(render_output));
+            }
+        }
+    }
+    blocks.retain(|block| !block.is_empty());
+
+    let mut markdown = blocks.join("\n\n");
+    markdown.push('\n');
+    markdown
+}
+
+fn render_output(output: &Output) -> Option<String> {
+    match output {
+        Output::Stream { text, .. } => Some(fenced(&strip_ansi(&text.0), "")),
+        Output::DisplayData(display_data) => {
+            let media = display_data.data.richest(rank)?;
+            render_media(media, &display_data.media_metadata(media.mime_type()))
+        }
+        Output::ExecuteResult(execute_result) => {
+            let media = execute_result.data.richest(rank)?;
+            render_media(media, &execute_result.media_metadata(media.mime_type()))
+        }
+        Output::Error(error) => Some(fenced(&strip_ansi(&error.traceback.join("\n")), "")),
+    }
+}
+
+/// Markdown outputs are kept as Markdown, ahead of their HTML.
+fn rank(media_type: &MediaType) -> usize {
+    match media_type {
+        MediaType::Markdown(_) => 55,
+        _ => RankProfile::NotebookExport.rank(media_type),
+    }
+}
+
+fn render_media(media_type: &MediaType, metadata: &MediaMetadata) -> Option<String> {
+    let rendered = match media_type {
+        MediaType::Png(data) | MediaType::Jpeg(data) | MediaType::Gif(data) => {
+            let mime_type = media_type.mime_type();
+            if metadata.width.is_some()
+                || metadata.height.is_some()
+                || metadata.unconfined == Some(true)
+            {
+                let mut html = String::new();
+                image(mime_type, data, metadata, &mut html);
+                html
+            } else {
+                let data: String = data.split_whitespace().collect();
+                format!("![{}](data:{};base64,{})", mime_type, mime_type, data)
+            }
+        }
+        MediaType::Svg(text)
+        | MediaType::Html(text)
+        | MediaType::Markdown(text)
+        | MediaType::Latex(text) => text.trim_end().to_string(),
+        MediaType::Json(json) => fenced(&serde_json::to_string_pretty(json).ok()?, "json"),
+        MediaType::Plain(text) => fenced(&strip_ansi(text), ""),
+        _ => return None,
+    };
+    Some(rendered)
+}
+
+/// `text` in a code fence longer than any run of backticks inside it.
+fn fenced(text: &str, language: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!(
+        "{fence}{language}\n{}\n{fence}",
+        text.trim_end_matches('\n')
+    )
+}
@@ -0,0 +1,211 @@
+//! Papermill-compatible parameter injection.
+//!
+//! A notebook marks its default parameters with a code cell tagged `parameters`.
+//! [`inject_parameters`] adds a code cell tagged `injected-parameters` right after
+//! it that overrides those defaults, and records the values under
+//! `metadata.papermill.parameters`, the same way papermill does. Running the
+//! same notebook again replaces the previously injected cell.
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::v4::{Cell, CellMetadata, Notebook};
+use crate::NotebookError;
+
+pub const PARAMETERS_TAG: &str = "parameters";
+pub const INJECTED_PARAMETERS_TAG: &str = "injected-parameters";
+
+fn has_tag(cell: &Cell, tag: &str) -> bool {
+    cell.metadata()
+        .tags
+        .as_ref()
+        .is_some_and(|tags| tags.iter().any(|t| t == tag))
+}
+
+/// The index of the first cell tagged `parameters`, if any.
+pub fn find_parameters_cell(notebook: &Notebook) -> Option<usize> {
+    notebook
+        .cells
+        .iter()
+        .position(|cell| has_tag(cell, PARAMETERS_TAG))
+}
+
+/// Inject `parameters` into the notebook as a new code cell.
+///
+/// The cell goes after the `parameters` cell, or at the top if there isn't one.
+/// The notebook's language (from the kernelspec or language_info, defaulting to
+/// Python) decides how values are written; Python and R are supported.
+pub fn inject_parameters(
+    notebook: &mut Notebook,
+    parameters: &HashMap<String, Value>,
+) -> Result<(), NotebookError> {
+    let language = notebook_language(notebook);
+    let source = render_parameters(&language, parameters)?;
+
+    notebook
+        .cells
+        .retain(|cell| !has_tag(cell, INJECTED_PARAMETERS_TAG));
+
+    let index = find_parameters_cell(notebook).map_or(0, |index| index + 1);
+    notebook.cells.insert(
+        index,
+        Cell::Code {
+            id: uuid::Uuid::new_v4().into(),
+            metadata: CellMetadata {
+                id: None,
+                collapsed: None,
+                scrolled: None,
+                deletable: None,
+                editable: None,
+                format: None,
+                name: None,
+                tags: Some(vec![INJECTED_PARAMETERS_TAG.to_string()]),
+                jupyter: None,
+                execution: None,
+                additional: HashMap::new(),
+            },
+            execution_count: None,
+            source,
+            outputs: Vec::new(),
+        },
+    );
+
+    let papermill = notebook
+        .metadata
+        .additional
+        .entry("papermill".to_string())
+        .or_insert_with(|| Value::Object(Default::default()));
+    if !papermill.is_object() {
+        *papermill = Value::Object(Default::default());
+    }
+    papermill["parameters"] = Value::Object(
+        parameters
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+    );
+
+    Ok(())
+}
+
+fn notebook_language(notebook: &Notebook) -> String {
+    notebook
+        .metadata
+        .kernelspec
+        .as_ref()
+        .and_then(|kernelspec| kernelspec.language.clone())
+        .or_else(|| {
+            notebook
+                .metadata
+                .language_info
+                .as_ref()
+                .map(|language_info| language_info.name.clone())
+        })
+        .unwrap_or_else(|| "python".to_string())
+        .to_lowercase()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Render parameters as notebook source lines, sorted by name.
+fn render_parameters(
+    language: &str,
+    parameters: &HashMap<String, Value>,
+) -> Result<Vec<String>, NotebookError> {
+    let literal: fn(&Value) -> String = match language {
+        "python" => python_literal,
+        "r" => r_literal,
+        other => {
+            return Err(NotebookError::ValidationError(format!(
+                "Parameter injection is not supported for {} notebooks",
+                other
+            )))
+        }
+    };
+
+    let mut names: Vec<&String> = parameters.keys().collect();
+    names.sort();
+
+    let mut lines = vec!["# Parameters".to_string()];
+    for name in names {
+        // Dots are valid in R names but not in Python
+        if !is_identifier(name) || (language == "python" && name.contains('.')) {
+            return Err(NotebookError::ValidationError(format!(
+                "Invalid parameter name: {}",
+                name
+            )));
+        }
+        lines.push(format!("{} = {}", name, literal(&parameters[name])));
+    }
+
+    // Notebook source lines carry their newline, except the last
+    let count = lines.len();
+    Ok(lines
+        .into_iter()
+        .enumerate()
+        .map(|(index, line)| {
+            if index + 1 < count {
+                format!("{}\n", line)
+            } else {
+                line
+            }
+        })
+        .collect())
+}
+
+fn string_literal(s: &str) -> String {
+    // JSON string escapes are valid in both Python and R string literals
+    Value::String(s.to_string()).to_string()
+}
+
+fn python_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "None".to_string(),
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(s) => string_literal(s),
+        Value::Array(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(python_literal)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Object(map) => format!(
+            "{{{}}}",
+            map.iter()
+                .map(|(key, value)| format!("{}: {}", string_literal(key), python_literal(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn r_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(true) => "TRUE".to_string(),
+        Value::Bool(false) => "FALSE".to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(s) => string_literal(s),
+        Value::Array(items) => format!(
+            "list({})",
+            items.iter().map(r_literal).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Object(map) => format!(
+            "list({})",
+            map.iter()
+                .map(|(key, value)| format!("{} = {}", string_literal(key), r_literal(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
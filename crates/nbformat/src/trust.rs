@@ -0,0 +1,378 @@
+//! Notebook trust, compatible with `jupyter trust`.
+//!
+//! Opening a notebook shouldn't run someone else's Javascript, so frontends
+//! only render HTML and Javascript outputs the user produced themselves or
+//! explicitly trusted. As in Jupyter, a notebook is trusted when its signature,
+//! an HMAC-SHA256 over its contents keyed with a per-user secret, is in the
+//! user's store of trusted signatures. Executing a notebook and signing it
+//! again keeps it trusted. Cells in an untrusted notebook are still trusted if
+//! they only have safe outputs, or if they were run in this session and marked
+//! with [`mark_cells`].
+//!
+//! Signatures are computed the same way as nbformat's `NotebookNotary`, so
+//! they match for the same secret. Trusted signatures are kept in the same
+//! `nbsignatures.db` SQLite database, so notebooks trusted with `runt trust`
+//! are trusted in Jupyter and the other way around.
+//!
+//! ```rust,no_run
+//! use nbformat::trust::Notary;
+//! # fn main() -> anyhow::Result<()> {
+//! # let notebook: serde_json::Value = serde_json::from_str("{}")?;
+//! let notary = Notary::open(std::path::Path::new("/home/me/.local/share/jupyter"))?;
+//! if !notary.check(&notebook)? {
+//!     // Render HTML and Javascript outputs as text
+//! }
+//! notary.sign(&notebook)?;
+//! # Ok(())
+//! # }
+//! ```
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use jupyter_protocol::canonical::format_float;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde_json::Value;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::v4::{Cell, Notebook, Output};
+use crate::NotebookError;
+
+/// The digest used for signatures, as recorded by `jupyter trust`.
+pub const SIGNATURE_ALGORITHM: &str = "sha256";
+
+/// Mimetypes that are safe to show from an untrusted notebook.
+const SAFE_MIME_TYPES: [&str; 3] = ["text/plain", "image/png", "image/jpeg"];
+
+/// How many signatures the store keeps before forgetting the least recently
+/// seen ones, Jupyter's default `NotebookNotary.cache_size`.
+pub const CACHE_SIZE: usize = 65535;
+
+#[derive(Error, Debug)]
+pub enum TrustError {
+    #[error("Trust store error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Trust database error: {0}")]
+    Database(#[from] rusqlite::Error),
+}
+
+/// The signature of a notebook's JSON.
+///
+/// Like nbformat, the `signature`, `orig_nbformat` and `orig_nbformat_minor`
+/// notebook metadata and each cell's `trusted` metadata are left out.
+pub fn compute_signature(notebook: &Value, secret: &[u8]) -> String {
+    let mut notebook = notebook.clone();
+    if let Some(metadata) = notebook.get_mut("metadata").and_then(Value::as_object_mut) {
+        for key in ["signature", "orig_nbformat", "orig_nbformat_minor"] {
+            metadata.remove(key);
+        }
+    }
+    if let Some(cells) = notebook.get_mut("cells").and_then(Value::as_array_mut) {
+        for cell in cells {
+            if let Some(metadata) = cell.get_mut("metadata").and_then(Value::as_object_mut) {
+                metadata.remove("trusted");
+            }
+        }
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    update_with_everything(&mut mac, &notebook);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Feed `value` to the HMAC like nbformat's `yield_everything`: object keys in
+/// sorted order followed by their values, and scalars as Python's `str()`.
+fn update_with_everything(mac: &mut Hmac<Sha256>, value: &Value) {
+    match value {
+        Value::Object(object) => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort();
+            for key in keys {
+                mac.update(key.as_bytes());
+                update_with_everything(mac, &object[key]);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                update_with_everything(mac, item);
+            }
+        }
+        Value::String(string) => mac.update(string.as_bytes()),
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => mac.update(format_float(float).as_bytes()),
+            _ => mac.update(number.to_string().as_bytes()),
+        },
+        Value::Bool(true) => mac.update(b"True"),
+        Value::Bool(false) => mac.update(b"False"),
+        Value::Null => mac.update(b"None"),
+    }
+}
+
+/// The signature of a parsed notebook, see [`compute_signature`].
+pub fn notebook_signature(notebook: &Notebook, secret: &[u8]) -> Result<String, NotebookError> {
+    Ok(compute_signature(&serde_json::to_value(notebook)?, secret))
+}
+
+/// Whether `output` can be shown from an untrusted notebook: anything but
+/// rich output with mimetypes other than plain text, PNG and JPEG.
+pub fn is_safe_output(output: &Output) -> bool {
+    let media = match output {
+        Output::DisplayData(display_data) => &display_data.data,
+        Output::ExecuteResult(execute_result) => &execute_result.data,
+        Output::Stream { .. } | Output::Error(_) => return true,
+    };
+    media
+        .content
+        .iter()
+        .all(|media_type| SAFE_MIME_TYPES.contains(&media_type.mime_type()))
+}
+
+/// Whether `cell`'s outputs can be rendered in full when the notebook itself
+/// isn't trusted. Cells marked with [`mark_cells`] keep that mark, otherwise
+/// a code cell is trusted if all of its outputs are safe.
+pub fn is_cell_trusted(cell: &Cell) -> bool {
+    let Cell::Code {
+        metadata, outputs, ..
+    } = cell
+    else {
+        return true;
+    };
+    match metadata.additional.get("trusted").and_then(Value::as_bool) {
+        Some(trusted) => trusted,
+        None => outputs.iter().all(is_safe_output),
+    }
+}
+
+/// Mark every code cell as trusted or untrusted in its `trusted` metadata,
+/// e.g. after running the notebook. Signatures ignore this metadata.
+pub fn mark_cells(notebook: &mut Notebook, trusted: bool) {
+    for cell in &mut notebook.cells {
+        if let Cell::Code { metadata, .. } = cell {
+            metadata
+                .additional
+                .insert("trusted".to_string(), Value::Bool(trusted));
+        }
+    }
+}
+
+/// Read the secret signatures are keyed with, creating it if there isn't one.
+///
+/// New secrets are 1024 random bytes, base64 encoded like Jupyter's
+/// `notebook_secret`. Like Jupyter, the file's bytes are the key as they are.
+pub fn read_or_create_secret(path: &Path) -> io::Result<Vec<u8>> {
+    match std::fs::read(path) {
+        Ok(secret) => return Ok(secret),
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        Err(_) => {}
+    }
+
+    let mut random = [0u8; 1024];
+    getrandom::fill(&mut random).map_err(io::Error::other)?;
+    let encoded = BASE64_STANDARD.encode(random);
+    let mut secret = String::with_capacity(encoded.len() + encoded.len() / 76 + 1);
+    for line in encoded.as_bytes().chunks(76) {
+        secret.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        secret.push('\n');
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_private(path, secret.as_bytes())?;
+    Ok(secret.into_bytes())
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+/// The signatures of trusted notebooks, in the `nbsignatures` table Jupyter's
+/// `NotebookNotary` uses.
+#[derive(Debug)]
+pub struct TrustStore {
+    path: PathBuf,
+    db: Connection,
+    read_only: bool,
+}
+
+impl TrustStore {
+    /// Open the database at `path`, creating it and its table if needed.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, TrustError> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let db = Connection::open(&path)?;
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS nbsignatures
+             (
+                 id integer PRIMARY KEY AUTOINCREMENT,
+                 algorithm text,
+                 signature text,
+                 path text,
+                 last_seen timestamp
+             );
+             CREATE INDEX IF NOT EXISTS algosig ON nbsignatures(algorithm, signature);",
+        )?;
+        Ok(Self {
+            path,
+            db,
+            read_only: false,
+        })
+    }
+
+    /// Open the database at `path` without changing it, or `None` if there
+    /// isn't one. Checking a signature in a read-only store doesn't mark it
+    /// as seen.
+    pub fn open_read_only(path: impl Into<PathBuf>) -> Result<Option<Self>, TrustError> {
+        let path = path.into();
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+        let db = Connection::open_with_flags(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Ok(Some(Self {
+            path,
+            db,
+            read_only: true,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `signature` is trusted. Like Jupyter, this marks it as seen,
+    /// unless the store was opened read-only.
+    pub fn contains(&self, signature: &str) -> Result<bool, TrustError> {
+        let found = self
+            .db
+            .query_row(
+                "SELECT id FROM nbsignatures WHERE algorithm = ?1 AND signature = ?2",
+                params![SIGNATURE_ALGORITHM, signature],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if found && !self.read_only {
+            self.db.execute(
+                "UPDATE nbsignatures SET last_seen = ?1 WHERE algorithm = ?2 AND signature = ?3",
+                params![last_seen(), SIGNATURE_ALGORITHM, signature],
+            )?;
+        }
+        Ok(found)
+    }
+
+    /// Trust `signature`, forgetting the least recently seen signatures once
+    /// there are more than [`CACHE_SIZE`].
+    pub fn insert(&self, signature: &str) -> Result<(), TrustError> {
+        if self.contains(signature)? {
+            return Ok(());
+        }
+        self.db.execute(
+            "INSERT INTO nbsignatures (algorithm, signature, last_seen) VALUES (?1, ?2, ?3)",
+            params![SIGNATURE_ALGORITHM, signature, last_seen()],
+        )?;
+        let count: usize = self
+            .db
+            .query_row("SELECT Count(*) FROM nbsignatures", [], |row| row.get(0))?;
+        if count > CACHE_SIZE {
+            self.db.execute(
+                "DELETE FROM nbsignatures WHERE id IN
+                 (SELECT id FROM nbsignatures ORDER BY last_seen DESC LIMIT -1 OFFSET ?1)",
+                params![CACHE_SIZE * 3 / 4],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, signature: &str) -> Result<(), TrustError> {
+        self.db.execute(
+            "DELETE FROM nbsignatures WHERE algorithm = ?1 AND signature = ?2",
+            params![SIGNATURE_ALGORITHM, signature],
+        )?;
+        Ok(())
+    }
+}
+
+/// Now, in the format Python's `sqlite3` stores `datetime.utcnow()` as.
+fn last_seen() -> String {
+    chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now())
+        .naive_utc()
+        .format("%Y-%m-%d %H:%M:%S%.6f")
+        .to_string()
+}
+
+/// Signs notebooks and checks their signatures, like Jupyter's `NotebookNotary`.
+#[derive(Debug)]
+pub struct Notary {
+    secret: Vec<u8>,
+    store: TrustStore,
+}
+
+impl Notary {
+    pub fn new(secret: Vec<u8>, store: TrustStore) -> Self {
+        Self { secret, store }
+    }
+
+    /// The notary for a Jupyter data directory, using its `notebook_secret`
+    /// and `nbsignatures.db`, the same files as `jupyter trust`.
+    pub fn open(data_dir: &Path) -> Result<Self, TrustError> {
+        let secret = read_or_create_secret(&data_dir.join("notebook_secret"))?;
+        let store = TrustStore::open(data_dir.join("nbsignatures.db"))?;
+        Ok(Self::new(secret, store))
+    }
+
+    /// The notary for a Jupyter data directory, without creating its secret
+    /// or database. `None` if either is missing, as then nothing was signed.
+    pub fn open_read_only(data_dir: &Path) -> Result<Option<Self>, TrustError> {
+        let secret = match std::fs::read(data_dir.join("notebook_secret")) {
+            Ok(secret) => secret,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let store = TrustStore::open_read_only(data_dir.join("nbsignatures.db"))?;
+        Ok(store.map(|store| Self::new(secret, store)))
+    }
+
+    pub fn signature(&self, notebook: &Value) -> String {
+        compute_signature(notebook, &self.secret)
+    }
+
+    /// Whether `notebook` was signed as it is now.
+    pub fn check(&self, notebook: &Value) -> Result<bool, TrustError> {
+        self.store.contains(&self.signature(notebook))
+    }
+
+    /// Trust `notebook`.
+    pub fn sign(&self, notebook: &Value) -> Result<(), TrustError> {
+        self.store.insert(&self.signature(notebook))
+    }
+
+    /// Stop trusting `notebook`.
+    pub fn unsign(&self, notebook: &Value) -> Result<(), TrustError> {
+        self.store.remove(&self.signature(notebook))
+    }
+}
@@ -0,0 +1,259 @@
+//! The nbformat 3 notebook model.
+//!
+//! Version 3 notebooks keep their cells in `worksheets`, have `heading` cells,
+//! and name outputs differently (`pyout`, `pyerr`, short keys like `png` instead
+//! of MIME types). Parse them with [`parse_notebook`](crate::parse_notebook) and
+//! convert them with [`upgrade_v3_notebook`](crate::upgrade_v3_notebook).
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::v4::{self, deserialize_multiline_string, MultilineString};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Notebook {
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    pub nbformat: i32,
+    #[serde(default)]
+    pub nbformat_minor: i32,
+    #[serde(default)]
+    pub worksheets: Vec<Worksheet>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Worksheet {
+    #[serde(default)]
+    pub cells: Vec<Cell>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "cell_type")]
+pub enum Cell {
+    #[serde(rename = "markdown")]
+    Markdown {
+        #[serde(default)]
+        metadata: Map<String, Value>,
+        #[serde(deserialize_with = "deserialize_multiline_string")]
+        source: MultilineString,
+    },
+    #[serde(rename = "heading")]
+    Heading {
+        #[serde(default)]
+        metadata: Map<String, Value>,
+        #[serde(deserialize_with = "deserialize_multiline_string")]
+        source: MultilineString,
+        #[serde(default = "default_heading_level")]
+        level: usize,
+    },
+    #[serde(rename = "raw", alias = "plaintext")]
+    Raw {
+        #[serde(default)]
+        metadata: Map<String, Value>,
+        #[serde(deserialize_with = "deserialize_multiline_string")]
+        source: MultilineString,
+    },
+    #[serde(rename = "code")]
+    Code {
+        #[serde(default)]
+        metadata: Map<String, Value>,
+        #[serde(deserialize_with = "deserialize_multiline_string")]
+        input: MultilineString,
+        #[serde(default)]
+        language: Option<String>,
+        #[serde(default)]
+        collapsed: Option<bool>,
+        #[serde(default)]
+        prompt_number: Option<i32>,
+        #[serde(default)]
+        outputs: Vec<Output>,
+    },
+}
+
+fn default_heading_level() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "output_type")]
+pub enum Output {
+    #[serde(rename = "pyout")]
+    Pyout {
+        #[serde(default)]
+        prompt_number: Option<i32>,
+        #[serde(default)]
+        metadata: Map<String, Value>,
+        /// Output data keyed by short names like `text` and `png`
+        #[serde(flatten)]
+        data: Map<String, Value>,
+    },
+    #[serde(rename = "display_data")]
+    DisplayData {
+        #[serde(default)]
+        metadata: Map<String, Value>,
+        #[serde(flatten)]
+        data: Map<String, Value>,
+    },
+    #[serde(rename = "stream")]
+    Stream {
+        #[serde(default = "default_stream")]
+        stream: String,
+        #[serde(deserialize_with = "deserialize_multiline_string")]
+        text: MultilineString,
+    },
+    #[serde(rename = "pyerr")]
+    Pyerr {
+        ename: String,
+        evalue: String,
+        #[serde(default)]
+        traceback: Vec<String>,
+    },
+}
+
+fn default_stream() -> String {
+    "stdout".to_string()
+}
+
+/// The MIME type for a v3 output key. Unknown keys are assumed to be MIME types already.
+pub fn mime_type(key: &str) -> &str {
+    match key {
+        "text" => "text/plain",
+        "html" => "text/html",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpeg" => "image/jpeg",
+        "latex" => "text/latex",
+        "json" => "application/json",
+        "javascript" => "application/javascript",
+        "pdf" => "application/pdf",
+        key => key,
+    }
+}
+
+/// Split text into notebook source lines, each keeping its newline.
+fn source_lines(text: &str) -> Vec<String> {
+    text.split_inclusive('\n').map(String::from).collect()
+}
+
+fn cell_metadata(metadata: Map<String, Value>) -> anyhow::Result<v4::CellMetadata> {
+    Ok(serde_json::from_value(Value::Object(metadata))?)
+}
+
+pub(crate) fn upgrade_cell(cell: Cell) -> anyhow::Result<v4::Cell> {
+    let id = uuid::Uuid::new_v4().into();
+    Ok(match cell {
+        Cell::Markdown { metadata, source } => v4::Cell::Markdown {
+            id,
+            metadata: cell_metadata(metadata)?,
+            source: source_lines(&source.0),
+            attachments: None,
+        },
+        // Headings become single line markdown headings, like nbformat does
+        Cell::Heading {
+            metadata,
+            source,
+            level,
+        } => v4::Cell::Markdown {
+            id,
+            metadata: cell_metadata(metadata)?,
+            source: vec![format!(
+                "{} {}",
+                "#".repeat(level.clamp(1, 6)),
+                source.0.lines().collect::<Vec<_>>().join(" ")
+            )],
+            attachments: None,
+        },
+        Cell::Raw { metadata, source } => v4::Cell::Raw {
+            id,
+            metadata: cell_metadata(metadata)?,
+            source: source_lines(&source.0),
+        },
+        Cell::Code {
+            metadata,
+            input,
+            collapsed,
+            prompt_number,
+            outputs,
+            ..
+        } => {
+            let mut metadata = cell_metadata(metadata)?;
+            if collapsed.is_some() {
+                metadata.collapsed = collapsed;
+            }
+            v4::Cell::Code {
+                id,
+                metadata,
+                execution_count: prompt_number,
+                source: source_lines(&input.0),
+                outputs: outputs
+                    .into_iter()
+                    .map(|output| upgrade_output(output, prompt_number))
+                    .collect::<anyhow::Result<_>>()?,
+            }
+        }
+    })
+}
+
+/// Rename short keys to MIME types, join multiline text, and parse JSON data.
+fn upgrade_mime_bundle(data: Map<String, Value>) -> Map<String, Value> {
+    data.into_iter()
+        .map(|(key, value)| {
+            let mime_type = mime_type(&key).to_string();
+            let value = match value {
+                Value::Array(lines) if lines.iter().all(Value::is_string) => Value::String(
+                    lines
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<_>>()
+                        .concat(),
+                ),
+                value => value,
+            };
+            let value = match (mime_type.as_str(), value) {
+                ("application/json", Value::String(json)) => {
+                    serde_json::from_str(&json).unwrap_or(Value::String(json))
+                }
+                (_, value) => value,
+            };
+            (mime_type, value)
+        })
+        .collect()
+}
+
+fn upgrade_output_metadata(metadata: Map<String, Value>) -> Map<String, Value> {
+    metadata
+        .into_iter()
+        .map(|(key, value)| (mime_type(&key).to_string(), value))
+        .collect()
+}
+
+fn upgrade_output(output: Output, prompt_number: Option<i32>) -> anyhow::Result<v4::Output> {
+    Ok(match output {
+        Output::Stream { stream, text } => v4::Output::Stream { name: stream, text },
+        Output::Pyerr {
+            ename,
+            evalue,
+            traceback,
+        } => v4::Output::Error(v4::ErrorOutput {
+            ename,
+            evalue,
+            traceback,
+        }),
+        Output::Pyout {
+            prompt_number: output_prompt_number,
+            metadata,
+            data,
+        } => serde_json::from_value(json!({
+            "output_type": "execute_result",
+            "execution_count": output_prompt_number.or(prompt_number).unwrap_or(0).max(0),
+            "data": upgrade_mime_bundle(data),
+            "metadata": upgrade_output_metadata(metadata),
+        }))?,
+        Output::DisplayData { metadata, data } => serde_json::from_value(json!({
+            "output_type": "display_data",
+            "data": upgrade_mime_bundle(data),
+            "metadata": upgrade_output_metadata(metadata),
+        }))?,
+    })
+}
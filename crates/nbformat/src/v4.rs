@@ -2,7 +2,14 @@ use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use uuid::Uuid;
 
-use jupyter_protocol::{media::serialize_media_for_notebook, media::Media, ExecutionCount};
+use chrono::{DateTime, SecondsFormat, Utc};
+use jupyter_protocol::{
+    media::serialize_media_for_notebook, media::DisplayMetadata, media::Media, CodeMirrorMode,
+    ExecutionCount, ExecutionTimings, JupyterKernelspec, JupyterMessageContent, KernelInfoReply,
+    StreamContent,
+};
+
+use crate::NotebookError;
 
 use core::fmt;
 use std::{
@@ -32,7 +39,9 @@ impl<'de> Deserialize<'de> for MultilineString {
         Ok(MultilineString(s))
     }
 }
-fn deserialize_multiline_string<'de, D>(deserializer: D) -> Result<MultilineString, D::Error>
+pub(crate) fn deserialize_multiline_string<'de, D>(
+    deserializer: D,
+) -> Result<MultilineString, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -89,6 +98,26 @@ pub struct ExecuteResult {
     pub metadata: serde_json::Map<String, Value>,
 }
 
+impl DisplayMetadata for DisplayData {
+    fn metadata_map(&self) -> &serde_json::Map<String, Value> {
+        &self.metadata
+    }
+
+    fn metadata_map_mut(&mut self) -> &mut serde_json::Map<String, Value> {
+        &mut self.metadata
+    }
+}
+
+impl DisplayMetadata for ExecuteResult {
+    fn metadata_map(&self) -> &serde_json::Map<String, Value> {
+        &self.metadata
+    }
+
+    fn metadata_map_mut(&mut self) -> &mut serde_json::Map<String, Value> {
+        &mut self.metadata
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ErrorOutput {
     pub ename: String,
@@ -150,6 +179,90 @@ pub enum CodemirrorMode {
     Object(Value),
 }
 
+impl Metadata {
+    /// Record the kernel that ran the notebook: `kernelspec` is what it was
+    /// started from as `kernel_name`, and `kernel_info` is its reply once it
+    /// was up.
+    ///
+    /// Fields other frontends added to the old `kernelspec` are kept.
+    pub fn sync_with_kernel(
+        &mut self,
+        kernel_name: &str,
+        kernelspec: &JupyterKernelspec,
+        kernel_info: &KernelInfoReply,
+    ) {
+        let additional = self
+            .kernelspec
+            .take()
+            .map(|kernelspec| kernelspec.additional)
+            .unwrap_or_default();
+        self.kernelspec = Some(KernelSpec {
+            display_name: kernelspec.display_name.clone(),
+            name: kernel_name.to_string(),
+            language: Some(kernelspec.language.clone()),
+            additional,
+        });
+        self.sync_language_info(kernel_info);
+    }
+
+    /// Replace `language_info` with what the kernel reports, for when the
+    /// kernelspec isn't known, like a kernel someone else started.
+    ///
+    /// Fields the kernel leaves out are dropped rather than kept from an older
+    /// kernel. A `kernelspec` without a language gets the kernel's.
+    pub fn sync_language_info(&mut self, kernel_info: &KernelInfoReply) {
+        let language_info = LanguageInfo::from(&kernel_info.language_info);
+        if let Some(kernelspec) = &mut self.kernelspec {
+            if kernelspec.language.is_none() {
+                kernelspec.language = Some(language_info.name.clone());
+            }
+        }
+        self.language_info = Some(language_info);
+    }
+}
+
+impl From<&jupyter_protocol::LanguageInfo> for LanguageInfo {
+    /// A kernel's `language_info`, laid out the way Jupyter saves it in notebooks.
+    fn from(language_info: &jupyter_protocol::LanguageInfo) -> Self {
+        let mut additional = HashMap::from([
+            (
+                "mimetype".to_string(),
+                Value::String(language_info.mimetype.clone()),
+            ),
+            (
+                "file_extension".to_string(),
+                Value::String(language_info.file_extension.clone()),
+            ),
+        ]);
+        for (key, value) in [
+            ("pygments_lexer", &language_info.pygments_lexer),
+            ("nbconvert_exporter", &language_info.nbconvert_exporter),
+        ] {
+            if let Some(value) = value {
+                additional.insert(key.to_string(), Value::String(value.clone()));
+            }
+        }
+        Self {
+            name: language_info.name.clone(),
+            // evcxr reports an empty version
+            version: Some(language_info.version.clone()).filter(|version| !version.is_empty()),
+            codemirror_mode: language_info
+                .codemirror_mode
+                .as_ref()
+                .map(|mode| match mode {
+                    CodeMirrorMode::Simple(name) => CodemirrorMode::String(name.clone()),
+                    CodeMirrorMode::CustomMode { name, version } => {
+                        CodemirrorMode::Object(serde_json::json!({
+                            "name": name,
+                            "version": version,
+                        }))
+                    }
+                }),
+            additional,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct CellId(String);
 
@@ -386,6 +499,23 @@ pub struct ExecutionMetadata {
     pub additional: HashMap<String, serde_json::Value>,
 }
 
+impl From<&ExecutionTimings> for ExecutionMetadata {
+    /// Timestamps in the format JupyterLab records, e.g. `2019-08-26T17:50:29.604738Z`.
+    fn from(timings: &ExecutionTimings) -> Self {
+        let format = |date: Option<DateTime<Utc>>| {
+            date.map(|date| date.to_rfc3339_opts(SecondsFormat::Micros, true))
+        };
+        Self {
+            iopub_execute_input: format(timings.execute_input),
+            iopub_status_busy: format(timings.busy),
+            shell_execute_reply: format(timings.reply),
+            shell_execute_reply_started: format(timings.reply_started),
+            iopub_status_idle: format(timings.idle),
+            additional: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(tag = "output_type")]
 pub enum Output {
@@ -406,6 +536,75 @@ pub enum Output {
     Error(ErrorOutput),
 }
 
+/// Re-emit a saved output as the iopub message that would have produced it.
+impl From<Output> for JupyterMessageContent {
+    fn from(output: Output) -> Self {
+        match output {
+            Output::Stream { name, text } => match name.as_str() {
+                "stderr" => StreamContent::stderr(&text.0).into(),
+                _ => StreamContent::stdout(&text.0).into(),
+            },
+            Output::DisplayData(display_data) => jupyter_protocol::DisplayData {
+                data: display_data.data,
+                metadata: display_data.metadata,
+                transient: None,
+            }
+            .into(),
+            Output::ExecuteResult(execute_result) => jupyter_protocol::ExecuteResult {
+                execution_count: execute_result.execution_count,
+                data: execute_result.data,
+                metadata: execute_result.metadata,
+                transient: None,
+            }
+            .into(),
+            Output::Error(error) => jupyter_protocol::ErrorOutput {
+                ename: error.ename,
+                evalue: error.evalue,
+                traceback: error.traceback,
+            }
+            .into(),
+        }
+    }
+}
+
+/// Save an iopub message as a notebook output. Only `stream`, `display_data`,
+/// `execute_result` and `error` messages are outputs; `transient` data like
+/// the display id isn't saved.
+impl TryFrom<JupyterMessageContent> for Output {
+    type Error = NotebookError;
+
+    fn try_from(content: JupyterMessageContent) -> Result<Self, NotebookError> {
+        match content {
+            JupyterMessageContent::StreamContent(stream) => Ok(Output::Stream {
+                name: match stream.name {
+                    jupyter_protocol::Stdio::Stdout => "stdout".to_string(),
+                    jupyter_protocol::Stdio::Stderr => "stderr".to_string(),
+                },
+                text: MultilineString(stream.text),
+            }),
+            JupyterMessageContent::DisplayData(display_data) => {
+                Ok(Output::DisplayData(DisplayData {
+                    data: display_data.data,
+                    metadata: display_data.metadata,
+                }))
+            }
+            JupyterMessageContent::ExecuteResult(execute_result) => {
+                Ok(Output::ExecuteResult(ExecuteResult {
+                    execution_count: execute_result.execution_count,
+                    data: execute_result.data,
+                    metadata: execute_result.metadata,
+                }))
+            }
+            JupyterMessageContent::ErrorOutput(error) => Ok(Output::Error(ErrorOutput {
+                ename: error.ename,
+                evalue: error.evalue,
+                traceback: error.traceback,
+            })),
+            other => Err(NotebookError::NotAnOutput(other.message_type().to_string())),
+        }
+    }
+}
+
 pub fn deserialize_outputs<'de, D>(deserializer: D) -> Result<Vec<Output>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -136,7 +136,6 @@ mod test {
             let path = entry.path();
             let path_str = path.to_str().expect("Failed to convert path to string");
             if path_str.ends_with(".ipynb") {
-                // If the file starts with `test3`, let's check that we got an error
                 let notebook_json = read_notebook(path_str);
                 let notebook = parse_notebook(&notebook_json);
 
@@ -148,7 +147,6 @@ mod test {
                         path_str
                     );
                 } else if path_str.starts_with("tests/notebooks/test2")
-                    || path_str.starts_with("tests/notebooks/test3")
                     || path_str.starts_with("tests/notebooks/test4plus")
                     || path_str.starts_with("tests/notebooks/invalid")
                     || path_str.starts_with("tests/notebooks/no_min_version")
@@ -246,12 +244,44 @@ mod test {
 
         println!("Structures match in contents!");
 
-        // std::fs::write("og.json", &notebook_json).expect("Failed to write original JSON");
-        // std::fs::write("ser.json", &serialized).expect("Failed to write serialized JSON");
+        // Multiline media keeps its exact line endings, trailing newline or not
+        assert_eq!(notebook_json, serialized);
+    }
+
+    #[test]
+    fn test_media_wire_and_notebook_serializations_agree() {
+        use jupyter_protocol::media::{Media, MediaType};
+
+        fn sorted(media: &Media) -> Vec<MediaType> {
+            let mut content = media.content.clone();
+            content.sort_by(|a, b| a.mime_type().cmp(b.mime_type()));
+            content
+        }
 
-        // Right now, this Mediatypes notebook has two outputs with a differing newline at the end
-        // between the original and the serialized.
-        // assert_eq!(notebook_json, serialized);
+        for path in [
+            "tests/notebooks/Mediatypes.ipynb",
+            "tests/notebooks/test4.5.ipynb",
+        ] {
+            let Notebook::V4(notebook) = parse_notebook(&read_notebook(path)).unwrap() else {
+                panic!("Expected a v4.5 notebook");
+            };
+            for cell in &notebook.cells {
+                let Cell::Code { outputs, .. } = cell else {
+                    continue;
+                };
+                for output in outputs {
+                    let media = match output {
+                        Output::DisplayData(display_data) => &display_data.data,
+                        Output::ExecuteResult(execute_result) => &execute_result.data,
+                        _ => continue,
+                    };
+                    // The same bundle sent to a kernel client reads back unchanged
+                    let wire = serde_json::to_value(media).unwrap();
+                    let from_wire: Media = serde_json::from_value(wire).unwrap();
+                    assert_eq!(sorted(&from_wire), sorted(media), "{}", path);
+                }
+            }
+        }
     }
 
     #[test]
@@ -473,6 +503,7 @@ mod test {
                 }
             }
             Notebook::Legacy(_) => panic!("Expected V4 notebook, got legacy"),
+            Notebook::V3(_) => panic!("Expected V4 notebook, got v3"),
         }
 
         let serialized = serialize_notebook(&notebook).expect("Failed to serialize notebook");
@@ -490,4 +521,766 @@ mod test {
 
         assert_eq!(notebook_json, serialized);
     }
+
+    fn parameterized_notebook(language: &str) -> nbformat::v4::Notebook {
+        let notebook_json = serde_json::json!({
+            "metadata": {
+                "kernelspec": {"display_name": language, "name": language, "language": language}
+            },
+            "nbformat": 4,
+            "nbformat_minor": 5,
+            "cells": [
+                {"cell_type": "markdown", "id": "intro", "metadata": {}, "source": ["# Report"]},
+                {
+                    "cell_type": "code",
+                    "id": "defaults",
+                    "metadata": {"tags": ["parameters"]},
+                    "execution_count": null,
+                    "source": ["alpha = 0.1\n", "label = \"default\""],
+                    "outputs": []
+                },
+                {"cell_type": "code", "id": "work", "metadata": {}, "execution_count": null, "source": ["alpha * 2"], "outputs": []}
+            ]
+        });
+        serde_json::from_value(notebook_json).expect("Failed to parse notebook")
+    }
+
+    #[test]
+    fn test_inject_parameters_after_parameters_cell() {
+        use nbformat::parameters::{find_parameters_cell, inject_parameters};
+        use std::collections::HashMap;
+
+        let mut notebook = parameterized_notebook("python");
+        assert_eq!(find_parameters_cell(&notebook), Some(1));
+
+        let parameters = HashMap::from([
+            ("alpha".to_string(), serde_json::json!(0.5)),
+            ("label".to_string(), serde_json::json!("sweep \"a\"")),
+            (
+                "flags".to_string(),
+                serde_json::json!({"fast": true, "seed": null}),
+            ),
+        ]);
+        inject_parameters(&mut notebook, &parameters).expect("Failed to inject parameters");
+
+        assert_eq!(notebook.cells.len(), 4);
+        let injected = &notebook.cells[2];
+        assert_eq!(
+            injected.metadata().tags,
+            Some(vec!["injected-parameters".to_string()])
+        );
+        assert_eq!(
+            injected.source(),
+            &[
+                "# Parameters\n",
+                "alpha = 0.5\n",
+                "flags = {\"fast\": True, \"seed\": None}\n",
+                "label = \"sweep \\\"a\\\"\"",
+            ]
+        );
+        assert_eq!(
+            notebook.metadata.additional["papermill"]["parameters"]["alpha"],
+            serde_json::json!(0.5)
+        );
+
+        // Injecting again replaces the previous injected cell
+        let parameters = HashMap::from([("alpha".to_string(), serde_json::json!(1))]);
+        inject_parameters(&mut notebook, &parameters).expect("Failed to inject parameters");
+        assert_eq!(notebook.cells.len(), 4);
+        assert_eq!(notebook.cells[2].source(), &["# Parameters\n", "alpha = 1"]);
+        assert_eq!(
+            notebook.metadata.additional["papermill"]["parameters"],
+            serde_json::json!({"alpha": 1})
+        );
+    }
+
+    #[test]
+    fn test_inject_parameters_r_and_unsupported_languages() {
+        use nbformat::parameters::inject_parameters;
+        use std::collections::HashMap;
+
+        let parameters = HashMap::from([("enabled".to_string(), serde_json::json!([true, false]))]);
+
+        let mut notebook = parameterized_notebook("R");
+        inject_parameters(&mut notebook, &parameters).expect("Failed to inject parameters");
+        assert_eq!(
+            notebook.cells[2].source(),
+            &["# Parameters\n", "enabled = list(TRUE, FALSE)"]
+        );
+
+        let mut notebook = parameterized_notebook("brainfuck");
+        assert!(inject_parameters(&mut notebook, &parameters).is_err());
+
+        let mut notebook = parameterized_notebook("python");
+        let invalid = HashMap::from([("not valid".to_string(), serde_json::json!(1))]);
+        assert!(inject_parameters(&mut notebook, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_v3_notebook() {
+        let notebook_json = read_notebook("tests/notebooks/test3.ipynb");
+        let notebook = match parse_notebook(&notebook_json).expect("Failed to parse notebook") {
+            Notebook::V3(notebook) => notebook,
+            _ => panic!("Expected v3 notebook"),
+        };
+        let notebook = nbformat::upgrade_v3_notebook(notebook).expect("Failed to upgrade");
+
+        assert_eq!(notebook.nbformat, 4);
+        assert_eq!(notebook.nbformat_minor, 5);
+
+        match &notebook.cells[0] {
+            Cell::Markdown { source, .. } => {
+                assert_eq!(source, &vec!["# nbconvert latex test".to_string()])
+            }
+            _ => panic!("Expected heading to become a markdown cell"),
+        }
+
+        match &notebook.cells[3] {
+            Cell::Code {
+                execution_count,
+                source,
+                outputs,
+                ..
+            } => {
+                assert_eq!(*execution_count, Some(1));
+                assert_eq!(source, &vec!["print(\"hello\")".to_string()]);
+                match &outputs[0] {
+                    Output::Stream { name, text } => {
+                        assert_eq!(name, "stdout");
+                        assert_eq!(text.0, "hello\n");
+                    }
+                    _ => panic!("Expected stream output"),
+                }
+            }
+            _ => panic!("Expected code cell"),
+        }
+
+        match &notebook.cells[5] {
+            Cell::Code { outputs, .. } => match &outputs[0] {
+                Output::ExecuteResult(result) => {
+                    assert!(result.data.content.iter().any(|media| matches!(
+                        media,
+                        jupyter_protocol::media::MediaType::Html(html) if html.contains("<b>HTML</b>")
+                    )));
+                }
+                _ => panic!("Expected pyout to become an execute_result"),
+            },
+            _ => panic!("Expected code cell"),
+        }
+
+        serialize_notebook(&Notebook::V4(notebook)).expect("Failed to serialize notebook");
+    }
+
+    fn read_upgraded(path: &str) -> nbformat::v4::Notebook {
+        match parse_notebook(&read_notebook(path)).expect("Failed to parse notebook") {
+            Notebook::V4(notebook) => notebook,
+            Notebook::Legacy(notebook) => {
+                nbformat::upgrade_legacy_notebook(notebook).expect("Failed to upgrade")
+            }
+            Notebook::V3(notebook) => {
+                nbformat::upgrade_v3_notebook(notebook).expect("Failed to upgrade")
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_html_report() {
+        let notebook = read_upgraded("tests/notebooks/test4jupyter_metadata_timings.ipynb");
+        let html = nbformat::html::render_html(&notebook);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<pre class=\"source\">1 + 1</pre>"));
+        assert!(html.contains("<div class=\"output\"><pre>2</pre></div>"));
+        // shell.execute_reply minus shell.execute_reply.started
+        assert!(html.contains("<span class=\"timing\">18ms</span>"));
+        assert!(html.contains("In [ ]"));
+
+        let notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        let html = nbformat::html::render_html(&notebook);
+        assert!(html.contains("<img src=\"data:image/png;base64,"));
+        // Javascript can't run in a static report, so its text/plain fallback is used
+        assert!(html.contains("&lt;IPython.core.display.Javascript at 0x1112b4b50&gt;"));
+        // The kernel, from the notebook's metadata
+        assert!(html.contains(
+            "<div class=\"kernel-info-title\"><strong>Python 3 (ipykernel)</strong> · python 3.10.0</div>"
+        ));
+    }
+
+    #[cfg(feature = "sanitize")]
+    #[test]
+    fn test_render_html_sanitized() {
+        use jupyter_protocol::media::sanitize::SanitizePolicy;
+
+        let notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        assert!(nbformat::html::render_html_trusted(&notebook).contains("<script>"));
+
+        let html = nbformat::html::render_html_sanitized(&notebook, SanitizePolicy::AllowStyles);
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<b>HTML</b>"));
+        assert!(html.contains("<img src=\"data:image/png;base64,"));
+
+        // Untrusted by default
+        assert_eq!(nbformat::html::render_html(&notebook), html);
+    }
+
+    #[cfg(not(feature = "sanitize"))]
+    #[test]
+    fn test_render_html_leaves_out_markup_without_a_sanitizer() {
+        let notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        assert!(nbformat::html::render_html_trusted(&notebook).contains("<b>HTML</b>"));
+
+        let html = nbformat::html::render_html(&notebook);
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>HTML</b>"));
+        assert!(html.contains("&lt;IPython.core.display.HTML at 0x1112757d0&gt;"));
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn test_render_html_markdown() {
+        let notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        let html = nbformat::html::render_html(&notebook);
+        assert!(html.contains(
+            "<div class=\"cell markdown rendered\"><h1>nbconvert latex test</h1>\n</div>"
+        ));
+        assert!(html.contains("<p><strong>Lorem ipsum</strong> dolor sit amet"));
+    }
+
+    #[test]
+    fn test_render_html_tracebacks_collapse() {
+        let notebook = read_upgraded("tests/notebooks/many_tracebacks.ipynb");
+        let html = nbformat::html::render_html(&notebook);
+
+        assert!(html.contains("<details class=\"output error\"><summary>NameError: "));
+        assert!(!html.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_execution_metadata_from_timings() {
+        let at = |micros: i64| {
+            chrono::DateTime::from_timestamp_micros(1_566_841_829_000_000 + micros).unwrap()
+        };
+        let timings = jupyter_protocol::ExecutionTimings {
+            busy: Some(at(603_423)),
+            execute_input: Some(at(604_738)),
+            reply_started: Some(at(604_616)),
+            reply: Some(at(623_438)),
+            idle: None,
+        };
+        let metadata = nbformat::v4::ExecutionMetadata::from(&timings);
+
+        assert_eq!(
+            serde_json::to_value(&metadata).unwrap(),
+            serde_json::json!({
+                "iopub.execute_input": "2019-08-26T17:50:29.604738Z",
+                "iopub.status.busy": "2019-08-26T17:50:29.603423Z",
+                "shell.execute_reply": "2019-08-26T17:50:29.623438Z",
+                "shell.execute_reply.started": "2019-08-26T17:50:29.604616Z",
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_html_image_size() {
+        let notebook = r#"{
+            "nbformat": 4, "nbformat_minor": 5, "metadata": {},
+            "cells": [{
+                "id": "plot", "cell_type": "code", "metadata": {}, "execution_count": 1,
+                "source": [],
+                "outputs": [{
+                    "output_type": "display_data",
+                    "data": {"image/png": "iVBORw0KGgo=\n"},
+                    "metadata": {"image/png": {"width": 320, "height": 200}}
+                }]
+            }]
+        }"#;
+        let Notebook::V4(notebook) = parse_notebook(notebook).unwrap() else {
+            panic!("Expected a v4.5 notebook");
+        };
+
+        assert!(nbformat::html::render_html(&notebook).contains(
+            r#"<img src="data:image/png;base64,iVBORw0KGgo=" width="320" height="200">"#
+        ));
+    }
+
+    #[test]
+    fn test_render_html_unconfined_image() {
+        let notebook = r#"{
+            "nbformat": 4, "nbformat_minor": 5, "metadata": {},
+            "cells": [{
+                "id": "plot", "cell_type": "code", "metadata": {}, "execution_count": 1,
+                "source": [],
+                "outputs": [{
+                    "output_type": "display_data",
+                    "data": {"image/png": "iVBORw0KGgo="},
+                    "metadata": {"image/png": {"unconfined": true}}
+                }]
+            }]
+        }"#;
+        let Notebook::V4(notebook) = parse_notebook(notebook).unwrap() else {
+            panic!("Expected a v4.5 notebook");
+        };
+
+        assert!(nbformat::html::render_html(&notebook)
+            .contains(r#"<img src="data:image/png;base64,iVBORw0KGgo=" style="max-width: none">"#));
+    }
+
+    #[test]
+    fn test_analyze_notebook() {
+        use nbformat::lint::{analyze, parse_for_lint};
+
+        let notebook = parse_for_lint(&read_notebook("tests/notebooks/test4.5.ipynb")).unwrap();
+        let stats = analyze(&notebook);
+        assert_eq!(
+            (stats.cells(), stats.code_cells, stats.markdown_cells),
+            (9, 4, 5)
+        );
+        assert_eq!(stats.unexecuted_code_cells, 0);
+        assert_eq!(stats.outputs, 4);
+        // 1, 3, 7, 6
+        assert_eq!(stats.execution_count_gaps, 2);
+        assert!(stats.has_kernelspec && stats.has_language_info);
+        assert_eq!(
+            stats.output_bytes.keys().collect::<Vec<_>>(),
+            vec![
+                "application/javascript",
+                "application/vnd.jupyter.stdout",
+                "image/png",
+                "text/html",
+                "text/plain"
+            ]
+        );
+        assert!(stats.output_bytes["image/png"] > stats.output_bytes["text/plain"]);
+        assert_eq!(
+            stats.total_output_bytes(),
+            stats.output_bytes.values().sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_lint_notebook() {
+        use nbformat::lint::{lint, parse_for_lint, LintOptions, LintRule, Severity};
+
+        let notebook = parse_for_lint(&read_notebook("tests/notebooks/test4.5.ipynb")).unwrap();
+        let lints = lint(&notebook, &LintOptions::default());
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, LintRule::OutOfOrderExecution);
+        assert_eq!(lints[0].cell_index, Some(8));
+        assert_eq!(lints[0].cell_id.as_deref(), Some("8b414a68"));
+        assert_eq!(
+            lints[0].to_string(),
+            "warning [out-of-order-execution] cell 8 (8b414a68): \
+             Execution count 6 comes after 7; run the notebook from the top"
+        );
+
+        let lints = lint(
+            &notebook,
+            &LintOptions {
+                max_output_bytes: 100,
+            },
+        );
+        assert!(lints
+            .iter()
+            .any(|lint| lint.rule == LintRule::HugeOutput && lint.cell_index == Some(8)));
+
+        // Rejected by parse_notebook, but read for linting
+        let json = read_notebook("tests/notebooks/invalid_unique_cell_id.ipynb");
+        assert!(parse_notebook(&json).is_err());
+        let lints = lint(&parse_for_lint(&json).unwrap(), &LintOptions::default());
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, LintRule::DuplicateCellId);
+        assert_eq!(lints[0].severity, Severity::Error);
+        assert_eq!(lints[0].cell_index, Some(1));
+
+        // Older notebooks are upgraded first
+        let notebook = parse_for_lint(&read_notebook("tests/notebooks/test4.ipynb")).unwrap();
+        let rules: Vec<_> = lint(&notebook, &LintOptions::default())
+            .into_iter()
+            .map(|lint| lint.rule)
+            .collect();
+        assert!(rules.starts_with(&[LintRule::MissingKernelspec, LintRule::MissingLanguageInfo]));
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let notebook = r##"{
+            "nbformat": 4, "nbformat_minor": 5,
+            "metadata": {"language_info": {"name": "python"}},
+            "cells": [
+                {"id": "intro", "cell_type": "markdown", "metadata": {}, "source": ["# Plots\n"]},
+                {
+                    "id": "plot", "cell_type": "code", "metadata": {}, "execution_count": 1,
+                    "source": ["show()"],
+                    "outputs": [
+                        {
+                            "output_type": "display_data",
+                            "data": {"image/png": "iVBORw0KGgo=\n", "text/plain": "<Figure>"},
+                            "metadata": {"image/png": {"width": 320, "height": 200}}
+                        },
+                        {
+                            "output_type": "display_data",
+                            "data": {"image/jpeg": "/9j/4AAQ"},
+                            "metadata": {"image/png": {"width": 100}}
+                        },
+                        {
+                            "output_type": "execute_result", "execution_count": 1,
+                            "data": {"text/plain": "```"}, "metadata": {}
+                        }
+                    ]
+                }
+            ]
+        }"##;
+        let Notebook::V4(notebook) = parse_notebook(notebook).unwrap() else {
+            panic!("Expected a v4.5 notebook");
+        };
+
+        assert_eq!(
+            nbformat::markdown::render_markdown(&notebook),
+            [
+                "# Plots",
+                "",
+                "```python",
+                "show()",
+                "```",
+                "",
+                r#"<img src="data:image/png;base64,iVBORw0KGgo=" width="320" height="200">"#,
+                "",
+                // Sizes under image/png don't apply to the jpeg
+                "![image/jpeg](data:image/jpeg;base64,/9j/4AAQ)",
+                "",
+                "````",
+                "```",
+                "````",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[cfg(feature = "trust")]
+    fn trust_secret() -> Vec<u8> {
+        fs::read("tests/trust/secret").expect("Failed to read secret")
+    }
+
+    #[cfg(feature = "trust")]
+    #[test]
+    fn test_signatures_match_jupyter_trust() {
+        // Computed with tests/trust/sign.py, which mirrors nbformat.sign
+        let golden = [
+            (
+                "tests/notebooks/test4.5.ipynb",
+                "fa5096f9ec50da5b968102db99700ec09c7ba227d9247ab485566a0f3c0e502c",
+            ),
+            (
+                "tests/notebooks/many_tracebacks.ipynb",
+                "36cd0b1f47ebf3f26ec8059fd8d8b1b4f0703473f042828a833236f92517fdaa",
+            ),
+            (
+                "tests/notebooks/Mediatypes.ipynb",
+                "a72d33ddd57cb217ee1ce48003c75ca6177dace6975e82fe6a9210f49b972f45",
+            ),
+        ];
+        let secret = trust_secret();
+        for (path, signature) in golden {
+            let notebook: Value = serde_json::from_str(&read_notebook(path)).unwrap();
+            assert_eq!(
+                nbformat::trust::compute_signature(&notebook, &secret),
+                signature,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[cfg(feature = "trust")]
+    #[test]
+    fn test_signature_ignores_transient_metadata() {
+        let secret = trust_secret();
+        let mut notebook: Value =
+            serde_json::from_str(&read_notebook("tests/notebooks/test4.5.ipynb")).unwrap();
+        let signature = nbformat::trust::compute_signature(&notebook, &secret);
+
+        notebook["metadata"]["signature"] = "sha256:abc".into();
+        notebook["cells"][0]["metadata"]["trusted"] = true.into();
+        assert_eq!(
+            nbformat::trust::compute_signature(&notebook, &secret),
+            signature
+        );
+
+        notebook["cells"][0]["source"] = "import os".into();
+        assert_ne!(
+            nbformat::trust::compute_signature(&notebook, &secret),
+            signature
+        );
+    }
+
+    #[cfg(feature = "trust")]
+    #[test]
+    fn test_untrusted_cells() {
+        use nbformat::trust::{is_cell_trusted, mark_cells};
+
+        fn code_cells(notebook: &nbformat::v4::Notebook) -> impl Iterator<Item = &Cell> {
+            notebook
+                .cells
+                .iter()
+                .filter(|cell| matches!(cell, Cell::Code { .. }))
+        }
+
+        let mut notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        // Cells with Javascript or HTML outputs aren't safe, plots and text are
+        assert!(code_cells(&notebook).any(|cell| !is_cell_trusted(cell)));
+        assert!(code_cells(&notebook).any(is_cell_trusted));
+
+        mark_cells(&mut notebook, true);
+        assert!(notebook.cells.iter().all(is_cell_trusted));
+        mark_cells(&mut notebook, false);
+        assert!(code_cells(&notebook).all(|cell| !is_cell_trusted(cell)));
+    }
+
+    #[cfg(feature = "trust")]
+    #[test]
+    fn test_notary_sign_and_check() {
+        let dir = std::env::temp_dir().join(format!("nbformat-trust-{}", std::process::id()));
+        let notebook: Value =
+            serde_json::from_str(&read_notebook("tests/notebooks/test4.5.ipynb")).unwrap();
+
+        let notary = nbformat::trust::Notary::open(&dir).unwrap();
+        assert!(!notary.check(&notebook).unwrap());
+        notary.sign(&notebook).unwrap();
+        assert!(notary.check(&notebook).unwrap());
+
+        // The secret and signature persist
+        let notary = nbformat::trust::Notary::open(&dir).unwrap();
+        assert!(notary.check(&notebook).unwrap());
+        notary.unsign(&notebook).unwrap();
+        assert!(!nbformat::trust::Notary::open(&dir)
+            .unwrap()
+            .check(&notebook)
+            .unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "trust")]
+    #[test]
+    fn test_notary_read_only() {
+        let dir = std::env::temp_dir().join(format!("nbformat-trust-ro-{}", std::process::id()));
+        let notebook: Value =
+            serde_json::from_str(&read_notebook("tests/notebooks/test4.5.ipynb")).unwrap();
+
+        // Nothing is created for a data directory without a trust store
+        assert!(nbformat::trust::Notary::open_read_only(&dir)
+            .unwrap()
+            .is_none());
+        assert!(!dir.exists());
+
+        nbformat::trust::Notary::open(&dir)
+            .unwrap()
+            .sign(&notebook)
+            .unwrap();
+        let notary = nbformat::trust::Notary::open_read_only(&dir)
+            .unwrap()
+            .unwrap();
+        assert!(notary.check(&notebook).unwrap());
+        assert!(notary.unsign(&notebook).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "trust")]
+    #[test]
+    fn test_notary_shares_jupyters_database() {
+        let dir = std::env::temp_dir().join(format!("nbformat-trust-db-{}", std::process::id()));
+        let notebook: Value =
+            serde_json::from_str(&read_notebook("tests/notebooks/test4.5.ipynb")).unwrap();
+        let signature = nbformat::trust::compute_signature(&notebook, &trust_secret());
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy("tests/trust/secret", dir.join("notebook_secret")).unwrap();
+
+        // A database as `jupyter trust` creates it, with one signed notebook
+        let db = rusqlite::Connection::open(dir.join("nbsignatures.db")).unwrap();
+        db.execute_batch(
+            "CREATE TABLE nbsignatures
+             (
+                 id integer PRIMARY KEY AUTOINCREMENT,
+                 algorithm text,
+                 signature text,
+                 path text,
+                 last_seen timestamp
+             );
+             CREATE INDEX algosig ON nbsignatures(algorithm, signature);",
+        )
+        .unwrap();
+        db.execute(
+            "INSERT INTO nbsignatures (algorithm, signature, last_seen) VALUES (?1, ?2, ?3)",
+            ["sha256", &signature, "2024-05-01 12:00:00.000001"],
+        )
+        .unwrap();
+
+        let notary = nbformat::trust::Notary::open(&dir).unwrap();
+        assert!(notary.check(&notebook).unwrap());
+
+        // Signing writes the rows Jupyter looks up
+        let mut other = notebook.clone();
+        other["metadata"]["title"] = "Changed".into();
+        notary.sign(&other).unwrap();
+        let rows: Vec<(String, String)> = db
+            .prepare("SELECT algorithm, signature FROM nbsignatures ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("sha256".to_string(), signature),
+                ("sha256".to_string(), notary.signature(&other)),
+            ]
+        );
+
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_outputs_round_trip_through_messages() {
+        use jupyter_protocol::JupyterMessageContent;
+
+        let mut message_types = Vec::new();
+        for path in [
+            "tests/notebooks/test4.5.ipynb",
+            "tests/notebooks/many_tracebacks.ipynb",
+            "tests/notebooks/Mediatypes.ipynb",
+        ] {
+            for cell in read_upgraded(path).cells {
+                let Cell::Code { outputs, .. } = cell else {
+                    continue;
+                };
+                for output in outputs {
+                    let saved = serde_json::to_value(&output).unwrap();
+                    let message = JupyterMessageContent::from(output);
+                    message_types.push(message.message_type().to_string());
+                    let restored = Output::try_from(message).unwrap();
+                    assert_eq!(serde_json::to_value(&restored).unwrap(), saved, "{}", path);
+                }
+            }
+        }
+        for message_type in ["stream", "display_data", "execute_result", "error"] {
+            assert!(message_types.iter().any(|seen| seen == message_type));
+        }
+    }
+
+    #[test]
+    fn test_only_output_messages_become_outputs() {
+        use jupyter_protocol::{ClearOutput, JupyterMessageContent, StreamContent};
+
+        let stderr = Output::try_from(JupyterMessageContent::from(StreamContent::stderr("oops")));
+        assert!(matches!(stderr, Ok(Output::Stream { name, .. }) if name == "stderr"));
+
+        let error =
+            Output::try_from(JupyterMessageContent::from(ClearOutput { wait: false })).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "clear_output messages aren't notebook outputs"
+        );
+    }
+
+    /// The parts of a `kernel_info_reply` that notebooks keep.
+    fn kernel_info(
+        implementation: &str,
+        language_info: Value,
+    ) -> jupyter_protocol::KernelInfoReply {
+        serde_json::from_value(serde_json::json!({
+            "status": "ok",
+            "protocol_version": "5.3",
+            "implementation": implementation,
+            "implementation_version": "1.0",
+            "language_info": language_info,
+            "banner": "",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sync_with_kernel() {
+        let mut notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        let kernelspec: jupyter_protocol::JupyterKernelspec = serde_json::from_value(
+            serde_json::json!({"argv": ["R"], "display_name": "R", "language": "R"}),
+        )
+        .unwrap();
+        notebook.metadata.sync_with_kernel(
+            "ir",
+            &kernelspec,
+            &kernel_info(
+                "IRkernel",
+                serde_json::json!({
+                    "name": "R",
+                    "codemirror_mode": "r",
+                    "pygments_lexer": "r",
+                    "mimetype": "text/x-r-source",
+                    "file_extension": ".r",
+                    "version": "4.3.2",
+                }),
+            ),
+        );
+
+        let metadata = serde_json::to_value(&notebook.metadata).unwrap();
+        assert_eq!(
+            metadata["kernelspec"],
+            serde_json::json!({"display_name": "R", "name": "ir", "language": "R"})
+        );
+        assert_eq!(
+            metadata["language_info"],
+            serde_json::json!({
+                "name": "R",
+                "version": "4.3.2",
+                "codemirror_mode": "r",
+                "pygments_lexer": "r",
+                "mimetype": "text/x-r-source",
+                "file_extension": ".r",
+            })
+        );
+    }
+
+    #[test]
+    fn test_sync_language_info_replaces_stale_fields() {
+        let mut notebook = read_upgraded("tests/notebooks/test4.5.ipynb");
+        notebook.metadata.sync_language_info(&kernel_info(
+            "ipython",
+            serde_json::json!({
+                "name": "python",
+                "version": "3.11.6",
+                "mimetype": "text/x-python",
+                "codemirror_mode": {"name": "ipython", "version": 3},
+                "pygments_lexer": "ipython3",
+                "nbconvert_exporter": "python",
+                "file_extension": ".py",
+            }),
+        ));
+        let language_info = notebook.metadata.language_info.as_ref().unwrap();
+        assert_eq!(language_info.name, "python");
+        assert_eq!(language_info.additional["nbconvert_exporter"], "python");
+        assert_eq!(
+            serde_json::to_value(&language_info.codemirror_mode).unwrap(),
+            serde_json::json!({"name": "ipython", "version": 3})
+        );
+
+        // evcxr has no version or nbconvert exporter to carry over
+        notebook.metadata.sync_language_info(&kernel_info(
+            "evcxr",
+            serde_json::json!({
+                "name": "Rust",
+                "version": "",
+                "mimetype": "text/rust",
+                "file_extension": ".rs",
+                "pygments_lexer": "rust",
+                "codemirror_mode": "rust",
+            }),
+        ));
+        let language_info = notebook.metadata.language_info.as_ref().unwrap();
+        assert_eq!(language_info.name, "Rust");
+        assert_eq!(language_info.version, None);
+        assert!(!language_info.additional.contains_key("nbconvert_exporter"));
+    }
 }
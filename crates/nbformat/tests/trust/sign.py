@@ -0,0 +1,56 @@
+"""Compute notebook signatures the way `jupyter trust` does.
+
+This mirrors `nbformat.sign.NotebookNotary.compute_signature` so the golden
+values in tests/conformance.rs can be regenerated without installing
+nbformat:
+
+    python3 tests/trust/sign.py tests/notebooks/test4.5.ipynb tests/trust/secret
+
+`nbformat.read` joins multiline strings and drops the transient fields
+before signing. Joining doesn't change the bytes that are hashed, since
+list items are fed to the HMAC one after another.
+"""
+
+import hashlib
+import hmac
+import json
+import sys
+
+
+def yield_everything(obj):
+    if isinstance(obj, dict):
+        for key in sorted(obj):
+            yield key.encode()
+            yield from yield_everything(obj[key])
+    elif isinstance(obj, (list, tuple)):
+        for element in obj:
+            yield from yield_everything(element)
+    elif isinstance(obj, str):
+        yield obj.encode("utf8")
+    else:
+        yield str(obj).encode("utf8")
+
+
+def strip_transient(nb):
+    nb["metadata"].pop("orig_nbformat", None)
+    nb["metadata"].pop("orig_nbformat_minor", None)
+    nb["metadata"].pop("signature", None)
+    for cell in nb["cells"]:
+        cell["metadata"].pop("trusted", None)
+    return nb
+
+
+def compute_signature(nb, secret):
+    mac = hmac.HMAC(secret, digestmod=hashlib.sha256)
+    for b in yield_everything(strip_transient(nb)):
+        mac.update(b)
+    return mac.hexdigest()
+
+
+if __name__ == "__main__":
+    notebook_path, secret_path = sys.argv[1:3]
+    with open(notebook_path, encoding="utf8") as f:
+        nb = json.load(f)
+    with open(secret_path, "rb") as f:
+        secret = f.read()
+    print(compute_signature(nb, secret))
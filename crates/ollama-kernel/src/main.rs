@@ -1,4 +1,9 @@
-use std::{collections::HashMap, env::current_exe};
+use std::{
+    collections::HashMap,
+    env::current_exe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context as _, Result};
 
@@ -8,20 +13,23 @@ mod structured_calling;
 use structured_calling::Structured;
 
 use futures::StreamExt;
+use jupyter_protocol::cursor::{convert_offset, CursorUnit};
 use jupyter_protocol::{
-    ClearOutput, CodeMirrorMode, CommInfoReply, CompleteReply, CompleteRequest, ConnectionInfo,
-    DisplayData, ErrorOutput, ExecuteReply, ExecutionCount, HelpLink, HistoryReply, InspectReply,
+    CodeMirrorMode, CommInfoReply, CompleteReply, CompleteRequest, ConnectionInfo, DisplayData,
+    DisplayHandle, ErrorOutput, ExecuteReply, ExecutionCount, HelpLink, InspectReply,
     IsCompleteReply, IsCompleteReplyStatus, JupyterMessage, JupyterMessageContent, KernelInfoReply,
-    LanguageInfo, Media, MediaType, ReplyStatus, Status, StreamContent,
+    LanguageInfo, Media, MediaType, ReplyStatus, Status,
 };
 
-use runtimelib::{KernelIoPubConnection, KernelShellConnection};
+use runtimelib::kernel::magic::{Dispatch, MagicCall, MagicRegistry, MagicSpec};
+use runtimelib::kernel::shutdown::{self, ShutdownHooks};
+use runtimelib::kernel::{ExecutionCounter, HistoryStore, IopubOptions, IopubPublisher};
+use runtimelib::KernelShellConnection;
 
 use ollama_client::{
     ChatMessage, Format, GenerateResponse, LocalModelListing, OllamaClient, Role, OLLAMA_ENDPOINT,
 };
 use serde_json::{json, Value};
-use uuid::Uuid;
 
 use clap::Parser;
 
@@ -39,95 +47,169 @@ struct Args {
 
 struct OllamaKernel {
     model: String,
-    execution_count: ExecutionCount,
-    iopub: KernelIoPubConnection,
+    execution_counter: ExecutionCounter,
+    iopub: IopubPublisher,
     previous_messages: Vec<ChatMessage>,
     last_context: Vec<usize>,
+    history: HistoryStore,
+    magics: Arc<MagicRegistry<OllamaKernel>>,
 }
 
-/// Convert a magic cell like `%model --set gemma`
-fn split_magic(input: &str) -> (&str, Option<&str>) {
-    input
-        .split_once('\n')
-        .map(|(header, body)| (header, Some(body)))
-        .unwrap_or((input, None))
+/// How often a streaming response is redrawn. Every update resends the whole
+/// response so far, so redrawing on each token sends quadratically many bytes.
+const RESPONSE_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The magic commands, like `%model --list`, for curating models and the conversation
+fn magics() -> MagicRegistry<OllamaKernel> {
+    let mut magics = MagicRegistry::<OllamaKernel>::new();
+    magics
+        .register(
+            MagicSpec::new("model", "Get details for the current model")
+                .flag("list", "List the available models")
+                .flag("show", "Show details for the named model")
+                .option("create", "NAME", "Create or update the modelfile for NAME")
+                .optional_arg("name", "The model to show. Defaults to the current model")
+                .body("The Modelfile, for `--create`"),
+            |kernel, call, parent| Box::pin(kernel.model_magic(call, parent)),
+        )
+        .register(
+            MagicSpec::new("use", "Set the currently used model").arg("name", "The model to use"),
+            |kernel, call, parent| Box::pin(kernel.use_magic(call, parent)),
+        )
+        .register(
+            MagicSpec::new("reset", "Clear out the conversation history"),
+            |kernel, _call, _parent| {
+                Box::pin(async move {
+                    kernel.previous_messages.clear();
+                    kernel.last_context.clear();
+                    Ok(())
+                })
+            },
+        );
+    magics
 }
 
 impl OllamaKernel {
     pub async fn start(model: String, connection_info: &ConnectionInfo) -> Result<()> {
-        let session_id = Uuid::new_v4().to_string();
+        let session = runtimelib::Session::new().with_username("ollama-kernel");
 
         let mut heartbeat = runtimelib::create_kernel_heartbeat_connection(connection_info).await?;
         let shell_connection =
-            runtimelib::create_kernel_shell_connection(connection_info, &session_id).await?;
+            runtimelib::create_kernel_shell_connection(connection_info, &session).await?;
         let mut control_connection =
-            runtimelib::create_kernel_control_connection(connection_info, &session_id).await?;
+            runtimelib::create_kernel_control_connection(connection_info, &session).await?;
         let _stdin_connection =
-            runtimelib::create_kernel_stdin_connection(connection_info, &session_id).await?;
-        let iopub_connection =
-            runtimelib::create_kernel_iopub_connection(connection_info, &session_id).await?;
+            runtimelib::create_kernel_stdin_connection(connection_info, &session).await?;
+        let iopub =
+            IopubPublisher::bind(connection_info, &session, IopubOptions::default()).await?;
         // let (mut tx, rx) = futures::channel::mpsc::unbounded::<JupyterMessage>();
 
+        let history = HistoryStore::default_path("ollama")
+            .and_then(HistoryStore::open)
+            .unwrap_or_else(|err| {
+                eprintln!("Keeping history in memory only: {}", err);
+                HistoryStore::in_memory()
+            });
+
         let mut ollama_kernel = Self {
             model,
-            execution_count: Default::default(),
-            iopub: iopub_connection,
+            execution_counter: ExecutionCounter::new(),
+            iopub,
             previous_messages: Default::default(),
             last_context: Default::default(),
+            history,
+            magics: Arc::new(magics()),
         };
 
         let heartbeat_handle = tokio::spawn({
             async move { while let Ok(()) = heartbeat.single_heartbeat().await {} }
         });
 
-        let control_handle = tokio::spawn({
-            async move {
-                while let Ok(message) = control_connection.read().await {
-                    if let JupyterMessageContent::KernelInfoRequest(_) = message.content {
-                        let sent = control_connection
-                            .send(Self::kernel_info().as_child_of(&message))
-                            .await;
-
-                        match sent {
-                            Ok(_) => {}
-                            Err(err) => eprintln!("Error on control {}", err),
-                        }
-                    }
-                }
-            }
-        });
-
         let shell_handle = tokio::spawn(async move {
             if let Err(err) = ollama_kernel.handle_shell(shell_connection).await {
                 eprintln!("Shell error: {}\nBacktrace:\n{}", err, err.backtrace());
             }
         });
 
-        let join_fut =
-            futures::future::try_join_all(vec![heartbeat_handle, control_handle, shell_handle]);
+        // Stop generating before replying, so nothing is published after the
+        // frontend thinks the kernel is gone
+        let mut shutdown = ShutdownHooks::new().on_shutdown(move |_restart| async move {
+            shell_handle.abort();
+            heartbeat_handle.abort();
+        });
 
-        join_fut.await?;
+        let terminated = shutdown::terminated();
+        tokio::pin!(terminated);
+        loop {
+            let message = tokio::select! {
+                message = control_connection.read() => message?,
+                _ = &mut terminated => {
+                    shutdown.run(false).await;
+                    return Ok(());
+                }
+            };
+
+            if shutdown
+                .handle_control(&message, &mut control_connection)
+                .await?
+                .is_some()
+            {
+                return Ok(());
+            }
+            if let JupyterMessageContent::KernelInfoRequest(_) = message.content {
+                let sent = control_connection
+                    .send(Self::kernel_info().as_child_of(&message))
+                    .await;
+
+                if let Err(err) = sent {
+                    eprintln!("Error on control {}", err);
+                }
+            }
+        }
+    }
 
+    async fn send_markdown(
+        &mut self,
+        markdown: &str,
+        parent: &JupyterMessage,
+    ) -> anyhow::Result<()> {
+        self.iopub
+            .send(DisplayData::from(MediaType::Markdown(markdown.to_string())).as_child_of(parent))
+            .await?;
         Ok(())
     }
 
-    async fn clear_output_after_next_output(
+    /// Show markdown that can be replaced later with `update_markdown`.
+    async fn display_markdown(
         &mut self,
+        handle: &DisplayHandle,
+        markdown: &str,
         parent: &JupyterMessage,
     ) -> anyhow::Result<()> {
         self.iopub
-            .send(ClearOutput { wait: true }.as_child_of(parent))
-            .await
+            .send(
+                handle
+                    .display(MediaType::Markdown(markdown.to_string()))
+                    .as_child_of(parent),
+            )
+            .await?;
+        Ok(())
     }
 
-    async fn send_markdown(
+    async fn update_markdown(
         &mut self,
+        handle: &DisplayHandle,
         markdown: &str,
         parent: &JupyterMessage,
     ) -> anyhow::Result<()> {
         self.iopub
-            .send(DisplayData::from(MediaType::Markdown(markdown.to_string())).as_child_of(parent))
-            .await
+            .send(
+                handle
+                    .update(MediaType::Markdown(markdown.to_string()))
+                    .as_child_of(parent),
+            )
+            .await?;
+        Ok(())
     }
 
     async fn send_json(
@@ -146,7 +228,8 @@ impl OllamaKernel {
 
         self.iopub
             .send(DisplayData::from(MediaType::Json(json_object)).as_child_of(parent))
-            .await
+            .await?;
+        Ok(())
     }
 
     async fn send_error(
@@ -164,135 +247,108 @@ impl OllamaKernel {
                 }
                 .as_child_of(parent),
             )
-            .await
-    }
-
-    async fn push_stdout(&mut self, text: &str, parent: &JupyterMessage) -> anyhow::Result<()> {
-        self.iopub
-            .send(StreamContent::stdout(text).as_child_of(parent))
-            .await
+            .await?;
+        Ok(())
     }
 
-    async fn command(&mut self, command: &str, parent: &JupyterMessage) -> anyhow::Result<()> {
-        let (header, body) = split_magic(command);
+    async fn use_magic(&mut self, call: MagicCall, parent: &JupyterMessage) -> Result<()> {
+        // todo: check that it's a valid model
+        self.model = call.value("name").unwrap_or_default().to_string();
+        let message = format!("Set model to {}", self.model);
 
-        let tokens: Vec<&str> = header.split_whitespace().collect();
+        self.send_markdown(&message, parent).await
+    }
 
+    async fn model_magic(&mut self, call: MagicCall, parent: &JupyterMessage) -> Result<()> {
         let mut ollama_client = OllamaClient::new();
 
-        match tokens[..] {
-            [] | ["h"] | ["help"] => {
-                self.send_markdown(
-                    r#"
-# Model curation
+        if call.flag("list") {
+            let models = ollama_client.list_local_models().await?;
 
-* **`%model`**: Get the current model
-* **`%model --list`**: List the available models
-* **`%model --create {name}`**: Create or update the modelfile for {name}. The cell body below the command is the Modelfile.
-* **`%model --show {name}`**: Show details for the named model. Omit name to get details on the current model.
+            let reformatted_models: HashMap<String, LocalModelListing> =
+                models.into_iter().map(|m| (m.name.clone(), m)).collect();
 
-# Conversation
+            let json_value = serde_json::to_value(reformatted_models)?;
 
-* **`%use {name}`**: Set the currently used model to `{name}`
-* **`%reset`**: Clear out the conversation history
+            return self.send_json(json_value, parent).await;
+        }
 
-# Help
+        if let Some(name) = call.value("create") {
+            let body = match &call.body {
+                Some(body) => body,
+                None => {
+                    return self.send_error("Missing Modelfile Body", "", parent).await;
+                }
+            };
 
-* **`%help`**: call this help menu
-"#
-                    .trim(),
-                    parent,
-                )
+            let mut updates = ollama_client.create(name, body).await?;
+
+            let progress = DisplayHandle::new();
+            self.display_markdown(&progress, "_creating model_", parent)
                 .await?;
+            while let Some(Ok(update)) = updates.next().await {
+                self.update_markdown(&progress, &update.status, parent)
+                    .await?;
             }
-            ["reset"] => {
-                self.previous_messages.clear();
-                self.last_context.clear()
-            }
-            ["model", "--list"] => {
-                let models = ollama_client.list_local_models().await?;
+            return self
+                .update_markdown(&progress, "Model created", parent)
+                .await;
+        }
 
-                let reformatted_models: HashMap<String, LocalModelListing> =
-                    models.into_iter().map(|m| (m.name.clone(), m)).collect();
+        let name = match call.value("name") {
+            Some(name) => name.to_string(),
+            None => self.model.clone(),
+        };
 
-                let json_value = serde_json::to_value(reformatted_models)?;
+        let message = format!("Getting details for model: {}", name);
+        let details = DisplayHandle::new();
+        self.display_markdown(&details, &message, parent).await?;
 
-                self.send_json(json_value, parent).await?;
-            }
-            ["use", name] => {
-                // todo: check that it's a valid model
-                self.model = name.to_string();
-                let message = format!("Set model to {name}");
+        let listing = ollama_client.show(&name).await?;
+        let mut display = String::new();
 
-                self.send_markdown(&message, parent).await?;
-            }
-            ["model", "--create", name] => {
-                let body = match body {
-                    Some(body) => body,
-                    None => {
-                        self.send_error("Missing Modelfile Body", "", parent)
-                            .await?;
-                        return Ok(());
-                    }
-                };
+        display += "# ";
+        display += &name;
 
-                let mut updates = ollama_client.create(name, body).await?;
+        display += "\n## Modelfile\n\n";
+        display += "```docker\n";
+        display += &listing.modelfile;
+        display += "\n```\n";
 
-                while let Some(Ok(update)) = updates.next().await {
-                    self.send_markdown(&update.status, parent).await?;
-                    self.clear_output_after_next_output(parent).await?;
-                }
-                self.send_markdown("Model created", parent).await?;
-            }
-            ["model", "--show", ..] | ["model"] => {
-                let name = match tokens[..] {
-                    ["model", "--show", name] => name,
-                    _ => &self.model.clone(),
-                };
-
-                let message = format!("Getting details for model: {}", name);
-                self.send_markdown(&message, parent).await?;
-                self.clear_output_after_next_output(parent).await?;
-
-                let listing = ollama_client.show(name).await?;
-                let mut display = String::new();
-
-                display += "# ";
-                display += name;
-
-                display += "\n## Modelfile\n\n";
-                display += "```docker\n";
-                display += &listing.modelfile;
-                display += "\n```\n";
-
-                if let Some(parameters) = &listing.parameters {
-                    display += "\n## Parameters\n\n";
-                    display += "```\n";
-                    display += parameters;
-                    display += "\n```\n";
-                }
-
-                display += "\n## Template\n\n";
-                display += "```\n";
-                display += &listing.template;
-                display += "\n```\n";
+        if let Some(parameters) = &listing.parameters {
+            display += "\n## Parameters\n\n";
+            display += "```\n";
+            display += parameters;
+            display += "\n```\n";
+        }
 
-                self.send_markdown(&display, parent).await?;
-                self.send_json(serde_json::to_value(listing.details)?, parent)
-                    .await?;
-            }
-            _ => self.send_error("Unknown command", header, parent).await?,
-        };
+        display += "\n## Template\n\n";
+        display += "```\n";
+        display += &listing.template;
+        display += "\n```\n";
 
-        anyhow::Ok(())
+        self.update_markdown(&details, &display, parent).await?;
+        self.send_json(serde_json::to_value(listing.details)?, parent)
+            .await
     }
 
     async fn complete(&mut self, request: &CompleteRequest) -> anyhow::Result<CompleteReply> {
         let cursor_pos = request.cursor_pos;
 
+        if let Some(reply) = self.magics.complete(&request.code, cursor_pos) {
+            return Ok(reply);
+        }
+
         let mut ollama_client = OllamaClient::new();
 
-        let (text_before, text_after) = match request.code.split_at_checked(cursor_pos) {
+        // The protocol counts the cursor in characters, not bytes
+        let split = convert_offset(
+            &request.code,
+            cursor_pos,
+            CursorUnit::Chars,
+            CursorUnit::Bytes,
+        );
+        let (text_before, text_after) = match split.map(|at| request.code.split_at(at)) {
             Some(text) => text,
             None => {
                 eprintln!("Invalid cursor position requested");
@@ -367,9 +423,15 @@ Please generate a few responses to complete their text for them.
         anyhow::Ok(reply)
     }
 
-    async fn execute(&mut self, request: &JupyterMessage) -> anyhow::Result<()> {
-        let code = match &request.content {
-            JupyterMessageContent::ExecuteRequest(req) => req.code.clone(),
+    async fn execute(
+        &mut self,
+        request: &JupyterMessage,
+        execution_count: ExecutionCount,
+    ) -> anyhow::Result<()> {
+        let (code, store_history) = match &request.content {
+            JupyterMessageContent::ExecuteRequest(req) => {
+                (req.code.clone(), ExecutionCounter::stores_history(req))
+            }
             _ => return Err(anyhow::anyhow!("Invalid message type for execution")),
         };
 
@@ -379,8 +441,20 @@ Please generate a few responses to complete their text for them.
         }
 
         // "Magics"
-        if let Some(command) = code.strip_prefix("%") {
-            return self.command(command, request).await;
+        let magics = self.magics.clone();
+        match magics.dispatch(self, &code, request).await {
+            Ok(Dispatch::NotMagic) => {}
+            Ok(Dispatch::Help(help)) => {
+                return self
+                    .send_markdown(&format!("# Magics\n\n{}", help), request)
+                    .await;
+            }
+            Ok(Dispatch::Ran) => return Ok(()),
+            Err(err) => {
+                return self
+                    .send_error("MagicError", &err.to_string(), request)
+                    .await;
+            }
         }
 
         self.previous_messages.push(ChatMessage {
@@ -388,12 +462,14 @@ Please generate a few responses to complete their text for them.
             content: code,
         });
 
-        self.send_markdown("_connecting to model_", request).await?;
-
-        // Clear the progress message after the first tokens come in
-        self.clear_output_after_next_output(request).await?;
+        // Replaced by the response as it streams in
+        let response_display = DisplayHandle::new();
+        self.display_markdown(&response_display, "_connecting to model_", request)
+            .await?;
 
         let mut in_progress_assistant_response = String::new();
+        let mut last_update: Option<Instant> = None;
+        let mut pending_update = false;
 
         let mut ollama_client = OllamaClient::new();
         let mut chunks = ollama_client
@@ -406,8 +482,18 @@ Please generate a few responses to complete their text for them.
                     let text_delta = response.message.content;
 
                     in_progress_assistant_response.push_str(&text_delta);
-
-                    self.push_stdout(&text_delta, request).await?;
+                    pending_update = true;
+
+                    if last_update.is_none_or(|at| at.elapsed() >= RESPONSE_UPDATE_INTERVAL) {
+                        self.update_markdown(
+                            &response_display,
+                            &in_progress_assistant_response,
+                            request,
+                        )
+                        .await?;
+                        last_update = Some(Instant::now());
+                        pending_update = false;
+                    }
                 }
                 Err(err) => {
                     self.send_error("OllamaKernelError", &err.to_string(), request)
@@ -416,10 +502,23 @@ Please generate a few responses to complete their text for them.
             }
         }
 
-        if !in_progress_assistant_response.trim().is_empty() {
-            self.clear_output_after_next_output(request).await?;
-            self.send_markdown(&in_progress_assistant_response, request)
+        // The tokens since the last redraw
+        if pending_update {
+            self.update_markdown(&response_display, &in_progress_assistant_response, request)
                 .await?;
+        }
+
+        if !in_progress_assistant_response.trim().is_empty() {
+            // A silent request shares the previous execution's count, so
+            // recording it would overwrite that output
+            if store_history {
+                if let Err(err) = self
+                    .history
+                    .record_output(execution_count.0, &in_progress_assistant_response)
+                {
+                    eprintln!("Error recording history: {}", err);
+                }
+            }
 
             self.previous_messages.push(ChatMessage {
                 role: Role::Assistant,
@@ -463,11 +562,18 @@ Please generate a few responses to complete their text for them.
                 let reply = self.complete(req).await?;
                 shell.send(reply.as_child_of(parent)).await?;
             }
-            JupyterMessageContent::ExecuteRequest(_) => {
+            JupyterMessageContent::ExecuteRequest(request) => {
+                let execution_count = self.execution_counter.start(parent, &self.iopub).await?;
+                if ExecutionCounter::stores_history(request) {
+                    if let Err(err) = self.history.record_input(execution_count.0, &request.code) {
+                        eprintln!("Error recording history: {}", err);
+                    }
+                }
+
                 // Respond back with reply immediately
                 let reply = ExecuteReply {
                     status: ReplyStatus::Ok,
-                    execution_count: self.one_up_execution_count(),
+                    execution_count,
                     user_expressions: Default::default(),
                     payload: Default::default(),
                     error: None,
@@ -475,18 +581,13 @@ Please generate a few responses to complete their text for them.
                 .as_child_of(parent);
                 shell.send(reply).await?;
 
-                if let Err(err) = self.execute(parent).await {
+                if let Err(err) = self.execute(parent, execution_count).await {
                     self.send_error("OllamaFailure", &err.to_string(), parent)
                         .await?;
                 }
             }
-            JupyterMessageContent::HistoryRequest(_) => {
-                let reply = HistoryReply {
-                    history: Default::default(),
-                    status: ReplyStatus::Ok,
-                    error: None,
-                }
-                .as_child_of(parent);
+            JupyterMessageContent::HistoryRequest(request) => {
+                let reply = self.history.reply(request).as_child_of(parent);
                 shell.send(reply).await?;
             }
             JupyterMessageContent::InspectRequest(_) => {
@@ -520,7 +621,7 @@ Please generate a few responses to complete their text for them.
                 shell.send(reply).await?;
             }
             // Not implemented for shell includes DebugRequest
-            // Not implemented for control (and sometimes shell...) includes InterruptRequest, ShutdownRequest
+            // Not implemented for control (and sometimes shell...) includes InterruptRequest
             _ => {}
         };
 
@@ -540,9 +641,9 @@ Please generate a few responses to complete their text for them.
                 version: "0.1".to_string(),
                 mimetype: "text/markdown".to_string(),
                 file_extension: ".md".to_string(),
-                pygments_lexer: "markdown".to_string(),
-                codemirror_mode: CodeMirrorMode::Simple("markdown".to_string()),
-                nbconvert_exporter: "script".to_string(),
+                pygments_lexer: Some("markdown".to_string()),
+                codemirror_mode: Some(CodeMirrorMode::Simple("markdown".to_string())),
+                nbconvert_exporter: Some("script".to_string()),
             },
             banner: "Ollama Kernel".to_string(),
             help_links: vec![
@@ -559,11 +660,6 @@ Please generate a few responses to complete their text for them.
             error: None,
         }
     }
-
-    fn one_up_execution_count(&mut self) -> ExecutionCount {
-        self.execution_count.0 += 1;
-        self.execution_count
-    }
 }
 
 pub async fn start_kernel(connection_filepath: &str) -> anyhow::Result<()> {
@@ -0,0 +1,192 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use jupyter_protocol::{ExecuteRequest, JupyterMessageContent, KernelInfoCard};
+use jupyter_render_term::{Renderer, TerminalCapabilities};
+use runtimelib::{ExecutionResult, HeartbeatConfig, KernelClient, Liveness, RuntimeError};
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use crate::output::{print_json, OutputFormat};
+
+#[derive(Args)]
+pub struct ExecArgs {
+    /// Connection file of a running kernel, e.g. one started by Jupyter or VS Code
+    #[arg(long)]
+    connection_file: PathBuf,
+    /// Code to run. Read from stdin if not given
+    code: Option<String>,
+    /// Give up, interrupting the kernel, if the code runs longer than this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+pub struct AttachArgs {
+    /// Connection file of a running kernel, e.g. one started by Jupyter or VS Code
+    #[arg(long)]
+    connection_file: PathBuf,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// Connection file of a running kernel, e.g. one started by Jupyter or VS Code
+    #[arg(long)]
+    connection_file: PathBuf,
+}
+
+async fn connect(connection_file: &Path) -> Result<KernelClient> {
+    match KernelClient::attach(connection_file).await {
+        Ok(client) => Ok(client),
+        Err(RuntimeError::ConnectionFileNotFound(path)) => bail!(
+            "No kernel at {}, it may have shut down. `runt ps` lists running kernels",
+            path.display()
+        ),
+        Err(error) => Err(error)
+            .with_context(|| format!("Failed to connect to {}", connection_file.display())),
+    }
+}
+
+/// One execution, as `--output json` reports it.
+#[derive(Serialize)]
+struct ExecutionReport<'a> {
+    /// `ok` or `error`
+    status: &'a str,
+    execution_count: usize,
+    outputs: Vec<OutputReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct OutputReport<'a> {
+    msg_type: &'a str,
+    content: &'a JupyterMessageContent,
+}
+
+impl<'a> From<&'a ExecutionResult> for ExecutionReport<'a> {
+    fn from(result: &'a ExecutionResult) -> Self {
+        Self {
+            status: if result.is_ok() { "ok" } else { "error" },
+            execution_count: result.reply.execution_count.value(),
+            outputs: result
+                .outputs
+                .iter()
+                .map(|content| OutputReport {
+                    msg_type: content.message_type(),
+                    content,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Run code on the kernel once, exiting with an error if it raised.
+pub async fn exec(args: &ExecArgs, output: OutputFormat) -> Result<()> {
+    let code = match &args.code {
+        Some(code) => code.clone(),
+        None => {
+            let mut code = String::new();
+            tokio::io::stdin().read_to_string(&mut code).await?;
+            code
+        }
+    };
+
+    let mut client = connect(&args.connection_file).await?;
+    if let Some(timeout) = args.timeout {
+        client = client.with_execute_timeout(Duration::from_secs(timeout));
+    }
+    let result = match client.execute(ExecuteRequest::new(code)).await {
+        Ok(result) => result,
+        Err(RuntimeError::Timeout(_)) if args.timeout.is_some() => {
+            // Don't leave the kernel busy with code nobody is waiting for
+            client.interrupt().await.ok();
+            bail!("Timed out after {}s", args.timeout.unwrap_or_default());
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if output.is_json() {
+        print_json(&ExecutionReport::from(&result))?;
+    } else {
+        print_result(&renderer(), &result)?;
+    }
+    if let Some(error) = result.error() {
+        bail!("{}: {}", error.ename, error.evalue);
+    }
+    Ok(())
+}
+
+/// Show which kernel is running, its banner and its help links.
+pub async fn info(args: &InfoArgs, output: OutputFormat) -> Result<()> {
+    let mut client = connect(&args.connection_file).await?;
+    let card = KernelInfoCard::from(&client.kernel_info().await?);
+    if output.is_json() {
+        return print_json(&card);
+    }
+    print!("{}", card.to_markdown());
+    Ok(())
+}
+
+/// A line by line console on the kernel, until end of input. With JSON
+/// output, each line's result is printed as one line of JSON, without
+/// prompts or the banner.
+pub async fn attach(args: &AttachArgs, output: OutputFormat) -> Result<()> {
+    let mut client = connect(&args.connection_file).await?;
+    let kernel_info = client.kernel_info().await?;
+    if !output.is_json() {
+        println!("{}", kernel_info.banner.trim_end());
+        client
+            .start_heartbeat(HeartbeatConfig::default(), |liveness| match liveness {
+                Liveness::Dead => eprintln!("\nThe kernel stopped answering its heartbeat"),
+                Liveness::Alive => eprintln!("\nThe kernel is answering again"),
+            })
+            .await?;
+    }
+
+    let renderer = renderer();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut execution_count = 1;
+    loop {
+        if !output.is_json() {
+            print!("In [{}]: ", execution_count);
+            std::io::stdout().flush()?;
+        }
+        let Some(line) = lines.next_line().await? else {
+            if !output.is_json() {
+                println!();
+            }
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = client.execute(ExecuteRequest::new(line)).await?;
+        if output.is_json() {
+            println!(
+                "{}",
+                serde_json::to_string(&ExecutionReport::from(&result))?
+            );
+        } else {
+            print_result(&renderer, &result)?;
+        }
+        execution_count = result.reply.execution_count.value() + 1;
+    }
+    Ok(())
+}
+
+fn print_result(renderer: &Renderer, result: &ExecutionResult) -> Result<()> {
+    let (mut stdout, mut stderr) = (std::io::stdout().lock(), std::io::stderr().lock());
+    for output in &result.outputs {
+        renderer.write_output(output, &mut stdout, &mut stderr)?;
+    }
+    Ok(())
+}
+
+/// Renders outputs for the terminal runt is writing to, or as plain text
+/// when it's writing to a pipe or file.
+fn renderer() -> Renderer {
+    Renderer::new(TerminalCapabilities::detect(
+        std::io::stdout().is_terminal(),
+    ))
+}
@@ -0,0 +1,168 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use jupyter_protocol::media::sanitize::SanitizePolicy;
+use nbformat::trust::Notary;
+use nbformat::{parse_notebook, upgrade_legacy_notebook, upgrade_v3_notebook, Notebook};
+use runtimelib::user_data_dir;
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::fs;
+
+use crate::output::{print_json, OutputFormat};
+
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A standalone HTML report with embedded images and rendered Markdown
+    Html,
+    /// Markdown with fenced code and embedded images, sized like the notebook shows them
+    Markdown,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Executed notebook to export
+    notebook: PathBuf,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = ExportFormat::Html)]
+    format: ExportFormat,
+    /// Where to write the report. Defaults to the notebook path with the format's extension.
+    /// `-o` still works as it did before `--output` became the global format flag
+    #[arg(short = 'o', long)]
+    output_file: Option<PathBuf>,
+    /// Strip scripts and event handlers from HTML and SVG outputs even if the notebook is trusted.
+    /// Untrusted notebooks are always sanitized
+    #[arg(long)]
+    sanitize: bool,
+    /// When sanitizing, also drop inline styles and data: images
+    #[arg(long)]
+    strict: bool,
+    /// Embed HTML and SVG outputs as they are, scripts and all, without checking that the
+    /// notebook is trusted
+    #[arg(long, conflicts_with_all = ["sanitize", "strict"])]
+    trusted: bool,
+}
+
+/// What `runt export --output json` reports.
+#[derive(Serialize)]
+struct ExportReport<'a> {
+    notebook: &'a PathBuf,
+    format: ExportFormat,
+    output: &'a PathBuf,
+    sanitized: bool,
+}
+
+pub async fn run(args: &ExportArgs, output_format: OutputFormat) -> Result<()> {
+    let content = fs::read_to_string(&args.notebook)
+        .await
+        .with_context(|| format!("Failed to read {}", args.notebook.display()))?;
+    let notebook = match parse_notebook(&content)? {
+        Notebook::V4(notebook) => notebook,
+        Notebook::Legacy(notebook) => upgrade_legacy_notebook(notebook)?,
+        Notebook::V3(notebook) => upgrade_v3_notebook(notebook)?,
+    };
+
+    if (args.sanitize || args.strict || args.trusted) && !matches!(args.format, ExportFormat::Html)
+    {
+        bail!("--sanitize, --strict and --trusted only apply to HTML exports");
+    }
+    let sanitized = match args.format {
+        ExportFormat::Html => {
+            args.sanitize || args.strict || !(args.trusted || is_trusted(&content))
+        }
+        ExportFormat::Markdown => false,
+    };
+    let (report, extension) = match args.format {
+        ExportFormat::Html if sanitized => {
+            let policy = if args.strict {
+                SanitizePolicy::Strict
+            } else {
+                SanitizePolicy::AllowStyles
+            };
+            (
+                nbformat::html::render_html_sanitized(&notebook, policy),
+                "html",
+            )
+        }
+        ExportFormat::Html => (nbformat::html::render_html_trusted(&notebook), "html"),
+        ExportFormat::Markdown => (nbformat::markdown::render_markdown(&notebook), "md"),
+    };
+    let output = args
+        .output_file
+        .clone()
+        .unwrap_or_else(|| args.notebook.with_extension(extension));
+    fs::write(&output, report)
+        .await
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+
+    if output_format.is_json() {
+        return print_json(&ExportReport {
+            notebook: &args.notebook,
+            format: args.format,
+            output: &output,
+            sanitized,
+        });
+    }
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Whether the notebook is signed in the user's trust store, like `jupyter trust` does.
+fn is_trusted(content: &str) -> bool {
+    let Ok(notebook) = serde_json::from_str::<serde_json::Value>(content) else {
+        return false;
+    };
+    user_data_dir()
+        .ok()
+        .and_then(|data_dir| Notary::open_read_only(&data_dir).ok().flatten())
+        .is_some_and(|notary| notary.check(&notebook).unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Commands};
+    use clap::{CommandFactory, Parser};
+
+    fn parse(args: &[&str]) -> (OutputFormat, ExportArgs) {
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Commands::Export(export) => (cli.output, export),
+            _ => panic!("expected runt export"),
+        }
+    }
+
+    #[test]
+    fn test_output_file_flags() {
+        Cli::command().debug_assert();
+
+        for args in [
+            &[
+                "runt",
+                "export",
+                "nb.ipynb",
+                "-o",
+                "report.html",
+                "--output",
+                "json",
+            ][..],
+            &[
+                "runt",
+                "--output",
+                "json",
+                "export",
+                "nb.ipynb",
+                "--output-file",
+                "report.html",
+            ],
+        ] {
+            let (output, export) = parse(args);
+            assert!(output == OutputFormat::Json);
+            assert_eq!(export.output_file, Some(PathBuf::from("report.html")));
+        }
+
+        let (output, export) = parse(&["runt", "export", "nb.ipynb"]);
+        assert!(output == OutputFormat::Text);
+        assert_eq!(export.output_file, None);
+    }
+}
@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+use jupyter_protocol::JupyterKernelspec;
+use runtimelib::{
+    find_kernelspec, install_kernelspec, install_kernelspec_dir, list_kernelspecs,
+    remove_kernelspec, user_data_dir, validate_kernelspec, KernelspecDir,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::output::{print_json, OutputFormat};
+
+#[derive(Subcommand)]
+pub enum KernelspecCommands {
+    /// List installed kernelspecs
+    List {
+        /// Same as `--output json`, like `jupyter kernelspec list --json`
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a kernelspec and the paths it resolves to
+    Show {
+        /// Name of the kernelspec
+        name: String,
+    },
+    /// Install a kernelspec from a directory or a kernel.json file
+    Install {
+        /// A kernelspec directory, or a JSON kernelspec definition
+        source: PathBuf,
+        /// Name to install as. Defaults to the directory name
+        #[arg(long)]
+        name: Option<String>,
+        /// Jupyter data directory to install into. Defaults to the user data directory
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        /// Replace an existing kernelspec with the same name
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Remove an installed kernelspec
+    Remove {
+        /// Name of the kernelspec
+        name: String,
+    },
+    /// Copy an installed kernelspec under a new name
+    Copy {
+        /// Name of the kernelspec to copy
+        source: String,
+        /// Name for the copy
+        destination: String,
+        /// Display name for the copy
+        #[arg(long)]
+        display_name: Option<String>,
+        /// Jupyter data directory to copy into. Defaults to the user data directory
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        /// Replace an existing kernelspec with the same name
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+/// A kernelspec that was installed, copied or removed, as `--output json` reports it.
+#[derive(Serialize)]
+struct KernelspecChange<'a> {
+    name: &'a str,
+    resource_dir: &'a Path,
+}
+
+pub async fn run(command: &KernelspecCommands, output: OutputFormat) -> Result<()> {
+    match command {
+        KernelspecCommands::List { json } => list(*json || output.is_json()).await,
+        KernelspecCommands::Show { name } => show(name, output).await,
+        KernelspecCommands::Install {
+            source,
+            name,
+            data_dir,
+            replace,
+        } => {
+            install(
+                source,
+                name.as_deref(),
+                data_dir.as_deref(),
+                *replace,
+                output,
+            )
+            .await
+        }
+        KernelspecCommands::Remove { name } => {
+            let path = remove_kernelspec(name).await?;
+            if output.is_json() {
+                return print_json(&KernelspecChange {
+                    name,
+                    resource_dir: &path,
+                });
+            }
+            println!("Removed {}", path.display());
+            Ok(())
+        }
+        KernelspecCommands::Copy {
+            source,
+            destination,
+            display_name,
+            data_dir,
+            replace,
+        } => {
+            copy(
+                source,
+                destination,
+                display_name.as_deref(),
+                data_dir.as_deref(),
+                *replace,
+                output,
+            )
+            .await
+        }
+    }
+}
+
+fn resolve_data_dir(data_dir: Option<&Path>) -> Result<PathBuf> {
+    match data_dir {
+        Some(data_dir) => Ok(data_dir.to_path_buf()),
+        None => Ok(user_data_dir()?),
+    }
+}
+
+/// Installed kernelspecs, keeping only the highest priority one for each name.
+pub(crate) async fn installed_kernelspecs() -> Vec<KernelspecDir> {
+    let mut seen = HashSet::new();
+    let mut kernelspecs: Vec<KernelspecDir> = list_kernelspecs()
+        .await
+        .into_iter()
+        .filter(|kernelspec| seen.insert(kernelspec.kernel_name.clone()))
+        .collect();
+    kernelspecs.sort_by(|a, b| a.kernel_name.cmp(&b.kernel_name));
+    kernelspecs
+}
+
+async fn list(json: bool) -> Result<()> {
+    let kernelspecs = installed_kernelspecs().await;
+
+    if json {
+        let kernelspecs: serde_json::Map<String, serde_json::Value> = kernelspecs
+            .into_iter()
+            .map(|kernelspec| {
+                (
+                    kernelspec.kernel_name,
+                    serde_json::json!({
+                        "resource_dir": kernelspec.path,
+                        "spec": kernelspec.kernelspec,
+                    }),
+                )
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({ "kernelspecs": kernelspecs }))?
+        );
+        return Ok(());
+    }
+
+    println!("{:<24} {:<24} PATH", "NAME", "DISPLAY_NAME");
+    for kernelspec in kernelspecs {
+        println!(
+            "{:<24} {:<24} {}",
+            kernelspec.kernel_name,
+            kernelspec.kernelspec.display_name,
+            kernelspec.path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Find `program` the way the kernel launcher will, via `PATH` unless it's a path already.
+fn resolve_program(program: &str) -> Option<PathBuf> {
+    let program = Path::new(program);
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .flat_map(|dir| {
+            let candidate = dir.join(program);
+            if cfg!(windows) {
+                vec![candidate.with_extension("exe"), candidate]
+            } else {
+                vec![candidate]
+            }
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// What `runt kernelspec show --output json` reports.
+#[derive(Serialize)]
+struct KernelspecDetails<'a> {
+    name: &'a str,
+    display_name: &'a str,
+    language: &'a str,
+    resource_dir: &'a Path,
+    definition: PathBuf,
+    interrupt_mode: &'a str,
+    argv: &'a [String],
+    /// Where `argv[0]` resolves to, or `null` if it can't be found
+    program: Option<PathBuf>,
+    /// Why the kernelspec might not launch, if it looks invalid
+    warning: Option<String>,
+    env: BTreeMap<&'a str, &'a str>,
+    logo: Option<PathBuf>,
+    resources: BTreeMap<String, PathBuf>,
+}
+
+async fn show(name: &str, output: OutputFormat) -> Result<()> {
+    let kernelspec = find_kernelspec(name)
+        .await
+        .ok_or_else(|| anyhow!("No such kernelspec: {}", name))?;
+    let spec = &kernelspec.kernelspec;
+
+    if output.is_json() {
+        return print_json(&KernelspecDetails {
+            name: &kernelspec.kernel_name,
+            display_name: &spec.display_name,
+            language: &spec.language,
+            resource_dir: &kernelspec.path,
+            definition: kernelspec.path.join("kernel.json"),
+            interrupt_mode: spec.interrupt_mode.as_deref().unwrap_or("signal"),
+            argv: &spec.argv,
+            program: spec
+                .argv
+                .first()
+                .and_then(|program| resolve_program(program)),
+            warning: validate_kernelspec(spec)
+                .err()
+                .map(|error| error.to_string()),
+            env: spec
+                .env
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+            logo: kernelspec.logo(),
+            resources: kernelspec.resources(),
+        });
+    }
+
+    println!("Name:           {}", kernelspec.kernel_name);
+    println!("Display name:   {}", spec.display_name);
+    println!("Language:       {}", spec.language);
+    println!("Directory:      {}", kernelspec.path.display());
+    println!(
+        "Definition:     {}",
+        kernelspec.path.join("kernel.json").display()
+    );
+    println!(
+        "Interrupt mode: {}",
+        spec.interrupt_mode.as_deref().unwrap_or("signal")
+    );
+    println!("Argv:           {}", spec.argv.join(" "));
+    match spec.argv.first() {
+        Some(program) => match resolve_program(program) {
+            Some(resolved) => println!("Program:        {}", resolved.display()),
+            None => println!("Program:        {} (not found)", program),
+        },
+        None => println!("Program:        (argv is empty)"),
+    }
+    if let Err(error) = validate_kernelspec(spec) {
+        println!("Warning:        {}", error);
+    }
+    if let Some(env) = &spec.env {
+        let mut env: Vec<_> = env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            println!("Env:            {}={}", key, value);
+        }
+    }
+    if let Some(logo) = kernelspec.logo() {
+        println!("Logo:           {}", logo.display());
+    }
+    for (name, path) in kernelspec.resources() {
+        println!("Resource:       {} ({})", name, path.display());
+    }
+    Ok(())
+}
+
+async fn install(
+    source: &Path,
+    name: Option<&str>,
+    data_dir: Option<&Path>,
+    replace: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let data_dir = resolve_data_dir(data_dir)?;
+
+    let installed = if source.is_dir() {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => source
+                .canonicalize()?
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| anyhow!("Can't name a kernelspec after {}", source.display()))?
+                .to_lowercase(),
+        };
+        install_kernelspec_dir(source, &name, &data_dir, replace).await?
+    } else {
+        let name = name.ok_or_else(|| {
+            anyhow!("--name is required when installing from a kernelspec JSON file")
+        })?;
+        let content = fs::read_to_string(source)
+            .await
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        let kernelspec: JupyterKernelspec = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid kernelspec in {}", source.display()))?;
+        install_kernelspec(&kernelspec, name, &data_dir, replace).await?
+    };
+
+    if output.is_json() {
+        return print_json(&KernelspecChange {
+            name: &installed.kernel_name,
+            resource_dir: &installed.path,
+        });
+    }
+    println!(
+        "Installed kernelspec {} in {}",
+        installed.kernel_name,
+        installed.path.display()
+    );
+    Ok(())
+}
+
+async fn copy(
+    source: &str,
+    destination: &str,
+    display_name: Option<&str>,
+    data_dir: Option<&Path>,
+    replace: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let data_dir = resolve_data_dir(data_dir)?;
+    let source = find_kernelspec(source)
+        .await
+        .ok_or_else(|| anyhow!("No such kernelspec: {}", source))?;
+
+    let mut copied = install_kernelspec_dir(&source.path, destination, &data_dir, replace).await?;
+    if let Some(display_name) = display_name {
+        // Patch the JSON rather than the struct, keeping keys it doesn't know
+        let kernel_json = copied.path.join("kernel.json");
+        let mut spec: serde_json::Value = serde_json::from_slice(&fs::read(&kernel_json).await?)?;
+        spec["display_name"] = display_name.into();
+        fs::write(&kernel_json, serde_json::to_vec_pretty(&spec)?).await?;
+        copied.kernelspec.display_name = display_name.to_string();
+    }
+
+    if output.is_json() {
+        return print_json(&KernelspecChange {
+            name: &copied.kernel_name,
+            resource_dir: &copied.path,
+        });
+    }
+    println!(
+        "Copied kernelspec {} to {}",
+        source.kernel_name,
+        copied.path.display()
+    );
+    Ok(())
+}
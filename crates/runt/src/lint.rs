@@ -0,0 +1,97 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use nbformat::lint::{analyze, lint, parse_for_lint, Lint, LintOptions, NotebookStats, Severity};
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::fs;
+
+use crate::output::{print_json, OutputFormat};
+
+#[derive(Args)]
+pub struct LintArgs {
+    /// Notebooks to check
+    #[arg(required = true)]
+    notebooks: Vec<PathBuf>,
+    /// Flag cells whose outputs add up to more than this many bytes
+    #[arg(long, default_value_t = nbformat::lint::DEFAULT_MAX_OUTPUT_BYTES)]
+    max_output_bytes: usize,
+    /// Also print each notebook's cell and output statistics
+    #[arg(long)]
+    stats: bool,
+}
+
+/// A notebook's lints, as `runt nblint --output json` reports them.
+#[derive(Serialize)]
+struct LintReport<'a> {
+    notebook: &'a PathBuf,
+    stats: NotebookStats,
+    lints: Vec<Lint>,
+}
+
+pub async fn run(args: &LintArgs, output: OutputFormat) -> Result<()> {
+    let options = LintOptions {
+        max_output_bytes: args.max_output_bytes,
+    };
+    let mut reports = Vec::new();
+    for path in &args.notebooks {
+        let content = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let notebook = parse_for_lint(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        reports.push(LintReport {
+            notebook: path,
+            stats: analyze(&notebook),
+            lints: lint(&notebook, &options),
+        });
+    }
+
+    if output.is_json() {
+        print_json(&reports)?;
+    } else {
+        for report in &reports {
+            for lint in &report.lints {
+                println!("{}: {}", report.notebook.display(), lint);
+            }
+            if args.stats {
+                print_stats(report);
+            }
+        }
+    }
+
+    let failed = reports
+        .iter()
+        .filter(|report| {
+            report
+                .lints
+                .iter()
+                .any(|lint| lint.severity == Severity::Error)
+        })
+        .count();
+    if failed > 0 {
+        bail!("{} of {} notebooks have errors", failed, reports.len());
+    }
+    Ok(())
+}
+
+fn print_stats(report: &LintReport) {
+    let stats = &report.stats;
+    println!(
+        "{}: {} cells ({} code, {} markdown, {} raw), {} unexecuted, {} execution count gaps",
+        report.notebook.display(),
+        stats.cells(),
+        stats.code_cells,
+        stats.markdown_cells,
+        stats.raw_cells,
+        stats.unexecuted_code_cells,
+        stats.execution_count_gaps
+    );
+    println!(
+        "  {} outputs, {} bytes",
+        stats.outputs,
+        stats.total_output_bytes()
+    );
+    for (mime_type, bytes) in &stats.output_bytes {
+        println!("  {:>12}  {}", bytes, mime_type);
+    }
+}
@@ -1,50 +1,222 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use jupyter_protocol::PublicConnectionInfo;
+use runtimelib::containers::ContainerDiscovery;
 use runtimelib::{runtime_dir, ConnectionInfo};
-use std::path::PathBuf;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use tokio::fs;
 
+mod attach;
+mod export;
+mod kernelspec;
+mod lint;
+mod output;
+mod render;
+mod run;
+mod trust;
+use attach::{AttachArgs, ExecArgs, InfoArgs};
+use export::ExportArgs;
+use kernelspec::KernelspecCommands;
+use lint::LintArgs;
+use output::{print_json, OutputFormat};
+use render::RenderArgs;
+use run::RunArgs;
+use trust::TrustArgs;
+
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, arg_required_else_help = true)]
 struct Cli {
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
     #[command(subcommand)]
-    command: Option<Commands>,
+    command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// List currently running kernels
-    Ps,
+    Ps {
+        /// Include each kernel's key, which lets anyone who sees it run code on the kernel
+        #[arg(long)]
+        show_secrets: bool,
+        /// Also list kernels running in local Docker containers labelled
+        /// `org.jupyter.kernels`
+        #[arg(long)]
+        containers: bool,
+        /// With --containers, look in every running container, labelled or not
+        #[arg(long, requires = "containers")]
+        all_containers: bool,
+    },
+    /// Manage kernelspecs
+    Kernelspec {
+        #[command(subcommand)]
+        command: KernelspecCommands,
+    },
+    /// Start a kernel from a kernelspec, until it exits or Ctrl-C
+    Run(RunArgs),
+    /// Run code on a kernel from its connection file
+    Exec(ExecArgs),
+    /// Start a console on a kernel from its connection file
+    Attach(AttachArgs),
+    /// Show a kernel's implementation, language, banner and help links
+    Info(InfoArgs),
+    /// Export an executed notebook as a shareable report
+    Export(ExportArgs),
+    /// Show a notebook in the terminal, with its outputs, through $PAGER
+    Render(RenderArgs),
+    /// Trust notebooks so their HTML and Javascript outputs render
+    Trust(TrustArgs),
+    /// Report notebook statistics and problems like huge outputs or cells run out of order
+    Nblint(LintArgs),
+    /// Print a shell completion script, e.g. `runt completions bash > /etc/bash_completion.d/runt`
+    Completions {
+        /// Shell to complete in
+        shell: Shell,
+    },
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
+    match run(&cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            output::print_error(cli.output, &error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: &Cli) -> Result<()> {
+    let output = cli.output;
     match &cli.command {
-        Some(Commands::Ps) => list_kernels().await?,
-        None => println!("No command specified. Use --help for usage information."),
+        Commands::Ps {
+            show_secrets,
+            containers,
+            all_containers,
+        } => {
+            let containers =
+                containers.then(|| ContainerDiscovery::new().with_all_containers(*all_containers));
+            list_kernels(*show_secrets, containers, output).await
+        }
+        Commands::Kernelspec { command } => kernelspec::run(command, output).await,
+        Commands::Run(args) => run::run(args, output).await,
+        Commands::Exec(args) => attach::exec(args, output).await,
+        Commands::Attach(args) => attach::attach(args, output).await,
+        Commands::Info(args) => attach::info(args, output).await,
+        Commands::Export(args) => export::run(args, output).await,
+        Commands::Render(args) => render::run(args, output).await,
+        Commands::Trust(args) => trust::run(args, output).await,
+        Commands::Nblint(args) => lint::run(args, output).await,
+        Commands::Completions { shell } => {
+            clap_complete::generate(*shell, &mut Cli::command(), "runt", &mut std::io::stdout());
+            Ok(())
+        }
     }
+}
 
-    Ok(())
+/// A running kernel, as `runt ps --output json` lists it.
+#[derive(Serialize)]
+struct KernelListing {
+    name: String,
+    /// The container the kernel runs in, for `--containers`. Its connection
+    /// file is inside the container, and its ports are the host's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    container: Option<String>,
+    connection_file: PathBuf,
+    /// Missing for container kernels that can't be reached from the host
+    #[serde(flatten)]
+    connection: Option<PublicConnectionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature_scheme: Option<String>,
 }
 
-async fn list_kernels() -> Result<()> {
+async fn list_kernels(
+    show_secrets: bool,
+    containers: Option<ContainerDiscovery>,
+    output: OutputFormat,
+) -> Result<()> {
     let runtime_dir = runtime_dir();
     let mut entries = fs::read_dir(runtime_dir).await?;
+    let container_kernels = match containers {
+        Some(discovery) => discovery
+            .discover()
+            .await
+            .context("Failed to list kernels in containers")?,
+        None => Vec::new(),
+    };
 
-    println!("{:<12} {:<10} {:<6} {:<6} {:<6} {:<6} {:<6} {:<6} {:<38} {:<10}", 
-             "KERNEL_NAME", "IP", "TRANS", "SHELL", "IOPUB", "STDIN", "CONTROL", "HB", "KEY", "SIG_SCHEME");
+    if output.is_json() {
+        let mut kernels = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                if let Ok(info) = read_connection_info(&path).await {
+                    kernels.push(KernelListing {
+                        name: kernel_name(&path).to_string(),
+                        container: None,
+                        connection: Some(info.to_public()),
+                        key: show_secrets.then(|| info.key.clone()),
+                        signature_scheme: show_secrets.then(|| info.signature_scheme.clone()),
+                        connection_file: path,
+                    });
+                }
+            }
+        }
+        kernels.sort_by(|a, b| a.name.cmp(&b.name));
+        kernels.extend(container_kernels.into_iter().map(|kernel| {
+            let info = kernel.connection_info.as_ref();
+            KernelListing {
+                name: kernel_name(Path::new(&kernel.connection_file)).to_string(),
+                connection: info.map(ConnectionInfo::to_public),
+                key: info.filter(|_| show_secrets).map(|info| info.key.clone()),
+                signature_scheme: info
+                    .filter(|_| show_secrets)
+                    .map(|info| info.signature_scheme.clone()),
+                container: Some(kernel.container_name),
+                connection_file: kernel.connection_file.into(),
+            }
+        }));
+        return print_json(&kernels);
+    }
+
+    print!(
+        "{:<12} {:<10} {:<6} {:<6} {:<6} {:<6} {:<6} {:<6}",
+        "KERNEL_NAME", "IP", "TRANS", "SHELL", "IOPUB", "STDIN", "CONTROL", "HB"
+    );
+    if show_secrets {
+        print!(" {:<38} {:<10}", "KEY", "SIG_SCHEME");
+    }
+    println!();
 
     while let Some(entry) = entries.next_entry().await? {
         let path = entry.path();
         if path.extension().and_then(|s| s.to_str()) == Some("json") {
             if let Ok(info) = read_connection_info(&path).await {
-                print_kernel_info(&path, &info);
+                print_kernel_info(kernel_name(&path), &info, show_secrets);
             }
         }
     }
 
+    for kernel in &container_kernels {
+        let name = format!(
+            "{}/{}",
+            kernel.container_name,
+            kernel_name(Path::new(&kernel.connection_file))
+        );
+        match &kernel.connection_info {
+            Some(info) => print_kernel_info(&name, info, show_secrets),
+            None => println!("{:<12} (not reachable from the host)", name),
+        }
+    }
+
     Ok(())
 }
 
@@ -54,17 +226,27 @@ async fn read_connection_info(path: &PathBuf) -> Result<ConnectionInfo> {
     Ok(info)
 }
 
-fn print_kernel_info(path: &PathBuf, info: &ConnectionInfo) {
-    let kernel_name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("unknown");
-    println!("{:<12} {:<10} {:<6} {:<6} {:<6} {:<6} {:<6} {:<6} {:<38} {:<10}",
-             kernel_name,
-             info.ip,
-             info.transport,
-             info.shell_port,
-             info.iopub_port,
-             info.stdin_port,
-             info.control_port,
-             info.hb_port,
-             info.key,
-             info.signature_scheme);
+fn kernel_name(path: &Path) -> &str {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown")
+}
+
+fn print_kernel_info(kernel_name: &str, info: &ConnectionInfo, show_secrets: bool) {
+    let public = info.to_public();
+    print!(
+        "{:<12} {:<10} {:<6} {:<6} {:<6} {:<6} {:<6} {:<6}",
+        kernel_name,
+        public.ip,
+        public.transport,
+        public.shell_port,
+        public.iopub_port,
+        public.stdin_port,
+        public.control_port,
+        public.hb_port
+    );
+    if show_secrets {
+        print!(" {:<38} {:<10}", info.key, info.signature_scheme);
+    }
+    println!();
 }
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How commands report what they did.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tables and messages for people
+    #[default]
+    Text,
+    /// One JSON document on stdout, for scripts. Errors go to stderr as
+    /// `{"error": "..."}` with a non-zero exit code
+    Json,
+}
+
+impl OutputFormat {
+    pub fn is_json(&self) -> bool {
+        *self == OutputFormat::Json
+    }
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Report a failed command on stderr.
+pub fn print_error(format: OutputFormat, error: &anyhow::Error) {
+    match format {
+        OutputFormat::Text => eprintln!("Error: {:?}", error),
+        OutputFormat::Json => {
+            eprintln!("{}", serde_json::json!({ "error": format!("{:#}", error) }))
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use jupyter_protocol::JupyterMessageContent;
+use jupyter_render_term::markdown::render_markdown;
+use jupyter_render_term::{Renderer, TerminalCapabilities};
+use nbformat::v4::{Cell, Notebook};
+use nbformat::{parse_notebook, upgrade_legacy_notebook, upgrade_v3_notebook};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use tokio::fs;
+
+use crate::output::OutputFormat;
+
+const GREEN: &str = "\x1b[32m";
+const BOLD_GREEN: &str = "\x1b[1;32m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Args)]
+pub struct RenderArgs {
+    /// Notebook to show
+    notebook: PathBuf,
+    /// Write to stdout rather than through $PAGER
+    #[arg(long)]
+    no_pager: bool,
+}
+
+pub async fn run(args: &RenderArgs, output: OutputFormat) -> Result<()> {
+    if output.is_json() {
+        bail!("runt render only writes text; use runt export for other formats");
+    }
+    let content = fs::read_to_string(&args.notebook)
+        .await
+        .with_context(|| format!("Failed to read {}", args.notebook.display()))?;
+    let notebook = match parse_notebook(&content)? {
+        nbformat::Notebook::V4(notebook) => notebook,
+        nbformat::Notebook::Legacy(notebook) => upgrade_legacy_notebook(notebook)?,
+        nbformat::Notebook::V3(notebook) => upgrade_v3_notebook(notebook)?,
+    };
+
+    let is_terminal = std::io::stdout().is_terminal();
+    let paged = is_terminal && !args.no_pager;
+    let mut capabilities = TerminalCapabilities::detect(is_terminal);
+    // Pagers can't draw images
+    if paged {
+        capabilities.images = None;
+    }
+    let text = render_notebook(&notebook, &Renderer::new(capabilities));
+
+    if !paged || !page(&text)? {
+        std::io::stdout().lock().write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The notebook the way `jupyter-console` would have shown it running, with
+/// Markdown cells in between.
+fn render_notebook(notebook: &Notebook, renderer: &Renderer) -> String {
+    let color = renderer.capabilities().color;
+    let mut blocks = Vec::new();
+    for cell in &notebook.cells {
+        match cell {
+            Cell::Markdown { source, .. } if color => {
+                blocks.push(render_markdown(&source.concat()));
+            }
+            Cell::Markdown { source, .. } | Cell::Raw { source, .. } => {
+                blocks.push(source.concat().trim_end().to_string());
+            }
+            Cell::Code {
+                execution_count,
+                source,
+                outputs,
+                ..
+            } => {
+                let count = execution_count
+                    .map(|count| count.to_string())
+                    .unwrap_or_else(|| " ".to_string());
+                let prompt = format!("In [{}]: ", count);
+                let indent = " ".repeat(prompt.len());
+                let prompt = if color {
+                    format!("{GREEN}In [{BOLD_GREEN}{count}{GREEN}]:{RESET} ")
+                } else {
+                    prompt
+                };
+
+                let source = source.concat();
+                let mut block = String::new();
+                for (index, line) in source.trim_end().lines().enumerate() {
+                    let line = if index == 0 {
+                        format!("{}{}", prompt, line)
+                    } else {
+                        format!("{}{}", indent, line)
+                    };
+                    block.push_str(line.trim_end());
+                    block.push('\n');
+                }
+                if block.is_empty() {
+                    block = format!("{}\n", prompt.trim_end());
+                }
+                for output in outputs {
+                    let content = JupyterMessageContent::from(output.clone());
+                    if let Some(rendered) = renderer.render_output(&content) {
+                        block.push_str(&rendered.text);
+                    }
+                }
+                blocks.push(block.trim_end().to_string());
+            }
+        }
+    }
+    blocks.retain(|block| !block.is_empty());
+
+    let mut text = blocks.join("\n\n");
+    text.push('\n');
+    text
+}
+
+/// Show `text` in `$PAGER`, or `less`. Returns `false` if there's no pager
+/// to run.
+fn page(text: &str) -> Result<bool> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    let mut words = pager.split_whitespace();
+    let Some(program) = words.next() else {
+        return Ok(false);
+    };
+
+    let mut command = Command::new(program);
+    command.args(words).stdin(Stdio::piped());
+    // Like git: quit if it fits on one screen, keep colors, and leave the text on screen
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let Ok(mut child) = command.spawn() else {
+        return Ok(false);
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager early closes the pipe, which is fine
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    child.wait()?;
+    Ok(true)
+}
@@ -0,0 +1,575 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use jupyter_protocol::connection_info::Transport;
+use jupyter_protocol::Session;
+use runtimelib::{
+    create_client_iopub_connection, peek_ports, runtime_dir, ConnectionInfo, KernelspecDir,
+};
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::kernelspec::installed_kernelspecs;
+use crate::output::{print_json, OutputFormat};
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Kernelspec to start. Partial names like `py` match `python3`, and
+    /// ambiguous ones bring up a picker
+    kernel: Option<String>,
+    /// Start the default kernel for this language instead of naming one
+    #[arg(long, conflicts_with = "kernel")]
+    language: Option<String>,
+    /// Open a sidecar window on the kernel's outputs, closed when the kernel stops
+    #[arg(long)]
+    with_sidecar: bool,
+    /// Append every message the kernel broadcasts on iopub to this file, one
+    /// JSON message per line
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+}
+
+/// A started kernel, as `--output json` reports it.
+#[derive(Serialize)]
+struct StartedKernel<'a> {
+    kernel_name: &'a str,
+    display_name: &'a str,
+    language: &'a str,
+    connection_file: &'a Path,
+    pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sidecar_pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<&'a Path>,
+}
+
+/// Start a kernel and keep it running until it exits or Ctrl-C.
+pub async fn run(args: &RunArgs, output: OutputFormat) -> Result<()> {
+    let kernelspecs = installed_kernelspecs().await;
+    if kernelspecs.is_empty() {
+        bail!("No kernelspecs are installed. `runt kernelspec install` adds one");
+    }
+    let kernelspec = match (&args.kernel, &args.language) {
+        (_, Some(language)) => default_for_language(&kernelspecs, language)?,
+        (Some(query), None) => resolve(&kernelspecs, query, output).await?,
+        (None, None) => pick(&kernelspecs, None, output).await?,
+    };
+    launch(kernelspec.clone(), args, output).await
+}
+
+/// How well `query` matches a kernelspec's name or display name, higher being
+/// better: exact, then prefix, then substring, then the query's characters in
+/// order, with fewer characters in between scoring higher.
+fn score(query: &str, kernelspec: &KernelspecDir) -> Option<usize> {
+    let query = query.to_lowercase();
+    [&kernelspec.kernel_name, &kernelspec.kernelspec.display_name]
+        .into_iter()
+        .filter_map(|candidate| {
+            let candidate = candidate.to_lowercase();
+            if candidate == query {
+                Some(4000)
+            } else if candidate.starts_with(&query) {
+                Some(3000 - candidate.len().min(999))
+            } else if candidate.contains(&query) {
+                Some(2000 - candidate.len().min(999))
+            } else {
+                subsequence_gaps(&query, &candidate).map(|gaps| 1000 - gaps.min(999))
+            }
+        })
+        .max()
+}
+
+/// How many characters of `candidate` lie between those matching `query`, if
+/// all of `query` appears in it in order.
+fn subsequence_gaps(query: &str, candidate: &str) -> Option<usize> {
+    let mut remaining = candidate.chars();
+    let mut gaps = 0;
+    for wanted in query.chars() {
+        gaps += remaining.position(|c| c == wanted)?;
+    }
+    Some(gaps)
+}
+
+/// Kernelspecs matching `query`, best first.
+fn matches<'a>(kernelspecs: &'a [KernelspecDir], query: &str) -> Vec<&'a KernelspecDir> {
+    let mut scored: Vec<(usize, &KernelspecDir)> = kernelspecs
+        .iter()
+        .filter_map(|kernelspec| Some((score(query, kernelspec)?, kernelspec)))
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+    scored
+        .into_iter()
+        .map(|(_, kernelspec)| kernelspec)
+        .collect()
+}
+
+/// The kernelspec `query` names, asking which one is meant if it's ambiguous
+/// or matches nothing.
+async fn resolve<'a>(
+    kernelspecs: &'a [KernelspecDir],
+    query: &str,
+    output: OutputFormat,
+) -> Result<&'a KernelspecDir> {
+    if let Some(exact) = kernelspecs
+        .iter()
+        .find(|kernelspec| kernelspec.kernel_name == query)
+    {
+        return Ok(exact);
+    }
+    if let [only] = matches(kernelspecs, query).as_slice() {
+        return Ok(only);
+    }
+    pick(kernelspecs, Some(query), output).await
+}
+
+/// The kernelspec for `language`: the one named after it if there is one,
+/// like `python3` for Python, and otherwise the first by name.
+fn default_for_language<'a>(
+    kernelspecs: &'a [KernelspecDir],
+    language: &str,
+) -> Result<&'a KernelspecDir> {
+    let language = language.to_lowercase();
+    let mut candidates: Vec<&KernelspecDir> = kernelspecs
+        .iter()
+        .filter(|kernelspec| kernelspec.kernelspec.language.to_lowercase() == language)
+        .collect();
+    candidates.sort_by_key(|kernelspec| {
+        let name = kernelspec.kernel_name.to_lowercase();
+        (
+            !(name == language || name == format!("{}3", language)),
+            !name.starts_with(&language),
+            name,
+        )
+    });
+    candidates
+        .first()
+        .copied()
+        .ok_or_else(|| anyhow!("No kernelspec for {}", language))
+}
+
+/// Ask which kernelspec to start, narrowing the list by whatever is typed
+/// that isn't a number. Without a terminal to ask on, fail naming the
+/// candidates instead.
+async fn pick<'a>(
+    kernelspecs: &'a [KernelspecDir],
+    query: Option<&str>,
+    output: OutputFormat,
+) -> Result<&'a KernelspecDir> {
+    let mut query = query.map(str::to_string);
+    let mut candidates = match &query {
+        Some(query) => matches(kernelspecs, query),
+        None => kernelspecs.iter().collect(),
+    };
+
+    if output.is_json() || !std::io::stdin().is_terminal() {
+        let names: Vec<&str> = candidates
+            .iter()
+            .map(|kernelspec| kernelspec.kernel_name.as_str())
+            .collect();
+        match (&query, names.is_empty()) {
+            (Some(query), true) => bail!("No kernelspec matches {}", query),
+            (Some(query), false) => {
+                bail!("{} could be any of: {}", query, names.join(", "))
+            }
+            (None, _) => bail!("Name a kernel to run, one of: {}", names.join(", ")),
+        }
+    }
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        match (&query, candidates.is_empty()) {
+            (Some(query), true) => {
+                println!("No kernels match {}, showing them all", query);
+                candidates = kernelspecs.iter().collect();
+            }
+            (Some(query), false) => println!("Kernels matching {}:", query),
+            (None, _) => println!("Installed kernels:"),
+        }
+        for (i, kernelspec) in candidates.iter().enumerate() {
+            println!(
+                "{:>3}) {:<20} {:<28} {:<10} {}",
+                i + 1,
+                kernelspec.kernel_name,
+                kernelspec.kernelspec.display_name,
+                kernelspec.kernelspec.language,
+                kernelspec.path.display()
+            );
+        }
+        print!(
+            "Pick a kernel [1-{}], or type to narrow the list: ",
+            candidates.len()
+        );
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next_line().await? else {
+            bail!("No kernel picked");
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            bail!("No kernel picked");
+        }
+        if let Ok(number) = line.parse::<usize>() {
+            if let Some(kernelspec) = number.checked_sub(1).and_then(|i| candidates.get(i)) {
+                return Ok(kernelspec);
+            }
+        }
+        candidates = matches(kernelspecs, line);
+        query = Some(line.to_string());
+    }
+}
+
+/// Start `kernelspec` with a new connection file in the runtime directory,
+/// and wait for it to exit. Ctrl-C stops it.
+async fn launch(kernelspec: KernelspecDir, args: &RunArgs, output: OutputFormat) -> Result<()> {
+    // Open the recording first, so a bad path fails before the kernel starts
+    let journal = match &args.record {
+        Some(path) => Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .with_context(|| format!("Failed to open {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let connection_info = local_connection_info(&kernelspec.kernel_name).await?;
+    let runtime_dir = runtime_dir();
+    fs::create_dir_all(&runtime_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", runtime_dir.display()))?;
+    let connection_file = runtime_dir.join(format!("kernel-{}.json", uuid::Uuid::new_v4()));
+    write_private(&connection_file, &serde_json::to_vec(&connection_info)?)
+        .await
+        .with_context(|| format!("Failed to write {}", connection_file.display()))?;
+
+    let started = kernelspec
+        .clone()
+        .command(
+            &connection_file,
+            Some(Stdio::inherit()),
+            Some(if output.is_json() {
+                Stdio::null()
+            } else {
+                Stdio::inherit()
+            }),
+        )?
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", kernelspec.kernel_name));
+    let mut process = match started {
+        Ok(process) => process,
+        Err(error) => {
+            let _ = fs::remove_file(&connection_file).await;
+            return Err(error);
+        }
+    };
+
+    let sidecar = if args.with_sidecar {
+        match start_sidecar(&connection_file) {
+            Ok(sidecar) => Some(sidecar),
+            Err(error) => {
+                let _ = process.kill().await;
+                let _ = fs::remove_file(&connection_file).await;
+                return Err(error);
+            }
+        }
+    } else {
+        None
+    };
+
+    let spec = &kernelspec.kernelspec;
+    if output.is_json() {
+        print_json(&StartedKernel {
+            kernel_name: &kernelspec.kernel_name,
+            display_name: &spec.display_name,
+            language: &spec.language,
+            connection_file: &connection_file,
+            pid: process.id(),
+            sidecar_pid: sidecar.as_ref().and_then(|sidecar| sidecar.id()),
+            record: args.record.as_deref(),
+        })?;
+    } else {
+        println!("Started {} ({})", spec.display_name, kernelspec.kernel_name);
+        println!("Connection file: {}", connection_file.display());
+        if let Some(path) = &args.record {
+            println!("Recording to {}", path.display());
+        }
+        println!(
+            "Connect with `runt attach --connection-file {}`. Press Ctrl-C to stop the kernel",
+            connection_file.display()
+        );
+    }
+
+    let recorder = journal.map(|journal| {
+        let connection_info = connection_info.clone();
+        tokio::spawn(async move {
+            if let Err(error) = record(&connection_info, journal).await {
+                eprintln!("Stopped recording: {:#}", error);
+            }
+        })
+    });
+
+    let exited = tokio::select! {
+        status = process.wait() => Some(status?),
+        _ = tokio::signal::ctrl_c() => {
+            process.kill().await?;
+            None
+        }
+    };
+    // The sidecar is killed on drop, along with the kernel
+    drop(sidecar);
+    if let Some(recorder) = recorder {
+        recorder.abort();
+    }
+    let _ = fs::remove_file(&connection_file).await;
+    match exited {
+        Some(status) if !status.success() => bail!("The kernel exited with {}", status),
+        _ => Ok(()),
+    }
+}
+
+/// Free localhost ports and a new key for a kernel.
+async fn local_connection_info(kernel_name: &str) -> Result<ConnectionInfo> {
+    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let ports = peek_ports(ip, 5).await?;
+    Ok(ConnectionInfo {
+        ip: ip.to_string(),
+        transport: Transport::TCP,
+        shell_port: ports[0],
+        iopub_port: ports[1],
+        stdin_port: ports[2],
+        control_port: ports[3],
+        hb_port: ports[4],
+        key: uuid::Uuid::new_v4().to_string(),
+        signature_scheme: "hmac-sha256".to_string(),
+        kernel_name: Some(kernel_name.to_string()),
+    })
+}
+
+/// Write a file only its owner can read, since connection files hold the key
+/// that signs messages.
+#[cfg(unix)]
+async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .await?
+        .write_all(contents)
+        .await
+}
+
+#[cfg(not(unix))]
+async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    fs::write(path, contents).await
+}
+
+/// A sidecar window on the kernel at `connection_file`. The `sidecar` binary
+/// has to be on the `PATH`.
+fn start_sidecar(connection_file: &Path) -> Result<tokio::process::Child> {
+    tokio::process::Command::new("sidecar")
+        .arg(connection_file)
+        .arg("--quiet")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start the sidecar. `cargo install sidecar` installs it")
+}
+
+/// Append the kernel's iopub messages to `journal` as JSON lines, until the
+/// task is aborted. Messages that don't parse are reported and skipped.
+async fn record(connection_info: &ConnectionInfo, mut journal: fs::File) -> Result<()> {
+    let mut iopub = create_client_iopub_connection(connection_info, "", &Session::new()).await?;
+    loop {
+        let message = match iopub.read().await {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("Not recording a message: {}", error);
+                continue;
+            }
+        };
+        let mut line = serde_json::to_vec(&message.redacted())?;
+        line.push(b'\n');
+        journal.write_all(&line).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jupyter_protocol::JupyterKernelspec;
+
+    fn kernelspec(kernel_name: &str, display_name: &str, language: &str) -> KernelspecDir {
+        KernelspecDir {
+            kernel_name: kernel_name.to_string(),
+            path: std::path::PathBuf::from("/kernels").join(kernel_name),
+            kernelspec: JupyterKernelspec {
+                argv: vec![],
+                display_name: display_name.to_string(),
+                language: language.to_string(),
+                metadata: None,
+                interrupt_mode: None,
+                env: None,
+            },
+        }
+    }
+
+    fn installed() -> Vec<KernelspecDir> {
+        vec![
+            kernelspec("deno", "Deno", "typescript"),
+            kernelspec("ir", "R", "R"),
+            kernelspec("python3", "Python 3 (ipykernel)", "python"),
+            kernelspec("python3.11", "Python 3.11", "python"),
+            kernelspec("pyodide", "Pyodide", "python"),
+        ]
+    }
+
+    fn names(kernelspecs: Vec<&KernelspecDir>) -> Vec<&str> {
+        kernelspecs
+            .into_iter()
+            .map(|kernelspec| kernelspec.kernel_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_score_ranks_exact_prefix_substring_then_subsequence() {
+        let python = kernelspec("python3", "Python 3 (ipykernel)", "python");
+        let exact = score("python3", &python).unwrap();
+        let prefix = score("pyth", &python).unwrap();
+        let substring = score("thon", &python).unwrap();
+        let subsequence = score("pyn3", &python).unwrap();
+        assert!(exact > prefix && prefix > substring && substring > subsequence);
+        assert_eq!(score("ruby", &python), None);
+    }
+
+    #[test]
+    fn test_score_ignores_case_and_checks_display_name() {
+        let ir = kernelspec("ir", "R", "R");
+        assert_eq!(score("IR", &ir), score("ir", &ir));
+        let python = kernelspec("python3", "Python 3 (ipykernel)", "python");
+        assert!(score("ipykernel", &python).is_some());
+    }
+
+    #[test]
+    fn test_subsequence_gaps_prefers_closer_matches() {
+        assert_eq!(subsequence_gaps("pn", "python"), Some(4));
+        assert_eq!(subsequence_gaps("py", "python"), Some(0));
+        assert_eq!(subsequence_gaps("np", "python"), None);
+    }
+
+    #[test]
+    fn test_matches_best_first() {
+        let kernelspecs = installed();
+        assert_eq!(
+            names(matches(&kernelspecs, "python3")),
+            ["python3", "python3.11"]
+        );
+        // A prefix beats the same letters spread out
+        assert_eq!(
+            names(matches(&kernelspecs, "pyo")),
+            ["pyodide", "python3", "python3.11"]
+        );
+        assert!(matches(&kernelspecs, "julia").is_empty());
+    }
+
+    #[test]
+    fn test_default_for_language() {
+        let kernelspecs = installed();
+        assert_eq!(
+            default_for_language(&kernelspecs, "Python")
+                .unwrap()
+                .kernel_name,
+            "python3"
+        );
+        assert_eq!(
+            default_for_language(&kernelspecs, "r").unwrap().kernel_name,
+            "ir"
+        );
+        assert_eq!(
+            default_for_language(&kernelspecs, "julia")
+                .unwrap_err()
+                .to_string(),
+            "No kernelspec for julia"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pick_without_a_terminal_names_the_candidates() {
+        let kernelspecs = installed();
+        let error = pick(&kernelspecs, Some("python3"), OutputFormat::Json)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "python3 could be any of: python3, python3.11"
+        );
+
+        let error = pick(&kernelspecs, Some("julia"), OutputFormat::Json)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "No kernelspec matches julia");
+
+        let error = pick(&kernelspecs, None, OutputFormat::Json)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Name a kernel to run, one of: deno, ir, python3, python3.11, pyodide"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_connection_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("runt-kernel-{}.json", uuid::Uuid::new_v4()));
+        write_private(&path, b"{}").await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_record_appends_iopub_messages() {
+        use jupyter_protocol::{JupyterMessage, Status};
+
+        let connection_info = local_connection_info("python3").await.unwrap();
+        let mut iopub =
+            runtimelib::create_kernel_iopub_connection(&connection_info, &Session::new())
+                .await
+                .unwrap();
+        let path = std::env::temp_dir().join(format!("runt-record-{}.jsonl", uuid::Uuid::new_v4()));
+        let journal = fs::File::create(&path).await.unwrap();
+        let recorder = tokio::spawn(async move { record(&connection_info, journal).await });
+
+        // Subscriptions take a moment to reach the kernel, so publish until
+        // something is recorded
+        let mut contents = String::new();
+        for _ in 0..100 {
+            iopub
+                .send(JupyterMessage::new(Status::busy(), None))
+                .await
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            contents = fs::read_to_string(&path).await.unwrap();
+            if !contents.is_empty() {
+                break;
+            }
+        }
+        recorder.abort();
+        fs::remove_file(&path).await.unwrap();
+
+        let line = contents.lines().next().expect("nothing was recorded");
+        let message = JupyterMessage::from_value(serde_json::from_str(line).unwrap()).unwrap();
+        assert_eq!(message.header.msg_type, "status");
+        assert!(contents.ends_with('\n'));
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use nbformat::trust::Notary;
+use runtimelib::user_data_dir;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::PathBuf;
+use tokio::fs;
+
+use crate::output::{print_json, OutputFormat};
+
+#[derive(Args)]
+pub struct TrustArgs {
+    /// Notebooks to trust
+    #[arg(required = true)]
+    notebooks: Vec<PathBuf>,
+    /// Only report whether each notebook is trusted
+    #[arg(long)]
+    check: bool,
+}
+
+/// A notebook's trust, as `runt trust --output json` reports it.
+#[derive(Serialize)]
+struct TrustStatus<'a> {
+    notebook: &'a PathBuf,
+    trusted: bool,
+    /// Whether this run signed the notebook
+    signed: bool,
+}
+
+pub async fn run(args: &TrustArgs, output: OutputFormat) -> Result<()> {
+    let notary = Notary::open(&user_data_dir()?)?;
+    let mut statuses = Vec::new();
+    for path in &args.notebooks {
+        let content = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let notebook: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let trusted = notary.check(&notebook)?;
+        let signed = !args.check && !trusted;
+        if signed {
+            notary.sign(&notebook)?;
+        }
+        statuses.push(TrustStatus {
+            notebook: path,
+            trusted: trusted || signed,
+            signed,
+        });
+
+        if output.is_json() {
+            continue;
+        }
+        if args.check {
+            let status = if trusted { "trusted" } else { "not trusted" };
+            println!("{}: {}", path.display(), status);
+        } else if signed {
+            println!("Signing notebook: {}", path.display());
+        } else {
+            println!("Notebook already signed: {}", path.display());
+        }
+    }
+
+    if output.is_json() {
+        print_json(&statuses)?;
+    }
+    Ok(())
+}
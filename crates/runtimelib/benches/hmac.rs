@@ -0,0 +1,83 @@
+//! Benchmarks for HMAC signing and verification of wire messages.
+//!
+//! Run with:
+//!
+//! ```sh
+//! cargo bench -p runtimelib --features tokio-runtime --bench hmac
+//! ```
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use data_encoding::HEXLOWER;
+use runtimelib::{HmacSha256Signer, RawMessage, Signer};
+use serde_json::json;
+
+const KEY: &str = "a0436f6c-1916-498b-8eb9-e81ab9368e84";
+
+/// Build a signed multipart message whose content carries `payload_len` bytes of text.
+fn signed_multipart(signer: &HmacSha256Signer, payload_len: usize) -> zeromq::ZmqMessage {
+    let header = json!({
+        "msg_id": "7b2b1f35-8c0f-4a5b-9d35-7b0a9a7f0d9e",
+        "username": "runtimelib",
+        "session": "c7e1a9b0-93d8-4c4f-8a7b-0e2f5b4d6a11",
+        "date": "2024-01-01T00:00:00Z",
+        "msg_type": "stream",
+        "version": "5.3",
+    });
+    let content = json!({ "name": "stdout", "text": "x".repeat(payload_len) });
+
+    let jparts: Vec<Bytes> = vec![
+        serde_json::to_vec(&header).unwrap().into(),
+        b"{}".to_vec().into(),
+        b"{}".to_vec().into(),
+        serde_json::to_vec(&content).unwrap().into(),
+    ];
+
+    let signed: Vec<&[u8]> = jparts.iter().map(|part| part.as_ref()).collect();
+    let signature = HEXLOWER.encode(&signer.sign(&signed));
+
+    let mut parts: Vec<Bytes> = vec![Bytes::from_static(b"<IDS|MSG>"), signature.into()];
+    parts.extend(jparts);
+    zeromq::ZmqMessage::try_from(parts).unwrap()
+}
+
+fn sign(c: &mut Criterion) {
+    let signer = HmacSha256Signer::new(KEY.as_bytes());
+    let mut group = c.benchmark_group("hmac_sign");
+
+    for payload_len in [128, 64 * 1024, 1024 * 1024] {
+        let content = vec![b'x'; payload_len];
+        let parts: [&[u8]; 4] = [b"{}", b"{}", b"{}", &content];
+        group.throughput(Throughput::Bytes(payload_len as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(payload_len),
+            &parts,
+            |b, parts| b.iter(|| signer.sign(black_box(parts))),
+        );
+    }
+
+    group.finish();
+}
+
+fn verify(c: &mut Criterion) {
+    let signer = HmacSha256Signer::new(KEY.as_bytes());
+    let mut group = c.benchmark_group("hmac_verify");
+
+    for payload_len in [128, 64 * 1024, 1024 * 1024] {
+        let message = signed_multipart(&signer, payload_len);
+        group.throughput(Throughput::Bytes(payload_len as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(payload_len),
+            &message,
+            |b, message| {
+                b.iter(|| {
+                    RawMessage::from_multipart(black_box(message.clone()), Some(&signer)).unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, sign, verify);
+criterion_main!(benches);
@@ -11,7 +11,6 @@ async fn main() -> anyhow::Result<()> {
     use jupyter_protocol::{
         ConnectionInfo, ExecuteRequest, ExecutionState, JupyterMessage, JupyterMessageContent,
     };
-    use uuid::Uuid;
 
     let kernel_name = "python";
     let kernelspecs = runtimelib::list_kernelspecs().await;
@@ -62,16 +61,16 @@ async fn main() -> anyhow::Result<()> {
         .kill_on_drop(true)
         .spawn()?;
 
-    let session_id = Uuid::new_v4().to_string();
+    let session = runtimelib::Session::new();
 
     // Listen for display data, execute result, stdout messages, etc.
     let mut iopub_socket =
-        runtimelib::create_client_iopub_connection(&connection_info, "", &session_id).await?;
+        runtimelib::create_client_iopub_connection(&connection_info, "", &session).await?;
     let mut shell_socket =
-        runtimelib::create_client_shell_connection(&connection_info, &session_id).await?;
+        runtimelib::create_client_shell_connection(&connection_info, &session).await?;
     // Control socket is for kernel management, not used here
     // let mut control_socket =
-    //     runtimelib::create_client_control_connection(&connection_info, &session_id).await?;
+    //     runtimelib::create_client_control_connection(&connection_info, &session).await?;
 
     let execute_request = ExecuteRequest::new("print('Hello, World!')".to_string());
     let execute_request: JupyterMessage = execute_request.into();
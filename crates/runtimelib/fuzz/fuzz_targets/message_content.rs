@@ -0,0 +1,24 @@
+//! Deserialize arbitrary JSON as the content of each message type.
+//!
+//! The input is a `msg_type`, a NUL byte, then the content.
+#![no_main]
+
+use jupyter_protocol::JupyterMessageContent;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(split) = data.iter().position(|byte| *byte == 0) else {
+        return;
+    };
+    let Ok(msg_type) = std::str::from_utf8(&data[..split]) else {
+        return;
+    };
+    let Ok(content) = serde_json::from_slice(&data[split + 1..]) else {
+        return;
+    };
+
+    if let Ok(content) = JupyterMessageContent::from_type_and_content(msg_type, content) {
+        // Whatever parses has to serialize again
+        serde_json::to_value(&content).unwrap();
+    }
+});
@@ -0,0 +1,36 @@
+//! Parse arbitrary ZeroMQ frames as a wire message, as `Connection::read` does.
+//!
+//! The input is the message's frames separated by NUL bytes, which never
+//! appear in the JSON parts.
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use runtimelib::{HmacSha256Signer, MessageLimits, RawMessage, Signer};
+
+/// The key the seed corpus was signed with, from `tests/messages`
+const KEY: &[u8] = b"b0e8c1f2-9a3d-4c5e-8f71-6d2a0b9c3e4f";
+
+fuzz_target!(|data: &[u8]| {
+    let frames: Vec<Bytes> = data
+        .split(|byte| *byte == 0)
+        .map(Bytes::copy_from_slice)
+        .collect();
+    let Ok(multipart) = zeromq::ZmqMessage::try_from(frames) else {
+        return;
+    };
+
+    let signer = HmacSha256Signer::new(KEY);
+    let tight_limits = MessageLimits::default()
+        .with_max_content_bytes(Some(64))
+        .with_max_buffer_bytes(Some(64))
+        .with_stream_truncation(true);
+
+    for signer in [None, Some(&signer as &dyn Signer)] {
+        for limits in [MessageLimits::default(), tight_limits] {
+            if let Ok(raw_message) = RawMessage::from_multipart(multipart.clone(), signer) {
+                let _ = raw_message.into_limited_jupyter_message(&limits);
+            }
+        }
+    }
+});
@@ -0,0 +1,48 @@
+"""Rebuild the seed corpus from the test messages.
+
+The seeds come from the signed messages that `../tests/messages/generate.py`
+writes.
+
+Each `raw_message` seed is a message's frames joined with NUL bytes, once as
+sent and once behind a ZeroMQ identity frame. Each `message_content` seed is a
+message's `msg_type`, a NUL byte, then its content.
+
+    python3 seed.py
+"""
+
+import json
+from pathlib import Path
+
+HERE = Path(__file__).parent
+MESSAGES = HERE.parent / "tests" / "messages"
+
+
+def signed_messages():
+    """`(name, frames)` for every message in `tests/messages`."""
+    for path in sorted(MESSAGES.glob("*.json")):
+        document = json.loads(path.read_text())
+        yield path.stem, [part.encode("utf8") for part in document["parts"]]
+
+
+def main():
+    raw_corpus = HERE / "corpus" / "raw_message"
+    content_corpus = HERE / "corpus" / "message_content"
+    for corpus in [raw_corpus, content_corpus]:
+        corpus.mkdir(parents=True, exist_ok=True)
+        for seed in corpus.iterdir():
+            seed.unlink()
+
+    for name, parts in signed_messages():
+        (raw_corpus / name).write_bytes(b"\0".join(parts))
+        (raw_corpus / f"{name}_routed").write_bytes(
+            b"\0".join([b"client-identity", *parts])
+        )
+
+        header = json.loads(parts[2])
+        (content_corpus / name).write_bytes(
+            header["msg_type"].encode("utf8") + b"\0" + parts[5]
+        )
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,1578 @@
+//! A higher level client for talking to a running kernel.
+//!
+//! [`KernelClient`] owns the client side sockets for one kernel and pairs
+//! requests with their replies and iopub output, so callers don't have to
+//! correlate `parent_header`s by hand.
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::future::{abortable, select, AbortHandle, Either};
+use futures::FutureExt;
+use jupyter_protocol::kernel_status::DEFAULT_MAX_MISSED_HEARTBEATS;
+use jupyter_protocol::{
+    CommId, CommInfo, CommInfoRequest, ConnectionInfo, ErrorOutput, ExecuteReply, ExecuteRequest,
+    ExecutionState, ExecutionTimings, ExpressionResult, InterruptReply, InterruptRequest,
+    JupyterKernelspec, JupyterMessage, JupyterMessageContent, KernelCapabilities, KernelInfoReply,
+    KernelInfoRequest, ReplyError, ReplyStatus, Session, ShutdownRequest,
+};
+use serde::Serialize;
+
+use crate::connection::{
+    create_client_control_connection, create_client_heartbeat_connection,
+    create_client_iopub_connection, create_client_shell_connection, read_connection_file,
+    ClientControlConnection, ClientHeartbeatConnection, ClientIoPubConnection,
+    ClientShellConnection,
+};
+use crate::error::{Result, RuntimeError};
+
+/// How long to wait for replies to control requests and `kernel_info` by default.
+pub const DEFAULT_CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many connections a [`ShellPool`] keeps open between requests by default.
+pub const DEFAULT_MAX_IDLE_SHELLS: usize = 2;
+
+/// How often [`KernelClient::start_heartbeat`] pings the kernel by default.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(3);
+
+/// How long [`KernelClient::execute`] waits for the kernel to go idle once
+/// it has replied, like nbclient's `iopub_timeout`.
+pub const DEFAULT_IOPUB_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// How long [`KernelClient::connect`] waits for its iopub subscription to
+/// take effect before carrying on without knowing.
+pub const IOPUB_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for an `iopub_welcome` before probing with a
+/// `kernel_info_request`.
+const IOPUB_PROBE_DELAY: Duration = Duration::from_millis(100);
+
+/// Run `future` to completion unless `duration` passes first.
+pub(crate) async fn with_timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let future = std::pin::pin!(future);
+    match select(future, futures_timer::Delay::new(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// `request`'s output, or [`RuntimeError::KernelDied`] if `death` comes first.
+async fn unless_dead<T>(
+    death: impl Future<Output = ()>,
+    request: impl Future<Output = T>,
+) -> Result<T> {
+    let request = std::pin::pin!(request);
+    let death = std::pin::pin!(death);
+    match select(request, death).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(RuntimeError::KernelDied),
+    }
+}
+
+fn is_child_of(message: &JupyterMessage, msg_id: &str) -> bool {
+    message
+        .parent_header
+        .as_ref()
+        .is_some_and(|parent| parent.msg_id == msg_id)
+}
+
+/// The reply and iopub output of one `execute_request`.
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub reply: ExecuteReply,
+    /// Output messages (`stream`, `display_data`, `execute_result`, `error`, ...)
+    /// in the order the kernel published them.
+    pub outputs: Vec<JupyterMessageContent>,
+    /// When the kernel went busy, replied and went idle, for the cell's
+    /// `execution` metadata.
+    pub timings: ExecutionTimings,
+}
+
+impl ExecutionResult {
+    pub fn is_ok(&self) -> bool {
+        self.reply.status == ReplyStatus::Ok
+    }
+
+    /// The `error` output, if the code raised.
+    pub fn error(&self) -> Option<&ErrorOutput> {
+        self.outputs.iter().find_map(|content| match content {
+            JupyterMessageContent::ErrorOutput(error) => Some(error),
+            _ => None,
+        })
+    }
+
+    /// Everything written to stdout, concatenated.
+    pub fn stdout(&self) -> String {
+        self.outputs
+            .iter()
+            .filter_map(|content| match content {
+                JupyterMessageContent::StreamContent(stream)
+                    if matches!(stream.name, jupyter_protocol::Stdio::Stdout) =>
+                {
+                    Some(stream.text.as_str())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// How a kernel expects to be interrupted, from its kernelspec's `interrupt_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptMode {
+    /// The process that launched the kernel sends it `SIGINT`, or on Windows,
+    /// sets the event it passed in `JPY_INTERRUPT_EVENT`.
+    Signal,
+    /// Clients send an `interrupt_request` on the control channel.
+    Message,
+}
+
+impl InterruptMode {
+    /// The kernelspec's mode. Kernelspecs without one are interrupted with a signal.
+    pub fn from_kernelspec(kernelspec: &JupyterKernelspec) -> Self {
+        match kernelspec.interrupt_mode.as_deref() {
+            Some("message") => InterruptMode::Message,
+            _ => InterruptMode::Signal,
+        }
+    }
+}
+
+/// How the kernel answered an `interrupt_request` or `shutdown_request`.
+///
+/// Serializes as `{"outcome": "rejected", "error": {...}}` for frontends.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", content = "error", rename_all = "snake_case")]
+pub enum ControlOutcome {
+    /// The kernel replied with an `ok` status.
+    Accepted,
+    /// The kernel replied with an `error` status.
+    Rejected(Option<Box<ReplyError>>),
+    /// The kernel replied without a `status`, though its protocol version
+    /// says it should have one, so it's unknown whether the request worked.
+    Unconfirmed,
+    /// No reply within the control timeout.
+    TimedOut,
+    /// The kernel can't be asked this over the protocol, e.g. interrupting a
+    /// kernel whose `interrupt_mode` is `signal`. Nothing was sent.
+    NotSupported,
+}
+
+impl ControlOutcome {
+    pub fn from_reply(status: ReplyStatus, error: Option<Box<ReplyError>>) -> Self {
+        match status {
+            ReplyStatus::Ok => ControlOutcome::Accepted,
+            _ => ControlOutcome::Rejected(error),
+        }
+    }
+
+    /// The outcome of an `interrupt_reply` from a kernel speaking `protocol_version`.
+    ///
+    /// Kernels older than protocol 5.5 may reply with an empty dict, which is
+    /// an acknowledgement rather than a failure.
+    pub fn from_interrupt_reply(reply: InterruptReply, protocol_version: &str) -> Self {
+        match reply.status_for_version(protocol_version) {
+            Some(status) => Self::from_reply(status, reply.error),
+            None => ControlOutcome::Unconfirmed,
+        }
+    }
+}
+
+/// Something that happened to a [`KernelClient`]'s connection, for attached UIs.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// The client reconnected to a restarted kernel. Execution counts start over.
+    Restarted { kernel_info: Box<KernelInfoReply> },
+}
+
+/// Whether the kernel answers on its heartbeat channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liveness {
+    Alive,
+    /// The kernel missed [`HeartbeatConfig::max_missed`] heartbeats in a row,
+    /// the point where a [`KernelStatusTracker`](jupyter_protocol::KernelStatusTracker)
+    /// with the same limit reports it dead.
+    Dead,
+}
+
+/// How [`KernelClient::start_heartbeat`] pings the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Time between pings. A ping without a reply within this long is missed.
+    pub interval: Duration,
+    /// Consecutive missed heartbeats before the kernel is [`Liveness::Dead`].
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            max_missed: DEFAULT_MAX_MISSED_HEARTBEATS,
+        }
+    }
+}
+
+/// The background heartbeat of a [`KernelClient`], stopped when dropped.
+struct Heartbeat {
+    liveness: Arc<Mutex<Liveness>>,
+    interval: Duration,
+    abort: AbortHandle,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+pub struct KernelClient {
+    connection_info: ConnectionInfo,
+    session: Session,
+    shell: ClientShellConnection,
+    iopub: ClientIoPubConnection,
+    control: ClientControlConnection,
+    interrupt_mode: InterruptMode,
+    control_timeout: Duration,
+    iopub_timeout: Duration,
+    execute_timeout: Option<Duration>,
+    capabilities: Option<KernelCapabilities>,
+    events: Vec<mpsc::UnboundedSender<ClientEvent>>,
+    heartbeat: Option<Heartbeat>,
+}
+
+impl KernelClient {
+    /// Connect to the kernel's shell, iopub and control channels with a new session.
+    pub async fn connect(connection_info: &ConnectionInfo) -> Result<Self> {
+        Self::connect_with_session(connection_info, Session::new()).await
+    }
+
+    /// Connect to the kernel described by a connection file.
+    ///
+    /// Fails with [`RuntimeError::ConnectionFileNotFound`] if the file is
+    /// gone, as it is once the kernel has shut down cleanly.
+    pub async fn attach(connection_file: impl AsRef<Path>) -> Result<Self> {
+        Self::connect(&read_connection_file(connection_file)?).await
+    }
+
+    /// Connect to the kernel's shell, iopub and control channels as part of `session`.
+    ///
+    /// Returns once the iopub subscription is likely live, so output from the
+    /// first request isn't lost. See [`wait_for_iopub`](Self::wait_for_iopub).
+    pub async fn connect_with_session(
+        connection_info: &ConnectionInfo,
+        session: Session,
+    ) -> Result<Self> {
+        let iopub = create_client_iopub_connection(connection_info, "", &session).await?;
+        let shell = create_client_shell_connection(connection_info, &session).await?;
+        let control = create_client_control_connection(connection_info, &session).await?;
+
+        let mut client = Self {
+            connection_info: connection_info.clone(),
+            session,
+            shell,
+            iopub,
+            control,
+            interrupt_mode: InterruptMode::Message,
+            control_timeout: DEFAULT_CONTROL_TIMEOUT,
+            iopub_timeout: DEFAULT_IOPUB_TIMEOUT,
+            execute_timeout: None,
+            capabilities: None,
+            events: Vec::new(),
+            heartbeat: None,
+        };
+        client.wait_for_iopub().await?;
+        Ok(client)
+    }
+
+    /// Wait until the iopub subscription has likely taken effect, for at
+    /// most [`IOPUB_READY_TIMEOUT`].
+    ///
+    /// ZeroMQ doesn't say when a subscription reaches the kernel, and
+    /// anything published before then is lost. Kernels speaking protocol 5.4
+    /// publish `iopub_welcome` once it has. Older kernels publish a status
+    /// for every request, so without a welcome the client sends one
+    /// `kernel_info_request` and waits for its reply or anything on iopub.
+    /// If neither arrives in time, it logs a warning and carries on rather
+    /// than giving up on a kernel that may just be slow to start.
+    async fn wait_for_iopub(&mut self) -> Result<()> {
+        // Any message, usually the welcome, will do
+        if let Some(message) = with_timeout(IOPUB_PROBE_DELAY, self.iopub.read()).await {
+            return message.map(|_| ());
+        }
+        self.shell
+            .send(self.session.message(KernelInfoRequest {}))
+            .await?;
+        let iopub = std::pin::pin!(self.iopub.read());
+        let reply = std::pin::pin!(self.shell.read());
+        match with_timeout(IOPUB_READY_TIMEOUT, select(iopub, reply)).await {
+            Some(Either::Left((message, _))) => message.map(|_| ()),
+            // A reply left unread would be skipped by the next request anyway
+            Some(Either::Right((reply, _))) => reply.map(|_| ()),
+            None => {
+                log::warn!(
+                    "Nothing arrived on iopub or shell within {:?}, output published before \
+                     the iopub subscription takes effect will be lost",
+                    IOPUB_READY_TIMEOUT
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Set how the kernel is interrupted. Without a kernelspec to go on, the
+    /// client assumes [`InterruptMode::Message`].
+    pub fn with_interrupt_mode(mut self, interrupt_mode: InterruptMode) -> Self {
+        self.interrupt_mode = interrupt_mode;
+        self
+    }
+
+    /// Set how long [`interrupt`](Self::interrupt), [`shutdown`](Self::shutdown)
+    /// and [`kernel_info`](Self::kernel_info) wait for a reply.
+    pub fn with_control_timeout(mut self, timeout: Duration) -> Self {
+        self.control_timeout = timeout;
+        self
+    }
+
+    /// Set how long [`execute`](Self::execute) waits for the kernel to go
+    /// idle after its reply. Defaults to [`DEFAULT_IOPUB_TIMEOUT`].
+    pub fn with_iopub_timeout(mut self, timeout: Duration) -> Self {
+        self.iopub_timeout = timeout;
+        self
+    }
+
+    /// Limit how long [`execute`](Self::execute) may take altogether. Without
+    /// a limit, code can run as long as it likes.
+    pub fn with_execute_timeout(mut self, timeout: Duration) -> Self {
+        self.execute_timeout = Some(timeout);
+        self
+    }
+
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn session_id(&self) -> &str {
+        self.session.id()
+    }
+
+    /// A [`ShellPool`] for one-shot requests to the same kernel, in this
+    /// client's session.
+    pub fn shell_pool(&self) -> ShellPool {
+        ShellPool::new(&self.connection_info, self.session.clone())
+            .with_timeout(self.control_timeout)
+    }
+
+    /// Receive [`ClientEvent`]s from now on.
+    pub fn events(&mut self) -> mpsc::UnboundedReceiver<ClientEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.events.push(sender);
+        receiver
+    }
+
+    fn emit(&mut self, event: ClientEvent) {
+        self.events
+            .retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Ping the kernel's heartbeat channel in the background until the client
+    /// is dropped, calling `on_liveness_change` whenever the kernel goes from
+    /// answering to [`Liveness::Dead`] or back. Replaces any heartbeat already
+    /// running.
+    ///
+    /// The kernel counts as alive until it misses enough heartbeats. Once
+    /// it's [`Liveness::Dead`], requests waiting on it fail with
+    /// [`RuntimeError::KernelDied`].
+    pub async fn start_heartbeat<F>(
+        &mut self,
+        config: HeartbeatConfig,
+        on_liveness_change: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Liveness) + Send + 'static,
+    {
+        let connection = create_client_heartbeat_connection(&self.connection_info).await?;
+        let liveness = Arc::new(Mutex::new(Liveness::Alive));
+        let (task, abort) = abortable(heartbeat(
+            self.connection_info.clone(),
+            connection,
+            config,
+            liveness.clone(),
+            on_liveness_change,
+        ));
+        crate::runtime::spawn(task.map(|_| ()));
+        self.heartbeat = Some(Heartbeat {
+            liveness,
+            interval: config.interval,
+            abort,
+        });
+        Ok(())
+    }
+
+    pub fn stop_heartbeat(&mut self) {
+        self.heartbeat = None;
+    }
+
+    /// Whether the kernel answers its heartbeat, or `None` if the client
+    /// isn't [pinging it](Self::start_heartbeat).
+    pub fn liveness(&self) -> Option<Liveness> {
+        let heartbeat = self.heartbeat.as_ref()?;
+        Some(*heartbeat.liveness.lock().unwrap())
+    }
+
+    /// Resolves once the heartbeat finds the kernel dead, and never if the
+    /// client isn't pinging it.
+    fn kernel_death(&self) -> impl Future<Output = ()> + 'static {
+        let heartbeat = self
+            .heartbeat
+            .as_ref()
+            .map(|heartbeat| (heartbeat.liveness.clone(), heartbeat.interval));
+        async move {
+            let Some((liveness, interval)) = heartbeat else {
+                return futures::future::pending().await;
+            };
+            while *liveness.lock().unwrap() != Liveness::Dead {
+                futures_timer::Delay::new(interval).await;
+            }
+        }
+    }
+
+    /// The kernel's capabilities from the last `kernel_info_reply`, if there was one.
+    pub fn capabilities(&self) -> Option<&KernelCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// The kernel's capabilities, asking for its `kernel_info` the first time.
+    pub async fn kernel_capabilities(&mut self) -> Result<&KernelCapabilities> {
+        if self.capabilities.is_none() {
+            self.kernel_info().await?;
+        }
+        Ok(self
+            .capabilities
+            .as_ref()
+            .expect("kernel_info sets the capabilities"))
+    }
+
+    /// Ask the kernel for its `kernel_info`, caching its [`capabilities`](Self::capabilities).
+    pub async fn kernel_info(&mut self) -> Result<KernelInfoReply> {
+        let message = self.session.message(KernelInfoRequest {});
+        let msg_id = message.header.msg_id.clone();
+        self.shell.send(message).await?;
+
+        let death = self.kernel_death();
+        let shell = &mut self.shell;
+        let reply = async {
+            loop {
+                let message = shell.read().await?;
+                if !is_child_of(&message, &msg_id) {
+                    continue;
+                }
+                if let JupyterMessageContent::KernelInfoReply(reply) = message.content {
+                    return Ok(*reply);
+                }
+            }
+        };
+        let reply = unless_dead(death, with_timeout(self.control_timeout, reply))
+            .await?
+            .unwrap_or_else(|| Err(RuntimeError::Timeout("kernel_info_reply".to_string())))?;
+        self.capabilities = Some(KernelCapabilities::from(&reply));
+        Ok(reply)
+    }
+
+    /// The comms open on the kernel with `target_name`, like the widget
+    /// manager asks for with `jupyter.widget` when it connects.
+    ///
+    /// Comms with other targets are left out even if the kernel includes them.
+    pub async fn comm_info(&mut self, target_name: &str) -> Result<HashMap<CommId, CommInfo>> {
+        let message = self
+            .session
+            .message(CommInfoRequest::for_target(target_name));
+        let msg_id = message.header.msg_id.clone();
+        self.shell.send(message).await?;
+
+        let death = self.kernel_death();
+        let shell = &mut self.shell;
+        let reply = async {
+            loop {
+                let message = shell.read().await?;
+                if !is_child_of(&message, &msg_id) {
+                    continue;
+                }
+                if let JupyterMessageContent::CommInfoReply(reply) = message.content {
+                    return Ok(reply);
+                }
+            }
+        };
+        let reply = unless_dead(death, with_timeout(self.control_timeout, reply))
+            .await?
+            .unwrap_or_else(|| Err(RuntimeError::Timeout("comm_info_reply".to_string())))?;
+        if let Some(error) = reply.error {
+            return Err(RuntimeError::ErrorReply(error));
+        }
+        Ok(reply
+            .comms
+            .into_iter()
+            .filter(|(_, info)| info.target_name == target_name)
+            .collect())
+    }
+
+    /// Re-dial every channel after the kernel restarted with the same connection
+    /// info, then confirm it's up with a `kernel_info_request`.
+    ///
+    /// Subscribers to [`events`](Self::events) get a [`ClientEvent::Restarted`].
+    /// Requests that were in flight when the kernel went away are not retried.
+    pub async fn reconnect(&mut self) -> Result<KernelInfoReply> {
+        // The kernel may have been swapped for another one
+        self.capabilities = None;
+        let connection_info = &self.connection_info;
+        self.shell.reconnect(&connection_info.shell_url()).await?;
+        self.iopub
+            .reconnect(&connection_info.iopub_url(), "")
+            .await?;
+        self.control
+            .reconnect(&connection_info.control_url())
+            .await?;
+        self.wait_for_iopub().await?;
+
+        let kernel_info = self.kernel_info().await?;
+        self.emit(ClientEvent::Restarted {
+            kernel_info: Box::new(kernel_info.clone()),
+        });
+        Ok(kernel_info)
+    }
+
+    /// Run code, collecting its output until the kernel goes idle.
+    ///
+    /// Fails with [`RuntimeError::Timeout`] if the kernel doesn't go idle
+    /// within the [iopub timeout](Self::with_iopub_timeout) of replying, or
+    /// if the whole execution takes longer than the
+    /// [execute timeout](Self::with_execute_timeout). The code may still be
+    /// running when it times out.
+    pub async fn execute(&mut self, request: ExecuteRequest) -> Result<ExecutionResult> {
+        let message = self.session.message(request);
+        let msg_id = message.header.msg_id.clone();
+        self.shell.send(message).await?;
+
+        let iopub_timeout = self.iopub_timeout;
+        let execute_timeout = self.execute_timeout;
+        let death = self.kernel_death();
+        let shell = &mut self.shell;
+        let iopub = &mut self.iopub;
+
+        let reply = async {
+            loop {
+                let message = shell.read().await?;
+                if !is_child_of(&message, &msg_id) {
+                    continue;
+                }
+                let mut timings = ExecutionTimings::default();
+                timings.record(&message);
+                if let JupyterMessageContent::ExecuteReply(reply) = message.content {
+                    return Ok::<_, RuntimeError>((reply, timings));
+                }
+            }
+        };
+
+        let outputs = async {
+            let mut outputs = Vec::new();
+            let mut timings = ExecutionTimings::default();
+            loop {
+                let message = iopub.read().await?;
+                if !is_child_of(&message, &msg_id) {
+                    continue;
+                }
+                timings.record(&message);
+                match message.content {
+                    JupyterMessageContent::Status(status)
+                        if status.execution_state == ExecutionState::Idle =>
+                    {
+                        return Ok::<_, RuntimeError>((outputs, timings));
+                    }
+                    JupyterMessageContent::Status(_) | JupyterMessageContent::ExecuteInput(_) => {}
+                    content => outputs.push(content),
+                }
+            }
+        };
+
+        let execution = async {
+            let reply = std::pin::pin!(reply);
+            let outputs = std::pin::pin!(outputs);
+            match select(reply, outputs).await {
+                Either::Left((reply, outputs)) => {
+                    let reply = reply?;
+                    // A kernel that replied goes idle right after, unless the
+                    // status was lost
+                    let outputs = with_timeout(iopub_timeout, outputs)
+                        .await
+                        .unwrap_or_else(|| Err(RuntimeError::Timeout("status idle".to_string())))?;
+                    Ok((reply, outputs))
+                }
+                Either::Right((outputs, reply)) => {
+                    let outputs = outputs?;
+                    Ok((reply.await?, outputs))
+                }
+            }
+        };
+        let execution = async {
+            match execute_timeout {
+                Some(timeout) => with_timeout(timeout, execution)
+                    .await
+                    .unwrap_or_else(|| Err(RuntimeError::Timeout("execute_reply".to_string()))),
+                None => execution.await,
+            }
+        };
+        let ((reply, reply_timings), (outputs, mut timings)) =
+            unless_dead(death, execution).await??;
+        timings.reply = reply_timings.reply;
+        timings.reply_started = reply_timings.reply_started;
+        Ok(ExecutionResult {
+            reply,
+            outputs,
+            timings,
+        })
+    }
+
+    /// Evaluate `user_expressions` with a silent [`ExecuteRequest::probe`],
+    /// returning each expression's result by name.
+    ///
+    /// Nothing shows up in the kernel's output or history, so frontends can
+    /// ask about the kernel's state whenever they like:
+    ///
+    /// ```no_run
+    /// # async fn cwd(client: &mut runtimelib::KernelClient) -> runtimelib::error::Result<()> {
+    /// use std::collections::HashMap;
+    ///
+    /// let results = client
+    ///     .probe_user_expressions(HashMap::from([(
+    ///         "cwd".to_string(),
+    ///         "__import__('os').getcwd()".to_string(),
+    ///     )]))
+    ///     .await?;
+    /// if let Some(cwd) = results.get("cwd").and_then(|result| result.plain_text()) {
+    ///     println!("The kernel is in {}", cwd);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// An expression that raises is an [`ExpressionResult::Error`], and
+    /// expressions the kernel didn't evaluate are left out.
+    pub async fn probe_user_expressions(
+        &mut self,
+        user_expressions: HashMap<String, String>,
+    ) -> Result<HashMap<String, ExpressionResult>> {
+        let reply = self
+            .execute(ExecuteRequest::probe(user_expressions))
+            .await?
+            .reply;
+        if let Some(error) = reply.error {
+            return Err(RuntimeError::ErrorReply(error));
+        }
+        Ok(reply.user_expressions.unwrap_or_default())
+    }
+
+    /// Ask the kernel to interrupt the code it's running.
+    ///
+    /// Kernels that are interrupted by signal return
+    /// [`ControlOutcome::NotSupported`]; whoever launched the kernel has to
+    /// signal the process instead. A reply other than an `interrupt_reply`
+    /// fails with [`RuntimeError::InvalidMessage`].
+    pub async fn interrupt(&mut self) -> Result<ControlOutcome> {
+        if self.interrupt_mode == InterruptMode::Signal {
+            return Ok(ControlOutcome::NotSupported);
+        }
+
+        let Some(message) = self.control_request(InterruptRequest {}).await? else {
+            return Ok(ControlOutcome::TimedOut);
+        };
+        match message.content {
+            JupyterMessageContent::InterruptReply(reply) => Ok(
+                ControlOutcome::from_interrupt_reply(reply, &message.header.version),
+            ),
+            content => Err(RuntimeError::InvalidMessage(format!(
+                "Expected an interrupt_reply, got {}",
+                content.message_type()
+            ))),
+        }
+    }
+
+    /// Ask the kernel to shut down, or to restart if `restart` is set.
+    ///
+    /// A reply other than a `shutdown_reply` fails with
+    /// [`RuntimeError::InvalidMessage`].
+    pub async fn shutdown(&mut self, restart: bool) -> Result<ControlOutcome> {
+        let Some(message) = self.control_request(ShutdownRequest { restart }).await? else {
+            return Ok(ControlOutcome::TimedOut);
+        };
+        match message.content {
+            JupyterMessageContent::ShutdownReply(reply) => {
+                Ok(ControlOutcome::from_reply(reply.status, reply.error))
+            }
+            content => Err(RuntimeError::InvalidMessage(format!(
+                "Expected a shutdown_reply, got {}",
+                content.message_type()
+            ))),
+        }
+    }
+
+    /// Send a request on control and wait for its reply, or `None` on timeout.
+    /// Fails with [`RuntimeError::KernelDied`] if the heartbeat finds the
+    /// kernel dead first.
+    async fn control_request(
+        &mut self,
+        content: impl Into<JupyterMessageContent>,
+    ) -> Result<Option<JupyterMessage>> {
+        let message = self.session.message(content);
+        let msg_id = message.header.msg_id.clone();
+        self.control.send(message).await?;
+
+        let death = self.kernel_death();
+        let control = &mut self.control;
+        let reply = async {
+            loop {
+                let message = control.read().await?;
+                if is_child_of(&message, &msg_id) {
+                    return Ok(message);
+                }
+            }
+        };
+        unless_dead(death, with_timeout(self.control_timeout, reply))
+            .await?
+            .transpose()
+    }
+}
+
+/// Shell connections shared by one-shot requests, like `kernel_info` probes,
+/// so each one doesn't dial the kernel again.
+///
+/// A request takes an idle connection, or opens one if they're all busy, and
+/// waits for the reply whose `parent_header` points back at it. A reply that
+/// arrives after its request timed out is skipped by the next request on that
+/// connection. Clones share the same connections.
+#[derive(Clone)]
+pub struct ShellPool {
+    connection_info: ConnectionInfo,
+    session: Session,
+    timeout: Duration,
+    max_idle: usize,
+    idle: Arc<Mutex<Vec<ClientShellConnection>>>,
+}
+
+impl ShellPool {
+    /// A pool with no connections yet. They're opened by the first requests.
+    pub fn new(connection_info: &ConnectionInfo, session: Session) -> Self {
+        Self {
+            connection_info: connection_info.clone(),
+            session,
+            timeout: DEFAULT_CONTROL_TIMEOUT,
+            max_idle: DEFAULT_MAX_IDLE_SHELLS,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Set how long [`request`](Self::request) waits for a reply.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how many connections are kept open between requests. Connections
+    /// opened for concurrent requests beyond that are closed afterwards.
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// How many connections are open and waiting for a request.
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Send `content` on shell and wait for its reply.
+    pub async fn request(
+        &self,
+        content: impl Into<JupyterMessageContent>,
+    ) -> Result<JupyterMessage> {
+        let message = self.session.message(content);
+        let msg_id = message.header.msg_id.clone();
+        let reply_type = message.header.msg_type.replace("_request", "_reply");
+
+        let idle = self.idle.lock().unwrap().pop();
+        let mut shell = match idle {
+            Some(shell) => shell,
+            None => create_client_shell_connection(&self.connection_info, &self.session).await?,
+        };
+        shell.send(message).await?;
+
+        let reply = async {
+            loop {
+                let message = shell.read().await?;
+                if is_child_of(&message, &msg_id) {
+                    return Ok(message);
+                }
+            }
+        };
+        let reply = with_timeout(self.timeout, reply)
+            .await
+            .unwrap_or_else(|| Err(RuntimeError::Timeout(reply_type)));
+
+        // A connection that failed is closed rather than handed to the next request
+        if matches!(reply, Ok(_) | Err(RuntimeError::Timeout(_))) {
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < self.max_idle {
+                idle.push(shell);
+            }
+        }
+        reply
+    }
+
+    /// Ask the kernel for its `kernel_info`.
+    pub async fn kernel_info(&self) -> Result<KernelInfoReply> {
+        match self.request(KernelInfoRequest {}).await?.content {
+            JupyterMessageContent::KernelInfoReply(reply) => Ok(*reply),
+            content => Err(RuntimeError::InvalidMessage(format!(
+                "Expected a kernel_info_reply, got {}",
+                content.message_type()
+            ))),
+        }
+    }
+}
+
+/// Ping the kernel every `config.interval`, keeping `liveness` up to date.
+async fn heartbeat<F>(
+    connection_info: ConnectionInfo,
+    connection: ClientHeartbeatConnection,
+    config: HeartbeatConfig,
+    liveness: Arc<Mutex<Liveness>>,
+    mut on_liveness_change: F,
+) where
+    F: FnMut(Liveness),
+{
+    let max_missed = config.max_missed.max(1);
+    let mut connection = Some(connection);
+    let mut missed = 0;
+    loop {
+        let started = Instant::now();
+        let answered = match &mut connection {
+            Some(connection) => matches!(
+                with_timeout(config.interval, connection.single_heartbeat()).await,
+                Some(Ok(()))
+            ),
+            None => false,
+        };
+        if answered {
+            missed = 0;
+        } else {
+            missed += 1;
+            // A REQ socket still waiting on its reply can't send another ping
+            connection = with_timeout(
+                config.interval,
+                create_client_heartbeat_connection(&connection_info),
+            )
+            .await
+            .and_then(Result::ok);
+        }
+
+        let next = if answered {
+            Liveness::Alive
+        } else if missed >= max_missed {
+            Liveness::Dead
+        } else {
+            *liveness.lock().unwrap()
+        };
+        let changed = std::mem::replace(&mut *liveness.lock().unwrap(), next) != next;
+        if changed {
+            on_liveness_change(next);
+        }
+
+        futures_timer::Delay::new(config.interval.saturating_sub(started.elapsed())).await;
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "tokio-runtime", feature = "async-dispatcher-runtime")
+))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::connection::{
+        create_kernel_control_connection, create_kernel_iopub_connection,
+        create_kernel_shell_connection, peek_ports,
+    };
+    use crate::kernel::{CommRegistry, IopubOptions, IopubPublisher};
+    use crate::runtime::async_test;
+    use futures::StreamExt;
+    use jupyter_protocol::{
+        connection_info::Transport, CodeMirrorMode, ErrorOutput, ExecutionCount, LanguageInfo,
+        Media, MediaType, ShutdownReply, Status, StreamContent,
+    };
+
+    fn echo_kernel_info() -> KernelInfoReply {
+        KernelInfoReply {
+            status: ReplyStatus::Ok,
+            protocol_version: "5.3".to_string(),
+            implementation: "echo".to_string(),
+            implementation_version: "0.1".to_string(),
+            language_info: LanguageInfo {
+                name: "text".to_string(),
+                version: "0.1".to_string(),
+                mimetype: "text/plain".to_string(),
+                file_extension: ".txt".to_string(),
+                pygments_lexer: Some("text".to_string()),
+                codemirror_mode: Some(CodeMirrorMode::Simple("text".to_string())),
+                nbconvert_exporter: Some("script".to_string()),
+            },
+            banner: "Echo".to_string(),
+            help_links: vec![],
+            debugger: false,
+            error: None,
+        }
+    }
+
+    pub(crate) async fn local_connection_info() -> ConnectionInfo {
+        let ip = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let ports = peek_ports(ip, 5).await.unwrap();
+        ConnectionInfo {
+            transport: Transport::TCP,
+            ip: ip.to_string(),
+            stdin_port: ports[0],
+            control_port: ports[1],
+            hb_port: ports[2],
+            shell_port: ports[3],
+            iopub_port: ports[4],
+            signature_scheme: "hmac-sha256".to_string(),
+            key: uuid::Uuid::new_v4().to_string(),
+            kernel_name: Some("echo".to_string()),
+        }
+    }
+
+    /// Start a fake kernel on localhost that echoes code to stdout.
+    ///
+    /// Code starting with `raise` produces an error, `sleep` runs until the
+    /// kernel is interrupted, and `no idle` is replied to without going idle. User expressions evaluate to themselves, or raise the same way.
+    /// Interrupt and shutdown requests are always accepted. Two comms are open,
+    /// and `comm_info_request`s list both whatever their target.
+    pub(crate) async fn start_echo_kernel() -> ConnectionInfo {
+        start_echo_kernel_with(IopubOptions::default()).await
+    }
+
+    /// [`start_echo_kernel`] with a choice of iopub startup signals. Without
+    /// `iopub_welcome` it acts like kernels older than protocol 5.4, whose
+    /// `kernel_info_request`s are bracketed by statuses.
+    ///
+    /// Kernels that welcome skip those statuses. Under
+    /// `async-dispatcher-runtime`, zeromq's ROUTER socket holds a lock across
+    /// an await in `send`, and publishing while another shell connection is
+    /// accepted can stall smol's single executor thread.
+    pub(crate) async fn start_echo_kernel_with(iopub_options: IopubOptions) -> ConnectionInfo {
+        let connection_info = local_connection_info().await;
+
+        let session = Session::new().with_username("echo-kernel");
+        let mut shell = create_kernel_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let iopub = IopubPublisher::bind(&connection_info, &session, iopub_options)
+            .await
+            .unwrap();
+        let mut control = create_kernel_control_connection(&connection_info, &session)
+            .await
+            .unwrap();
+
+        let (interrupts, mut interrupted) = futures::channel::mpsc::unbounded::<()>();
+        crate::runtime::spawn(async move {
+            while let Ok(request) = control.read().await {
+                let reply: JupyterMessageContent = match &request.content {
+                    JupyterMessageContent::InterruptRequest(_) => {
+                        interrupts.unbounded_send(()).ok();
+                        InterruptReply::new().into()
+                    }
+                    JupyterMessageContent::ShutdownRequest(shutdown) => ShutdownReply {
+                        restart: shutdown.restart,
+                        ..Default::default()
+                    }
+                    .into(),
+                    _ => continue,
+                };
+                control
+                    .send(JupyterMessage::new(reply, Some(&request)))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut comms = CommRegistry::new();
+        comms.open(CommId("slider".to_string()), "jupyter.widget");
+        comms.open(CommId("logger".to_string()), "echo.logger");
+
+        crate::runtime::spawn(async move {
+            let mut execution_count = 0;
+            while let Ok(request) = shell.read().await {
+                if let JupyterMessageContent::KernelInfoRequest(_) = &request.content {
+                    if !iopub_options.send_welcome {
+                        iopub
+                            .send(Status::busy().as_child_of(&request))
+                            .await
+                            .unwrap();
+                    }
+                    shell
+                        .send(echo_kernel_info().as_child_of(&request))
+                        .await
+                        .unwrap();
+                    if !iopub_options.send_welcome {
+                        iopub
+                            .send(Status::idle().as_child_of(&request))
+                            .await
+                            .unwrap();
+                    }
+                    continue;
+                }
+                if let JupyterMessageContent::CommInfoRequest(_) = &request.content {
+                    shell
+                        .send(
+                            comms
+                                .reply(&CommInfoRequest::default())
+                                .as_child_of(&request),
+                        )
+                        .await
+                        .unwrap();
+                    continue;
+                }
+                let JupyterMessageContent::ExecuteRequest(execute) = &request.content else {
+                    continue;
+                };
+                iopub
+                    .send(Status::busy().as_child_of(&request))
+                    .await
+                    .unwrap();
+                execution_count += 1;
+
+                let reply = if execute.code.starts_with("sleep") {
+                    // Interrupts from before this request don't count
+                    while interrupted.try_recv().is_ok() {}
+                    interrupted.next().await;
+                    let error = ErrorOutput {
+                        ename: "KeyboardInterrupt".to_string(),
+                        evalue: String::new(),
+                        traceback: vec!["KeyboardInterrupt".to_string()],
+                    };
+                    iopub.send(error.as_child_of(&request)).await.unwrap();
+                    ExecuteReply {
+                        status: ReplyStatus::Error,
+                        execution_count: ExecutionCount::new(execution_count),
+                        ..Default::default()
+                    }
+                } else if let Some(message) = execute.code.strip_prefix("raise ") {
+                    let error = ErrorOutput {
+                        ename: "Exception".to_string(),
+                        evalue: message.to_string(),
+                        traceback: vec![format!("Exception: {}", message)],
+                    };
+                    iopub.send(error.as_child_of(&request)).await.unwrap();
+                    ExecuteReply {
+                        status: ReplyStatus::Error,
+                        execution_count: ExecutionCount::new(execution_count),
+                        ..Default::default()
+                    }
+                } else {
+                    if !execute.silent {
+                        iopub
+                            .send(StreamContent::stdout(&execute.code).as_child_of(&request))
+                            .await
+                            .unwrap();
+                    }
+                    let user_expressions = execute.user_expressions.as_ref().map(|expressions| {
+                        expressions
+                            .iter()
+                            .map(|(name, expression)| {
+                                let result =
+                                    match expression.strip_prefix("raise ") {
+                                        Some(message) => ExpressionResult::from(ErrorOutput {
+                                            ename: "Exception".to_string(),
+                                            evalue: message.to_string(),
+                                            traceback: vec![],
+                                        }),
+                                        None => ExpressionResult::from(Media::new(vec![
+                                            MediaType::Plain(expression.clone()),
+                                        ])),
+                                    };
+                                (name.clone(), result)
+                            })
+                            .collect()
+                    });
+                    ExecuteReply {
+                        execution_count: ExecutionCount::new(execution_count),
+                        user_expressions,
+                        ..Default::default()
+                    }
+                };
+
+                shell.send(reply.as_child_of(&request)).await.unwrap();
+                if execute.code != "no idle" {
+                    iopub
+                        .send(Status::idle().as_child_of(&request))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        connection_info
+    }
+
+    pub(crate) async fn connect_client(connection_info: &ConnectionInfo) -> KernelClient {
+        KernelClient::connect(connection_info).await.unwrap()
+    }
+
+    #[async_test]
+    async fn test_connect_waits_for_iopub() {
+        for iopub_options in [
+            IopubOptions::default(),
+            // Only the statuses from kernel_info probes to go on
+            IopubOptions::default()
+                .with_starting(false)
+                .with_welcome(false),
+        ] {
+            let connection_info = start_echo_kernel_with(iopub_options).await;
+            let mut client = KernelClient::connect(&connection_info).await.unwrap();
+            let result = client
+                .execute(ExecuteRequest::new("first".to_string()))
+                .await
+                .unwrap();
+            assert_eq!(result.stdout(), "first", "{:?}", iopub_options);
+        }
+    }
+
+    #[async_test]
+    async fn test_connect_settles_for_a_kernel_info_reply() {
+        // A kernel that never publishes on iopub, but answers on shell
+        let connection_info = local_connection_info().await;
+        let session = Session::new().with_username("quiet-kernel");
+        let mut shell = create_kernel_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let _iopub = create_kernel_iopub_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let _control = create_kernel_control_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let probes = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        crate::runtime::spawn({
+            let probes = probes.clone();
+            async move {
+                while let Ok(request) = shell.read().await {
+                    probes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    shell
+                        .send(echo_kernel_info().as_child_of(&request))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let started = Instant::now();
+        KernelClient::connect(&connection_info).await.unwrap();
+        assert!(started.elapsed() < IOPUB_READY_TIMEOUT);
+        assert_eq!(probes.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[async_test]
+    async fn test_execute_collects_output() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info).await;
+
+        let result = client
+            .execute(ExecuteRequest::new("hello".to_string()))
+            .await
+            .unwrap();
+        assert!(result.is_ok());
+        assert_eq!(result.stdout(), "hello");
+        assert_eq!(result.reply.execution_count, ExecutionCount::new(1));
+        let timings = result.timings;
+        assert!(timings.busy.unwrap() <= timings.reply.unwrap());
+        assert!(timings.reply.unwrap() <= timings.idle.unwrap());
+
+        let result = client
+            .execute(ExecuteRequest::new("raise nope".to_string()))
+            .await
+            .unwrap();
+        assert!(!result.is_ok());
+        assert_eq!(result.error().unwrap().evalue, "nope");
+    }
+
+    #[async_test]
+    async fn test_execute_timeout() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info)
+            .await
+            .with_execute_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            client.execute(ExecuteRequest::new("sleep".to_string())).await,
+            Err(RuntimeError::Timeout(waiting_for)) if waiting_for == "execute_reply"
+        ));
+    }
+
+    #[async_test]
+    async fn test_execute_gives_up_on_a_lost_idle() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info)
+            .await
+            .with_iopub_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            client.execute(ExecuteRequest::new("no idle".to_string())).await,
+            Err(RuntimeError::Timeout(waiting_for)) if waiting_for == "status idle"
+        ));
+    }
+
+    #[async_test]
+    async fn test_probe_user_expressions() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info).await;
+
+        let results = client
+            .probe_user_expressions(HashMap::from([
+                ("cwd".to_string(), "'/home/user'".to_string()),
+                ("oops".to_string(), "raise nope".to_string()),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(results["cwd"].plain_text(), Some("'/home/user'"));
+        assert!(
+            matches!(&results["oops"], ExpressionResult::Error(error) if error.evalue == "nope")
+        );
+
+        // The probe published nothing, so the next execution's output is its own
+        let result = client
+            .execute(ExecuteRequest::new("hello".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(result.outputs.len(), 1);
+    }
+
+    #[async_test]
+    async fn test_control_requests() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info).await;
+
+        assert!(matches!(
+            client.interrupt().await.unwrap(),
+            ControlOutcome::Accepted
+        ));
+        assert!(matches!(
+            client.shutdown(true).await.unwrap(),
+            ControlOutcome::Accepted
+        ));
+
+        let mut client = client.with_interrupt_mode(InterruptMode::Signal);
+        assert!(matches!(
+            client.interrupt().await.unwrap(),
+            ControlOutcome::NotSupported
+        ));
+    }
+
+    #[async_test]
+    async fn test_reconnect_emits_restarted() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info).await;
+        let mut events = client.events();
+
+        let kernel_info = client.reconnect().await.unwrap();
+        assert_eq!(kernel_info.implementation, "echo");
+        assert!(matches!(
+            events.next().await,
+            Some(ClientEvent::Restarted { .. })
+        ));
+
+        let result = client
+            .execute(ExecuteRequest::new("still here".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(result.stdout(), "still here");
+    }
+
+    #[async_test]
+    async fn test_kernel_capabilities_are_cached() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info).await;
+        assert!(client.capabilities().is_none());
+
+        let capabilities = client.kernel_capabilities().await.unwrap();
+        assert_eq!(capabilities.language, "text");
+        assert_eq!(capabilities.file_extension, ".txt");
+        assert!(!capabilities.supports_debugger);
+        assert_eq!(
+            client.capabilities().map(|c| c.implementation.as_str()),
+            Some("echo")
+        );
+    }
+
+    #[async_test]
+    async fn test_comm_info_filters_by_target() {
+        let connection_info = start_echo_kernel().await;
+        let mut client = connect_client(&connection_info).await;
+
+        let comms = client.comm_info("jupyter.widget").await.unwrap();
+        assert_eq!(comms.len(), 1);
+        assert_eq!(
+            comms.get(&CommId("slider".to_string())),
+            Some(&CommInfo::new("jupyter.widget"))
+        );
+        assert!(client.comm_info("unknown").await.unwrap().is_empty());
+    }
+
+    #[async_test]
+    async fn test_shell_pool_reuses_connections() {
+        let connection_info = start_echo_kernel().await;
+        let pool = ShellPool::new(&connection_info, Session::new()).with_max_idle(1);
+        assert_eq!(pool.idle_connections(), 0);
+
+        // Concurrent requests each get their own connection and their own reply
+        let clone = pool.clone();
+        let (first, second) = futures::join!(pool.kernel_info(), clone.kernel_info());
+        assert_eq!(first.unwrap().implementation, "echo");
+        assert_eq!(second.unwrap().implementation, "echo");
+        assert_eq!(pool.idle_connections(), 1);
+
+        let reply = pool
+            .request(CommInfoRequest::for_target("jupyter.widget"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            reply.content,
+            JupyterMessageContent::CommInfoReply(_)
+        ));
+        assert_eq!(pool.idle_connections(), 1);
+    }
+
+    #[async_test]
+    async fn test_shell_pool_timeout() {
+        // A kernel that never answers on shell
+        let connection_info = local_connection_info().await;
+        let _shell = create_kernel_shell_connection(&connection_info, &Session::new())
+            .await
+            .unwrap();
+
+        let pool = ShellPool::new(&connection_info, Session::new())
+            .with_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            pool.kernel_info().await,
+            Err(RuntimeError::Timeout(reply)) if reply == "kernel_info_reply"
+        ));
+        // The connection is still good for the next request
+        assert_eq!(pool.idle_connections(), 1);
+    }
+
+    #[async_test]
+    async fn test_control_request_timeout() {
+        // A kernel that never answers on control
+        let connection_info = local_connection_info().await;
+        let session = Session::new();
+        let _shell = create_kernel_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        // It does welcome clients, so they can connect
+        let _iopub = IopubPublisher::bind(&connection_info, &session, IopubOptions::default())
+            .await
+            .unwrap();
+        let _control = create_kernel_control_connection(&connection_info, &session)
+            .await
+            .unwrap();
+
+        let mut client = connect_client(&connection_info)
+            .await
+            .with_control_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            client.shutdown(false).await.unwrap(),
+            ControlOutcome::TimedOut
+        ));
+    }
+
+    #[async_test]
+    async fn test_control_request_wrong_reply() {
+        // A kernel that answers every control request with the other reply
+        let connection_info = local_connection_info().await;
+        let session = Session::new();
+        let _shell = create_kernel_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let _iopub = IopubPublisher::bind(&connection_info, &session, IopubOptions::default())
+            .await
+            .unwrap();
+        let mut control = create_kernel_control_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        crate::runtime::spawn(async move {
+            while let Ok(request) = control.read().await {
+                let reply: JupyterMessageContent = match &request.content {
+                    JupyterMessageContent::InterruptRequest(_) => ShutdownReply::default().into(),
+                    _ => InterruptReply::new().into(),
+                };
+                control
+                    .send(JupyterMessage::new(reply, Some(&request)))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut client = connect_client(&connection_info).await;
+        assert!(matches!(
+            client.interrupt().await,
+            Err(RuntimeError::InvalidMessage(message)) if message.contains("shutdown_reply")
+        ));
+        assert!(matches!(
+            client.shutdown(false).await,
+            Err(RuntimeError::InvalidMessage(message)) if message.contains("interrupt_reply")
+        ));
+    }
+
+    #[test]
+    fn test_interrupt_mode_from_kernelspec() {
+        let mut kernelspec = JupyterKernelspec {
+            argv: vec![],
+            display_name: "Echo".to_string(),
+            language: "text".to_string(),
+            metadata: None,
+            interrupt_mode: None,
+            env: None,
+        };
+        assert_eq!(
+            InterruptMode::from_kernelspec(&kernelspec),
+            InterruptMode::Signal
+        );
+        kernelspec.interrupt_mode = Some("message".to_string());
+        assert_eq!(
+            InterruptMode::from_kernelspec(&kernelspec),
+            InterruptMode::Message
+        );
+    }
+
+    #[test]
+    fn test_control_outcome_serialization() {
+        assert_eq!(
+            serde_json::to_value(ControlOutcome::TimedOut).unwrap(),
+            serde_json::json!({"outcome": "timed_out"})
+        );
+        let rejected = ControlOutcome::Rejected(Some(Box::new(ReplyError {
+            ename: "KernelError".to_string(),
+            evalue: "busy".to_string(),
+            traceback: vec![],
+        })));
+        assert_eq!(
+            serde_json::to_value(rejected).unwrap()["error"]["ename"],
+            "KernelError"
+        );
+    }
+
+    #[test]
+    fn test_control_outcome_from_interrupt_reply() {
+        let empty = InterruptReply {
+            status: None,
+            error: None,
+        };
+        assert!(matches!(
+            ControlOutcome::from_interrupt_reply(empty.clone(), "5.3"),
+            ControlOutcome::Accepted
+        ));
+        assert!(matches!(
+            ControlOutcome::from_interrupt_reply(empty, "5.5"),
+            ControlOutcome::Unconfirmed
+        ));
+        assert!(matches!(
+            ControlOutcome::from_interrupt_reply(InterruptReply::new(), "5.5"),
+            ControlOutcome::Accepted
+        ));
+        assert_eq!(
+            serde_json::to_value(ControlOutcome::Unconfirmed).unwrap(),
+            serde_json::json!({"outcome": "unconfirmed"})
+        );
+    }
+
+    #[async_test]
+    async fn test_with_timeout() {
+        assert_eq!(
+            with_timeout(Duration::from_secs(5), async { 1 }).await,
+            Some(1)
+        );
+        assert_eq!(
+            with_timeout(Duration::from_millis(10), futures::future::pending::<()>()).await,
+            None
+        );
+    }
+
+    #[async_test]
+    async fn test_heartbeat_reports_dead_kernel() {
+        let connection_info = start_echo_kernel().await;
+        let mut kernel_heartbeat =
+            crate::connection::create_kernel_heartbeat_connection(&connection_info)
+                .await
+                .unwrap();
+        let (beating, stop) =
+            abortable(async move { while kernel_heartbeat.single_heartbeat().await.is_ok() {} });
+        crate::runtime::spawn(beating.map(|_| ()));
+
+        let mut client = connect_client(&connection_info).await;
+        assert_eq!(client.liveness(), None);
+        let (changes, mut liveness_changes) = mpsc::unbounded();
+        let config = HeartbeatConfig {
+            interval: Duration::from_millis(50),
+            max_missed: 2,
+        };
+        client
+            .start_heartbeat(config, move |liveness| {
+                let _ = changes.unbounded_send(liveness);
+            })
+            .await
+            .unwrap();
+
+        futures_timer::Delay::new(Duration::from_millis(300)).await;
+        assert_eq!(client.liveness(), Some(Liveness::Alive));
+        assert!(liveness_changes.try_recv().is_err(), "Nothing changed yet");
+
+        stop.abort();
+        let change = with_timeout(Duration::from_secs(5), liveness_changes.next()).await;
+        assert_eq!(change, Some(Some(Liveness::Dead)));
+        assert_eq!(client.liveness(), Some(Liveness::Dead));
+
+        client.stop_heartbeat();
+        assert_eq!(client.liveness(), None);
+    }
+
+    #[async_test]
+    async fn test_dead_kernel_fails_execution() {
+        // The heartbeat is bound but never answered, so the kernel dies while
+        // running code
+        let connection_info = start_echo_kernel().await;
+        let _kernel_heartbeat =
+            crate::connection::create_kernel_heartbeat_connection(&connection_info)
+                .await
+                .unwrap();
+        let mut client = connect_client(&connection_info).await;
+        let config = HeartbeatConfig {
+            interval: Duration::from_millis(50),
+            max_missed: 2,
+        };
+        client.start_heartbeat(config, |_| {}).await.unwrap();
+
+        let execution = with_timeout(
+            Duration::from_secs(5),
+            client.execute(ExecuteRequest::new("sleep".to_string())),
+        )
+        .await;
+        assert!(matches!(execution, Some(Err(RuntimeError::KernelDied))));
+        assert!(matches!(
+            client.kernel_info().await,
+            Err(RuntimeError::KernelDied)
+        ));
+    }
+}
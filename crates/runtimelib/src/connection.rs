@@ -3,17 +3,26 @@
 //! This module provides structures for understanding the connection information,
 //! existing jupyter runtimes, and a client with ZeroMQ sockets to
 //! communicate with the kernels.
-use anyhow::{anyhow, bail, Result};
+//!
+//! Everything here fails with a [`RuntimeError`].
 use bytes::Bytes;
 use data_encoding::HEXLOWER;
 
 use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 
-use ring::hmac;
 use serde_json;
 use serde_json::Value;
 
-pub use jupyter_protocol::ConnectionInfo;
+use jupyter_protocol::{truncate_stream_text, JupyterError, Transport};
+pub use jupyter_protocol::{ConnectionInfo, MessageLimits, Session};
+
+use crate::error::{Result, RuntimeError};
+use crate::signer::{HmacSha256Signer, Signer};
+#[cfg(feature = "perf-instrumentation")]
+use jupyter_protocol::instrumentation::Instrumentation;
+#[cfg(feature = "perf-instrumentation")]
+use std::sync::Arc;
 
 pub use jupyter_protocol::messaging::*;
 // For backwards compatibility, for now:
@@ -52,9 +61,22 @@ pub async fn peek_ports(ip: IpAddr, num: usize) -> Result<Vec<u16>> {
 
 pub struct Connection<S> {
     pub socket: S,
+    /// The channel the socket is for, named in socket errors.
+    pub channel: Channel,
     /// Will be None if our key was empty (digest authentication disabled).
-    pub mac: Option<hmac::Key>,
-    pub session_id: String,
+    pub mac: Option<Box<dyn Signer>>,
+    /// Outgoing messages are sent as part of this session.
+    pub session: Session,
+    /// Incoming messages over these limits are rejected (or truncated) when read.
+    pub limits: MessageLimits,
+    /// Serialize outgoing message parts in the deterministic form from
+    /// [`jupyter_protocol::canonical`] instead of serde's field order.
+    pub canonical_json: bool,
+    /// Refuse to send messages whose `channel` is set to another socket's.
+    pub check_channel: bool,
+    /// Told about every message sent or parsed, for protocol stats.
+    #[cfg(feature = "perf-instrumentation")]
+    pub instrumentation: Option<Arc<dyn Instrumentation>>,
 }
 
 pub type KernelIoPubConnection = Connection<zeromq::PubSocket>;
@@ -74,56 +96,191 @@ pub struct ClientHeartbeatConnection {
 }
 
 impl<S: zeromq::Socket> Connection<S> {
-    pub fn new(socket: S, key: &str, session_id: &str) -> Self {
-        let mac = if key.is_empty() {
+    pub fn new(socket: S, channel: Channel, key: &str, session: &Session) -> Self {
+        let mac: Option<Box<dyn Signer>> = if key.is_empty() {
             None
         } else {
-            Some(hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()))
+            Some(Box::new(HmacSha256Signer::new(key.as_bytes())))
         };
 
         Connection {
             socket,
+            channel,
             mac,
-            session_id: session_id.to_string(),
+            session: session.clone(),
+            limits: MessageLimits::default(),
+            canonical_json: false,
+            check_channel: true,
+            #[cfg(feature = "perf-instrumentation")]
+            instrumentation: None,
         }
     }
+
+    pub fn session_id(&self) -> &str {
+        self.session.id()
+    }
+
+    /// Replace the signer used for outgoing and incoming messages, e.g. to use a
+    /// custom crypto backend. `None` disables digest authentication.
+    pub fn with_signer(mut self, signer: Option<Box<dyn Signer>>) -> Self {
+        self.mac = signer;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: MessageLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_canonical_json(mut self, canonical_json: bool) -> Self {
+        self.canonical_json = canonical_json;
+        self
+    }
+
+    /// Whether [`send`](Connection::send) refuses messages whose `channel`
+    /// is set to another socket's, with [`JupyterError::ChannelMismatch`].
+    /// On by default, since they're usually routing bugs.
+    pub fn with_channel_check(mut self, check_channel: bool) -> Self {
+        self.check_channel = check_channel;
+        self
+    }
+
+    /// Report every message this connection sends or parses to
+    /// `instrumentation`. Share one hook between connections to count them
+    /// together.
+    #[cfg(feature = "perf-instrumentation")]
+    pub fn with_instrumentation(mut self, instrumentation: Arc<dyn Instrumentation>) -> Self {
+        self.instrumentation = Some(instrumentation);
+        self
+    }
 }
 
-impl<S: zeromq::SocketSend> Connection<S> {
-    pub async fn send(&mut self, message: JupyterMessage) -> Result<(), anyhow::Error> {
-        let message = message.with_session(&self.session_id);
-        let raw_message: RawMessage = RawMessage::from_jupyter_message(message)?;
-        let zmq_message = raw_message.into_zmq_message(&self.mac)?;
+impl Connection<zeromq::DealerSocket> {
+    /// Dial `endpoint` again with a fresh socket, e.g. after the kernel restarted.
+    /// The session, signer and limits are kept.
+    pub async fn reconnect(&mut self, endpoint: &str) -> Result<()> {
+        let mut socket = zeromq::DealerSocket::new();
+        socket
+            .connect(endpoint)
+            .await
+            .map_err(RuntimeError::socket(self.channel))?;
+        self.socket = socket;
+        Ok(())
+    }
+}
 
-        self.socket.send(zmq_message).await?;
+impl Connection<zeromq::SubSocket> {
+    /// Dial `endpoint` again with a fresh socket subscribed to `topic`, e.g. after
+    /// the kernel restarted. The session, signer and limits are kept.
+    pub async fn reconnect(&mut self, endpoint: &str, topic: &str) -> Result<()> {
+        let mut socket = zeromq::SubSocket::new();
+        socket
+            .subscribe(topic)
+            .await
+            .map_err(RuntimeError::socket(self.channel))?;
+        socket
+            .connect(endpoint)
+            .await
+            .map_err(RuntimeError::socket(self.channel))?;
+        self.socket = socket;
         Ok(())
     }
 }
 
+impl<S: zeromq::SocketSend> Connection<S> {
+    pub async fn send(&mut self, message: JupyterMessage) -> Result<()> {
+        if let (true, Some(channel)) = (self.check_channel, message.channel) {
+            if channel != self.channel {
+                return Err(JupyterError::ChannelMismatch {
+                    msg_type: message.header.msg_type,
+                    channel,
+                    socket: self.channel,
+                }
+                .into());
+            }
+        }
+        let message = self.session.stamp(message);
+        #[cfg(feature = "perf-instrumentation")]
+        let msg_type = self
+            .instrumentation
+            .as_ref()
+            .map(|_| message.header.msg_type.clone());
+        let raw_message: RawMessage =
+            RawMessage::from_jupyter_message(message, self.canonical_json)?;
+        let zmq_message = raw_message.into_zmq_message(self.mac.as_deref())?;
+        #[cfg(feature = "perf-instrumentation")]
+        if let (Some(instrumentation), Some(msg_type)) = (&self.instrumentation, msg_type) {
+            instrumentation.message_sent(self.channel, &msg_type, wire_size(&zmq_message));
+        }
+
+        self.socket
+            .send(zmq_message)
+            .await
+            .map_err(RuntimeError::socket(self.channel))
+    }
+}
+
 impl<S: zeromq::SocketRecv> Connection<S> {
-    pub async fn read(&mut self) -> Result<JupyterMessage, anyhow::Error> {
-        let raw_message = RawMessage::from_multipart(self.socket.recv().await?, &self.mac)?;
-        let message = raw_message.into_jupyter_message()?;
-        Ok(message)
+    /// Read the next message, with its `channel` set to this connection's.
+    ///
+    /// Messages over the connection's [`MessageLimits`] fail with
+    /// [`RuntimeError::Protocol`] wrapping a
+    /// [`JupyterError::MessageTooLarge`](jupyter_protocol::JupyterError).
+    pub async fn read(&mut self) -> Result<JupyterMessage> {
+        let multipart = self
+            .socket
+            .recv()
+            .await
+            .map_err(RuntimeError::socket(self.channel))?;
+        #[cfg(feature = "perf-instrumentation")]
+        if let Some(instrumentation) = &self.instrumentation {
+            let bytes = wire_size(&multipart);
+            let message = RawMessage::from_multipart(multipart, self.mac.as_deref())
+                .and_then(|raw_message| raw_message.into_limited_jupyter_message(&self.limits));
+            match &message {
+                Ok(message) => {
+                    instrumentation.message_parsed(self.channel, &message.header.msg_type, bytes)
+                }
+                Err(error) => instrumentation.parse_failed(self.channel, error),
+            }
+            return message.map(|message| message.with_channel(self.channel));
+        }
+        let raw_message = RawMessage::from_multipart(multipart, self.mac.as_deref())?;
+        let message = raw_message.into_limited_jupyter_message(&self.limits)?;
+        Ok(message.with_channel(self.channel))
     }
 }
 
+#[cfg(feature = "perf-instrumentation")]
+fn wire_size(multipart: &zeromq::ZmqMessage) -> usize {
+    multipart.iter().map(|frame| frame.len()).sum()
+}
+
 impl KernelHeartbeatConnection {
-    pub async fn single_heartbeat(&mut self) -> Result<(), anyhow::Error> {
-        let _msg = self.socket.recv().await?;
+    pub async fn single_heartbeat(&mut self) -> Result<()> {
+        let _msg = self
+            .socket
+            .recv()
+            .await
+            .map_err(RuntimeError::socket(Channel::Heartbeat))?;
         self.socket
             .send(zeromq::ZmqMessage::from(b"pong".to_vec()))
-            .await?;
-        Ok(())
+            .await
+            .map_err(RuntimeError::socket(Channel::Heartbeat))
     }
 }
 
 impl ClientHeartbeatConnection {
-    pub async fn single_heartbeat(&mut self) -> Result<(), anyhow::Error> {
+    pub async fn single_heartbeat(&mut self) -> Result<()> {
         self.socket
             .send(zeromq::ZmqMessage::from(b"ping".to_vec()))
-            .await?;
-        let _msg = self.socket.recv().await?;
+            .await
+            .map_err(RuntimeError::socket(Channel::Heartbeat))?;
+        let _msg = self
+            .socket
+            .recv()
+            .await
+            .map_err(RuntimeError::socket(Channel::Heartbeat))?;
         Ok(())
     }
 }
@@ -137,21 +294,27 @@ pub struct RawMessage {
 // ZeroMQ delimiter
 const DELIMITER: &[u8] = b"<IDS|MSG>";
 
+// Only the header, parent_header, metadata, and content are signed. Buffers are not.
+const SIGNED_PARTS: usize = 4;
+
 impl RawMessage {
     pub fn from_multipart(
         multipart: zeromq::ZmqMessage,
-        key: &Option<hmac::Key>,
-    ) -> Result<RawMessage, anyhow::Error> {
+        signer: Option<&dyn Signer>,
+    ) -> Result<RawMessage> {
         let delimiter_index = multipart
             .iter()
             .position(|part| &part[..] == DELIMITER)
-            .ok_or_else(|| anyhow!("Missing delimiter"))?;
+            .ok_or_else(|| RuntimeError::InvalidMessage("Missing delimiter".to_string()))?;
         let mut parts = multipart.into_vec();
 
+        // Identities, the delimiter, the signature, then the message parts
+        let expected_hmac = parts
+            .get(delimiter_index + 1)
+            .cloned()
+            .ok_or_else(|| RuntimeError::InvalidMessage("Missing hmac".to_string()))?;
         let jparts: Vec<_> = parts.drain(delimiter_index + 2..).collect();
-        let expected_hmac = parts.pop().ok_or_else(|| anyhow!("Missing hmac"))?;
-        // Remove delimiter, so that what's left is just the identities.
-        parts.pop();
+        parts.truncate(delimiter_index);
         let zmq_identities = parts;
 
         let raw_message = RawMessage {
@@ -159,47 +322,40 @@ impl RawMessage {
             jparts,
         };
 
-        if let Some(key) = key {
-            let sig = HEXLOWER.decode(&expected_hmac)?;
-            let mut msg = Vec::new();
-            // Only include header, parent_header, metadata, and content in the HMAC.
-            // Buffers are not included
-            for part in &raw_message.jparts[..4] {
-                msg.extend(part);
-            }
-
-            if let Err(err) = hmac::verify(key, msg.as_ref(), sig.as_ref()) {
-                bail!("{}", err);
+        if let Some(signer) = signer {
+            if raw_message.jparts.len() < SIGNED_PARTS {
+                return Err(RuntimeError::InvalidMessage(format!(
+                    "Insufficient message parts {}",
+                    raw_message.jparts.len()
+                )));
             }
+            // A signature that isn't even hex can't match
+            let sig = HEXLOWER
+                .decode(&expected_hmac)
+                .map_err(|_| RuntimeError::HmacMismatch)?;
+            signer.verify(&raw_message.signed_parts(), &sig)?;
         }
 
         Ok(raw_message)
     }
 
-    fn hmac(&self, key: &Option<hmac::Key>) -> String {
-        let hmac = if let Some(key) = key {
-            let ctx = self.digest(key);
-            let tag = ctx.sign();
-            HEXLOWER.encode(tag.as_ref())
-        } else {
-            String::new()
-        };
-        hmac
+    fn signed_parts(&self) -> Vec<&[u8]> {
+        self.jparts
+            .iter()
+            .take(SIGNED_PARTS)
+            .map(|part| part.as_ref())
+            .collect()
     }
 
-    fn digest(&self, mac: &hmac::Key) -> hmac::Context {
-        let mut hmac_ctx = hmac::Context::with_key(mac);
-        for part in &self.jparts {
-            hmac_ctx.update(part);
+    fn hmac(&self, signer: Option<&dyn Signer>) -> String {
+        match signer {
+            Some(signer) => HEXLOWER.encode(&signer.sign(&self.signed_parts())),
+            None => String::new(),
         }
-        hmac_ctx
     }
 
-    fn into_zmq_message(
-        self,
-        key: &Option<hmac::Key>,
-    ) -> Result<zeromq::ZmqMessage, anyhow::Error> {
-        let hmac = self.hmac(key);
+    fn into_zmq_message(self, signer: Option<&dyn Signer>) -> Result<zeromq::ZmqMessage> {
+        let hmac = self.hmac(signer);
 
         let mut parts: Vec<bytes::Bytes> = Vec::new();
         for part in &self.zmq_identities {
@@ -212,20 +368,35 @@ impl RawMessage {
         }
         // ZmqMessage::try_from only fails if parts is empty, which it never
         // will be here.
-        let message = zeromq::ZmqMessage::try_from(parts).map_err(|err| anyhow::anyhow!(err))?;
+        let message = zeromq::ZmqMessage::try_from(parts)
+            .map_err(|err| RuntimeError::InvalidMessage(err.to_string()))?;
         Ok(message)
     }
 
-    fn from_jupyter_message(jupyter_message: JupyterMessage) -> Result<RawMessage, anyhow::Error> {
+    fn from_jupyter_message(
+        jupyter_message: JupyterMessage,
+        canonical: bool,
+    ) -> Result<RawMessage> {
+        fn pack<T: serde::Serialize>(value: &T, canonical: bool) -> Result<Bytes> {
+            let bytes = if canonical {
+                jupyter_protocol::canonical::to_canonical_vec(value)
+            } else {
+                serde_json::to_vec(value)
+            };
+            bytes
+                .map(Bytes::from)
+                .map_err(|err| RuntimeError::InvalidMessage(err.to_string()))
+        }
+
         let mut jparts: Vec<Bytes> = vec![
-            serde_json::to_vec(&jupyter_message.header)?.into(),
+            pack(&jupyter_message.header, canonical)?,
             if let Some(parent_header) = jupyter_message.parent_header.as_ref() {
-                serde_json::to_vec(parent_header)?.into()
+                pack(parent_header, canonical)?
             } else {
-                serde_json::to_vec(&serde_json::Map::new())?.into()
+                pack(&serde_json::Map::new(), canonical)?
             },
-            serde_json::to_vec(&jupyter_message.metadata)?.into(),
-            serde_json::to_vec(&jupyter_message.content)?.into(),
+            pack(&jupyter_message.metadata, canonical)?,
+            pack(&jupyter_message.content, canonical)?,
         ];
         jparts.extend_from_slice(&jupyter_message.buffers);
         let raw_message = RawMessage {
@@ -235,25 +406,58 @@ impl RawMessage {
         Ok(raw_message)
     }
 
-    fn into_jupyter_message(self) -> Result<JupyterMessage, anyhow::Error> {
+    /// Deserialize the message parts, checking their sizes against `limits`
+    /// before deserializing anything.
+    pub fn into_limited_jupyter_message(self, limits: &MessageLimits) -> Result<JupyterMessage> {
+        limits.check_buffers(self.jparts.get(4..).unwrap_or_default())?;
+
+        let content_size = self.jparts.get(3).map_or(0, Bytes::len);
+        let (Err(error), Some(limit)) =
+            (limits.check_content(content_size), limits.max_content_bytes)
+        else {
+            return self.into_jupyter_message();
+        };
+
+        // Stream text is the one thing worth keeping part of
+        let is_stream = serde_json::from_slice::<Header>(&self.jparts[0])
+            .is_ok_and(|header| header.msg_type == "stream");
+        if !(limits.truncate_streams && is_stream) {
+            return Err(error.into());
+        }
+
+        let mut message = self.into_jupyter_message()?;
+        if let JupyterMessageContent::StreamContent(stream) = &mut message.content {
+            truncate_stream_text(stream, limit);
+        }
+        Ok(message)
+    }
+
+    fn into_jupyter_message(self) -> Result<JupyterMessage> {
+        fn parse<T: serde::de::DeserializeOwned>(part: &[u8]) -> Result<T> {
+            serde_json::from_slice(part)
+                .map_err(|err| RuntimeError::InvalidMessage(err.to_string()))
+        }
+
         if self.jparts.len() < 4 {
             // Be explicit with error here
-            return Err(anyhow!("Insufficient message parts {}", self.jparts.len()));
+            return Err(RuntimeError::InvalidMessage(format!(
+                "Insufficient message parts {}",
+                self.jparts.len()
+            )));
         }
 
-        let header: Header = serde_json::from_slice(&self.jparts[0])?;
-        let content: Value = serde_json::from_slice(&self.jparts[3])?;
+        let header: Header = parse(&self.jparts[0])?;
+        let content: Value = parse(&self.jparts[3])?;
 
         let content = JupyterMessageContent::from_type_and_content(&header.msg_type, content);
 
         let content = match content {
             Ok(content) => content,
             Err(err) => {
-                return Err(anyhow!(
+                return Err(RuntimeError::InvalidMessage(format!(
                     "Error deserializing content for msg_type `{}`: {}",
-                    &header.msg_type,
-                    err
-                ));
+                    &header.msg_type, err
+                )));
             }
         };
 
@@ -263,7 +467,7 @@ impl RawMessage {
             zmq_identities: self.zmq_identities,
             header,
             parent_header,
-            metadata: serde_json::from_slice(&self.jparts[2])?,
+            metadata: parse(&self.jparts[2])?,
             content,
             buffers: if self.jparts.len() > 4 {
                 self.jparts[4..].to_vec()
@@ -277,114 +481,550 @@ impl RawMessage {
     }
 }
 
+/// Checks to run before binding or connecting any socket.
+fn prepare_connection(connection_info: &ConnectionInfo) -> Result<()> {
+    crate::runtime::ensure_dispatcher();
+    check_transport(connection_info)
+}
+
+/// ZeroMQ's `ipc` transport uses Unix domain socket paths, which Windows kernels
+/// don't support. Fail early with a clear error instead of a confusing bind failure.
+fn check_transport(connection_info: &ConnectionInfo) -> Result<()> {
+    if cfg!(windows) && connection_info.transport == Transport::IPC {
+        return Err(RuntimeError::Unsupported(
+            "The ipc transport is not supported on Windows, use tcp instead".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Read a kernel's connection file, like the ones Jupyter writes to
+/// [`runtime_dir`](crate::runtime_dir).
+pub fn read_connection_file(path: impl AsRef<Path>) -> Result<ConnectionInfo> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => RuntimeError::ConnectionFileNotFound(path.to_path_buf()),
+        _ => RuntimeError::Io(error),
+    })?;
+    serde_json::from_str(&content).map_err(|source| RuntimeError::InvalidConnectionFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
 pub async fn create_kernel_iopub_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<KernelIoPubConnection> {
+    session: &Session,
+) -> Result<KernelIoPubConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.iopub_url();
 
     let mut socket = zeromq::PubSocket::new();
-    socket.bind(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .bind(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::IOPub))?;
+    Ok(Connection::new(
+        socket,
+        Channel::IOPub,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_kernel_shell_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<KernelShellConnection> {
+    session: &Session,
+) -> Result<KernelShellConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.shell_url();
 
     let mut socket = zeromq::RouterSocket::new();
-    socket.bind(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .bind(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Shell))?;
+    Ok(Connection::new(
+        socket,
+        Channel::Shell,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_kernel_control_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<KernelControlConnection> {
+    session: &Session,
+) -> Result<KernelControlConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.control_url();
 
     let mut socket = zeromq::RouterSocket::new();
-    socket.bind(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .bind(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Control))?;
+    Ok(Connection::new(
+        socket,
+        Channel::Control,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_kernel_stdin_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<KernelStdinConnection> {
+    session: &Session,
+) -> Result<KernelStdinConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.stdin_url();
 
     let mut socket = zeromq::RouterSocket::new();
-    socket.bind(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .bind(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Stdin))?;
+    Ok(Connection::new(
+        socket,
+        Channel::Stdin,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_kernel_heartbeat_connection(
     connection_info: &ConnectionInfo,
-) -> anyhow::Result<KernelHeartbeatConnection> {
+) -> Result<KernelHeartbeatConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.hb_url();
 
     let mut socket = zeromq::RepSocket::new();
-    socket.bind(&endpoint).await?;
-    anyhow::Ok(KernelHeartbeatConnection { socket })
+    socket
+        .bind(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Heartbeat))?;
+    Ok(KernelHeartbeatConnection { socket })
 }
 
 pub async fn create_client_iopub_connection(
     connection_info: &ConnectionInfo,
     topic: &str,
-    session_id: &str,
-) -> anyhow::Result<ClientIoPubConnection> {
+    session: &Session,
+) -> Result<ClientIoPubConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.iopub_url();
 
     let mut socket = zeromq::SubSocket::new();
-    socket.subscribe(topic).await?;
+    socket
+        .subscribe(topic)
+        .await
+        .map_err(RuntimeError::socket(Channel::IOPub))?;
 
-    socket.connect(&endpoint).await?;
+    socket
+        .connect(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::IOPub))?;
 
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    Ok(Connection::new(
+        socket,
+        Channel::IOPub,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_client_shell_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<ClientShellConnection> {
+    session: &Session,
+) -> Result<ClientShellConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.shell_url();
 
     let mut socket = zeromq::DealerSocket::new();
-    socket.connect(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .connect(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Shell))?;
+    Ok(Connection::new(
+        socket,
+        Channel::Shell,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_client_control_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<ClientControlConnection> {
+    session: &Session,
+) -> Result<ClientControlConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.control_url();
 
     let mut socket = zeromq::DealerSocket::new();
-    socket.connect(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .connect(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Control))?;
+    Ok(Connection::new(
+        socket,
+        Channel::Control,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_client_stdin_connection(
     connection_info: &ConnectionInfo,
-    session_id: &str,
-) -> anyhow::Result<ClientStdinConnection> {
+    session: &Session,
+) -> Result<ClientStdinConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.stdin_url();
 
     let mut socket = zeromq::DealerSocket::new();
-    socket.connect(&endpoint).await?;
-    anyhow::Ok(Connection::new(socket, &connection_info.key, session_id))
+    socket
+        .connect(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Stdin))?;
+    Ok(Connection::new(
+        socket,
+        Channel::Stdin,
+        &connection_info.key,
+        session,
+    ))
 }
 
 pub async fn create_client_heartbeat_connection(
     connection_info: &ConnectionInfo,
-) -> anyhow::Result<ClientHeartbeatConnection> {
+) -> Result<ClientHeartbeatConnection> {
+    prepare_connection(connection_info)?;
     let endpoint = connection_info.hb_url();
 
     let mut socket = zeromq::ReqSocket::new();
-    socket.connect(&endpoint).await?;
-    anyhow::Ok(ClientHeartbeatConnection { socket })
+    socket
+        .connect(&endpoint)
+        .await
+        .map_err(RuntimeError::socket(Channel::Heartbeat))?;
+    Ok(ClientHeartbeatConnection { socket })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> HmacSha256Signer {
+        HmacSha256Signer::new(b"a0436f6c-1916-498b-8eb9-e81ab9368e84")
+    }
+
+    #[test]
+    fn test_signed_message_round_trips_with_buffers() {
+        let signer = signer();
+        let mut message: JupyterMessage = ExecuteRequest::new("1 + 1".to_string()).into();
+        message.buffers = vec![Bytes::from_static(b"\x00\x01\x02")];
+
+        let raw_message = RawMessage::from_jupyter_message(message, false).unwrap();
+        let zmq_message = raw_message.into_zmq_message(Some(&signer)).unwrap();
+
+        let raw_message = RawMessage::from_multipart(zmq_message, Some(&signer)).unwrap();
+        let message = raw_message.into_jupyter_message().unwrap();
+        assert_eq!(message.buffers, vec![Bytes::from_static(b"\x00\x01\x02")]);
+    }
+
+    #[test]
+    fn test_malformed_multiparts_are_rejected() {
+        let multipart = |parts: &[&'static [u8]]| {
+            zeromq::ZmqMessage::try_from(
+                parts
+                    .iter()
+                    .map(|part| Bytes::from_static(part))
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap()
+        };
+
+        // Nothing after the delimiter, not even a signature
+        for signer in [None, Some(&signer() as &dyn Signer)] {
+            assert!(matches!(
+                RawMessage::from_multipart(multipart(&[b"identity", DELIMITER]), signer),
+                Err(RuntimeError::InvalidMessage(_))
+            ));
+        }
+
+        // A signature that isn't hex
+        let zmq_message = multipart(&[DELIMITER, b"not hex!", b"{}", b"{}", b"{}", b"{}"]);
+        assert!(matches!(
+            RawMessage::from_multipart(zmq_message, Some(&signer())),
+            Err(RuntimeError::HmacMismatch)
+        ));
+
+        // Unsigned, with too few parts to deserialize
+        let raw_message =
+            RawMessage::from_multipart(multipart(&[b"identity", DELIMITER, b"", b"{}"]), None)
+                .unwrap();
+        assert_eq!(
+            raw_message.zmq_identities,
+            vec![Bytes::from_static(b"identity")]
+        );
+        assert!(raw_message
+            .into_limited_jupyter_message(&MessageLimits::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_is_hmac_mismatch() {
+        let message: JupyterMessage = ExecuteRequest::new("1 + 1".to_string()).into();
+        let zmq_message = RawMessage::from_jupyter_message(message, false)
+            .unwrap()
+            .into_zmq_message(Some(&signer()))
+            .unwrap();
+
+        let other_signer = HmacSha256Signer::new(b"another-key");
+        assert!(matches!(
+            RawMessage::from_multipart(zmq_message, Some(&other_signer)),
+            Err(RuntimeError::HmacMismatch)
+        ));
+    }
+
+    #[cfg(feature = "perf-instrumentation")]
+    #[crate::runtime::async_test]
+    async fn test_instrumentation_counts_both_ends() {
+        use jupyter_protocol::instrumentation::MessageCounters;
+
+        let connection_info = crate::client::tests::local_connection_info().await;
+        let session = Session::new();
+        let counters = Arc::new(MessageCounters::new());
+        let mut kernel = create_kernel_shell_connection(&connection_info, &session)
+            .await
+            .unwrap()
+            .with_instrumentation(counters.clone());
+        let mut client = create_client_shell_connection(&connection_info, &session)
+            .await
+            .unwrap()
+            .with_instrumentation(counters.clone());
+
+        client
+            .send(session.message(KernelInfoRequest {}))
+            .await
+            .unwrap();
+        kernel.read().await.unwrap();
+
+        let stats = counters.snapshot();
+        let sent = stats.sent["kernel_info_request"];
+        assert_eq!(sent.count, 1);
+        // The router adds an identity frame on the way in
+        assert!(stats.parsed["kernel_info_request"].bytes > sent.bytes);
+        assert_eq!(stats.parse_failures, 0);
+    }
+
+    #[crate::runtime::async_test]
+    async fn test_channel_is_set_on_read_and_checked_on_send() {
+        let connection_info = crate::client::tests::local_connection_info().await;
+        let session = Session::new();
+        let mut kernel = create_kernel_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let mut client = create_client_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+
+        let misrouted = session
+            .message(InterruptRequest {})
+            .with_channel(Channel::Control);
+        assert!(matches!(
+            client.send(misrouted.clone()).await,
+            Err(RuntimeError::Protocol(JupyterError::ChannelMismatch {
+                channel: Channel::Control,
+                socket: Channel::Shell,
+                ..
+            }))
+        ));
+
+        let mut client = client.with_channel_check(false);
+        client.send(misrouted).await.unwrap();
+        let read = kernel.read().await.unwrap();
+        assert_eq!(read.message_type(), "interrupt_request");
+        assert_eq!(read.channel, Some(Channel::Shell));
+    }
+
+    /// A message from `tests/messages`, as `(key, multipart)`. `generate.py`
+    /// there writes them with jupyter_client's `Session.serialize`.
+    fn fixture(name: &str) -> (String, zeromq::ZmqMessage) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/messages")
+            .join(name);
+        let fixture: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let parts: Vec<Bytes> = fixture["parts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|part| Bytes::from(part.as_str().unwrap().as_bytes().to_vec()))
+            .collect();
+        (
+            fixture["key"].as_str().unwrap().to_string(),
+            zeromq::ZmqMessage::try_from(parts).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_json_dumps_signatures_verify() {
+        for name in [
+            "json_dumps_execute_request.json",
+            "json_dumps_execute_result.json",
+        ] {
+            let (key, zmq_message) = fixture(name);
+            let signer = HmacSha256Signer::new(key.as_bytes());
+            let raw_message = RawMessage::from_multipart(zmq_message, Some(&signer))
+                .unwrap_or_else(|e| panic!("{name} failed to verify: {e}"));
+            raw_message.into_jupyter_message().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_canonical_parts_match_python() {
+        for (source, expected) in [
+            (
+                "json_dumps_execute_request.json",
+                "canonical_execute_request.json",
+            ),
+            (
+                "json_dumps_execute_result.json",
+                "canonical_execute_result.json",
+            ),
+        ] {
+            let (key, zmq_message) = fixture(source);
+            let signer = HmacSha256Signer::new(key.as_bytes());
+            let message = RawMessage::from_multipart(zmq_message, Some(&signer))
+                .unwrap()
+                .into_jupyter_message()
+                .unwrap();
+
+            let canonical = RawMessage::from_jupyter_message(message, true)
+                .unwrap()
+                .into_zmq_message(Some(&signer))
+                .unwrap();
+            let (_, expected_message) = fixture(expected);
+            let as_text = |message: zeromq::ZmqMessage| -> Vec<String> {
+                message
+                    .into_vec()
+                    .iter()
+                    .map(|part| String::from_utf8_lossy(part).into_owned())
+                    .collect()
+            };
+            assert_eq!(as_text(canonical), as_text(expected_message), "{expected}");
+        }
+    }
+
+    fn raw_message(content: impl Into<JupyterMessageContent>) -> RawMessage {
+        RawMessage::from_jupyter_message(JupyterMessage::new(content, None), false).unwrap()
+    }
+
+    #[test]
+    fn test_oversized_content_is_rejected() {
+        let limits = MessageLimits::default().with_max_content_bytes(Some(64));
+        let error = raw_message(StreamContent::stdout(&"x".repeat(100)))
+            .into_limited_jupyter_message(&limits)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RuntimeError::Protocol(jupyter_protocol::JupyterError::MessageTooLarge {
+                part: "content",
+                ..
+            })
+        ));
+
+        let mut message = raw_message(StreamContent::stdout("ok"));
+        message.jparts.push(Bytes::from(vec![0; 100]));
+        let limits = MessageLimits::default().with_max_buffer_bytes(Some(64));
+        assert!(message.into_limited_jupyter_message(&limits).is_err());
+    }
+
+    #[test]
+    fn test_oversized_stream_is_truncated() {
+        let limits = MessageLimits::default()
+            .with_max_content_bytes(Some(64))
+            .with_stream_truncation(true);
+        let message = raw_message(StreamContent::stdout(&"x".repeat(100)))
+            .into_limited_jupyter_message(&limits)
+            .unwrap();
+        let JupyterMessageContent::StreamContent(stream) = message.content else {
+            panic!("expected a stream message");
+        };
+        assert!(stream.text.starts_with(&"x".repeat(64)));
+        assert!(stream
+            .text
+            .ends_with("[output truncated: 36 bytes omitted]\n"));
+
+        // Other message types are still rejected
+        assert!(raw_message(ExecuteRequest::new("x".repeat(100)))
+            .into_limited_jupyter_message(&limits)
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_connection_file() {
+        let dir = std::env::temp_dir().join(format!("runtimelib-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let missing = dir.join("kernel-missing.json");
+        assert!(matches!(
+            read_connection_file(&missing),
+            Err(RuntimeError::ConnectionFileNotFound(path)) if path == missing
+        ));
+
+        let invalid = dir.join("kernel-invalid.json");
+        std::fs::write(&invalid, "{\"ip\": ").unwrap();
+        assert!(matches!(
+            read_connection_file(&invalid),
+            Err(RuntimeError::InvalidConnectionFile { .. })
+        ));
+
+        let valid = dir.join("kernel-valid.json");
+        std::fs::write(
+            &valid,
+            r#"{"ip": "127.0.0.1", "transport": "tcp", "shell_port": 1, "iopub_port": 2,
+                "stdin_port": 3, "control_port": 4, "hb_port": 5, "key": "",
+                "signature_scheme": "hmac-sha256"}"#,
+        )
+        .unwrap();
+        assert_eq!(read_connection_file(&valid).unwrap().iopub_port, 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_transport() {
+        let mut connection_info = ConnectionInfo {
+            ip: "127.0.0.1".to_string(),
+            transport: Transport::TCP,
+            shell_port: 6767,
+            iopub_port: 6768,
+            stdin_port: 6769,
+            control_port: 6770,
+            hb_port: 6771,
+            key: String::new(),
+            signature_scheme: "hmac-sha256".to_string(),
+            kernel_name: None,
+        };
+        assert!(check_transport(&connection_info).is_ok());
+
+        connection_info.transport = Transport::IPC;
+        assert_eq!(check_transport(&connection_info).is_err(), cfg!(windows));
+    }
+
+    #[test]
+    fn test_truncated_signed_message_is_rejected() {
+        let signer = signer();
+        let signature = HEXLOWER.encode(&signer.sign(&[b"{}", b"{}"]));
+        let parts: Vec<Bytes> = vec![
+            Bytes::from_static(DELIMITER),
+            signature.into(),
+            Bytes::from_static(b"{}"),
+            Bytes::from_static(b"{}"),
+        ];
+        let zmq_message = zeromq::ZmqMessage::try_from(parts).unwrap();
+
+        assert!(matches!(
+            RawMessage::from_multipart(zmq_message, Some(&signer)),
+            Err(RuntimeError::InvalidMessage(_))
+        ));
+    }
 }
@@ -0,0 +1,446 @@
+//! Finding kernels running inside local Docker containers.
+//!
+//! Kernels in containers write their connection files to the container's
+//! runtime directory, out of sight of the host. [`discover_container_kernels`]
+//! looks in each running container that opts in with `docker exec`: in the
+//! directory named by its [`RUNTIME_DIR_LABEL`] label if it has one, and
+//! otherwise in `$JUPYTER_RUNTIME_DIR` and the usual runtime directories of
+//! root and the Jupyter Docker Stacks' `jovyan` user.
+//!
+//! Containers opt in with the [`DISCOVERY_LABEL`] or [`RUNTIME_DIR_LABEL`]
+//! label, like `docker run --label org.jupyter.kernels ...`, so nothing runs
+//! in containers that didn't ask for it. [`ContainerDiscovery`] can look in
+//! every container instead, and sets how long each `docker` call may take.
+//!
+//! The ports in those files are the container's. They're rewritten to the
+//! host ports Docker publishes them on, so the connection info works from the
+//! host. Kernels whose ports aren't all published, or that listen on the
+//! container's loopback address, can't be reached from the host and come
+//! back without connection info.
+//!
+//! This runs the `docker` command, so it needs Docker installed and the
+//! permission to use it.
+//!
+//! ```rust,no_run
+//! use runtimelib::containers::discover_container_kernels;
+//!
+//! # async fn example() -> runtimelib::error::Result<()> {
+//! for kernel in discover_container_kernels().await? {
+//!     match &kernel.connection_info {
+//!         Some(info) => println!("{}: {}", kernel.container_name, info),
+//!         None => println!("{}: not reachable from the host", kernel.container_name),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::process::Command;
+use std::time::Duration;
+
+use futures::future::join_all;
+use jupyter_protocol::connection_info::Transport;
+use serde::Deserialize;
+
+use crate::client::with_timeout;
+use crate::error::{Result, RuntimeError};
+use crate::ConnectionInfo;
+
+/// Container label that lets discovery look for kernels in the container.
+pub const DISCOVERY_LABEL: &str = "org.jupyter.kernels";
+
+/// How long each `docker` command may run before discovery gives up on it.
+pub const DEFAULT_DOCKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Container label naming the directory the container's kernels write their
+/// connection files to.
+pub const RUNTIME_DIR_LABEL: &str = "org.jupyter.runtime-dir";
+
+/// Prints each connection file in the directories given as arguments, or in
+/// the usual runtime directories if there are none, as its path and contents
+/// each followed by a NUL.
+const LIST_CONNECTION_FILES: &str = r#"
+[ $# -eq 0 ] && set -- "${JUPYTER_RUNTIME_DIR:-$HOME/.local/share/jupyter/runtime}" \
+    /root/.local/share/jupyter/runtime /home/jovyan/.local/share/jupyter/runtime
+for dir in "$@"; do
+    for file in "$dir"/kernel-*.json; do
+        [ -f "$file" ] && printf '%s\0' "$file" && cat "$file" && printf '\0'
+    done
+done
+exit 0
+"#;
+
+/// A kernel found inside a container.
+#[derive(Debug, Clone)]
+pub struct ContainerKernel {
+    pub container_id: String,
+    pub container_name: String,
+    /// Where the connection file is inside the container.
+    pub connection_file: String,
+    /// How to reach the kernel from the host, if Docker publishes its ports.
+    pub connection_info: Option<ConnectionInfo>,
+}
+
+/// What's needed from `docker inspect`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInspect {
+    id: String,
+    name: String,
+    config: ContainerConfig,
+    host_config: HostConfig,
+    network_settings: NetworkSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerConfig {
+    #[serde(default)]
+    labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HostConfig {
+    #[serde(default)]
+    network_mode: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    /// Keyed by container port, like `8888/tcp`
+    #[serde(default)]
+    ports: Option<HashMap<String, Option<Vec<PortBinding>>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PortBinding {
+    host_ip: String,
+    host_port: String,
+}
+
+impl ContainerInspect {
+    fn label(&self, name: &str) -> Option<&str> {
+        self.config.labels.as_ref()?.get(name).map(String::as_str)
+    }
+
+    /// Whether the container asked for its kernels to be found.
+    fn opted_in(&self) -> bool {
+        self.label(DISCOVERY_LABEL)
+            .is_some_and(|value| value != "false")
+            || self.label(RUNTIME_DIR_LABEL).is_some()
+    }
+}
+
+/// Kernels in the running containers that opt in, with the default settings
+/// of [`ContainerDiscovery`].
+pub async fn discover_container_kernels() -> Result<Vec<ContainerKernel>> {
+    ContainerDiscovery::new().discover().await
+}
+
+/// Settings for finding kernels in containers.
+#[derive(Debug, Clone)]
+pub struct ContainerDiscovery {
+    timeout: Duration,
+    all_containers: bool,
+}
+
+impl Default for ContainerDiscovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContainerDiscovery {
+    pub fn new() -> Self {
+        Self {
+            timeout: DEFAULT_DOCKER_TIMEOUT,
+            all_containers: false,
+        }
+    }
+
+    /// Set how long each `docker` command may run. A `docker ps` or `docker
+    /// inspect` that takes longer fails with [`RuntimeError::Timeout`], and a
+    /// container whose `docker exec` takes longer is skipped.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Look in every running container, not just those with the
+    /// [`DISCOVERY_LABEL`] or [`RUNTIME_DIR_LABEL`] label.
+    pub fn with_all_containers(mut self, all_containers: bool) -> Self {
+        self.all_containers = all_containers;
+        self
+    }
+
+    /// Kernels in the running containers, in the order Docker lists the
+    /// containers. Containers that can't be looked into, like ones without a
+    /// shell, are skipped.
+    pub async fn discover(&self) -> Result<Vec<ContainerKernel>> {
+        let ids = docker(&["ps", "--quiet", "--no-trunc"], self.timeout).await?;
+        let ids: Vec<&str> = std::str::from_utf8(&ids)
+            .map_err(|e| RuntimeError::Docker(e.to_string()))?
+            .split_whitespace()
+            .collect();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut inspect_args = vec!["inspect"];
+        inspect_args.extend(&ids);
+        let inspected = docker(&inspect_args, self.timeout).await?;
+        let containers: Vec<ContainerInspect> =
+            serde_json::from_slice(&inspected).map_err(|e| {
+                RuntimeError::Docker(format!("Unexpected `docker inspect` output: {}", e))
+            })?;
+
+        let found = join_all(
+            containers
+                .iter()
+                .filter(|container| self.all_containers || container.opted_in())
+                .map(|container| kernels_in(container, self.timeout)),
+        )
+        .await;
+        Ok(found.into_iter().flatten().collect())
+    }
+}
+
+async fn kernels_in(container: &ContainerInspect, timeout: Duration) -> Vec<ContainerKernel> {
+    let mut args = vec![
+        "exec",
+        container.id.as_str(),
+        "sh",
+        "-c",
+        LIST_CONNECTION_FILES,
+        "sh",
+    ];
+    if let Some(dir) = container.label(RUNTIME_DIR_LABEL) {
+        args.push(dir);
+    }
+    let Ok(listing) = docker(&args, timeout).await else {
+        return Vec::new();
+    };
+
+    connection_files(&listing)
+        .into_iter()
+        .filter_map(|(path, contents)| {
+            let info: ConnectionInfo = serde_json::from_str(contents).ok()?;
+            Some(ContainerKernel {
+                container_id: container.id.clone(),
+                container_name: container.name.trim_start_matches('/').to_string(),
+                connection_file: path.to_string(),
+                connection_info: host_connection_info(&info, container),
+            })
+        })
+        .collect()
+}
+
+/// The path and contents of each connection file in the output of
+/// [`LIST_CONNECTION_FILES`].
+fn connection_files(listing: &[u8]) -> Vec<(&str, &str)> {
+    let mut fields = listing
+        .split(|byte| *byte == 0)
+        .map(|field| std::str::from_utf8(field).unwrap_or_default());
+    let mut files = Vec::new();
+    while let (Some(path), Some(contents)) = (fields.next(), fields.next()) {
+        files.push((path, contents));
+    }
+    files
+}
+
+/// `info` rewritten to the host side of the container's published ports.
+fn host_connection_info(
+    info: &ConnectionInfo,
+    container: &ContainerInspect,
+) -> Option<ConnectionInfo> {
+    if container.host_config.network_mode == "host" {
+        return Some(info.clone());
+    }
+    // Docker forwards published ports to the container's own address, which
+    // a kernel on loopback doesn't listen on
+    if info.transport != Transport::TCP || matches!(info.ip.as_str(), "127.0.0.1" | "localhost") {
+        return None;
+    }
+    let ports = container.network_settings.ports.as_ref()?;
+    let published = |port: u16| -> Option<(String, u16)> {
+        let binding = ports.get(&format!("{}/tcp", port))?.as_ref()?.first()?;
+        let ip = match binding.host_ip.as_str() {
+            "" | "0.0.0.0" | "::" => "127.0.0.1",
+            ip => ip,
+        };
+        Some((ip.to_string(), binding.host_port.parse().ok()?))
+    };
+
+    let (ip, shell_port) = published(info.shell_port)?;
+    Some(ConnectionInfo {
+        ip,
+        shell_port,
+        iopub_port: published(info.iopub_port)?.1,
+        stdin_port: published(info.stdin_port)?.1,
+        control_port: published(info.control_port)?.1,
+        hb_port: published(info.hb_port)?.1,
+        ..info.clone()
+    })
+}
+
+/// Run `docker` with `args`, returning its stdout. It's killed if it runs
+/// longer than `timeout`.
+async fn docker(args: &[&str], timeout: Duration) -> Result<Vec<u8>> {
+    let mut command = Command::new("docker");
+    command.args(args);
+    let what = format!("docker {}", args.first().unwrap_or(&""));
+    run(command, &what, timeout).await
+}
+
+async fn run(command: Command, what: &str, timeout: Duration) -> Result<Vec<u8>> {
+    let output = match with_timeout(timeout, crate::runtime::output(command)).await {
+        Some(Ok(output)) => output,
+        Some(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(RuntimeError::Docker(
+                "The docker command isn't installed".to_string(),
+            ))
+        }
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err(RuntimeError::Timeout(what.to_string())),
+    };
+    if !output.status.success() {
+        return Err(RuntimeError::Docker(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn container(network_mode: &str, ports: serde_json::Value) -> ContainerInspect {
+        labelled(json!(null), network_mode, ports)
+    }
+
+    fn labelled(
+        labels: serde_json::Value,
+        network_mode: &str,
+        ports: serde_json::Value,
+    ) -> ContainerInspect {
+        serde_json::from_value(json!({
+            "Id": "4f1c2e",
+            "Name": "/notebook",
+            "Config": {"Labels": labels},
+            "HostConfig": {"NetworkMode": network_mode},
+            "NetworkSettings": {"Ports": ports}
+        }))
+        .unwrap()
+    }
+
+    fn kernel_info(ip: &str) -> ConnectionInfo {
+        ConnectionInfo {
+            ip: ip.to_string(),
+            transport: Transport::TCP,
+            shell_port: 50001,
+            iopub_port: 50002,
+            stdin_port: 50003,
+            control_port: 50004,
+            hb_port: 50005,
+            key: "secret".to_string(),
+            signature_scheme: "hmac-sha256".to_string(),
+            kernel_name: Some("python3".to_string()),
+        }
+    }
+
+    fn published(ports: &[(u16, u16)]) -> serde_json::Value {
+        ports
+            .iter()
+            .map(|(container, host)| {
+                (
+                    format!("{}/tcp", container),
+                    json!([
+                        {"HostIp": "0.0.0.0", "HostPort": host.to_string()},
+                        {"HostIp": "::", "HostPort": host.to_string()}
+                    ]),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    #[test]
+    fn test_ports_are_rewritten_to_the_host() {
+        let all = published(&[
+            (50001, 32801),
+            (50002, 32802),
+            (50003, 32803),
+            (50004, 32804),
+            (50005, 32805),
+        ]);
+        let info =
+            host_connection_info(&kernel_info("0.0.0.0"), &container("bridge", all)).unwrap();
+        assert_eq!(info.ip, "127.0.0.1");
+        assert_eq!(
+            [
+                info.shell_port,
+                info.iopub_port,
+                info.stdin_port,
+                info.control_port,
+                info.hb_port
+            ],
+            [32801, 32802, 32803, 32804, 32805]
+        );
+        assert_eq!(info.key, "secret");
+    }
+
+    #[test]
+    fn test_unreachable_kernels_have_no_connection_info() {
+        let some = published(&[(50001, 32801), (50002, 32802)]);
+        assert!(
+            host_connection_info(&kernel_info("0.0.0.0"), &container("bridge", some)).is_none()
+        );
+
+        let all = published(&[(50001, 1), (50002, 2), (50003, 3), (50004, 4), (50005, 5)]);
+        assert!(
+            host_connection_info(&kernel_info("127.0.0.1"), &container("bridge", all)).is_none()
+        );
+
+        // Unless the container shares the host's network
+        let info = host_connection_info(&kernel_info("127.0.0.1"), &container("host", json!({})));
+        assert_eq!(info, Some(kernel_info("127.0.0.1")));
+    }
+
+    #[test]
+    fn test_containers_opt_in_with_a_label() {
+        assert!(!container("bridge", json!({})).opted_in());
+        assert!(!labelled(json!({"maintainer": "me"}), "bridge", json!({})).opted_in());
+        assert!(!labelled(json!({DISCOVERY_LABEL: "false"}), "bridge", json!({})).opted_in());
+        assert!(labelled(json!({DISCOVERY_LABEL: ""}), "bridge", json!({})).opted_in());
+        assert!(labelled(json!({RUNTIME_DIR_LABEL: "/rt"}), "bridge", json!({})).opted_in());
+    }
+
+    #[cfg(unix)]
+    #[crate::runtime::async_test]
+    async fn test_slow_commands_time_out() {
+        let mut command = Command::new("sleep");
+        command.arg("10");
+        let started = std::time::Instant::now();
+        let result = run(command, "docker ps", Duration::from_millis(100)).await;
+        assert!(matches!(result, Err(RuntimeError::Timeout(what)) if what == "docker ps"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_connection_files_from_listing() {
+        let listing = b"/root/rt/kernel-1.json\0{\"a\": 1}\n\0/root/rt/kernel-2.json\0{}\0";
+        assert_eq!(
+            connection_files(listing),
+            vec![
+                ("/root/rt/kernel-1.json", "{\"a\": 1}\n"),
+                ("/root/rt/kernel-2.json", "{}")
+            ]
+        );
+        assert!(connection_files(b"").is_empty());
+    }
+}
@@ -1,9 +1,10 @@
-use anyhow::{Context, Result};
 use dirs::{data_dir, home_dir};
 use serde_json::Value;
 use std::env;
 use std::path::PathBuf;
 
+use crate::error::{Result, RuntimeError};
+
 #[cfg(feature = "tokio-runtime")]
 use tokio::process::Command;
 
@@ -16,17 +17,23 @@ pub async fn ask_jupyter() -> Result<Value> {
         .args(["--paths", "--json"])
         .output()
         .await
-        .context("Failed to execute `jupyter --paths --json` command")?;
+        .map_err(|error| {
+            RuntimeError::JupyterPaths(format!(
+                "Failed to execute `jupyter --paths --json`: {}",
+                error
+            ))
+        })?;
 
     if output.status.success() {
-        let paths: Value = serde_json::from_slice(&output.stdout)
-            .context("Failed to parse JSON from jupyter output")?;
+        let paths: Value = serde_json::from_slice(&output.stdout).map_err(|error| {
+            RuntimeError::JupyterPaths(format!("Invalid JSON from jupyter: {}", error))
+        })?;
         Ok(paths)
     } else {
-        Err(anyhow::anyhow!(
+        Err(RuntimeError::JupyterPaths(format!(
             "Jupyter command failed with status: {:?}",
             output.status
-        ))
+        )))
     }
 }
 
@@ -80,21 +87,18 @@ pub fn system_data_dirs() -> Vec<PathBuf> {
 }
 
 pub fn user_data_dir() -> Result<PathBuf> {
+    let no_home = || RuntimeError::JupyterPaths("Failed to get home directory".to_string());
     if cfg!(target_os = "macos") {
-        Ok(home_dir()
-            .context("Failed to get home directory")?
-            .join("Library/Jupyter"))
+        Ok(home_dir().ok_or_else(no_home)?.join("Library/Jupyter"))
     } else if cfg!(windows) {
-        Ok(PathBuf::from(env::var("APPDATA").context("Failed to get APPDATA")?).join("jupyter"))
+        let app_data = env::var("APPDATA")
+            .map_err(|_| RuntimeError::JupyterPaths("Failed to get APPDATA".to_string()))?;
+        Ok(PathBuf::from(app_data).join("jupyter"))
     } else {
         // TODO: Respect XDG_DATA_HOME if set
         match data_dir() {
-            None => Ok(home_dir()
-                .context("Failed to get home directory")?
-                .join(".local/share")),
-            Some(data_dir) => {
-                return Ok(data_dir.join("jupyter"));
-            }
+            None => Ok(home_dir().ok_or_else(no_home)?.join(".local/share")),
+            Some(data_dir) => Ok(data_dir.join("jupyter")),
         }
     }
 }
@@ -102,8 +106,10 @@ pub fn user_data_dir() -> Result<PathBuf> {
 pub fn data_dirs() -> Vec<PathBuf> {
     let mut paths = vec![];
 
-    if let Ok(jupyter_path) = env::var("JUPYTER_PATH") {
-        paths.push(PathBuf::from(jupyter_path));
+    // Like PATH, JUPYTER_PATH can hold several directories, separated by `;` on
+    // Windows and `:` elsewhere
+    if let Some(jupyter_path) = env::var_os("JUPYTER_PATH") {
+        paths.extend(env::split_paths(&jupyter_path).filter(|path| !path.as_os_str().is_empty()));
     }
 
     if let Ok(user_data_dir) = user_data_dir() {
@@ -116,11 +122,23 @@ pub fn data_dirs() -> Vec<PathBuf> {
     paths
 }
 
+/// `XDG_RUNTIME_DIR` only means something on Linux and the BSDs. A stray one on
+/// macOS or Windows (e.g. from MSYS2 or WSL interop) must not move the runtime dir.
+fn xdg_runtime_dir() -> Option<PathBuf> {
+    if cfg!(any(windows, target_os = "macos")) {
+        return None;
+    }
+    env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from)
+}
+
+/// Where kernel connection files live.
+///
+/// On Windows this is `%APPDATA%\jupyter\runtime`, matching `jupyter --runtime-dir`.
 pub fn runtime_dir() -> PathBuf {
     if let Ok(jupyter_runtime_dir) = env::var("JUPYTER_RUNTIME_DIR") {
         PathBuf::from(jupyter_runtime_dir)
-    } else if let Ok(xdg_runtime_dir) = env::var("XDG_RUNTIME_DIR") {
-        PathBuf::from(xdg_runtime_dir).join("jupyter")
+    } else if let Some(xdg_runtime_dir) = xdg_runtime_dir() {
+        xdg_runtime_dir.join("jupyter")
     } else if let Ok(user_data_dir) = user_data_dir() {
         user_data_dir.join("runtime")
     } else {
@@ -0,0 +1,134 @@
+//! Errors from connecting to and talking with kernels.
+//!
+//! Connections, [`KernelClient`](crate::KernelClient) and the
+//! [`Orchestrator`](crate::Orchestrator) return a [`RuntimeError`] that callers
+//! can match on, e.g. to re-read a connection file after an
+//! [`HmacMismatch`](RuntimeError::HmacMismatch) or retry after a
+//! [`Timeout`](RuntimeError::Timeout). Finding and installing kernelspecs
+//! fails with one too. It converts into `anyhow::Error` with `?` for
+//! applications that only report errors.
+//!
+//! ```rust,no_run
+//! use runtimelib::{KernelClient, RuntimeError};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! match KernelClient::attach("kernel-1234.json").await {
+//!     Ok(client) => println!("connected to {}", client.connection_info()),
+//!     Err(RuntimeError::ConnectionFileNotFound(path)) => {
+//!         eprintln!("{} is gone, the kernel has probably exited", path.display())
+//!     }
+//!     Err(error) => return Err(error.into()),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::path::PathBuf;
+
+use jupyter_protocol::{Channel, JupyterError, ReplyError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RuntimeError {
+    #[error("No connection file at {}", .0.display())]
+    ConnectionFileNotFound(PathBuf),
+
+    #[error("Invalid connection file {}: {source}", path.display())]
+    InvalidConnectionFile {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// Binding, connecting, sending or receiving on a socket failed.
+    #[error("{channel} socket failed: {source}")]
+    SocketError {
+        channel: Channel,
+        #[source]
+        source: zeromq::ZmqError,
+    },
+
+    /// A message's signature didn't match, usually because the two sides use
+    /// different connection keys.
+    #[error("Message signature doesn't match the connection key")]
+    HmacMismatch,
+
+    /// A message that couldn't be decoded from the wire.
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+
+    /// The message was over a size limit, or broke another protocol rule.
+    #[error(transparent)]
+    Protocol(#[from] JupyterError),
+
+    /// No reply came in time. Names what was being waited for.
+    #[error("Timed out waiting for {0}")]
+    Timeout(String),
+
+    /// The kernel answered a request with an `error` status.
+    #[error("The kernel replied with {}: {}", .0.ename, .0.evalue)]
+    ErrorReply(Box<ReplyError>),
+
+    /// The kernel went away while a request was in flight, as found by a
+    /// [`KernelClient`](crate::KernelClient) heartbeat.
+    #[error("The kernel died")]
+    KernelDied,
+
+    /// The connection info asks for something this platform can't do.
+    #[error("{0}")]
+    Unsupported(String),
+
+    /// Running the `docker` command failed, or it isn't installed.
+    #[error("docker: {0}")]
+    Docker(String),
+
+    /// The Jupyter directories couldn't be found, or `jupyter --paths` failed.
+    #[error("Can't find the Jupyter paths: {0}")]
+    JupyterPaths(String),
+
+    /// No kernelspec by that name in any data directory.
+    #[error("No such kernelspec: {0}")]
+    KernelspecNotFound(String),
+
+    /// A kernelspec is already installed where another would go.
+    #[error("Kernelspec {kernel_name} already exists at {}", path.display())]
+    KernelspecExists { kernel_name: String, path: PathBuf },
+
+    /// A kernelspec that can't be read, installed or launched, or a kernel
+    /// name Jupyter wouldn't accept.
+    #[error("{0}")]
+    InvalidKernelspec(String),
+
+    /// The kernelspec directory has no such logo or resource.
+    #[error("No resource {resource:?} in kernelspec {kernel_name}")]
+    ResourceNotFound {
+        kernel_name: String,
+        resource: String,
+    },
+
+    /// Reading or writing a file failed.
+    #[error("{}: {source}", path.display())]
+    FileError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl RuntimeError {
+    pub(crate) fn socket(channel: Channel) -> impl FnOnce(zeromq::ZmqError) -> Self {
+        move |source| RuntimeError::SocketError { channel, source }
+    }
+
+    pub(crate) fn file(path: &std::path::Path) -> impl FnOnce(std::io::Error) -> Self + '_ {
+        move |source| RuntimeError::FileError {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+pub type Result<T, E = RuntimeError> = std::result::Result<T, E>;
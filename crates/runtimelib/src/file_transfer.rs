@@ -0,0 +1,369 @@
+//! Moving files between a client and a kernel's working directory over comms.
+//!
+//! Each transfer is its own comm on the `runtimed.file_transfer` target. The
+//! `comm_open` data says what to do, file contents travel as binary buffers on
+//! `comm_msg`s, and the kernel side reports back with `done` or `error`:
+//!
+//! - Upload: the client opens with `{"op": "upload", "path", "size", "chunk_size"}`,
+//!   sends `{"op": "chunk", "seq", "final"}` messages carrying one buffer each, and
+//!   the kernel answers `{"op": "done", "path", "size"}`.
+//! - Download: the client opens with `{"op": "download", "path", "chunk_size"}` and
+//!   the kernel sends the chunks.
+//! - Either side may send `{"op": "error", "message"}` instead.
+//!
+//! Chunks are numbered from zero and must arrive in order. Paths are relative to
+//! the kernel's working directory.
+//!
+//! This module is the client side. Kernels answer the comms with
+//! [`FileTransferHandler`](crate::kernel::FileTransferHandler).
+//!
+//! ```rust
+//! use runtimelib::file_transfer::FileUpload;
+//!
+//! let upload = FileUpload::new("data/iris.csv", vec![0u8; 10]).with_chunk_size(4);
+//! // A comm_open followed by three chunks, ready to send on the shell channel
+//! assert_eq!(upload.messages().len(), 4);
+//! ```
+use anyhow::{anyhow, bail, Result};
+use bytes::{Bytes, BytesMut};
+use jupyter_protocol::{CommId, CommMsg, CommOpen, JupyterMessage, JupyterMessageContent};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const FILE_TRANSFER_TARGET: &str = "runtimed.file_transfer";
+
+/// Chunks are kept well below typical websocket and ZeroMQ message limits.
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The `data` of a file transfer `comm_open` or `comm_msg`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum FileTransferMessage {
+    Upload {
+        path: String,
+        size: u64,
+        chunk_size: usize,
+    },
+    Download {
+        path: String,
+        chunk_size: usize,
+    },
+    Chunk {
+        seq: u64,
+        #[serde(rename = "final")]
+        last: bool,
+    },
+    Done {
+        path: String,
+        size: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl FileTransferMessage {
+    pub fn to_data(&self) -> serde_json::Map<String, Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(data)) => data,
+            // Internally tagged enums always serialize to objects
+            _ => unreachable!("file transfer messages serialize to objects"),
+        }
+    }
+
+    pub fn from_data(data: &serde_json::Map<String, Value>) -> Result<Self> {
+        Ok(serde_json::from_value(Value::Object(data.clone()))?)
+    }
+}
+
+/// Find the file transfer message addressed to `comm_id`, if `message` is one.
+fn transfer_message<'a>(
+    comm_id: &CommId,
+    message: &'a JupyterMessage,
+) -> Option<Result<(FileTransferMessage, &'a [Bytes])>> {
+    match &message.content {
+        JupyterMessageContent::CommMsg(comm_msg) if comm_msg.comm_id == *comm_id => Some(
+            FileTransferMessage::from_data(&comm_msg.data)
+                .map(|transfer| (transfer, message.buffers.as_slice())),
+        ),
+        _ => None,
+    }
+}
+
+/// Upload a file into the kernel's working directory.
+#[derive(Debug, Clone)]
+pub struct FileUpload {
+    comm_id: CommId,
+    path: String,
+    contents: Bytes,
+    chunk_size: usize,
+}
+
+impl FileUpload {
+    pub fn new(path: impl Into<String>, contents: impl Into<Bytes>) -> Self {
+        Self {
+            comm_id: CommId(uuid::Uuid::new_v4().to_string()),
+            path: path.into(),
+            contents: contents.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn comm_id(&self) -> &CommId {
+        &self.comm_id
+    }
+
+    /// The messages to send on the shell channel, in order: the `comm_open`
+    /// followed by one `comm_msg` per chunk. Chunks share the file's memory.
+    pub fn messages(&self) -> Vec<JupyterMessage> {
+        let open = CommOpen {
+            comm_id: self.comm_id.clone(),
+            target_name: FILE_TRANSFER_TARGET.to_string(),
+            data: FileTransferMessage::Upload {
+                path: self.path.clone(),
+                size: self.contents.len() as u64,
+                chunk_size: self.chunk_size,
+            }
+            .to_data(),
+        };
+
+        let mut messages = vec![JupyterMessage::from(open)];
+
+        // An empty file is still sent as a single, empty, final chunk
+        let chunk_count = self.contents.len().div_ceil(self.chunk_size).max(1);
+        for seq in 0..chunk_count {
+            let start = seq * self.chunk_size;
+            let end = (start + self.chunk_size).min(self.contents.len());
+            let chunk = CommMsg {
+                comm_id: self.comm_id.clone(),
+                data: FileTransferMessage::Chunk {
+                    seq: seq as u64,
+                    last: seq + 1 == chunk_count,
+                }
+                .to_data(),
+            };
+            messages.push(
+                JupyterMessage::from(chunk).with_buffers(vec![self.contents.slice(start..end)]),
+            );
+        }
+
+        messages
+    }
+
+    /// Check an iopub message for the outcome of this upload.
+    ///
+    /// Returns `Ok(Some(size))` once the kernel has written the file, and
+    /// `Ok(None)` for messages that don't belong to this transfer.
+    pub fn handle(&self, message: &JupyterMessage) -> Result<Option<u64>> {
+        match transfer_message(&self.comm_id, message) {
+            None => Ok(None),
+            Some(transfer) => match transfer?.0 {
+                FileTransferMessage::Done { size, .. } => Ok(Some(size)),
+                FileTransferMessage::Error { message } => {
+                    bail!("Upload of {} failed: {}", self.path, message)
+                }
+                other => bail!("Unexpected file transfer message {:?}", other),
+            },
+        }
+    }
+}
+
+/// Download a file from the kernel's working directory.
+#[derive(Debug)]
+pub struct FileDownload {
+    comm_id: CommId,
+    path: String,
+    chunk_size: usize,
+    next_seq: u64,
+    contents: BytesMut,
+}
+
+impl FileDownload {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            comm_id: CommId(uuid::Uuid::new_v4().to_string()),
+            path: path.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_seq: 0,
+            contents: BytesMut::new(),
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn comm_id(&self) -> &CommId {
+        &self.comm_id
+    }
+
+    /// The `comm_open` that asks the kernel to start sending the file.
+    pub fn open_message(&self) -> JupyterMessage {
+        CommOpen {
+            comm_id: self.comm_id.clone(),
+            target_name: FILE_TRANSFER_TARGET.to_string(),
+            data: FileTransferMessage::Download {
+                path: self.path.clone(),
+                chunk_size: self.chunk_size,
+            }
+            .to_data(),
+        }
+        .into()
+    }
+
+    /// Feed an iopub message to the download.
+    ///
+    /// Returns `Ok(Some(contents))` once the final chunk has arrived, and
+    /// `Ok(None)` while more chunks are expected or for unrelated messages.
+    pub fn handle(&mut self, message: &JupyterMessage) -> Result<Option<Bytes>> {
+        let Some(transfer) = transfer_message(&self.comm_id, message) else {
+            return Ok(None);
+        };
+
+        match transfer? {
+            (FileTransferMessage::Chunk { seq, last }, buffers) => {
+                if seq != self.next_seq {
+                    bail!(
+                        "Download of {} received chunk {} but expected {}",
+                        self.path,
+                        seq,
+                        self.next_seq
+                    );
+                }
+                let chunk = buffers.first().ok_or_else(|| {
+                    anyhow!("Download of {} chunk {} has no buffer", self.path, seq)
+                })?;
+                self.contents.extend_from_slice(chunk);
+                self.next_seq += 1;
+
+                if last {
+                    Ok(Some(std::mem::take(&mut self.contents).freeze()))
+                } else {
+                    Ok(None)
+                }
+            }
+            (FileTransferMessage::Error { message }, _) => {
+                bail!("Download of {} failed: {}", self.path, message)
+            }
+            (other, _) => bail!("Unexpected file transfer message {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_message(
+        comm_id: &CommId,
+        seq: u64,
+        last: bool,
+        buffer: &'static [u8],
+    ) -> JupyterMessage {
+        JupyterMessage::from(CommMsg {
+            comm_id: comm_id.clone(),
+            data: FileTransferMessage::Chunk { seq, last }.to_data(),
+        })
+        .with_buffers(vec![Bytes::from_static(buffer)])
+    }
+
+    #[test]
+    fn test_upload_splits_contents_into_chunks() {
+        let upload = FileUpload::new("data.bin", b"0123456789".to_vec()).with_chunk_size(4);
+        let messages = upload.messages();
+
+        match &messages[0].content {
+            JupyterMessageContent::CommOpen(open) => {
+                assert_eq!(open.target_name, FILE_TRANSFER_TARGET);
+                assert_eq!(
+                    FileTransferMessage::from_data(&open.data).unwrap(),
+                    FileTransferMessage::Upload {
+                        path: "data.bin".to_string(),
+                        size: 10,
+                        chunk_size: 4
+                    }
+                );
+            }
+            other => panic!("Expected comm_open, got {:?}", other),
+        }
+
+        let chunks: Vec<&[u8]> = messages[1..]
+            .iter()
+            .map(|message| message.buffers[0].as_ref())
+            .collect();
+        assert_eq!(chunks, vec![&b"0123"[..], b"4567", b"89"]);
+
+        let JupyterMessageContent::CommMsg(last) = &messages[3].content else {
+            panic!("Expected comm_msg");
+        };
+        assert_eq!(
+            FileTransferMessage::from_data(&last.data).unwrap(),
+            FileTransferMessage::Chunk { seq: 2, last: true }
+        );
+    }
+
+    #[test]
+    fn test_empty_upload_sends_one_final_chunk() {
+        let messages = FileUpload::new("empty.txt", Vec::new()).messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].buffers[0].is_empty());
+    }
+
+    #[test]
+    fn test_download_reassembles_chunks() {
+        let mut download = FileDownload::new("results.csv");
+        let comm_id = download.comm_id().clone();
+
+        assert!(download
+            .handle(&chunk_message(&comm_id, 0, false, b"a,b\n"))
+            .unwrap()
+            .is_none());
+        // Messages for other comms are ignored
+        assert!(download
+            .handle(&chunk_message(&CommId("other".into()), 1, true, b"zz"))
+            .unwrap()
+            .is_none());
+        let contents = download
+            .handle(&chunk_message(&comm_id, 1, true, b"1,2\n"))
+            .unwrap();
+        assert_eq!(contents.as_deref(), Some(&b"a,b\n1,2\n"[..]));
+    }
+
+    #[test]
+    fn test_download_rejects_out_of_order_chunks() {
+        let mut download = FileDownload::new("results.csv");
+        let comm_id = download.comm_id().clone();
+        assert!(download
+            .handle(&chunk_message(&comm_id, 1, true, b"1,2\n"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_upload_reports_kernel_errors() {
+        let upload = FileUpload::new("../etc/passwd", b"nope".to_vec());
+        let error = JupyterMessage::from(CommMsg {
+            comm_id: upload.comm_id().clone(),
+            data: FileTransferMessage::Error {
+                message: "path escapes the working directory".to_string(),
+            }
+            .to_data(),
+        });
+        assert!(upload.handle(&error).is_err());
+
+        let done = JupyterMessage::from(CommMsg {
+            comm_id: upload.comm_id().clone(),
+            data: FileTransferMessage::Done {
+                path: "../etc/passwd".to_string(),
+                size: 4,
+            }
+            .to_data(),
+        });
+        assert_eq!(upload.handle(&done).unwrap(), Some(4));
+    }
+}
@@ -0,0 +1,389 @@
+//! Watching a runtime dir for kernels coming and going.
+//!
+//! Filesystem notifications don't work on NFS, and often not across
+//! container bind mounts either, which is where shared runtime dirs tend to
+//! live. [`watch_instances`] polls instead: every interval it lists the
+//! connection files, and only reads the ones whose size or modification time
+//! changed, or that were modified too close to the last read to tell. A file that was rewritten with the same contents isn't reported,
+//! and one that doesn't parse yet (e.g. half-written) is picked up once it
+//! does. Intervals are jittered, so many watchers on one server don't all hit
+//! the filesystem at once.
+//!
+//! ```rust,no_run
+//! use futures::StreamExt;
+//! use runtimelib::instances::{watch_instances, InstanceEvent, WatchOptions};
+//!
+//! # async fn example() {
+//! let mut events = Box::pin(watch_instances(runtimelib::runtime_dir(), WatchOptions::default()));
+//! while let Some(event) = events.next().await {
+//!     match event {
+//!         InstanceEvent::Added { path, .. } => println!("+ {}", path.display()),
+//!         InstanceEvent::Changed { path, .. } => println!("~ {}", path.display()),
+//!         InstanceEvent::Removed { path } => println!("- {}", path.display()),
+//!     }
+//! }
+//! # }
+//! ```
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{BTreeMap, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use futures::Stream;
+use jupyter_protocol::ConnectionInfo;
+
+/// How often [`watch_instances`] lists the runtime dir, before jitter.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far each interval may stray from the set one, as a fraction of it.
+pub const DEFAULT_JITTER: f64 = 0.2;
+
+/// The coarsest modification time a filesystem keeps, FAT's two seconds. A
+/// file modified within this long of being read may be rewritten without its
+/// mtime changing.
+const MTIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+/// A kernel connection file appearing, changing or going away.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstanceEvent {
+    Added {
+        path: PathBuf,
+        connection_info: ConnectionInfo,
+    },
+    Changed {
+        path: PathBuf,
+        connection_info: ConnectionInfo,
+    },
+    Removed {
+        path: PathBuf,
+    },
+}
+
+impl InstanceEvent {
+    pub fn path(&self) -> &Path {
+        match self {
+            InstanceEvent::Added { path, .. }
+            | InstanceEvent::Changed { path, .. }
+            | InstanceEvent::Removed { path } => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    pub interval: Duration,
+    /// From 0, for a fixed interval, to 1
+    pub jitter: f64,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_POLL_INTERVAL,
+            jitter: DEFAULT_JITTER,
+        }
+    }
+}
+
+impl WatchOptions {
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The interval to wait next, somewhere within the jitter of `interval`.
+    fn next_interval(&self) -> Duration {
+        // A fresh RandomState is randomly seeded, which is all the randomness needed here
+        let random = RandomState::new().build_hasher().finish();
+        let unit = random as f64 / u64::MAX as f64;
+        self.interval
+            .mul_f64(1.0 + self.jitter * (2.0 * unit - 1.0))
+    }
+}
+
+/// What a connection file looked like the last time it was read.
+#[derive(Debug, Clone)]
+struct Seen {
+    modified: Option<SystemTime>,
+    /// When the contents were last read
+    read_at: SystemTime,
+    len: u64,
+    hash: u64,
+    connection_info: ConnectionInfo,
+}
+
+/// The connection files in a runtime dir, and what's changed since the last
+/// [`scan`](Self::scan).
+#[derive(Debug, Clone, Default)]
+pub struct InstanceSnapshot {
+    instances: BTreeMap<PathBuf, Seen>,
+}
+
+impl InstanceSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The connection files found so far, sorted by path.
+    pub fn instances(&self) -> impl Iterator<Item = (&Path, &ConnectionInfo)> {
+        self.instances
+            .iter()
+            .map(|(path, seen)| (path.as_path(), &seen.connection_info))
+    }
+
+    /// List `dir` again, returning what changed, sorted by path. A missing
+    /// dir has no instances.
+    pub fn scan(&mut self, dir: &Path) -> Vec<InstanceEvent> {
+        let mut found = BTreeMap::new();
+        let mut events = Vec::new();
+        let entries = std::fs::read_dir(dir).into_iter().flatten().flatten();
+        let mut paths: Vec<PathBuf> = entries
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("json"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let modified = metadata.modified().ok();
+            let len = metadata.len();
+
+            let previous = self.instances.remove(&path);
+            if let Some(previous) = &previous {
+                // Some filesystems only keep mtimes to the second or two, so
+                // a same-size rewrite just after the last read can keep the
+                // same one. Only trust an mtime that was already old then.
+                let settled = modified
+                    .is_some_and(|modified| modified + MTIME_GRANULARITY <= previous.read_at);
+                if previous.modified == modified && previous.len == len && settled {
+                    found.insert(path, previous.clone());
+                    continue;
+                }
+            }
+
+            let read_at = SystemTime::now();
+            let Ok(content) = std::fs::read(&path) else {
+                continue;
+            };
+            let mut hasher = DefaultHasher::new();
+            content.hash(&mut hasher);
+            let hash = hasher.finish();
+
+            match previous {
+                Some(previous) if previous.hash == hash => {
+                    // Touched, but the same contents
+                    found.insert(
+                        path,
+                        Seen {
+                            modified,
+                            read_at,
+                            len,
+                            ..previous
+                        },
+                    );
+                }
+                previous => {
+                    let Ok(connection_info) = serde_json::from_slice::<ConnectionInfo>(&content)
+                    else {
+                        // Not a connection file, or not finished being written.
+                        // Keep what was there before, if anything.
+                        if let Some(previous) = previous {
+                            found.insert(path, previous);
+                        }
+                        continue;
+                    };
+                    events.push(match previous {
+                        Some(_) => InstanceEvent::Changed {
+                            path: path.clone(),
+                            connection_info: connection_info.clone(),
+                        },
+                        None => InstanceEvent::Added {
+                            path: path.clone(),
+                            connection_info: connection_info.clone(),
+                        },
+                    });
+                    found.insert(
+                        path,
+                        Seen {
+                            modified,
+                            read_at,
+                            len,
+                            hash,
+                            connection_info,
+                        },
+                    );
+                }
+            }
+        }
+
+        // Whatever wasn't found this time is gone
+        let removed = std::mem::replace(&mut self.instances, found);
+        events.extend(
+            removed
+                .into_keys()
+                .map(|path| InstanceEvent::Removed { path }),
+        );
+        events.sort_by(|a, b| a.path().cmp(b.path()));
+        events
+    }
+}
+
+/// Poll `dir` for connection files, yielding an [`InstanceEvent::Added`] for
+/// each one already there, then every change. The stream never ends.
+pub fn watch_instances(
+    dir: impl Into<PathBuf>,
+    options: WatchOptions,
+) -> impl Stream<Item = InstanceEvent> {
+    let state = (dir.into(), InstanceSnapshot::new(), VecDeque::new(), true);
+    futures::stream::unfold(
+        state,
+        move |(dir, mut snapshot, mut queued, mut first)| async move {
+            loop {
+                if let Some(event) = queued.pop_front() {
+                    return Some((event, (dir, snapshot, queued, first)));
+                }
+                if !first {
+                    futures_timer::Delay::new(options.next_interval()).await;
+                }
+                first = false;
+                queued.extend(snapshot.scan(&dir));
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::async_test;
+    use futures::StreamExt;
+
+    fn connection_file(shell_port: u16) -> String {
+        format!(
+            r#"{{"ip": "127.0.0.1", "transport": "tcp", "shell_port": {}, "iopub_port": 2,
+                "stdin_port": 3, "control_port": 4, "hb_port": 5, "key": "",
+                "signature_scheme": "hmac-sha256"}}"#,
+            shell_port
+        )
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("runtimelib-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_scan_reports_changes() {
+        let dir = temp_dir();
+        let mut snapshot = InstanceSnapshot::new();
+        assert!(snapshot.scan(&dir.join("missing")).is_empty());
+
+        let a = dir.join("kernel-a.json");
+        std::fs::write(&a, connection_file(1)).unwrap();
+        std::fs::write(dir.join("kernel-b.json"), "{\"ip\": ").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a kernel").unwrap();
+        let events = snapshot.scan(&dir);
+        assert!(matches!(
+            &events[..],
+            [InstanceEvent::Added { path, connection_info }]
+                if *path == a && connection_info.shell_port == 1
+        ));
+
+        // Nothing changed, then the same contents rewritten
+        assert!(snapshot.scan(&dir).is_empty());
+        std::fs::write(&a, connection_file(1)).unwrap();
+        assert!(snapshot.scan(&dir).is_empty());
+
+        // Longer, so it's noticed even when the mtime is the same
+        std::fs::write(&a, connection_file(10)).unwrap();
+        std::fs::write(dir.join("kernel-b.json"), connection_file(20)).unwrap();
+        let events = snapshot.scan(&dir);
+        assert!(matches!(
+            &events[..],
+            [
+                InstanceEvent::Changed { connection_info: a, .. },
+                InstanceEvent::Added { connection_info: b, .. },
+            ] if a.shell_port == 10 && b.shell_port == 20
+        ));
+        assert_eq!(snapshot.instances().count(), 2);
+
+        std::fs::remove_file(&a).unwrap();
+        assert_eq!(
+            snapshot.scan(&dir),
+            vec![InstanceEvent::Removed { path: a }]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scan_rehashes_rewrites_that_keep_the_mtime() {
+        let dir = temp_dir();
+        let mut snapshot = InstanceSnapshot::new();
+        let a = dir.join("kernel-a.json");
+        std::fs::write(&a, connection_file(1)).unwrap();
+        let modified = std::fs::metadata(&a).unwrap().modified().unwrap();
+        assert_eq!(snapshot.scan(&dir).len(), 1);
+
+        // Same size and mtime, as on a filesystem with coarse timestamps
+        std::fs::write(&a, connection_file(2)).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&a)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(matches!(
+            &snapshot.scan(&dir)[..],
+            [InstanceEvent::Changed { connection_info, .. }] if connection_info.shell_port == 2
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_jitter_stays_in_bounds() {
+        let options = WatchOptions::default()
+            .with_interval(Duration::from_millis(100))
+            .with_jitter(0.5);
+        for _ in 0..100 {
+            let interval = options.next_interval();
+            assert!(interval >= Duration::from_millis(50));
+            assert!(interval <= Duration::from_millis(150));
+        }
+        let fixed = options.with_jitter(0.0).next_interval();
+        assert_eq!(fixed, Duration::from_millis(100));
+    }
+
+    #[async_test]
+    async fn test_watch_instances() {
+        let dir = temp_dir();
+        let existing = dir.join("kernel-existing.json");
+        std::fs::write(&existing, connection_file(1)).unwrap();
+
+        let options = WatchOptions::default().with_interval(Duration::from_millis(20));
+        let mut events = Box::pin(watch_instances(&dir, options));
+        let first = events.next().await.unwrap();
+        assert!(matches!(first, InstanceEvent::Added { path, .. } if path == existing));
+
+        let started = dir.join("kernel-started.json");
+        std::fs::write(&started, connection_file(2)).unwrap();
+        let next = events.next().await.unwrap();
+        assert!(matches!(next, InstanceEvent::Added { path, .. } if path == started));
+
+        std::fs::remove_file(&existing).unwrap();
+        let next = events.next().await.unwrap();
+        assert_eq!(next, InstanceEvent::Removed { path: existing });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,5 @@
+//! Tracking which comms are open, to answer `comm_info_request`s.
+//!
+//! The [`CommRegistry`] lives in `jupyter_protocol`, where
+//! [`CommObserver`](jupyter_protocol::comm_events::CommObserver) builds on it.
+pub use jupyter_protocol::comm_registry::CommRegistry;
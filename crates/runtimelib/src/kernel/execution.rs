@@ -0,0 +1,175 @@
+//! A kernel's execution count, and the `execute_input` that announces each
+//! execution.
+//!
+//! Before running code, kernels are expected to broadcast it on iopub with
+//! the count it runs under, so every frontend attached to the kernel sees
+//! the cell, not just the one that sent it. Executions that don't store
+//! history leave the count where it was, and silent ones aren't broadcast
+//! at all. [`ExecutionCounter`] does this bookkeeping, so the kernel only
+//! runs the code with the count it's given.
+//!
+//! ```rust,no_run
+//! use jupyter_protocol::{ExecuteReply, JupyterMessage};
+//! use runtimelib::kernel::{ExecutionCounter, IopubPublisher};
+//! use runtimelib::KernelShellConnection;
+//!
+//! # async fn example(
+//! #     shell: &mut KernelShellConnection,
+//! #     iopub: &IopubPublisher,
+//! #     request: &JupyterMessage,
+//! # ) -> runtimelib::error::Result<()> {
+//! let mut counter = ExecutionCounter::new();
+//! let execution_count = counter.start(request, iopub).await?;
+//! // Run the code, sending an `execute_result` with `execution_count`
+//! let reply = ExecuteReply {
+//!     execution_count,
+//!     ..Default::default()
+//! };
+//! shell.send(reply.as_child_of(request)).await?;
+//! # Ok(())
+//! # }
+//! ```
+use jupyter_protocol::{
+    ExecuteInput, ExecuteRequest, ExecutionCount, JupyterMessage, JupyterMessageContent,
+};
+
+use super::IopubPublisher;
+use crate::error::{Result, RuntimeError};
+
+/// Counts a kernel's executions, starting from 0 before the first one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionCounter {
+    count: ExecutionCount,
+}
+
+impl ExecutionCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The count of the last execution that stored history.
+    pub fn current(&self) -> ExecutionCount {
+        self.count
+    }
+
+    /// The count to run `request` under, moving on to the next one if the
+    /// request [stores history](Self::stores_history).
+    pub fn next(&mut self, request: &ExecuteRequest) -> ExecutionCount {
+        if Self::stores_history(request) {
+            self.count.0 += 1;
+        }
+        self.count
+    }
+
+    /// Whether `request` gets its own execution count and belongs in the
+    /// kernel's history. Silent requests never do, whatever `store_history` says.
+    pub fn stores_history(request: &ExecuteRequest) -> bool {
+        request.store_history && !request.silent
+    }
+
+    /// Count the `execute_request` in `parent` with [`next`](Self::next) and
+    /// broadcast its `execute_input`, unless it's silent. Returns the count
+    /// for its reply and `execute_result`.
+    pub async fn start(
+        &mut self,
+        parent: &JupyterMessage,
+        iopub: &IopubPublisher,
+    ) -> Result<ExecutionCount> {
+        let JupyterMessageContent::ExecuteRequest(request) = &parent.content else {
+            return Err(RuntimeError::InvalidMessage(format!(
+                "Expected an execute_request, got {}",
+                parent.header.msg_type
+            )));
+        };
+        let execution_count = self.next(request);
+        if !request.silent {
+            let input = ExecuteInput {
+                code: request.code.clone(),
+                execution_count,
+            };
+            iopub.send(iopub.session().reply_to(parent, input)).await?;
+        }
+        Ok(execution_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::local_connection_info;
+    use crate::connection::create_client_iopub_connection;
+    use crate::kernel::IopubOptions;
+    use crate::runtime::async_test;
+    use jupyter_protocol::Session;
+
+    fn request(code: &str, silent: bool, store_history: bool) -> ExecuteRequest {
+        ExecuteRequest {
+            silent,
+            store_history,
+            ..ExecuteRequest::new(code.to_string())
+        }
+    }
+
+    #[test]
+    fn test_only_stored_executions_count() {
+        let mut counter = ExecutionCounter::new();
+        assert_eq!(counter.next(&request("a", false, true)), ExecutionCount(1));
+        assert_eq!(counter.next(&request("b", false, false)), ExecutionCount(1));
+        assert_eq!(counter.next(&request("c", true, true)), ExecutionCount(1));
+        assert_eq!(counter.next(&request("d", false, true)), ExecutionCount(2));
+        assert_eq!(counter.current(), ExecutionCount(2));
+
+        assert!(ExecutionCounter::stores_history(&request("e", false, true)));
+        assert!(!ExecutionCounter::stores_history(&request("f", true, true)));
+        assert!(!ExecutionCounter::stores_history(&request(
+            "g", false, false
+        )));
+    }
+
+    #[async_test]
+    async fn test_execute_input_is_broadcast() {
+        let connection_info = local_connection_info().await;
+        let session = Session::new();
+        let options = IopubOptions::default().with_starting(false);
+        let iopub = IopubPublisher::bind(&connection_info, &session, options)
+            .await
+            .unwrap();
+        let mut client = create_client_iopub_connection(&connection_info, "", &session)
+            .await
+            .unwrap();
+        // The welcome says the subscription is up
+        client.read().await.unwrap();
+
+        let mut counter = ExecutionCounter::new();
+        let silent = session.message(request("quiet", true, false));
+        assert_eq!(
+            counter.start(&silent, &iopub).await.unwrap(),
+            ExecutionCount(0)
+        );
+        let parent = session.message(request("1 + 1", false, true));
+        assert_eq!(
+            counter.start(&parent, &iopub).await.unwrap(),
+            ExecutionCount(1)
+        );
+
+        // The silent request's input never went out
+        let message = loop {
+            let message = client.read().await.unwrap();
+            if message.header.msg_type != "iopub_welcome" {
+                break message;
+            }
+        };
+        assert_eq!(
+            message.parent_header.map(|header| header.msg_id),
+            Some(parent.header.msg_id)
+        );
+        let JupyterMessageContent::ExecuteInput(input) = message.content else {
+            panic!("Expected execute_input, got {}", message.header.msg_type);
+        };
+        assert_eq!(input.code, "1 + 1");
+        assert_eq!(input.execution_count, ExecutionCount(1));
+
+        let status = session.message(jupyter_protocol::Status::idle());
+        assert!(counter.start(&status, &iopub).await.is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! The kernel side of [`file_transfer`](crate::file_transfer): writing uploads
+//! into the working directory and sending files back for downloads.
+//!
+//! Route every `comm_open`, `comm_msg` and `comm_close` from shell through
+//! [`FileTransferHandler::handle`] and publish the messages it returns on
+//! iopub. Messages for other comms come back empty.
+//!
+//! Paths are resolved against the handler's root and may not leave it, through
+//! `..`, an absolute path or a symlink. An upload that fails part way is
+//! removed rather than left half written.
+//!
+//! ```rust
+//! use runtimelib::file_transfer::FileUpload;
+//! use runtimelib::kernel::FileTransferHandler;
+//!
+//! # let root = std::env::temp_dir().join(format!("runtimelib-doc-{}", uuid::Uuid::new_v4()));
+//! # std::fs::create_dir_all(&root).unwrap();
+//! let mut handler = FileTransferHandler::new(&root);
+//! let upload = FileUpload::new("data/iris.csv", b"sepal_length\n5.1\n".to_vec());
+//!
+//! let mut replies = Vec::new();
+//! for message in upload.messages() {
+//!     replies.extend(handler.handle(&message));
+//! }
+//! assert_eq!(upload.handle(&replies[0]).unwrap(), Some(17));
+//! # std::fs::remove_dir_all(&root).unwrap();
+//! ```
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use jupyter_protocol::{CommId, CommMsg, JupyterMessage, JupyterMessageContent};
+
+use crate::file_transfer::{FileTransferMessage, FILE_TRANSFER_TARGET};
+
+/// An upload that is still receiving chunks.
+#[derive(Debug)]
+struct Upload {
+    /// The path as the client sent it, for the `done` reply
+    path: String,
+    full_path: PathBuf,
+    file: File,
+    size: u64,
+    written: u64,
+    next_seq: u64,
+}
+
+/// Answers file transfer comms for a kernel, relative to one directory.
+#[derive(Debug)]
+pub struct FileTransferHandler {
+    root: PathBuf,
+    uploads: HashMap<CommId, Upload>,
+}
+
+impl FileTransferHandler {
+    /// A handler that reads and writes files under `root`, usually the
+    /// kernel's working directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            uploads: HashMap::new(),
+        }
+    }
+
+    /// Uploads that have been opened but haven't received their final chunk.
+    pub fn active_uploads(&self) -> usize {
+        self.uploads.len()
+    }
+
+    /// Handle a message from shell, returning the messages to publish on
+    /// iopub in reply.
+    pub fn handle(&mut self, message: &JupyterMessage) -> Vec<JupyterMessage> {
+        let (comm_id, result) = match &message.content {
+            JupyterMessageContent::CommOpen(open) if open.target_name == FILE_TRANSFER_TARGET => (
+                &open.comm_id,
+                FileTransferMessage::from_data(&open.data)
+                    .and_then(|transfer| self.open(&open.comm_id, transfer)),
+            ),
+            JupyterMessageContent::CommMsg(comm_msg)
+                if self.uploads.contains_key(&comm_msg.comm_id) =>
+            {
+                (
+                    &comm_msg.comm_id,
+                    FileTransferMessage::from_data(&comm_msg.data).and_then(|transfer| {
+                        self.receive(&comm_msg.comm_id, transfer, &message.buffers)
+                    }),
+                )
+            }
+            JupyterMessageContent::CommClose(close) => {
+                // The client gave up, so there's nobody to tell
+                self.abort(&close.comm_id);
+                return Vec::new();
+            }
+            _ => return Vec::new(),
+        };
+
+        let replies = match result {
+            Ok(replies) => replies,
+            Err(error) => {
+                self.abort(comm_id);
+                vec![(
+                    FileTransferMessage::Error {
+                        message: format!("{:#}", error),
+                    },
+                    None,
+                )]
+            }
+        };
+
+        replies
+            .into_iter()
+            .map(|(transfer, buffer)| {
+                let reply = JupyterMessage::new(
+                    CommMsg {
+                        comm_id: comm_id.clone(),
+                        data: transfer.to_data(),
+                    },
+                    Some(message),
+                );
+                match buffer {
+                    Some(buffer) => reply.with_buffers(vec![buffer]),
+                    None => reply,
+                }
+            })
+            .collect()
+    }
+
+    fn open(
+        &mut self,
+        comm_id: &CommId,
+        transfer: FileTransferMessage,
+    ) -> Result<Vec<(FileTransferMessage, Option<Bytes>)>> {
+        match transfer {
+            FileTransferMessage::Upload { path, size, .. } => {
+                let full_path = self.resolve(&path)?;
+                // Check what already exists before creating anything, since
+                // both the directories and the file itself could be symlinks
+                let existing = full_path
+                    .ancestors()
+                    .find(|ancestor| ancestor.symlink_metadata().is_ok())
+                    .unwrap_or(&self.root);
+                self.check_inside_root(&path, existing)?;
+                if let Some(parent) = full_path.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create the directory for {}", path))?;
+                }
+                let file = File::create(&full_path)
+                    .with_context(|| format!("Failed to create {}", path))?;
+                self.uploads.insert(
+                    comm_id.clone(),
+                    Upload {
+                        path,
+                        full_path,
+                        file,
+                        size,
+                        written: 0,
+                        next_seq: 0,
+                    },
+                );
+                Ok(Vec::new())
+            }
+            FileTransferMessage::Download { path, chunk_size } => {
+                let full_path = self.resolve(&path)?;
+                self.check_inside_root(&path, &full_path)?;
+                let contents = Bytes::from(
+                    fs::read(&full_path).with_context(|| format!("Failed to read {}", path))?,
+                );
+
+                // An empty file is still sent as a single, empty, final chunk
+                let chunk_size = chunk_size.max(1);
+                let chunk_count = contents.len().div_ceil(chunk_size).max(1);
+                Ok((0..chunk_count)
+                    .map(|seq| {
+                        let start = seq * chunk_size;
+                        let end = (start + chunk_size).min(contents.len());
+                        (
+                            FileTransferMessage::Chunk {
+                                seq: seq as u64,
+                                last: seq + 1 == chunk_count,
+                            },
+                            Some(contents.slice(start..end)),
+                        )
+                    })
+                    .collect())
+            }
+            other => bail!("Expected an upload or download, got {:?}", other),
+        }
+    }
+
+    fn receive(
+        &mut self,
+        comm_id: &CommId,
+        transfer: FileTransferMessage,
+        buffers: &[Bytes],
+    ) -> Result<Vec<(FileTransferMessage, Option<Bytes>)>> {
+        let upload = self
+            .uploads
+            .get_mut(comm_id)
+            .ok_or_else(|| anyhow!("No upload is open on comm {}", comm_id.0))?;
+        let FileTransferMessage::Chunk { seq, last } = transfer else {
+            bail!("Expected a chunk, got {:?}", transfer);
+        };
+        if seq != upload.next_seq {
+            bail!("Received chunk {} but expected {}", seq, upload.next_seq);
+        }
+        let chunk = buffers
+            .first()
+            .ok_or_else(|| anyhow!("Chunk {} has no buffer", seq))?;
+        upload.written += chunk.len() as u64;
+        if upload.written > upload.size {
+            bail!("Received more than the {} bytes announced", upload.size);
+        }
+        upload
+            .file
+            .write_all(chunk)
+            .with_context(|| format!("Failed to write {}", upload.path))?;
+        upload.next_seq += 1;
+
+        if !last {
+            return Ok(Vec::new());
+        }
+        if upload.written != upload.size {
+            bail!(
+                "Received {} bytes but {} were announced",
+                upload.written,
+                upload.size
+            );
+        }
+        upload
+            .file
+            .sync_all()
+            .with_context(|| format!("Failed to write {}", upload.path))?;
+
+        let upload = self.uploads.remove(comm_id).expect("the upload was found");
+        Ok(vec![(
+            FileTransferMessage::Done {
+                path: upload.path,
+                size: upload.written,
+            },
+            None,
+        )])
+    }
+
+    /// Drop an unfinished upload along with what it wrote so far.
+    fn abort(&mut self, comm_id: &CommId) {
+        if let Some(upload) = self.uploads.remove(comm_id) {
+            drop(upload.file);
+            fs::remove_file(&upload.full_path).ok();
+        }
+    }
+
+    /// `path` under the root, refusing paths that are absolute or climb out.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path);
+        let mut resolved = self.root.clone();
+        let mut has_name = false;
+        for component in relative.components() {
+            match component {
+                Component::Normal(name) => {
+                    resolved.push(name);
+                    has_name = true;
+                }
+                Component::CurDir => {}
+                _ => bail!("{} is outside the working directory", path),
+            }
+        }
+        if !has_name {
+            bail!("{} is not a file path", path);
+        }
+        Ok(resolved)
+    }
+
+    /// Fail if `existing`, once symlinks are followed, isn't under the root.
+    fn check_inside_root(&self, path: &str, existing: &Path) -> Result<()> {
+        let root = self
+            .root
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", self.root.display()))?;
+        let existing = existing
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", path))?;
+        if !existing.starts_with(&root) {
+            bail!("{} is outside the working directory", path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::{FileDownload, FileUpload};
+    use jupyter_protocol::CommClose;
+
+    fn temp_root() -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("runtimelib-file-transfer-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn upload(handler: &mut FileTransferHandler, upload: &FileUpload) -> Vec<JupyterMessage> {
+        upload
+            .messages()
+            .iter()
+            .flat_map(|message| handler.handle(message))
+            .collect()
+    }
+
+    #[test]
+    fn test_upload_then_download() {
+        let root = temp_root();
+        let mut handler = FileTransferHandler::new(&root);
+
+        let contents = b"a,b\n1,2\n3,4\n".to_vec();
+        let file_upload = FileUpload::new("data/table.csv", contents.clone()).with_chunk_size(5);
+        let replies = upload(&mut handler, &file_upload);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(file_upload.handle(&replies[0]).unwrap(), Some(12));
+        assert_eq!(fs::read(root.join("data/table.csv")).unwrap(), contents);
+        assert_eq!(handler.active_uploads(), 0);
+
+        let mut download = FileDownload::new("./data/table.csv").with_chunk_size(5);
+        let chunks = handler.handle(&download.open_message());
+        assert_eq!(chunks.len(), 3);
+        let mut downloaded = None;
+        for chunk in &chunks {
+            downloaded = download.handle(chunk).unwrap();
+        }
+        assert_eq!(downloaded.as_deref(), Some(&contents[..]));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_paths_stay_in_the_root() {
+        let root = temp_root();
+        let mut handler = FileTransferHandler::new(root.join("work"));
+        fs::create_dir_all(root.join("work")).unwrap();
+
+        for path in ["../escaped.txt", "/tmp/escaped.txt", "", "."] {
+            let file_upload = FileUpload::new(path, b"nope".to_vec());
+            let replies = upload(&mut handler, &file_upload);
+            assert!(file_upload.handle(&replies[0]).is_err(), "{path:?}");
+        }
+        assert!(!root.join("escaped.txt").exists());
+
+        let mut download = FileDownload::new("../secret.txt");
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        let replies = handler.handle(&download.open_message());
+        assert!(download.handle(&replies[0]).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&root, root.join("work/link")).unwrap();
+            let mut download = FileDownload::new("link/secret.txt");
+            let replies = handler.handle(&download.open_message());
+            assert!(download.handle(&replies[0]).is_err());
+
+            let file_upload = FileUpload::new("link/escaped.txt", b"nope".to_vec());
+            let replies = upload(&mut handler, &file_upload);
+            assert!(file_upload.handle(&replies[0]).is_err());
+            assert!(!root.join("escaped.txt").exists());
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_failed_uploads_are_removed() {
+        let root = temp_root();
+        let mut handler = FileTransferHandler::new(&root);
+
+        // The second chunk is skipped
+        let file_upload = FileUpload::new("partial.bin", b"0123456789".to_vec()).with_chunk_size(4);
+        let messages = file_upload.messages();
+        assert!(handler.handle(&messages[0]).is_empty());
+        assert!(handler.handle(&messages[1]).is_empty());
+        assert!(root.join("partial.bin").exists());
+        let replies = handler.handle(&messages[3]);
+        assert!(file_upload.handle(&replies[0]).is_err());
+        assert!(!root.join("partial.bin").exists());
+        assert_eq!(handler.active_uploads(), 0);
+
+        // The client closes the comm part way
+        let file_upload = FileUpload::new("closed.bin", b"0123456789".to_vec()).with_chunk_size(4);
+        let messages = file_upload.messages();
+        handler.handle(&messages[0]);
+        handler.handle(&messages[1]);
+        let close = JupyterMessage::from(CommClose {
+            comm_id: file_upload.comm_id().clone(),
+            data: Default::default(),
+        });
+        assert!(handler.handle(&close).is_empty());
+        assert!(!root.join("closed.bin").exists());
+        assert_eq!(handler.active_uploads(), 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
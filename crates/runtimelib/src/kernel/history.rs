@@ -0,0 +1,558 @@
+//! Input and output history that answers `history_request`s like IPython does.
+//!
+//! Each time a [`HistoryStore`] is opened it starts a new session, numbered
+//! one past the last session in the database. Kernels record each execution's
+//! input and, once it's known, its output. [`HistoryStore::reply`] then
+//! answers `range`, `tail` and `search` requests, honoring `unique` and `output`.
+//!
+//! With the `sqlite-history` feature, history persists in a SQLite database per
+//! kernel, with the same `sessions`, `history` and `output_history` tables as
+//! IPython's `history.sqlite`. Recording a line again replaces it in place, and
+//! requests are answered by querying the database rather than loading it.
+//! Several kernels can share a database: SQLite allocates each one its own
+//! session. Only the latest [`MAX_SESSIONS`] sessions are kept.
+//!
+//! ```rust
+//! use jupyter_protocol::{HistoryEntry, HistoryRequest};
+//! use runtimelib::kernel::HistoryStore;
+//!
+//! let mut history = HistoryStore::in_memory();
+//! history.record_input(1, "x = 1").unwrap();
+//! history.record_input(2, "x + 1").unwrap();
+//! history.record_output(2, "2").unwrap();
+//!
+//! let reply = history.reply(&HistoryRequest::Tail { n: 1, output: true, raw: true });
+//! assert!(matches!(
+//!     &reply.history[..],
+//!     [HistoryEntry::InputOutput(1, 2, (input, output))] if input == "x + 1" && output == "2"
+//! ));
+//! ```
+#[cfg(feature = "sqlite-history")]
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use jupyter_protocol::{HistoryEntry, HistoryReply, HistoryRequest};
+#[cfg(feature = "sqlite-history")]
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "sqlite-history")]
+use crate::dirs::user_data_dir;
+
+/// How many sessions a database keeps. Opening a store forgets older ones.
+pub const MAX_SESSIONS: usize = 1000;
+
+/// One execution in the history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRecord {
+    pub session: usize,
+    /// The execution count of the input.
+    pub line: usize,
+    pub input: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+impl HistoryRecord {
+    fn to_entry(&self, output: bool) -> HistoryEntry {
+        if output {
+            HistoryEntry::InputOutput(
+                self.session,
+                self.line,
+                (self.input.clone(), self.output.clone().unwrap_or_default()),
+            )
+        } else {
+            HistoryEntry::Input(self.session, self.line, self.input.clone())
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Backend {
+    /// Ordered by session, then line.
+    Memory(Vec<HistoryRecord>),
+    #[cfg(feature = "sqlite-history")]
+    Sqlite(Connection),
+}
+
+/// A kernel's execution history across sessions.
+#[derive(Debug)]
+pub struct HistoryStore {
+    backend: Backend,
+    session: usize,
+}
+
+impl HistoryStore {
+    /// History that only lasts as long as the store, starting at session 1.
+    pub fn in_memory() -> Self {
+        Self {
+            backend: Backend::Memory(Vec::new()),
+            session: 1,
+        }
+    }
+
+    /// Open the history database at `path`, creating it if needed, and start
+    /// a new session in it.
+    #[cfg(feature = "sqlite-history")]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let db = Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+        // Other kernels may be writing to the same database
+        db.busy_timeout(std::time::Duration::from_secs(5))?;
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS sessions
+             (
+                 session integer PRIMARY KEY AUTOINCREMENT,
+                 start timestamp,
+                 end timestamp,
+                 num_cmds integer,
+                 remark text
+             );
+             CREATE TABLE IF NOT EXISTS history
+             (
+                 session integer,
+                 line integer,
+                 source text,
+                 source_raw text,
+                 PRIMARY KEY (session, line)
+             );
+             CREATE TABLE IF NOT EXISTS output_history
+             (
+                 session integer,
+                 line integer,
+                 output text,
+                 PRIMARY KEY (session, line)
+             );",
+        )
+        .context("Failed to create history tables")?;
+
+        // AUTOINCREMENT never hands out a session twice, even across kernels
+        db.execute(
+            "INSERT INTO sessions (start) VALUES (?1)",
+            params![
+                chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now())
+                    .naive_utc()
+                    .to_string()
+            ],
+        )?;
+        let session = db.last_insert_rowid() as usize;
+        let oldest = session.saturating_sub(MAX_SESSIONS - 1);
+        db.execute_batch(&format!(
+            "DELETE FROM sessions WHERE session < {oldest};
+             DELETE FROM history WHERE session < {oldest};
+             DELETE FROM output_history WHERE session < {oldest};"
+        ))?;
+
+        Ok(Self {
+            backend: Backend::Sqlite(db),
+            session,
+        })
+    }
+
+    /// Where the history for `kernel_name` lives by default, in the Jupyter data directory.
+    #[cfg(feature = "sqlite-history")]
+    pub fn default_path(kernel_name: &str) -> Result<PathBuf> {
+        Ok(user_data_dir()?
+            .join("history")
+            .join(format!("{}.sqlite", kernel_name)))
+    }
+
+    /// The number of the current session.
+    pub fn session(&self) -> usize {
+        self.session
+    }
+
+    /// Record the input executed as `line` in the current session.
+    pub fn record_input(&mut self, line: usize, input: impl Into<String>) -> Result<()> {
+        let record = HistoryRecord {
+            session: self.session,
+            line,
+            input: input.into(),
+            output: None,
+        };
+        match &mut self.backend {
+            Backend::Memory(records) => insert(records, record),
+            #[cfg(feature = "sqlite-history")]
+            Backend::Sqlite(db) => {
+                db.execute(
+                    "INSERT OR REPLACE INTO history (session, line, source, source_raw)
+                     VALUES (?1, ?2, ?3, ?3)",
+                    params![record.session, record.line, record.input],
+                )?;
+                db.execute(
+                    "DELETE FROM output_history WHERE session = ?1 AND line = ?2",
+                    params![record.session, record.line],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the output of `line` in the current session, if its input was recorded.
+    pub fn record_output(&mut self, line: usize, output: impl Into<String>) -> Result<()> {
+        let session = self.session;
+        match &mut self.backend {
+            Backend::Memory(records) => {
+                if let Some(record) = records
+                    .iter_mut()
+                    .find(|record| record.session == session && record.line == line)
+                {
+                    record.output = Some(output.into());
+                }
+            }
+            #[cfg(feature = "sqlite-history")]
+            Backend::Sqlite(db) => {
+                db.execute(
+                    "INSERT OR REPLACE INTO output_history (session, line, output)
+                     SELECT session, line, ?3 FROM history WHERE session = ?1 AND line = ?2",
+                    params![session, line, output.into()],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lines `start` up to but not including `stop` of `session`. `stop` of 0
+    /// means the end of the session. Sessions of 0 or less count back from the
+    /// current one, so 0 is the current session and -1 the one before.
+    pub fn range(&self, session: Option<i32>, start: i32, stop: i32) -> Result<Vec<HistoryRecord>> {
+        let session = match session.unwrap_or(0) {
+            session if session <= 0 => self.session as i64 + session as i64,
+            session => session as i64,
+        };
+        match &self.backend {
+            Backend::Memory(records) => Ok(records
+                .iter()
+                .filter(|record| record.session as i64 == session)
+                .filter(|record| record.line as i64 >= start as i64)
+                .filter(|record| stop <= 0 || (record.line as i64) < stop as i64)
+                .cloned()
+                .collect()),
+            #[cfg(feature = "sqlite-history")]
+            Backend::Sqlite(db) => query(
+                db,
+                "WHERE session = ?1 AND line >= ?2 AND (?3 <= 0 OR line < ?3)
+                 ORDER BY session, line",
+                params![session, start, stop],
+            ),
+        }
+    }
+
+    /// The last `n` records across all sessions.
+    pub fn tail(&self, n: usize) -> Result<Vec<HistoryRecord>> {
+        match &self.backend {
+            Backend::Memory(records) => Ok(records[records.len().saturating_sub(n)..].to_vec()),
+            #[cfg(feature = "sqlite-history")]
+            Backend::Sqlite(db) => {
+                let mut records = query(
+                    db,
+                    "ORDER BY session DESC, line DESC LIMIT ?1",
+                    params![n as i64],
+                )?;
+                records.reverse();
+                Ok(records)
+            }
+        }
+    }
+
+    /// Records whose input matches the glob `pattern`, like `*import*`. With
+    /// `unique`, only the latest record of each distinct input is kept.
+    pub fn search(&self, pattern: &str, unique: bool) -> Result<Vec<HistoryRecord>> {
+        let glob = glob::Pattern::new(pattern)
+            .with_context(|| format!("Invalid history search pattern {:?}", pattern))?;
+        let mut matches: Vec<HistoryRecord> = match &self.backend {
+            Backend::Memory(records) => records
+                .iter()
+                .filter(|record| glob.matches(&record.input))
+                .cloned()
+                .collect(),
+            #[cfg(feature = "sqlite-history")]
+            Backend::Sqlite(db) => query(
+                db,
+                "WHERE source GLOB ?1 ORDER BY session, line",
+                params![pattern],
+            )?,
+        };
+        if unique {
+            let mut seen = std::collections::HashSet::new();
+            matches.reverse();
+            matches.retain(|record| seen.insert(record.input.clone()));
+            matches.reverse();
+        }
+        Ok(matches)
+    }
+
+    /// Answer a `history_request`.
+    pub fn reply(&self, request: &HistoryRequest) -> HistoryReply {
+        let (records, output) = match request {
+            HistoryRequest::Range {
+                session,
+                start,
+                stop,
+                output,
+                ..
+            } => (self.range(*session, *start, *stop), *output),
+            HistoryRequest::Tail { n, output, .. } => (self.tail((*n).max(0) as usize), *output),
+            HistoryRequest::Search {
+                pattern,
+                unique,
+                output,
+                ..
+            } => (self.search(pattern, *unique), *output),
+        };
+        match records {
+            Ok(records) => HistoryReply::new(
+                records
+                    .iter()
+                    .map(|record| record.to_entry(output))
+                    .collect(),
+            ),
+            // An invalid pattern matches nothing
+            Err(_) => HistoryReply::default(),
+        }
+    }
+}
+
+fn insert(records: &mut Vec<HistoryRecord>, record: HistoryRecord) {
+    let key = (record.session, record.line);
+    match records.binary_search_by_key(&key, |record| (record.session, record.line)) {
+        Ok(index) => records[index] = record,
+        Err(index) => records.insert(index, record),
+    }
+}
+
+/// The history records selected by `clauses`, with their outputs.
+#[cfg(feature = "sqlite-history")]
+fn query(
+    db: &Connection,
+    clauses: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<HistoryRecord>> {
+    let mut statement = db.prepare_cached(&format!(
+        "SELECT session, line, source, output FROM history
+         LEFT JOIN output_history USING (session, line) {clauses}"
+    ))?;
+    let records = statement
+        .query_map(params, |row| {
+            Ok(HistoryRecord {
+                session: row.get(0)?,
+                line: row.get(1)?,
+                input: row.get(2)?,
+                output: row.get(3)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(records: Result<Vec<HistoryRecord>>) -> Vec<(usize, usize, String)> {
+        records
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.session, record.line, record.input))
+            .collect()
+    }
+
+    fn expected(records: &[(usize, usize, &str)]) -> Vec<(usize, usize, String)> {
+        records
+            .iter()
+            .map(|&(session, line, input)| (session, line, input.to_string()))
+            .collect()
+    }
+
+    #[cfg(feature = "sqlite-history")]
+    fn history_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "runtimelib-history-{}-{}.sqlite",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[cfg(feature = "sqlite-history")]
+    #[test]
+    fn test_sessions_persist() {
+        let path = history_file("sessions");
+
+        let mut history = HistoryStore::open(&path).unwrap();
+        assert_eq!(history.session(), 1);
+        history.record_input(1, "import os").unwrap();
+        history.record_input(2, "os.getcwd()").unwrap();
+        history.record_output(2, "'/tmp'").unwrap();
+        drop(history);
+
+        let mut history = HistoryStore::open(&path).unwrap();
+        assert_eq!(history.session(), 2);
+        history.record_input(1, "print('hi')").unwrap();
+
+        assert_eq!(
+            inputs(history.range(None, 1, 0)),
+            expected(&[(2, 1, "print('hi')")])
+        );
+        assert_eq!(
+            inputs(history.range(Some(-1), 1, 0)),
+            expected(&[(1, 1, "import os"), (1, 2, "os.getcwd()")])
+        );
+        assert_eq!(
+            inputs(history.range(Some(1), 2, 3)),
+            expected(&[(1, 2, "os.getcwd()")])
+        );
+        assert_eq!(
+            history.range(Some(1), 2, 3).unwrap()[0].output.as_deref(),
+            Some("'/tmp'")
+        );
+        assert_eq!(
+            inputs(history.tail(2)),
+            expected(&[(1, 2, "os.getcwd()"), (2, 1, "print('hi')")])
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite-history")]
+    #[test]
+    fn test_concurrent_stores_get_their_own_sessions() {
+        let path = history_file("concurrent");
+
+        // Neither has recorded anything when the next one opens
+        let mut first = HistoryStore::open(&path).unwrap();
+        let mut second = HistoryStore::open(&path).unwrap();
+        assert_eq!((first.session(), second.session()), (1, 2));
+        first.record_input(1, "a = 1").unwrap();
+        second.record_input(1, "b = 2").unwrap();
+        drop((first, second));
+
+        let history = HistoryStore::open(&path).unwrap();
+        assert_eq!(history.session(), 3);
+        assert_eq!(
+            inputs(history.tail(2)),
+            expected(&[(1, 1, "a = 1"), (2, 1, "b = 2")])
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite-history")]
+    #[test]
+    fn test_database_replaces_lines_and_forgets_old_sessions() {
+        let path = history_file("replace");
+
+        let mut history = HistoryStore::open(&path).unwrap();
+        history.record_input(1, "x = 1").unwrap();
+        history.record_output(1, "None").unwrap();
+        history.record_input(1, "x = 2").unwrap();
+        history.record_output(2, "Never ran").unwrap();
+        assert_eq!(
+            history.tail(10).unwrap(),
+            [HistoryRecord {
+                session: 1,
+                line: 1,
+                input: "x = 2".to_string(),
+                output: None,
+            }]
+        );
+        drop(history);
+
+        for _ in 0..MAX_SESSIONS {
+            HistoryStore::open(&path).unwrap();
+        }
+        let history = HistoryStore::open(&path).unwrap();
+        assert_eq!(history.session(), MAX_SESSIONS + 2);
+        assert!(history.tail(10).unwrap().is_empty());
+        assert!(history.range(Some(2), 0, 0).unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite-history")]
+    #[test]
+    fn test_database_search() {
+        let path = history_file("search");
+
+        let mut history = HistoryStore::open(&path).unwrap();
+        history.record_input(1, "import os").unwrap();
+        history.record_input(2, "import sys").unwrap();
+        history.record_input(3, "import os").unwrap();
+        history.record_output(3, "None").unwrap();
+
+        assert_eq!(history.search("import*", false).unwrap().len(), 3);
+        assert_eq!(
+            history.search("import*", true).unwrap(),
+            [
+                HistoryRecord {
+                    session: 1,
+                    line: 2,
+                    input: "import sys".to_string(),
+                    output: None,
+                },
+                HistoryRecord {
+                    session: 1,
+                    line: 3,
+                    input: "import os".to_string(),
+                    output: Some("None".to_string()),
+                },
+            ]
+        );
+        assert!(history.search("[", false).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_search_unique() {
+        let mut history = HistoryStore::in_memory();
+        history.record_input(1, "import os").unwrap();
+        history.record_input(2, "import sys").unwrap();
+        history.record_input(3, "import os").unwrap();
+        history.record_input(4, "x = 1").unwrap();
+
+        assert_eq!(history.search("import*", false).unwrap().len(), 3);
+        assert_eq!(
+            inputs(history.search("import*", true)),
+            expected(&[(1, 2, "import sys"), (1, 3, "import os")])
+        );
+        assert_eq!(history.search("*os", false).unwrap().len(), 2);
+        assert!(history.search("[", false).is_err());
+    }
+
+    #[test]
+    fn test_reply_entries() {
+        let mut history = HistoryStore::in_memory();
+        history.record_input(1, "1 + 1").unwrap();
+        history.record_output(1, "2").unwrap();
+        history.record_input(2, "print('no result')").unwrap();
+
+        let reply = history.reply(&HistoryRequest::Range {
+            session: Some(0),
+            start: 0,
+            stop: 0,
+            output: true,
+            raw: true,
+        });
+        assert_eq!(
+            serde_json::to_value(&reply.history).unwrap(),
+            serde_json::json!([[1, 1, ["1 + 1", "2"]], [1, 2, ["print('no result')", ""]]])
+        );
+
+        let reply = history.reply(&HistoryRequest::Search {
+            pattern: "print*".to_string(),
+            unique: false,
+            output: false,
+            raw: true,
+        });
+        assert_eq!(
+            serde_json::to_value(&reply.history).unwrap(),
+            serde_json::json!([[1, 2, "print('no result')"]])
+        );
+    }
+}
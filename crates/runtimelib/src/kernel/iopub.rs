@@ -0,0 +1,244 @@
+//! Publishing a kernel's iopub messages, with the startup signals frontends
+//! wait for.
+//!
+//! A client can't tell when its iopub subscription takes effect, and
+//! anything published before then is lost. Some frontends hold their first
+//! request until the kernel says it's there, either with a `starting` status
+//! or with the `iopub_welcome` message proposed for protocol 5.4.
+//! [`IopubPublisher`] sends both, as set in [`IopubOptions`].
+//!
+//! zeromq's `PUB` socket reports new connections but not subscriptions, so
+//! the welcome goes to every subscriber, a few times over the second after
+//! each client connects. Clients ignore welcomes they weren't waiting for.
+//!
+//! ```rust,no_run
+//! use jupyter_protocol::{ConnectionInfo, Session, StreamContent};
+//! use runtimelib::kernel::{IopubOptions, IopubPublisher};
+//!
+//! # async fn example(connection_info: ConnectionInfo) -> runtimelib::error::Result<()> {
+//! let session = Session::new();
+//! let iopub = IopubPublisher::bind(&connection_info, &session, IopubOptions::default()).await?;
+//!
+//! // Clones publish on the same socket
+//! let stdout = iopub.clone();
+//! stdout.send(StreamContent::stdout("ready\n").into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::time::Duration;
+
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+use jupyter_protocol::{ConnectionInfo, IopubWelcome, JupyterMessage, Session, Status};
+use zeromq::{Socket, SocketEvent};
+
+use crate::connection::{create_kernel_iopub_connection, KernelIoPubConnection};
+use crate::error::{Result, RuntimeError};
+
+/// After a client connects, send welcomes this long after the previous one.
+/// Its subscription usually lands within the first few milliseconds.
+const WELCOME_DELAYS: [Duration; 3] = [
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_millis(1000),
+];
+
+/// Which startup signals an [`IopubPublisher`] sends. Both are on by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IopubOptions {
+    /// Publish a `starting` status as soon as the socket is bound.
+    pub send_starting: bool,
+    /// Publish `iopub_welcome` after clients connect.
+    pub send_welcome: bool,
+}
+
+impl Default for IopubOptions {
+    fn default() -> Self {
+        Self {
+            send_starting: true,
+            send_welcome: true,
+        }
+    }
+}
+
+impl IopubOptions {
+    pub fn with_starting(mut self, send_starting: bool) -> Self {
+        self.send_starting = send_starting;
+        self
+    }
+
+    pub fn with_welcome(mut self, send_welcome: bool) -> Self {
+        self.send_welcome = send_welcome;
+        self
+    }
+}
+
+type Outgoing = (JupyterMessage, oneshot::Sender<Result<()>>);
+
+/// A kernel's iopub socket, published on from a background task so that
+/// welcomes go out while the kernel is busy. Clone it to publish from
+/// several tasks; the socket closes when the last clone is dropped.
+#[derive(Clone)]
+pub struct IopubPublisher {
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+    session: Session,
+}
+
+impl IopubPublisher {
+    /// Bind the kernel's iopub socket and start publishing on it.
+    pub async fn bind(
+        connection_info: &ConnectionInfo,
+        session: &Session,
+        options: IopubOptions,
+    ) -> Result<Self> {
+        let connection = create_kernel_iopub_connection(connection_info, session).await?;
+        Self::start(connection, options).await
+    }
+
+    /// Start publishing on an iopub connection that's already bound. Clients
+    /// that connected before this aren't welcomed.
+    pub async fn start(
+        mut connection: KernelIoPubConnection,
+        options: IopubOptions,
+    ) -> Result<Self> {
+        let connections = connection.socket.monitor();
+        let session = connection.session.clone();
+        if options.send_starting {
+            connection.send(session.message(Status::starting())).await?;
+        }
+
+        let (outgoing, requests) = mpsc::unbounded();
+        crate::runtime::spawn(publish(
+            connection,
+            connections,
+            requests,
+            options.send_welcome,
+        ));
+        Ok(Self { outgoing, session })
+    }
+
+    /// The session messages are published in. Build them with
+    /// [`Session::message`] or [`Session::reply_to`] to take their dates from
+    /// its clock.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Publish `message`, returning once the socket has taken it.
+    pub async fn send(&self, message: JupyterMessage) -> Result<()> {
+        let (sent, result) = oneshot::channel();
+        self.outgoing
+            .unbounded_send((message, sent))
+            .map_err(|_| stopped())?;
+        result.await.map_err(|_| stopped())?
+    }
+}
+
+fn stopped() -> RuntimeError {
+    RuntimeError::Io(std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        "The iopub publisher stopped",
+    ))
+}
+
+async fn publish(
+    mut connection: KernelIoPubConnection,
+    mut connections: mpsc::Receiver<SocketEvent>,
+    mut requests: mpsc::UnboundedReceiver<Outgoing>,
+    send_welcome: bool,
+) {
+    let (welcome_sender, mut welcomes) = mpsc::unbounded::<()>();
+    loop {
+        futures::select! {
+            request = requests.next() => match request {
+                Some((message, sent)) => {
+                    let _ = sent.send(connection.send(message).await);
+                }
+                // Every publisher was dropped
+                None => return,
+            },
+            event = connections.next() => {
+                if let Some(SocketEvent::Accepted(..)) = event {
+                    if send_welcome {
+                        crate::runtime::spawn(schedule_welcomes(welcome_sender.clone()));
+                    }
+                }
+            }
+            _ = welcomes.next() => {
+                // A failed welcome is no worse than none. If the socket is
+                // broken, the kernel's next send reports it.
+                let welcome = connection.session.message(IopubWelcome { subscription: String::new() });
+                let _ = connection.send(welcome).await;
+            }
+        }
+    }
+}
+
+async fn schedule_welcomes(welcomes: mpsc::UnboundedSender<()>) {
+    for delay in WELCOME_DELAYS {
+        futures_timer::Delay::new(delay).await;
+        if welcomes.unbounded_send(()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::local_connection_info;
+    use crate::connection::create_client_iopub_connection;
+    use crate::runtime::async_test;
+    use jupyter_protocol::{FixedClock, JupyterMessageContent, StreamContent};
+
+    #[async_test]
+    async fn test_late_subscriber_is_welcomed() {
+        let connection_info = local_connection_info().await;
+        let session = Session::new();
+        let iopub = IopubPublisher::bind(&connection_info, &session, IopubOptions::default())
+            .await
+            .unwrap();
+
+        // Nobody was subscribed to see `starting`, so the welcome comes first
+        let mut client = create_client_iopub_connection(&connection_info, "", &session)
+            .await
+            .unwrap();
+        let welcome = client.read().await.unwrap();
+        assert_eq!(welcome.header.msg_type, "iopub_welcome");
+        assert!(matches!(
+            welcome.content,
+            JupyterMessageContent::IopubWelcome(IopubWelcome { ref subscription }) if subscription.is_empty()
+        ));
+
+        // Messages still get through, from any clone
+        iopub
+            .clone()
+            .send(StreamContent::stdout("hi").into())
+            .await
+            .unwrap();
+        loop {
+            let message = client.read().await.unwrap();
+            if let JupyterMessageContent::StreamContent(stream) = message.content {
+                assert_eq!(stream.text, "hi");
+                break;
+            }
+        }
+    }
+
+    #[async_test]
+    async fn test_startup_messages_use_the_session_clock() {
+        let connection_info = local_connection_info().await;
+        let date = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let session = Session::new().with_clock(FixedClock(date));
+        let _iopub = IopubPublisher::bind(&connection_info, &session, IopubOptions::default())
+            .await
+            .unwrap();
+
+        let mut client = create_client_iopub_connection(&connection_info, "", &Session::new())
+            .await
+            .unwrap();
+        let welcome = client.read().await.unwrap();
+        assert_eq!(welcome.header.msg_type, "iopub_welcome");
+        assert_eq!(welcome.header.date, date);
+    }
+}
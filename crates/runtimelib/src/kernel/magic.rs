@@ -0,0 +1,602 @@
+//! Declarative `%magic` commands for kernels.
+//!
+//! Kernels declare each magic's flags, options and arguments once in a
+//! [`MagicRegistry`], along with an async handler. The registry parses cells
+//! that start with `%`, reports unknown magics and bad arguments, answers
+//! `%help` from the declarations and completes magic names and options.
+//!
+//! ```rust
+//! use anyhow::Result;
+//! use futures::future::BoxFuture;
+//! use jupyter_protocol::{ExecuteRequest, JupyterMessage};
+//! use runtimelib::kernel::magic::{Dispatch, MagicCall, MagicRegistry, MagicSpec};
+//!
+//! struct Kernel {
+//!     model: String,
+//! }
+//!
+//! fn use_model<'a>(
+//!     kernel: &'a mut Kernel,
+//!     call: MagicCall,
+//!     _parent: &'a JupyterMessage,
+//! ) -> BoxFuture<'a, Result<()>> {
+//!     Box::pin(async move {
+//!         kernel.model = call.value("name").unwrap_or_default().to_string();
+//!         Ok(())
+//!     })
+//! }
+//!
+//! # futures::executor::block_on(async {
+//! let mut magics = MagicRegistry::new();
+//! magics.register(
+//!     MagicSpec::new("use", "Switch to another model").arg("name", "The model to use"),
+//!     use_model,
+//! );
+//!
+//! let mut kernel = Kernel { model: "llama3".into() };
+//! let parent = JupyterMessage::new(ExecuteRequest::new("%use gemma".into()), None);
+//! let dispatch = magics.dispatch(&mut kernel, "%use gemma", &parent).await.unwrap();
+//! assert!(matches!(dispatch, Dispatch::Ran));
+//! assert_eq!(kernel.model, "gemma");
+//!
+//! let Dispatch::Help(help) = magics.dispatch(&mut kernel, "%help", &parent).await.unwrap() else {
+//!     unreachable!()
+//! };
+//! assert!(help.contains("* **`%use NAME`**: Switch to another model"));
+//! # });
+//! ```
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{anyhow, bail, Result};
+use futures::future::BoxFuture;
+use jupyter_protocol::cursor::{convert_offset, CursorUnit};
+use jupyter_protocol::{CompleteReply, JupyterMessage, ReplyStatus};
+
+/// A flag, option or argument a magic accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagicArg {
+    /// `--name`, on or off.
+    Flag { name: String, help: String },
+    /// `--name VALUE` or `--name=VALUE`.
+    Option {
+        name: String,
+        value_name: String,
+        help: String,
+    },
+    /// A value given by position, in declaration order.
+    Positional {
+        name: String,
+        required: bool,
+        help: String,
+    },
+}
+
+/// What a magic is called and what it accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagicSpec {
+    pub name: String,
+    pub summary: String,
+    pub args: Vec<MagicArg>,
+    /// What the lines after the magic are for, if the magic uses them.
+    pub body: Option<String>,
+}
+
+impl MagicSpec {
+    pub fn new(name: impl Into<String>, summary: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            summary: summary.into(),
+            args: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub fn flag(mut self, name: impl Into<String>, help: impl Into<String>) -> Self {
+        self.args.push(MagicArg::Flag {
+            name: name.into(),
+            help: help.into(),
+        });
+        self
+    }
+
+    pub fn option(
+        mut self,
+        name: impl Into<String>,
+        value_name: impl Into<String>,
+        help: impl Into<String>,
+    ) -> Self {
+        self.args.push(MagicArg::Option {
+            name: name.into(),
+            value_name: value_name.into(),
+            help: help.into(),
+        });
+        self
+    }
+
+    /// A required positional argument.
+    pub fn arg(self, name: impl Into<String>, help: impl Into<String>) -> Self {
+        self.positional(name, true, help)
+    }
+
+    /// A positional argument that may be left out.
+    pub fn optional_arg(self, name: impl Into<String>, help: impl Into<String>) -> Self {
+        self.positional(name, false, help)
+    }
+
+    fn positional(
+        mut self,
+        name: impl Into<String>,
+        required: bool,
+        help: impl Into<String>,
+    ) -> Self {
+        self.args.push(MagicArg::Positional {
+            name: name.into(),
+            required,
+            help: help.into(),
+        });
+        self
+    }
+
+    /// Accept the rest of the cell after the magic's line, described by `help`.
+    pub fn body(mut self, help: impl Into<String>) -> Self {
+        self.body = Some(help.into());
+        self
+    }
+
+    /// The magic as it's typed, like `%model [--list] [--show NAME]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("%{}", self.name);
+        for arg in &self.args {
+            match arg {
+                MagicArg::Flag { name, .. } => usage += &format!(" [--{}]", name),
+                MagicArg::Option {
+                    name, value_name, ..
+                } => usage += &format!(" [--{} {}]", name, value_name),
+                MagicArg::Positional {
+                    name,
+                    required: true,
+                    ..
+                } => usage += &format!(" {}", name.to_uppercase()),
+                MagicArg::Positional { name, .. } => {
+                    usage += &format!(" [{}]", name.to_uppercase())
+                }
+            }
+        }
+        usage
+    }
+
+    fn find_named(&self, name: &str) -> Option<&MagicArg> {
+        self.args.iter().find(|arg| match arg {
+            MagicArg::Flag { name: arg_name, .. } | MagicArg::Option { name: arg_name, .. } => {
+                arg_name == name
+            }
+            MagicArg::Positional { .. } => false,
+        })
+    }
+
+    /// Parse the words after the magic's name, and the cell body below it.
+    pub fn parse(&self, line: &str, body: Option<&str>) -> Result<MagicCall> {
+        let mut call = MagicCall {
+            name: self.name.clone(),
+            flags: HashSet::new(),
+            values: HashMap::new(),
+            body: None,
+        };
+        let mut positionals = self.args.iter().filter_map(|arg| match arg {
+            MagicArg::Positional { name, .. } => Some(name),
+            _ => None,
+        });
+
+        let mut words = line.split_whitespace();
+        while let Some(word) = words.next() {
+            let Some(option) = word.strip_prefix("--") else {
+                let Some(name) = positionals.next() else {
+                    bail!("%{} got an unexpected argument {:?}", self.name, word);
+                };
+                call.values.insert(name.clone(), word.to_string());
+                continue;
+            };
+            let (option, inline_value) = match option.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (option, None),
+            };
+            match self.find_named(option) {
+                Some(MagicArg::Flag { name, .. }) if inline_value.is_none() => {
+                    call.flags.insert(name.clone());
+                }
+                Some(MagicArg::Option { name, .. }) => {
+                    let value = inline_value
+                        .or_else(|| words.next())
+                        .ok_or_else(|| anyhow!("%{} --{} needs a value", self.name, name))?;
+                    call.values.insert(name.clone(), value.to_string());
+                }
+                _ => bail!("%{} has no option --{}", self.name, option),
+            }
+        }
+
+        for arg in &self.args {
+            if let MagicArg::Positional {
+                name,
+                required: true,
+                ..
+            } = arg
+            {
+                if !call.values.contains_key(name) {
+                    bail!(
+                        "%{} is missing {}. Usage: {}",
+                        self.name,
+                        name,
+                        self.usage()
+                    );
+                }
+            }
+        }
+
+        let body = body.filter(|body| !body.trim().is_empty());
+        if body.is_some() && self.body.is_none() {
+            bail!("%{} doesn't take a cell body", self.name);
+        }
+        call.body = body.map(String::from);
+        Ok(call)
+    }
+}
+
+/// A parsed magic, ready for its handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagicCall {
+    pub name: String,
+    flags: HashSet<String>,
+    values: HashMap<String, String>,
+    /// The lines of the cell after the magic, if there were any.
+    pub body: Option<String>,
+}
+
+impl MagicCall {
+    /// Whether the flag `--name` was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// The value of an option or positional argument, if it was given.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+type Handler<C> = Box<
+    dyn for<'a> Fn(&'a mut C, MagicCall, &'a JupyterMessage) -> BoxFuture<'a, Result<()>>
+        + Send
+        + Sync,
+>;
+
+/// What [`MagicRegistry::dispatch`] did with a cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    /// The cell isn't a magic. Run it as code.
+    NotMagic,
+    /// The cell was `%help`. Display this Markdown.
+    Help(String),
+    /// A magic's handler ran.
+    Ran,
+}
+
+/// The magics a kernel supports, with a handler for each that gets mutable
+/// access to the kernel state `C`.
+pub struct MagicRegistry<C> {
+    magics: BTreeMap<String, (MagicSpec, Handler<C>)>,
+}
+
+impl<C> Default for MagicRegistry<C> {
+    fn default() -> Self {
+        Self {
+            magics: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C> MagicRegistry<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a magic, replacing any with the same name.
+    pub fn register<H>(&mut self, spec: MagicSpec, handler: H) -> &mut Self
+    where
+        H: for<'a> Fn(&'a mut C, MagicCall, &'a JupyterMessage) -> BoxFuture<'a, Result<()>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.magics
+            .insert(spec.name.clone(), (spec, Box::new(handler)));
+        self
+    }
+
+    pub fn spec(&self, name: &str) -> Option<&MagicSpec> {
+        self.magics.get(name).map(|(spec, _)| spec)
+    }
+
+    /// Markdown listing every magic with its usage, options and arguments.
+    pub fn help(&self) -> String {
+        let mut help = String::new();
+        for (spec, _) in self.magics.values() {
+            help += &format!("* **`{}`**: {}\n", spec.usage(), spec.summary);
+            for arg in &spec.args {
+                let (name, help_text) = match arg {
+                    MagicArg::Flag { name, help } => (format!("--{}", name), help),
+                    MagicArg::Option {
+                        name,
+                        value_name,
+                        help,
+                    } => (format!("--{} {}", name, value_name), help),
+                    MagicArg::Positional { name, help, .. } => (name.to_uppercase(), help),
+                };
+                if !help_text.is_empty() {
+                    help += &format!("  * `{}`: {}\n", name, help_text);
+                }
+            }
+            if let Some(body) = &spec.body {
+                help += &format!("  * Cell body: {}\n", body);
+            }
+        }
+        help += "* **`%help`**: Show this list\n";
+        help
+    }
+
+    /// Parse `code` as a magic and run its handler. `%help` and `%h` answer
+    /// with [`help`](Self::help); unknown magics and bad arguments are errors.
+    pub async fn dispatch(
+        &self,
+        context: &mut C,
+        code: &str,
+        parent: &JupyterMessage,
+    ) -> Result<Dispatch> {
+        let Some(magic) = code.trim_start().strip_prefix('%') else {
+            return Ok(Dispatch::NotMagic);
+        };
+        let (line, body) = match magic.split_once('\n') {
+            Some((line, body)) => (line, Some(body)),
+            None => (magic, None),
+        };
+        let (name, rest) = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((line.trim(), ""));
+
+        if matches!(name, "" | "h" | "help") && !self.magics.contains_key(name) {
+            return Ok(Dispatch::Help(self.help()));
+        }
+        let Some((spec, handler)) = self.magics.get(name) else {
+            bail!(
+                "Unknown magic %{}. Run %help to see the available magics",
+                name
+            );
+        };
+        let call = spec.parse(rest, body)?;
+        handler(context, call, parent).await?;
+        Ok(Dispatch::Ran)
+    }
+
+    /// Complete a magic name, or one of its options, at `cursor_pos`, which
+    /// counts characters like the protocol's `cursor_pos`. `None` if the
+    /// cursor isn't on the magic's line, so the kernel can complete its own
+    /// language instead.
+    pub fn complete(&self, code: &str, cursor_pos: usize) -> Option<CompleteReply> {
+        let cursor = convert_offset(code, cursor_pos, CursorUnit::Chars, CursorUnit::Bytes)?;
+        let before = &code[..cursor];
+        let line = before.trim_start();
+        let magic = line.strip_prefix('%')?;
+        if magic.contains('\n') {
+            return None;
+        }
+        let word_start = before
+            .rfind(char::is_whitespace)
+            .map_or(before.len() - line.len(), |index| index + 1);
+        let word = &before[word_start..];
+
+        let matches: Vec<String> = match magic.split_once(char::is_whitespace) {
+            None => self
+                .magics
+                .keys()
+                .map(String::as_str)
+                .chain(["help"])
+                .filter(|name| name.starts_with(magic))
+                .map(|name| format!("%{}", name))
+                .collect(),
+            Some((name, _)) if word.starts_with("--") => self
+                .spec(name)?
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    MagicArg::Flag { name, .. } | MagicArg::Option { name, .. } => {
+                        Some(format!("--{}", name))
+                    }
+                    MagicArg::Positional { .. } => None,
+                })
+                .filter(|option| option.starts_with(word))
+                .collect(),
+            Some(_) => return None,
+        };
+
+        Some(CompleteReply {
+            matches,
+            cursor_start: before[..word_start].chars().count(),
+            cursor_end: cursor_pos,
+            metadata: Default::default(),
+            status: ReplyStatus::Ok,
+            error: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::async_test;
+    use jupyter_protocol::ExecuteRequest;
+
+    #[derive(Default)]
+    struct State {
+        calls: Vec<MagicCall>,
+    }
+
+    fn record<'a>(
+        state: &'a mut State,
+        call: MagicCall,
+        _parent: &'a JupyterMessage,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            state.calls.push(call);
+            Ok(())
+        })
+    }
+
+    fn registry() -> MagicRegistry<State> {
+        let mut magics = MagicRegistry::new();
+        magics
+            .register(
+                MagicSpec::new("model", "Get the current model")
+                    .flag("list", "List the available models")
+                    .option("show", "NAME", "Show details for a model")
+                    .option("create", "NAME", "Create a model")
+                    .body("The Modelfile, for --create"),
+                record,
+            )
+            .register(
+                MagicSpec::new("use", "Switch models")
+                    .arg("name", "The model to use")
+                    .optional_arg("tag", ""),
+                record,
+            );
+        magics
+    }
+
+    fn parent() -> JupyterMessage {
+        JupyterMessage::new(ExecuteRequest::new(String::new()), None)
+    }
+
+    async fn dispatch(magics: &MagicRegistry<State>, code: &str) -> Result<MagicCall> {
+        let mut state = State::default();
+        magics.dispatch(&mut state, code, &parent()).await?;
+        Ok(state.calls.pop().unwrap())
+    }
+
+    #[async_test]
+    async fn test_parse_arguments() {
+        let magics = registry();
+
+        let call = dispatch(&magics, "%model --list").await.unwrap();
+        assert!(call.flag("list"));
+        assert_eq!(call.value("show"), None);
+
+        let call = dispatch(&magics, "%model --show=gemma").await.unwrap();
+        assert_eq!(call.value("show"), Some("gemma"));
+
+        let call = dispatch(&magics, "%model --create mine\nFROM llama3\n")
+            .await
+            .unwrap();
+        assert_eq!(call.value("create"), Some("mine"));
+        assert_eq!(call.body.as_deref(), Some("FROM llama3\n"));
+
+        let call = dispatch(&magics, "  %use gemma latest").await.unwrap();
+        assert_eq!(call.value("name"), Some("gemma"));
+        assert_eq!(call.value("tag"), Some("latest"));
+    }
+
+    #[async_test]
+    async fn test_errors() {
+        let magics = registry();
+        let error = |code: &'static str| {
+            let magics = &magics;
+            async move { dispatch(magics, code).await.unwrap_err().to_string() }
+        };
+
+        assert_eq!(
+            error("%nope").await,
+            "Unknown magic %nope. Run %help to see the available magics"
+        );
+        assert_eq!(
+            error("%model --bogus").await,
+            "%model has no option --bogus"
+        );
+        assert_eq!(error("%model --show").await, "%model --show needs a value");
+        assert_eq!(
+            error("%use").await,
+            "%use is missing name. Usage: %use NAME [TAG]"
+        );
+        assert_eq!(
+            error("%use a b c").await,
+            "%use got an unexpected argument \"c\""
+        );
+        assert_eq!(error("%use a\nbody").await, "%use doesn't take a cell body");
+    }
+
+    #[async_test]
+    async fn test_help_and_not_magic() {
+        let magics = registry();
+        let mut state = State::default();
+
+        let dispatch = magics
+            .dispatch(&mut state, "print('hi')", &parent())
+            .await
+            .unwrap();
+        assert_eq!(dispatch, Dispatch::NotMagic);
+
+        let Dispatch::Help(help) = magics
+            .dispatch(&mut state, "%help", &parent())
+            .await
+            .unwrap()
+        else {
+            panic!("Expected help");
+        };
+        assert_eq!(
+            help,
+            "* **`%model [--list] [--show NAME] [--create NAME]`**: Get the current model\n\
+             \x20 * `--list`: List the available models\n\
+             \x20 * `--show NAME`: Show details for a model\n\
+             \x20 * `--create NAME`: Create a model\n\
+             \x20 * Cell body: The Modelfile, for --create\n\
+             * **`%use NAME [TAG]`**: Switch models\n\
+             \x20 * `NAME`: The model to use\n\
+             * **`%help`**: Show this list\n"
+        );
+        assert!(state.calls.is_empty());
+    }
+
+    #[test]
+    fn test_complete() {
+        let magics = registry();
+
+        let reply = magics.complete("%mo", 3).unwrap();
+        assert_eq!(reply.matches, vec!["%model"]);
+        assert_eq!((reply.cursor_start, reply.cursor_end), (0, 3));
+
+        let reply = magics.complete("%", 1).unwrap();
+        assert_eq!(reply.matches, vec!["%model", "%use", "%help"]);
+
+        let reply = magics.complete("%model --s", 10).unwrap();
+        assert_eq!(reply.matches, vec!["--show"]);
+        assert_eq!(reply.cursor_start, 7);
+
+        assert!(magics.complete("%use gem", 8).is_none());
+        assert!(magics.complete("print(", 6).is_none());
+        assert!(magics.complete("%model\nFROM", 11).is_none());
+    }
+
+    #[test]
+    fn test_complete_counts_characters() {
+        let magics = registry();
+
+        // The cursor is after "--s", 12 characters in but 13 bytes
+        let reply = magics.complete("%model é --s", 12).unwrap();
+        assert_eq!(reply.matches, vec!["--show"]);
+        assert_eq!((reply.cursor_start, reply.cursor_end), (9, 12));
+        assert_eq!(
+            reply.replace_range("%model é --s", CursorUnit::Bytes),
+            Some(10..13)
+        );
+
+        // Past the end
+        assert!(magics.complete("%mé", 4).is_none());
+    }
+}
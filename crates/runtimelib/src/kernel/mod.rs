@@ -0,0 +1,34 @@
+//! Building blocks for writing kernels.
+//!
+//! The connection functions (`create_kernel_shell_connection` and friends)
+//! get a kernel onto the wire. The pieces here handle the protocol semantics
+//! most kernels would otherwise stub out or get subtly wrong.
+pub mod comm;
+pub use comm::CommRegistry;
+
+pub mod file_transfer;
+pub use file_transfer::FileTransferHandler;
+
+pub mod history;
+pub use history::{HistoryRecord, HistoryStore};
+
+pub mod magic;
+pub use magic::{MagicCall, MagicRegistry, MagicSpec};
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod iopub;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use iopub::{IopubOptions, IopubPublisher};
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod execution;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use execution::ExecutionCounter;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod stdin;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use stdin::StdinBridge;
+
+pub mod shutdown;
+pub use shutdown::{Shutdown, ShutdownHooks};
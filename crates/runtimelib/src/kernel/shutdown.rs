@@ -0,0 +1,210 @@
+//! Shutting a kernel down cleanly, instead of waiting for the frontend to
+//! kill it.
+//!
+//! Frontends ask a kernel to stop with a `shutdown_request` on control, then
+//! give it a few seconds before killing the process; Jupyter servers and
+//! process managers send SIGTERM. Either way, a kernel holding conversations,
+//! database connections or buffered files gets one chance to put them away.
+//! [`ShutdownHooks`] runs the kernel's cleanup in both cases, and answers the
+//! request with a `shutdown_reply` once it's done. The kernel then returns
+//! from its main loop and exits.
+//!
+//! For SIGTERM, race the control loop against [`terminated`] and call
+//! [`ShutdownHooks::run`] when it wins. SIGINT is left alone, as frontends
+//! send it to interrupt an execution.
+//!
+//! ```rust,no_run
+//! use runtimelib::kernel::shutdown::{Shutdown, ShutdownHooks};
+//! use runtimelib::KernelControlConnection;
+//!
+//! # async fn example(mut control: KernelControlConnection) -> runtimelib::error::Result<()> {
+//! let mut shutdown = ShutdownHooks::new().on_shutdown(|restart| async move {
+//!     println!("Closing the database, restart: {}", restart);
+//! });
+//!
+//! loop {
+//!     let message = control.read().await?;
+//!     if let Some(Shutdown::Requested { .. }) =
+//!         shutdown.handle_control(&message, &mut control).await?
+//!     {
+//!         return Ok(());
+//!     }
+//!     // Answer the other control requests
+//! }
+//! # }
+//! ```
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use jupyter_protocol::{JupyterMessage, JupyterMessageContent, ShutdownReply};
+
+use crate::connection::KernelControlConnection;
+use crate::error::Result;
+
+type Hook = Box<dyn FnOnce(bool) -> BoxFuture<'static, ()> + Send>;
+
+/// Why a kernel stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    /// A frontend sent a `shutdown_request`, which has been answered.
+    Requested { restart: bool },
+    /// The process got SIGTERM.
+    Terminated,
+}
+
+/// Cleanup for a kernel to run before it exits. The hooks run once, in the
+/// order they were added, however the kernel is stopped.
+#[derive(Default)]
+pub struct ShutdownHooks {
+    hooks: Vec<Hook>,
+}
+
+impl ShutdownHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` on shutdown. It's told whether the frontend will restart the
+    /// kernel, which is always `false` for SIGTERM.
+    pub fn on_shutdown<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(bool) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.hooks
+            .push(Box::new(move |restart| hook(restart).boxed()));
+        self
+    }
+
+    /// Run the hooks not run yet.
+    pub async fn run(&mut self, restart: bool) {
+        for hook in std::mem::take(&mut self.hooks) {
+            hook(restart).await;
+        }
+    }
+
+    /// If `message` is a `shutdown_request`, run the hooks and reply on
+    /// `control`, returning [`Shutdown::Requested`]. Other messages are left
+    /// for the kernel to answer.
+    pub async fn handle_control(
+        &mut self,
+        message: &JupyterMessage,
+        control: &mut KernelControlConnection,
+    ) -> Result<Option<Shutdown>> {
+        let JupyterMessageContent::ShutdownRequest(request) = &message.content else {
+            return Ok(None);
+        };
+        let restart = request.restart;
+        self.run(restart).await;
+        let reply = control.session.reply_to(
+            message,
+            ShutdownReply {
+                restart,
+                ..Default::default()
+            },
+        );
+        control.send(reply).await?;
+        Ok(Some(Shutdown::Requested { restart }))
+    }
+}
+
+/// Resolves when the process gets SIGTERM. Never resolves where there's no
+/// SIGTERM, or it can't be listened for.
+pub async fn terminated() {
+    #[cfg(all(unix, feature = "tokio-runtime"))]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut sigterm) = signal(SignalKind::terminate()) {
+            sigterm.recv().await;
+            return;
+        }
+    }
+    #[cfg(all(unix, feature = "async-dispatcher-runtime"))]
+    {
+        use async_signal::{Signal, Signals};
+        use futures::StreamExt;
+        if let Ok(mut sigterm) = Signals::new([Signal::Term]) {
+            sigterm.next().await;
+            return;
+        }
+    }
+    futures::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::local_connection_info;
+    use crate::connection::{create_client_control_connection, create_kernel_control_connection};
+    use crate::runtime::async_test;
+    use jupyter_protocol::{Session, ShutdownRequest};
+    use std::sync::{Arc, Mutex};
+
+    #[async_test]
+    async fn test_hooks_run_in_order_before_the_reply() {
+        let connection_info = local_connection_info().await;
+        let kernel_session = Session::new();
+        let mut control = create_kernel_control_connection(&connection_info, &kernel_session)
+            .await
+            .unwrap();
+        let session = Session::new();
+        let mut client = create_client_control_connection(&connection_info, &session)
+            .await
+            .unwrap();
+
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let mut shutdown = ShutdownHooks::new()
+            .on_shutdown({
+                let ran = ran.clone();
+                move |restart| async move { ran.lock().unwrap().push(("flush", restart)) }
+            })
+            .on_shutdown({
+                let ran = ran.clone();
+                move |restart| async move { ran.lock().unwrap().push(("close", restart)) }
+            });
+
+        client
+            .send(session.message(ShutdownRequest { restart: true }))
+            .await
+            .unwrap();
+        let request = control.read().await.unwrap();
+        assert_eq!(
+            shutdown
+                .handle_control(&request, &mut control)
+                .await
+                .unwrap(),
+            Some(Shutdown::Requested { restart: true })
+        );
+        assert_eq!(*ran.lock().unwrap(), vec![("flush", true), ("close", true)]);
+
+        let reply = client.read().await.unwrap();
+        assert!(matches!(
+            reply.content,
+            JupyterMessageContent::ShutdownReply(ShutdownReply { restart: true, .. })
+        ));
+
+        // Hooks only run once
+        shutdown.run(false).await;
+        assert_eq!(ran.lock().unwrap().len(), 2);
+    }
+
+    #[async_test]
+    async fn test_other_control_messages_are_left_alone() {
+        let connection_info = local_connection_info().await;
+        let session = Session::new();
+        let mut control = create_kernel_control_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let mut shutdown = ShutdownHooks::new().on_shutdown(|_| async {
+            panic!("Ran the shutdown hooks for an interrupt");
+        });
+
+        let interrupt = session.message(jupyter_protocol::InterruptRequest {});
+        assert_eq!(
+            shutdown
+                .handle_control(&interrupt, &mut control)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
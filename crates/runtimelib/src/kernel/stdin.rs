@@ -0,0 +1,207 @@
+//! Asking the frontend for input, like Python's `input()` and `getpass()`.
+//!
+//! A kernel asks by sending an `input_request` on stdin, addressed with the
+//! ZeroMQ identities of the `execute_request` being run, and waits for the
+//! matching `input_reply`. Frontends that don't take input say so with
+//! `allow_stdin: false`, and users can walk away from a prompt, so
+//! [`StdinBridge::read_input`] checks the first and gives up after a timeout
+//! for the second.
+//!
+//! Frontends route the request back to the right client by giving their
+//! shell and stdin sockets the same identity, as `jupyter_client` does.
+//!
+//! ```rust,no_run
+//! use jupyter_protocol::{ConnectionInfo, JupyterMessage, Session};
+//! use runtimelib::kernel::StdinBridge;
+//!
+//! # async fn example(connection_info: ConnectionInfo, execute_request: JupyterMessage) -> runtimelib::error::Result<()> {
+//! let session = Session::new();
+//! let mut stdin = StdinBridge::new(
+//!     runtimelib::create_kernel_stdin_connection(&connection_info, &session).await?,
+//! );
+//!
+//! // While running `execute_request`
+//! let name = stdin.read_input(&execute_request, "Name: ", false).await?;
+//! let password = stdin.read_input(&execute_request, "Password: ", true).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::time::Duration;
+
+use jupyter_protocol::{InputRequest, JupyterMessage, JupyterMessageContent, ReplyStatus};
+
+use crate::client::with_timeout;
+use crate::connection::KernelStdinConnection;
+use crate::error::{Result, RuntimeError};
+
+/// How long to wait for the user to answer a prompt by default.
+pub const DEFAULT_INPUT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A kernel's stdin socket, for asking the frontend for input.
+pub struct StdinBridge {
+    connection: KernelStdinConnection,
+    timeout: Duration,
+}
+
+impl StdinBridge {
+    pub fn new(connection: KernelStdinConnection) -> Self {
+        Self {
+            connection,
+            timeout: DEFAULT_INPUT_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Show `prompt` to whoever sent `parent`, usually the `execute_request`
+    /// being run, and return what they type. With `password`, the frontend
+    /// hides the input.
+    ///
+    /// Fails with [`RuntimeError::Unsupported`] if `parent` was sent with
+    /// `allow_stdin: false`, and [`RuntimeError::Timeout`] if no answer comes
+    /// in time.
+    pub async fn read_input(
+        &mut self,
+        parent: &JupyterMessage,
+        prompt: &str,
+        password: bool,
+    ) -> Result<String> {
+        if let JupyterMessageContent::ExecuteRequest(request) = &parent.content {
+            if !request.allow_stdin {
+                return Err(RuntimeError::Unsupported(
+                    "The frontend doesn't accept input for this execution".to_string(),
+                ));
+            }
+        }
+
+        let request = self.connection.session.reply_to(
+            parent,
+            InputRequest {
+                prompt: prompt.to_string(),
+                password,
+            },
+        );
+        let msg_id = request.header.msg_id.clone();
+        self.connection.send(request).await?;
+
+        let connection = &mut self.connection;
+        let reply = with_timeout(self.timeout, async {
+            loop {
+                let message = connection.read().await?;
+                // Answers to earlier prompts that timed out are stale
+                let answers_this_prompt = message
+                    .parent_header
+                    .as_ref()
+                    .is_some_and(|parent| parent.msg_id == msg_id);
+                if let (true, JupyterMessageContent::InputReply(reply)) =
+                    (answers_this_prompt, message.content)
+                {
+                    return Ok::<_, RuntimeError>(reply);
+                }
+            }
+        })
+        .await
+        .ok_or_else(|| RuntimeError::Timeout("input_reply".to_string()))??;
+
+        match reply.status {
+            ReplyStatus::Error => Err(RuntimeError::ErrorReply(reply.error.unwrap_or_default())),
+            _ => Ok(reply.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::local_connection_info;
+    use crate::connection::{
+        create_kernel_shell_connection, create_kernel_stdin_connection, Connection,
+    };
+    use crate::runtime::async_test;
+    use jupyter_protocol::{Channel, ExecuteRequest, InputReply, Session};
+    use zeromq::{Socket, SocketOptions};
+
+    /// A frontend's shell and stdin sockets, sharing an identity like
+    /// jupyter_client's.
+    async fn frontend(
+        connection_info: &jupyter_protocol::ConnectionInfo,
+        session: &Session,
+    ) -> (
+        Connection<zeromq::DealerSocket>,
+        Connection<zeromq::DealerSocket>,
+    ) {
+        let options = || {
+            let mut options = SocketOptions::default();
+            options.peer_identity(session.id().as_bytes().to_vec().try_into().unwrap());
+            options
+        };
+        // The kernel registers a peer a moment after it connects. Connecting
+        // stdin first means it's registered by the time a request arrives.
+        let mut stdin = zeromq::DealerSocket::with_options(options());
+        stdin.connect(&connection_info.stdin_url()).await.unwrap();
+        let mut shell = zeromq::DealerSocket::with_options(options());
+        shell.connect(&connection_info.shell_url()).await.unwrap();
+        (
+            Connection::new(shell, Channel::Shell, &connection_info.key, session),
+            Connection::new(stdin, Channel::Stdin, &connection_info.key, session),
+        )
+    }
+
+    #[async_test]
+    async fn test_read_input_round_trip() {
+        let connection_info = local_connection_info().await;
+        let kernel_session = Session::new();
+        let mut kernel_shell = create_kernel_shell_connection(&connection_info, &kernel_session)
+            .await
+            .unwrap();
+        let mut bridge = StdinBridge::new(
+            create_kernel_stdin_connection(&connection_info, &kernel_session)
+                .await
+                .unwrap(),
+        )
+        .with_timeout(Duration::from_secs(5));
+
+        let session = Session::new();
+        let (mut shell, mut stdin) = frontend(&connection_info, &session).await;
+        let mut request = ExecuteRequest::new("input('Name: ')".to_string());
+        request.allow_stdin = true;
+        shell.send(session.message(request)).await.unwrap();
+        let parent = kernel_shell.read().await.unwrap();
+
+        let frontend = async {
+            let input_request = stdin.read().await.unwrap();
+            let JupyterMessageContent::InputRequest(prompt) = &input_request.content else {
+                panic!("Expected an input_request, got {:?}", input_request.content);
+            };
+            assert_eq!(prompt.prompt, "Name: ");
+            stdin
+                .send(session.reply_to(&input_request, InputReply::new("Ada")))
+                .await
+                .unwrap();
+        };
+        let (value, ()) = futures::join!(bridge.read_input(&parent, "Name: ", false), frontend);
+        assert_eq!(value.unwrap(), "Ada");
+    }
+
+    #[async_test]
+    async fn test_read_input_respects_allow_stdin() {
+        let connection_info = local_connection_info().await;
+        let session = Session::new();
+        let mut bridge = StdinBridge::new(
+            create_kernel_stdin_connection(&connection_info, &session)
+                .await
+                .unwrap(),
+        );
+
+        let mut request = ExecuteRequest::new("input()".to_string());
+        request.allow_stdin = false;
+        let parent = session.message(request);
+        assert!(matches!(
+            bridge.read_input(&parent, "", false).await,
+            Err(RuntimeError::Unsupported(_))
+        ));
+    }
+}
@@ -1,16 +1,18 @@
-use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
 use jupyter_protocol::JupyterKernelspec;
 
+use crate::error::{Result, RuntimeError};
+
 #[cfg(feature = "tokio-runtime")]
-use tokio::{fs, io::AsyncReadExt, process::Command};
+use tokio::{fs, process::Command};
 
 #[cfg(feature = "async-dispatcher-runtime")]
-use smol::process::Command;
+use smol::{fs, process::Command};
 
 /// A pointer to a kernelspec directory, with name and specification
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -31,7 +33,10 @@ impl KernelspecDir {
 
         let argv = self.kernelspec.argv;
         if argv.is_empty() {
-            return Err(anyhow!("Empty argv in kernelspec {}", kernel_name));
+            return Err(RuntimeError::InvalidKernelspec(format!(
+                "Empty argv in kernelspec {}",
+                kernel_name
+            )));
         }
 
         let mut cmd_builder = Command::new(&argv[0]);
@@ -56,6 +61,139 @@ impl KernelspecDir {
 
         Ok(cmd_builder)
     }
+
+    /// The files UIs load from the kernelspec directory, keyed the way
+    /// jupyter_server's kernelspec API names them: `kernel.js`, `kernel.css`
+    /// and each `logo-*` file without its extension, e.g. `logo-64x64`.
+    pub fn resources(&self) -> BTreeMap<String, PathBuf> {
+        let mut resources = BTreeMap::new();
+        let Ok(entries) = std::fs::read_dir(&self.path) else {
+            return resources;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if !path.is_file() {
+                continue;
+            }
+            let Some(file_name) = path.file_name().and_then(OsStr::to_str) else {
+                continue;
+            };
+            if file_name == "kernel.js" || file_name == "kernel.css" {
+                resources.insert(file_name.to_string(), path);
+            } else if file_name.starts_with("logo-") {
+                let name = Path::new(file_name)
+                    .file_stem()
+                    .and_then(OsStr::to_str)
+                    .unwrap_or(file_name);
+                resources.insert(name.to_string(), path);
+            }
+        }
+        resources
+    }
+
+    /// The best logo to show: `logo-svg` if there is one, otherwise the largest
+    /// `logo-<width>x<height>` image.
+    pub fn logo(&self) -> Option<PathBuf> {
+        let mut resources = self.resources();
+        if let Some(svg) = resources.remove("logo-svg") {
+            return Some(svg);
+        }
+        resources
+            .into_iter()
+            .filter_map(|(name, path)| Some((logo_width(&name)?, path)))
+            .max_by_key(|(width, _)| *width)
+            .map(|(_, path)| path)
+    }
+
+    /// The logo closest to `size` pixels wide, e.g. `logo-64x64` for 64.
+    pub fn logo_for_size(&self, size: u32) -> Option<PathBuf> {
+        let mut resources = self.resources();
+        let svg = resources.remove("logo-svg");
+        resources
+            .into_iter()
+            .filter_map(|(name, path)| Some((logo_width(&name)?, path)))
+            .min_by_key(|(width, _)| width.abs_diff(size))
+            .map(|(_, path)| path)
+            .or(svg)
+    }
+
+    /// The `kernel.js` frontend extension, if the kernel ships one.
+    pub fn kernel_js(&self) -> Option<PathBuf> {
+        self.resources().remove("kernel.js")
+    }
+
+    /// The path of `resource` inside the kernelspec directory. `resource` is
+    /// either a name from [`KernelspecDir::resources`] or a relative path to
+    /// a custom resource. Paths that would leave the directory are refused.
+    pub fn resource_path(&self, resource: &str) -> Option<PathBuf> {
+        if let Some(path) = self.resources().remove(resource) {
+            return Some(path);
+        }
+        let relative = Path::new(resource);
+        if !relative
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+        {
+            return None;
+        }
+        let path = self.path.join(relative);
+        path.is_file().then_some(path)
+    }
+
+    /// Read a resource from the kernelspec directory, see [`KernelspecDir::resource_path`].
+    #[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+    pub async fn read_resource(&self, resource: &str) -> Result<Vec<u8>> {
+        let path = self
+            .resource_path(resource)
+            .ok_or_else(|| RuntimeError::ResourceNotFound {
+                kernel_name: self.kernel_name.clone(),
+                resource: resource.to_string(),
+            })?;
+        fs::read(&path).await.map_err(RuntimeError::file(&path))
+    }
+
+    /// The display name for `locale`, a language tag like `fr` or `pt-BR`.
+    ///
+    /// Jupyter has no standard for translated kernel names. This is a
+    /// runtimelib convention: translations go in the kernelspec metadata
+    /// under `display_names`, which other Jupyter tools ignore.
+    ///
+    /// ```json
+    /// "metadata": {"display_names": {"fr": "Python 3 (français)"}}
+    /// ```
+    ///
+    /// `pt-BR` falls back to `pt`, and a missing translation falls back to
+    /// `display_name`.
+    pub fn localized_display_name(&self, locale: &str) -> &str {
+        let display_names = self
+            .kernelspec
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("display_names"))
+            .and_then(|names| names.as_object());
+        let Some(display_names) = display_names else {
+            return &self.kernelspec.display_name;
+        };
+
+        // POSIX locales use `_`, like `pt_BR`
+        let locale = locale.replace('_', "-");
+        let language = locale.split('-').next().unwrap_or_default();
+        for tag in [locale.as_str(), language] {
+            let name = display_names
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(tag))
+                .and_then(|(_, name)| name.as_str());
+            if let Some(name) = name {
+                return name;
+            }
+        }
+        &self.kernelspec.display_name
+    }
+}
+
+/// The width of a `logo-<width>x<height>` resource.
+fn logo_width(name: &str) -> Option<u32> {
+    let (width, _) = name.strip_prefix("logo-")?.split_once('x')?;
+    width.parse().ok()
 }
 
 // We look for files of the sort:
@@ -63,7 +201,7 @@ impl KernelspecDir {
 // But we must check through all the possible <datadir> to figure that out.
 //
 // For now, just use a combination of the standard system and user data directories.
-#[cfg(feature = "tokio-runtime")]
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
 pub async fn list_kernelspecs() -> Vec<KernelspecDir> {
     let mut kernelspecs = Vec::new();
     let data_dirs = crate::dirs::data_dirs();
@@ -76,14 +214,14 @@ pub async fn list_kernelspecs() -> Vec<KernelspecDir> {
 
 // Design choice here is to not report any errors, keep going if possible,
 // and skip any paths that don't have a kernels subdirectory.
-#[cfg(feature = "tokio-runtime")]
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
 pub async fn list_kernelspec_names_at(data_dir: &Path) -> Vec<String> {
     let mut kernelspecs = Vec::new();
     let kernels_dir = data_dir.join("kernels");
-    if let Ok(mut entries) = fs::read_dir(kernels_dir).await {
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            if entry.path().is_dir() {
-                if let Some(kernel_name) = entry.file_name().to_str() {
+    if let Ok(entries) = read_dir_paths(&kernels_dir).await {
+        for path in entries {
+            if path.is_dir() {
+                if let Some(kernel_name) = path.file_name().and_then(OsStr::to_str) {
                     kernelspecs.push(kernel_name.to_string());
                 }
             }
@@ -93,7 +231,7 @@ pub async fn list_kernelspec_names_at(data_dir: &Path) -> Vec<String> {
 }
 
 // For a given data directory, return all the parsed kernelspecs and corresponding directories
-#[cfg(feature = "tokio-runtime")]
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
 pub async fn read_kernelspec_jsons(data_dir: &Path) -> Vec<KernelspecDir> {
     let mut kernelspecs = Vec::new();
     let kernel_names = list_kernelspec_names_at(data_dir).await;
@@ -110,21 +248,261 @@ pub async fn read_kernelspec_jsons(data_dir: &Path) -> Vec<KernelspecDir> {
     kernelspecs
 }
 
-#[cfg(feature = "tokio-runtime")]
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
 async fn read_kernelspec_json(json_file_path: &Path) -> Result<JupyterKernelspec> {
-    let mut file = fs::File::open(json_file_path).await?;
-    let mut contents = vec![];
+    let contents = fs::read(json_file_path)
+        .await
+        .map_err(RuntimeError::file(json_file_path))?;
+    serde_json::from_slice(&contents).map_err(|error| {
+        RuntimeError::InvalidKernelspec(format!("{}: {}", json_file_path.display(), error))
+    })
+}
+
+/// Find a kernelspec by name, searching the data directories in priority order.
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub async fn find_kernelspec(kernel_name: &str) -> Option<KernelspecDir> {
+    for data_dir in crate::dirs::data_dirs() {
+        let kernel_path = data_dir.join("kernels").join(kernel_name);
+        if let Ok(kernelspec) = read_kernelspec_json(&kernel_path.join("kernel.json")).await {
+            return Some(KernelspecDir {
+                kernel_name: kernel_name.to_string(),
+                path: kernel_path,
+                kernelspec,
+            });
+        }
+    }
+    None
+}
+
+/// The paths of the entries in `dir`.
+#[cfg(feature = "tokio-runtime")]
+async fn read_dir_paths(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir).await?;
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        paths.push(entry.path());
+    }
+    Ok(paths)
+}
+
+/// The paths of the entries in `dir`.
+#[cfg(feature = "async-dispatcher-runtime")]
+async fn read_dir_paths(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    use futures::StreamExt;
+
+    let mut entries = fs::read_dir(dir).await?;
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next().await {
+        paths.push(entry?.path());
+    }
+    Ok(paths)
+}
 
-    file.read_to_end(&mut contents).await?;
-    let jupyter_runtime: JupyterKernelspec = serde_json::from_slice(&contents)?;
-    Ok(jupyter_runtime)
+/// Check that a kernelspec can be launched: `argv` must name a program and pass
+/// the `{connection_file}`.
+pub fn validate_kernelspec(kernelspec: &JupyterKernelspec) -> Result<()> {
+    match kernelspec.argv.first() {
+        None => {
+            return Err(RuntimeError::InvalidKernelspec(
+                "Kernelspec argv is empty".to_string(),
+            ))
+        }
+        Some(program) if program.trim().is_empty() => {
+            return Err(RuntimeError::InvalidKernelspec(
+                "Kernelspec argv starts with an empty program".to_string(),
+            ))
+        }
+        Some(_) => {}
+    }
+    if !kernelspec.argv.iter().any(|arg| arg == "{connection_file}") {
+        return Err(RuntimeError::InvalidKernelspec(
+            "Kernelspec argv does not contain the {connection_file} placeholder".to_string(),
+        ));
+    }
+    Ok(())
 }
 
-#[cfg(all(test, feature = "tokio-runtime"))]
+/// Kernel names follow Jupyter's rules: ASCII letters, numbers, `.`, `_` and `-`.
+pub fn validate_kernel_name(kernel_name: &str) -> Result<()> {
+    if kernel_name.is_empty()
+        || !kernel_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(RuntimeError::InvalidKernelspec(format!(
+            "Invalid kernel name: {:?}",
+            kernel_name
+        )));
+    }
+    Ok(())
+}
+
+/// Write `kernelspec` to `<data_dir>/kernels/<kernel_name>/kernel.json`.
+///
+/// Fails if the kernelspec already exists, unless `replace` is set.
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub async fn install_kernelspec(
+    kernelspec: &JupyterKernelspec,
+    kernel_name: &str,
+    data_dir: &Path,
+    replace: bool,
+) -> Result<KernelspecDir> {
+    validate_kernelspec(kernelspec)?;
+    let kernel_path = prepare_kernel_dir(kernel_name, data_dir, replace).await?;
+
+    let kernel_json = kernel_path.join("kernel.json");
+    let contents = serde_json::to_vec_pretty(kernelspec).map_err(std::io::Error::from)?;
+    fs::write(&kernel_json, contents)
+        .await
+        .map_err(RuntimeError::file(&kernel_json))?;
+
+    Ok(KernelspecDir {
+        kernel_name: kernel_name.to_string(),
+        path: kernel_path,
+        kernelspec: kernelspec.clone(),
+    })
+}
+
+/// Install the kernelspec directory at `source` (a `kernel.json` plus any logos
+/// and resources) as `<data_dir>/kernels/<kernel_name>`.
+///
+/// Fails if the kernelspec already exists, unless `replace` is set.
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub async fn install_kernelspec_dir(
+    source: &Path,
+    kernel_name: &str,
+    data_dir: &Path,
+    replace: bool,
+) -> Result<KernelspecDir> {
+    let kernelspec = read_kernelspec_json(&source.join("kernel.json")).await?;
+    validate_kernelspec(&kernelspec)?;
+    // Replacing would delete the source before it's copied
+    let destination = data_dir.join("kernels").join(kernel_name);
+    if let (Ok(source), Ok(destination)) = (
+        fs::canonicalize(source).await,
+        fs::canonicalize(&destination).await,
+    ) {
+        if source.starts_with(&destination) {
+            return Err(RuntimeError::InvalidKernelspec(format!(
+                "Can't install {} over itself",
+                source.display()
+            )));
+        }
+    }
+    let kernel_path = prepare_kernel_dir(kernel_name, data_dir, replace).await?;
+
+    // Copy the whole tree, like `jupyter kernelspec install`. Symlinks are
+    // followed as long as they stay inside the source, so the installed
+    // kernelspec doesn't depend on where it was installed from
+    let root = fs::canonicalize(source)
+        .await
+        .map_err(RuntimeError::file(source))?;
+    let mut pending = vec![(source.to_path_buf(), kernel_path.clone())];
+    while let Some((from, to)) = pending.pop() {
+        fs::create_dir_all(&to)
+            .await
+            .map_err(RuntimeError::file(&to))?;
+        let from_resolved = fs::canonicalize(&from)
+            .await
+            .map_err(RuntimeError::file(&from))?;
+        for path in read_dir_paths(&from)
+            .await
+            .map_err(RuntimeError::file(&from))?
+        {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            let target = to.join(file_name);
+            let mut file_type = fs::symlink_metadata(&path)
+                .await
+                .map_err(RuntimeError::file(&path))?
+                .file_type();
+            if file_type.is_symlink() {
+                let resolved = fs::canonicalize(&path)
+                    .await
+                    .map_err(RuntimeError::file(&path))?;
+                if !resolved.starts_with(&root) {
+                    return Err(RuntimeError::InvalidKernelspec(format!(
+                        "{} links outside of {}",
+                        path.display(),
+                        source.display()
+                    )));
+                }
+                // Following a link to a directory containing it would copy forever
+                if from_resolved.starts_with(&resolved) {
+                    return Err(RuntimeError::InvalidKernelspec(format!(
+                        "{} links to a directory containing it",
+                        path.display()
+                    )));
+                }
+                file_type = fs::metadata(&resolved)
+                    .await
+                    .map_err(RuntimeError::file(&path))?
+                    .file_type();
+            }
+            if file_type.is_dir() {
+                pending.push((path, target));
+            } else {
+                fs::copy(&path, &target)
+                    .await
+                    .map_err(RuntimeError::file(&target))?;
+            }
+        }
+    }
+
+    Ok(KernelspecDir {
+        kernel_name: kernel_name.to_string(),
+        path: kernel_path,
+        kernelspec,
+    })
+}
+
+/// Remove an installed kernelspec, returning the directory that was deleted.
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub async fn remove_kernelspec(kernel_name: &str) -> Result<PathBuf> {
+    let kernelspec = find_kernelspec(kernel_name)
+        .await
+        .ok_or_else(|| RuntimeError::KernelspecNotFound(kernel_name.to_string()))?;
+    fs::remove_dir_all(&kernelspec.path)
+        .await
+        .map_err(RuntimeError::file(&kernelspec.path))?;
+    Ok(kernelspec.path)
+}
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+async fn prepare_kernel_dir(kernel_name: &str, data_dir: &Path, replace: bool) -> Result<PathBuf> {
+    validate_kernel_name(kernel_name)?;
+    let kernel_path = data_dir.join("kernels").join(kernel_name);
+    // A symlink is replaced, not the directory it points to
+    if let Ok(metadata) = fs::symlink_metadata(&kernel_path).await {
+        if !replace {
+            return Err(RuntimeError::KernelspecExists {
+                kernel_name: kernel_name.to_string(),
+                path: kernel_path,
+            });
+        }
+        if metadata.is_dir() {
+            fs::remove_dir_all(&kernel_path).await
+        } else {
+            fs::remove_file(&kernel_path).await
+        }
+        .map_err(RuntimeError::file(&kernel_path))?;
+    }
+    fs::create_dir_all(&kernel_path)
+        .await
+        .map_err(RuntimeError::file(&kernel_path))?;
+    Ok(kernel_path)
+}
+
+#[cfg(all(
+    test,
+    any(feature = "tokio-runtime", feature = "async-dispatcher-runtime")
+))]
 mod tests {
     use super::*;
+    use crate::runtime::async_test;
 
-    #[tokio::test]
+    #[async_test]
     async fn test_read_jupyter_runtime_config() {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("tests/kernels/ir/kernel.json");
@@ -142,7 +520,156 @@ mod tests {
         assert_eq!(jupyter_runtime.interrupt_mode, Some("signal".to_string()));
     }
 
-    #[tokio::test]
+    fn scratch_data_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("runtimelib-kernelspecs-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[async_test]
+    async fn test_install_kernelspec_dir() {
+        let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/kernels/ir");
+        let data_dir = scratch_data_dir();
+
+        let installed = install_kernelspec_dir(&source, "my-r", &data_dir, false)
+            .await
+            .unwrap();
+        assert_eq!(installed.path, data_dir.join("kernels/my-r"));
+        assert_eq!(installed.kernelspec.display_name, "R");
+        assert_eq!(read_kernelspec_jsons(&data_dir).await.len(), 1);
+
+        assert!(matches!(
+            install_kernelspec_dir(&source, "my-r", &data_dir, false).await,
+            Err(RuntimeError::KernelspecExists { kernel_name, .. }) if kernel_name == "my-r"
+        ));
+        assert!(install_kernelspec_dir(&source, "my-r", &data_dir, true)
+            .await
+            .is_ok());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[async_test]
+    async fn test_install_kernelspec_dir_over_itself() {
+        let data_dir = scratch_data_dir();
+        let source = data_dir.join("kernels").join("echo");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join("kernel.json"),
+            r#"{"argv": ["echo", "{connection_file}"], "display_name": "Echo", "language": "text"}"#,
+        )
+        .unwrap();
+
+        let result = install_kernelspec_dir(&source, "echo", &data_dir, true).await;
+        assert!(result.unwrap_err().to_string().contains("over itself"));
+        assert!(source.join("kernel.json").exists());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[async_test]
+    async fn test_install_kernelspec_dir_follows_symlinks_inside_source() {
+        let data_dir = scratch_data_dir();
+        let source = data_dir.join("source");
+        std::fs::create_dir_all(source.join("resources")).unwrap();
+        std::fs::write(
+            source.join("kernel.json"),
+            r#"{"argv": ["echo", "{connection_file}"], "display_name": "Echo", "language": "text"}"#,
+        )
+        .unwrap();
+        std::fs::write(source.join("resources").join("logo.svg"), "<svg/>").unwrap();
+        std::os::unix::fs::symlink("resources/logo.svg", source.join("logo-64x64.svg")).unwrap();
+        std::os::unix::fs::symlink("resources", source.join("assets")).unwrap();
+
+        let installed = install_kernelspec_dir(&source, "echo", &data_dir, false)
+            .await
+            .unwrap();
+        for copied in ["logo-64x64.svg", "assets/logo.svg"] {
+            let copied = installed.path.join(copied);
+            assert!(copied.symlink_metadata().unwrap().is_file());
+            assert_eq!(std::fs::read_to_string(&copied).unwrap(), "<svg/>");
+        }
+        assert!(installed
+            .path
+            .join("assets")
+            .symlink_metadata()
+            .unwrap()
+            .is_dir());
+
+        // Links out of the source, or to a directory containing them, are refused
+        std::fs::write(data_dir.join("outside.svg"), "<svg/>").unwrap();
+        for (link, target) in [("outside.svg", "../outside.svg"), ("parent", ".")] {
+            std::os::unix::fs::symlink(target, source.join(link)).unwrap();
+            let result = install_kernelspec_dir(&source, "refused", &data_dir, true).await;
+            assert!(
+                matches!(result, Err(RuntimeError::InvalidKernelspec(_))),
+                "{link}: {result:?}"
+            );
+            std::fs::remove_file(source.join(link)).unwrap();
+        }
+        std::fs::remove_file(source.join("logo-64x64.svg")).unwrap();
+        std::os::unix::fs::symlink("missing.svg", source.join("logo-64x64.svg")).unwrap();
+        assert!(install_kernelspec_dir(&source, "refused", &data_dir, true)
+            .await
+            .is_err());
+        std::fs::remove_file(source.join("logo-64x64.svg")).unwrap();
+
+        // Replacing a symlinked kernelspec removes the link, not what it points to
+        let kernels = data_dir.join("kernels");
+        std::fs::remove_dir_all(&installed.path).unwrap();
+        let linked = data_dir.join("linked");
+        std::fs::create_dir_all(&linked).unwrap();
+        std::fs::write(linked.join("keep.txt"), "keep").unwrap();
+        std::os::unix::fs::symlink(&linked, kernels.join("echo")).unwrap();
+        install_kernelspec_dir(&source, "echo", &data_dir, true)
+            .await
+            .unwrap();
+        assert!(linked.join("keep.txt").exists());
+        assert!(!kernels
+            .join("echo")
+            .symlink_metadata()
+            .unwrap()
+            .is_symlink());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[async_test]
+    async fn test_install_kernelspec_validates() {
+        let data_dir = scratch_data_dir();
+        let mut kernelspec = JupyterKernelspec {
+            argv: vec![
+                "python3".to_string(),
+                "-m".to_string(),
+                "ipykernel".to_string(),
+            ],
+            display_name: "Python".to_string(),
+            language: "python".to_string(),
+            metadata: None,
+            interrupt_mode: None,
+            env: None,
+        };
+        assert!(install_kernelspec(&kernelspec, "python", &data_dir, false)
+            .await
+            .is_err());
+
+        kernelspec
+            .argv
+            .extend(["-f".to_string(), "{connection_file}".to_string()]);
+        assert!(
+            install_kernelspec(&kernelspec, "../python", &data_dir, false)
+                .await
+                .is_err()
+        );
+        let installed = install_kernelspec(&kernelspec, "python", &data_dir, false)
+            .await
+            .unwrap();
+        let kernelspecs = read_kernelspec_jsons(&data_dir).await;
+        assert_eq!(kernelspecs[0].kernelspec.argv, installed.kernelspec.argv);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[async_test]
     async fn test_read_missing_config() {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("tests/kernels/NONEXISTENT/kernel.json");
@@ -150,7 +677,7 @@ mod tests {
         assert!(jupyter_runtime.is_err());
     }
 
-    #[tokio::test]
+    #[async_test]
     async fn test_list_kernelspec_jsons() {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("tests");
@@ -161,7 +688,7 @@ mod tests {
         assert!(kernelspecs.contains(&"rust".to_string()));
     }
 
-    #[tokio::test]
+    #[async_test]
     async fn test_read_kernelspec_jsons() {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("tests");
@@ -199,7 +726,82 @@ mod tests {
         assert_eq!(rust_count, 1);
     }
 
-    #[tokio::test]
+    fn test_kernelspec_dir(kernel_name: &str, path: &str) -> KernelspecDir {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path);
+        let kernelspec: JupyterKernelspec =
+            serde_json::from_slice(&std::fs::read(path.join("kernel.json")).unwrap()).unwrap();
+        KernelspecDir {
+            kernel_name: kernel_name.to_string(),
+            path,
+            kernelspec,
+        }
+    }
+
+    fn python3_kernelspec_dir() -> KernelspecDir {
+        test_kernelspec_dir("python3", "tests/kernels/python3")
+    }
+
+    #[test]
+    fn test_kernelspec_resources() {
+        let kernelspec = python3_kernelspec_dir();
+        let path = &kernelspec.path;
+
+        let resources = kernelspec.resources();
+        assert_eq!(
+            resources.keys().collect::<Vec<_>>(),
+            ["kernel.js", "logo-32x32", "logo-64x64", "logo-svg"]
+        );
+        assert_eq!(kernelspec.logo(), Some(path.join("logo-svg.svg")));
+        assert_eq!(
+            kernelspec.logo_for_size(40),
+            Some(path.join("logo-32x32.png"))
+        );
+        assert_eq!(kernelspec.kernel_js(), Some(path.join("kernel.js")));
+
+        assert_eq!(
+            kernelspec.resource_path("logo-64x64"),
+            Some(path.join("logo-64x64.png"))
+        );
+        assert_eq!(
+            kernelspec.resource_path("kernel.json"),
+            Some(path.join("kernel.json"))
+        );
+        assert_eq!(kernelspec.resource_path("../ir/kernel.json"), None);
+        assert_eq!(kernelspec.resource_path("missing.png"), None);
+    }
+
+    #[async_test]
+    async fn test_read_kernelspec_resource() {
+        let kernelspec = python3_kernelspec_dir();
+        let logo = kernelspec.read_resource("logo-32x32").await.unwrap();
+        assert!(logo.starts_with(b"\x89PNG"));
+        assert!(kernelspec.read_resource("logo-128x128").await.is_err());
+    }
+
+    #[test]
+    fn test_localized_display_name() {
+        let kernelspec = test_kernelspec_dir("localized", "tests/localized");
+        assert_eq!(
+            kernelspec.localized_display_name("fr"),
+            "Python 3 (français)"
+        );
+        assert_eq!(
+            kernelspec.localized_display_name("pt_BR"),
+            "Python 3 (Brasil)"
+        );
+        assert_eq!(
+            kernelspec.localized_display_name("pt-PT"),
+            "Python 3 (português)"
+        );
+        assert_eq!(kernelspec.localized_display_name("de"), "Python 3");
+
+        assert_eq!(
+            python3_kernelspec_dir().localized_display_name("fr"),
+            "Python 3"
+        );
+    }
+
+    #[async_test]
     async fn list_nonexistent_kernelspec_datadir() {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("tests/NOTHINGHERE");
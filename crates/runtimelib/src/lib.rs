@@ -27,7 +27,46 @@ pub use kernelspec::*;
 pub mod dirs;
 pub use dirs::*;
 
+pub mod file_transfer;
+
+pub mod kernel;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+mod runtime;
+
+pub mod error;
+pub use error::RuntimeError;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod signer;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use signer::*;
+
 #[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
 pub mod connection;
 #[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
 pub use connection::*;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod message_stream;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use message_stream::MessageStream;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod containers;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod instances;
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod client;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use client::{
+    ClientEvent, ControlOutcome, ExecutionResult, HeartbeatConfig, InterruptMode, KernelClient,
+    Liveness, ShellPool,
+};
+
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub mod orchestrator;
+#[cfg(any(feature = "tokio-runtime", feature = "async-dispatcher-runtime"))]
+pub use orchestrator::{KernelOutcome, KernelRun, OrchestrationReport, Orchestrator};
@@ -0,0 +1,241 @@
+//! [`Stream`] and [`Sink`] adapters for ZeroMQ connections.
+//!
+//! A [`Connection`] reads and sends with `async fn`s, which don't compose with
+//! the `futures` combinators. [`MessageStream`] wraps one so that it does:
+//! sockets that receive are a `Stream<Item = Result<JupyterMessage>>`, sockets
+//! that send are a `Sink<JupyterMessage>`, and shell, control and stdin
+//! connections are both, implementing
+//! [`JupyterConnection`] like the WebSocket transport does. Like that
+//! transport, errors come out as `anyhow::Error`s; downcast them to
+//! [`RuntimeError`](crate::RuntimeError) to tell them apart.
+//!
+//! ```rust,no_run
+//! use futures::{SinkExt, StreamExt};
+//! use jupyter_protocol::{JupyterMessageContent, KernelInfoRequest};
+//! use runtimelib::{create_client_shell_connection, ConnectionInfo, Session};
+//!
+//! # async fn example(connection_info: ConnectionInfo) -> anyhow::Result<()> {
+//! let session = Session::new();
+//! let shell = create_client_shell_connection(&connection_info, &session).await?;
+//! let (mut sender, receiver) = shell.into_stream().split();
+//!
+//! sender.send(session.message(KernelInfoRequest {})).await?;
+//! let mut replies = receiver.filter_map(|message| async move {
+//!     match message.ok()?.content {
+//!         JupyterMessageContent::KernelInfoReply(reply) => Some(reply),
+//!         _ => None,
+//!     }
+//! });
+//! let reply = Box::pin(replies).next().await;
+//! # Ok(())
+//! # }
+//! ```
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use anyhow::Result;
+use futures::lock::Mutex;
+use futures::{Sink, Stream};
+use jupyter_protocol::{JupyterConnection, JupyterMessage};
+
+use crate::connection::Connection;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// A [`Connection`] as a `Stream` of incoming messages and a `Sink` for outgoing ones.
+///
+/// Sending takes priority: a read waiting for the next message is cancelled
+/// while a message goes out and resumes after, without losing messages.
+pub struct MessageStream<S> {
+    connection: Arc<Mutex<Connection<S>>>,
+    read: Option<BoxFuture<Result<JupyterMessage>>>,
+    read_waker: Option<Waker>,
+    send: Option<BoxFuture<Result<()>>>,
+}
+
+impl<S> Connection<S> {
+    /// Use this connection through the `Stream` and `Sink` traits.
+    pub fn into_stream(self) -> MessageStream<S> {
+        MessageStream::from(self)
+    }
+}
+
+impl<S> From<Connection<S>> for MessageStream<S> {
+    fn from(connection: Connection<S>) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(connection)),
+            read: None,
+            read_waker: None,
+            send: None,
+        }
+    }
+}
+
+impl<S> MessageStream<S> {
+    /// The connection back, dropping any read in progress. Returns `None` if
+    /// a send hasn't been flushed yet.
+    pub fn into_connection(mut self) -> Option<Connection<S>> {
+        if self.send.is_some() {
+            return None;
+        }
+        self.read = None;
+        Arc::try_unwrap(self.connection).ok().map(Mutex::into_inner)
+    }
+
+    /// Stop waiting for the next message so a send can lock the connection,
+    /// and wake the reader to start reading again afterwards.
+    fn cancel_read(&mut self) {
+        if self.read.take().is_some() {
+            if let Some(waker) = self.read_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let Some(send) = &mut self.send else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = futures::ready!(send.as_mut().poll(cx));
+        self.send = None;
+        Poll::Ready(result)
+    }
+}
+
+impl<S> Stream for MessageStream<S>
+where
+    S: zeromq::SocketRecv + Send + 'static,
+{
+    type Item = Result<JupyterMessage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let connection = this.connection.clone();
+        let read = this.read.get_or_insert_with(|| {
+            // Receiving is cancel safe: a message only leaves the socket's
+            // queue when this future completes
+            Box::pin(async move { Ok(connection.lock().await.read().await?) })
+        });
+        match read.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                this.read = None;
+                Poll::Ready(Some(result))
+            }
+            Poll::Pending => {
+                this.read_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S> Sink<JupyterMessage> for MessageStream<S>
+where
+    S: zeromq::SocketSend + Send + 'static,
+{
+    type Error = anyhow::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_send(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: JupyterMessage) -> Result<()> {
+        let this = self.get_mut();
+        this.cancel_read();
+        let connection = this.connection.clone();
+        this.send = Some(Box::pin(async move {
+            Ok(connection.lock().await.send(message).await?)
+        }));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_send(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_send(cx)
+    }
+}
+
+impl<S> JupyterConnection for MessageStream<S> where
+    S: zeromq::SocketSend + zeromq::SocketRecv + Send + 'static
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::tests::start_echo_kernel;
+    use crate::connection::{create_client_iopub_connection, create_client_shell_connection};
+    use crate::runtime::async_test;
+    use futures::channel::oneshot;
+    use futures::{SinkExt, StreamExt};
+    use jupyter_protocol::{ExecuteRequest, JupyterMessageContent, KernelInfoRequest, Session};
+    use std::time::Duration;
+
+    #[async_test]
+    async fn test_split_shell_and_filter_iopub() {
+        let connection_info = start_echo_kernel().await;
+        let session = Session::new();
+        let shell = create_client_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let iopub = create_client_iopub_connection(&connection_info, "", &session)
+            .await
+            .unwrap();
+        futures_timer::Delay::new(Duration::from_millis(100)).await;
+
+        let (mut sender, mut receiver) = shell.into_stream().split();
+        let mut stdout = Box::pin(iopub.into_stream().filter_map(|message| async move {
+            match message.ok()?.content {
+                JupyterMessageContent::StreamContent(stream) => Some(stream.text),
+                _ => None,
+            }
+        }));
+
+        // A read is waiting on shell while the request goes out
+        let (reply_sender, reply) = oneshot::channel();
+        crate::runtime::spawn(async move {
+            let _ = reply_sender.send(receiver.next().await);
+        });
+        futures_timer::Delay::new(Duration::from_millis(50)).await;
+        sender
+            .send(session.message(KernelInfoRequest {}))
+            .await
+            .unwrap();
+        let reply = reply.await.unwrap().unwrap().unwrap();
+        assert!(matches!(
+            reply.content,
+            JupyterMessageContent::KernelInfoReply(_)
+        ));
+
+        sender
+            .send(session.message(ExecuteRequest::new("hello".to_string())))
+            .await
+            .unwrap();
+        assert_eq!(stdout.next().await.as_deref(), Some("hello"));
+    }
+
+    #[async_test]
+    async fn test_into_connection() {
+        let connection_info = start_echo_kernel().await;
+        let session = Session::new();
+        let shell = create_client_shell_connection(&connection_info, &session)
+            .await
+            .unwrap();
+        let mut stream = shell.into_stream();
+        stream
+            .send(session.message(KernelInfoRequest {}))
+            .await
+            .unwrap();
+
+        let mut shell = stream.into_connection().unwrap();
+        let reply = shell.read().await.unwrap();
+        assert!(matches!(
+            reply.content,
+            JupyterMessageContent::KernelInfoReply(_)
+        ));
+    }
+}
@@ -0,0 +1,338 @@
+//! Running code across several kernels at once.
+//!
+//! An [`Orchestrator`] drives a set of named [`KernelClient`]s concurrently, for
+//! parameter sweeps and test matrices. Each execution gets its own timeout, and
+//! the outcome for every kernel is gathered into an [`OrchestrationReport`]
+//! rather than failing fast on the first error. A kernel that times out is
+//! interrupted before its next execution, and skipped for the rest of the
+//! run if the interrupt isn't accepted.
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use jupyter_protocol::ExecuteRequest;
+
+use crate::client::{with_timeout, ControlOutcome, ExecutionResult, KernelClient};
+use crate::error::RuntimeError;
+
+/// How a single execution on a single kernel ended.
+#[derive(Debug)]
+pub enum KernelOutcome {
+    /// The kernel replied. The code itself may still have raised; see
+    /// [`ExecutionResult::is_ok`].
+    Completed(ExecutionResult),
+    /// No reply within the timeout. The kernel was interrupted afterwards.
+    TimedOut,
+    /// The request couldn't be sent or the reply couldn't be read.
+    Failed(RuntimeError),
+    /// Not run, because the kernel timed out earlier in the run and didn't
+    /// accept an interrupt.
+    Skipped,
+}
+
+/// The outcome of one piece of code on one kernel.
+#[derive(Debug)]
+pub struct KernelRun {
+    pub kernel: String,
+    pub code: String,
+    pub outcome: KernelOutcome,
+    pub elapsed: Duration,
+}
+
+impl KernelRun {
+    /// Whether the kernel replied with an `ok` status.
+    pub fn succeeded(&self) -> bool {
+        matches!(&self.outcome, KernelOutcome::Completed(result) if result.is_ok())
+    }
+}
+
+/// Every run from one orchestration, in submission order.
+#[derive(Debug)]
+pub struct OrchestrationReport {
+    pub runs: Vec<KernelRun>,
+}
+
+impl OrchestrationReport {
+    pub fn all_succeeded(&self) -> bool {
+        self.runs.iter().all(KernelRun::succeeded)
+    }
+
+    pub fn succeeded(&self) -> impl Iterator<Item = &KernelRun> {
+        self.runs.iter().filter(|run| run.succeeded())
+    }
+
+    /// Runs that raised, timed out, or failed to communicate.
+    pub fn failed(&self) -> impl Iterator<Item = &KernelRun> {
+        self.runs.iter().filter(|run| !run.succeeded())
+    }
+
+    pub fn timed_out(&self) -> impl Iterator<Item = &KernelRun> {
+        self.runs
+            .iter()
+            .filter(|run| matches!(run.outcome, KernelOutcome::TimedOut))
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &KernelRun> {
+        self.runs
+            .iter()
+            .filter(|run| matches!(run.outcome, KernelOutcome::Skipped))
+    }
+}
+
+struct NamedKernel {
+    name: String,
+    client: KernelClient,
+}
+
+pub struct Orchestrator {
+    kernels: Vec<NamedKernel>,
+    timeout: Duration,
+}
+
+impl Orchestrator {
+    /// Create an orchestrator where each execution may take up to `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            kernels: Vec::new(),
+            timeout,
+        }
+    }
+
+    pub fn with_kernel(mut self, name: impl Into<String>, client: KernelClient) -> Self {
+        self.add_kernel(name, client);
+        self
+    }
+
+    pub fn add_kernel(&mut self, name: impl Into<String>, client: KernelClient) {
+        self.kernels.push(NamedKernel {
+            name: name.into(),
+            client,
+        });
+    }
+
+    pub fn kernel_names(&self) -> impl Iterator<Item = &str> {
+        self.kernels.iter().map(|kernel| kernel.name.as_str())
+    }
+
+    /// Run the same code on every kernel concurrently.
+    pub async fn broadcast(&mut self, code: &str) -> OrchestrationReport {
+        self.broadcast_with(|_| code.to_string()).await
+    }
+
+    /// Run per-kernel code, built from each kernel's name, on every kernel concurrently.
+    pub async fn broadcast_with(
+        &mut self,
+        mut code_for: impl FnMut(&str) -> String,
+    ) -> OrchestrationReport {
+        let assignments = self
+            .kernels
+            .iter()
+            .map(|kernel| vec![code_for(&kernel.name)])
+            .collect();
+        self.run(assignments).await
+    }
+
+    /// Spread `codes` round-robin across the kernels, e.g. one entry per parameter set.
+    ///
+    /// Kernels work in parallel, and each kernel runs its share in order. The
+    /// report lists runs in the same order as `codes`.
+    pub async fn sweep(&mut self, codes: Vec<String>) -> OrchestrationReport {
+        if self.kernels.is_empty() {
+            return OrchestrationReport { runs: Vec::new() };
+        }
+
+        let mut assignments = vec![Vec::new(); self.kernels.len()];
+        let mut order = Vec::with_capacity(codes.len());
+        for (index, code) in codes.into_iter().enumerate() {
+            let kernel = index % self.kernels.len();
+            order.push((kernel, assignments[kernel].len()));
+            assignments[kernel].push(code);
+        }
+
+        let mut per_kernel: Vec<Vec<Option<KernelRun>>> = self
+            .run_per_kernel(assignments)
+            .await
+            .into_iter()
+            .map(|runs| runs.into_iter().map(Some).collect())
+            .collect();
+
+        let runs = order
+            .into_iter()
+            .filter_map(|(kernel, position)| per_kernel[kernel][position].take())
+            .collect();
+        OrchestrationReport { runs }
+    }
+
+    async fn run(&mut self, assignments: Vec<Vec<String>>) -> OrchestrationReport {
+        let runs = self
+            .run_per_kernel(assignments)
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        OrchestrationReport { runs }
+    }
+
+    async fn run_per_kernel(&mut self, assignments: Vec<Vec<String>>) -> Vec<Vec<KernelRun>> {
+        let timeout = self.timeout;
+        let kernels = self
+            .kernels
+            .iter_mut()
+            .zip(assignments)
+            .map(|(kernel, codes)| async move {
+                let mut runs = Vec::with_capacity(codes.len());
+                let mut unresponsive = false;
+                for code in codes {
+                    if unresponsive {
+                        runs.push(KernelRun {
+                            kernel: kernel.name.clone(),
+                            code,
+                            outcome: KernelOutcome::Skipped,
+                            elapsed: Duration::ZERO,
+                        });
+                        continue;
+                    }
+                    let started = Instant::now();
+                    let outcome = match with_timeout(
+                        timeout,
+                        kernel.client.execute(ExecuteRequest::new(code.clone())),
+                    )
+                    .await
+                    {
+                        Some(Ok(result)) => KernelOutcome::Completed(result),
+                        Some(Err(error)) => KernelOutcome::Failed(error),
+                        None => KernelOutcome::TimedOut,
+                    };
+                    let elapsed = started.elapsed();
+                    if matches!(outcome, KernelOutcome::TimedOut) {
+                        // The kernel is still busy with this code until it's interrupted
+                        unresponsive = !matches!(
+                            kernel.client.interrupt().await,
+                            Ok(ControlOutcome::Accepted)
+                        );
+                    }
+                    runs.push(KernelRun {
+                        kernel: kernel.name.clone(),
+                        code,
+                        outcome,
+                        elapsed,
+                    });
+                }
+                runs
+            });
+        join_all(kernels).await
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "tokio-runtime", feature = "async-dispatcher-runtime")
+))]
+mod tests {
+    use super::*;
+    use crate::client::tests::{connect_client, start_echo_kernel};
+    use crate::client::InterruptMode;
+    use crate::runtime::async_test;
+
+    async fn orchestrator(kernels: usize, timeout: Duration) -> Orchestrator {
+        let mut orchestrator = Orchestrator::new(timeout);
+        for index in 0..kernels {
+            let connection_info = start_echo_kernel().await;
+            orchestrator.add_kernel(
+                format!("kernel-{index}"),
+                connect_client(&connection_info).await,
+            );
+        }
+        orchestrator
+    }
+
+    #[async_test]
+    async fn test_broadcast_with_gathers_every_kernel() {
+        let mut orchestrator = orchestrator(3, Duration::from_secs(5)).await;
+
+        let report = orchestrator
+            .broadcast_with(|name| format!("hello from {name}"))
+            .await;
+
+        assert!(report.all_succeeded());
+        let outputs: Vec<String> = report
+            .runs
+            .iter()
+            .map(|run| match &run.outcome {
+                KernelOutcome::Completed(result) => result.stdout(),
+                other => panic!("unexpected outcome {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            outputs,
+            vec![
+                "hello from kernel-0",
+                "hello from kernel-1",
+                "hello from kernel-2"
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn test_sweep_preserves_order_and_reports_failures() {
+        let mut orchestrator = orchestrator(2, Duration::from_millis(500)).await;
+
+        let report = orchestrator
+            .sweep(vec![
+                "a = 1".to_string(),
+                "raise boom".to_string(),
+                "a = 3".to_string(),
+                "sleep".to_string(),
+            ])
+            .await;
+
+        let kernels: Vec<&str> = report.runs.iter().map(|run| run.kernel.as_str()).collect();
+        assert_eq!(
+            kernels,
+            vec!["kernel-0", "kernel-1", "kernel-0", "kernel-1"]
+        );
+        assert_eq!(report.succeeded().count(), 2);
+        assert_eq!(report.failed().count(), 2);
+        assert_eq!(report.timed_out().count(), 1);
+        assert_eq!(report.timed_out().next().unwrap().code, "sleep");
+    }
+
+    #[async_test]
+    async fn test_sweep_interrupts_kernels_that_time_out() {
+        let mut orchestrator = orchestrator(1, Duration::from_millis(500)).await;
+
+        let report = orchestrator
+            .sweep(vec!["sleep".to_string(), "after".to_string()])
+            .await;
+
+        assert_eq!(report.timed_out().next().unwrap().code, "sleep");
+        let after = report.succeeded().next().unwrap();
+        assert_eq!(after.code, "after");
+        match &after.outcome {
+            KernelOutcome::Completed(result) => assert_eq!(result.stdout(), "after"),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+
+    #[async_test]
+    async fn test_sweep_skips_kernels_that_cant_be_interrupted() {
+        let connection_info = start_echo_kernel().await;
+        let client = connect_client(&connection_info)
+            .await
+            .with_interrupt_mode(InterruptMode::Signal);
+        let mut orchestrator =
+            Orchestrator::new(Duration::from_millis(500)).with_kernel("signal", client);
+
+        let report = orchestrator
+            .sweep(vec![
+                "sleep".to_string(),
+                "after".to_string(),
+                "again".to_string(),
+            ])
+            .await;
+
+        assert_eq!(report.timed_out().count(), 1);
+        let skipped: Vec<&str> = report.skipped().map(|run| run.code.as_str()).collect();
+        assert_eq!(skipped, vec!["after", "again"]);
+        assert!(report.skipped().all(|run| run.elapsed.is_zero()));
+    }
+}
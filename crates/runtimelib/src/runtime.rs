@@ -0,0 +1,74 @@
+//! What differs between the `tokio-runtime` and `async-dispatcher-runtime` features.
+//!
+//! zeromq spawns background tasks for bound sockets and reconnects. Under
+//! tokio they go on the current tokio runtime. Under `async-dispatcher-runtime`
+//! they go through `async_dispatcher`, which panics unless a dispatcher has
+//! been installed, so connections install one backed by smol's global executor
+//! first. Applications that want their own dispatcher just need to call
+//! `async_dispatcher::set_dispatcher` before creating any connections.
+
+#[cfg(feature = "tokio-runtime")]
+pub(crate) fn ensure_dispatcher() {}
+
+#[cfg(feature = "async-dispatcher-runtime")]
+pub(crate) fn ensure_dispatcher() {
+    use async_dispatcher::{Dispatcher, Runnable};
+    use std::time::Duration;
+
+    struct SmolDispatcher;
+
+    impl Dispatcher for SmolDispatcher {
+        fn dispatch(&self, runnable: Runnable) {
+            smol::spawn(async move {
+                runnable.run();
+            })
+            .detach();
+        }
+
+        fn dispatch_after(&self, duration: Duration, runnable: Runnable) {
+            smol::spawn(async move {
+                smol::Timer::after(duration).await;
+                runnable.run();
+            })
+            .detach();
+        }
+    }
+
+    // Does nothing if a dispatcher is already installed
+    async_dispatcher::set_dispatcher(SmolDispatcher);
+}
+
+/// Run `future` in the background on whichever runtime is enabled.
+pub(crate) fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    #[cfg(feature = "tokio-runtime")]
+    tokio::spawn(future);
+    #[cfg(feature = "async-dispatcher-runtime")]
+    smol::spawn(future).detach();
+}
+
+/// Run `command` to completion on whichever runtime is enabled, collecting
+/// its output. The process is killed if the future is dropped first.
+pub(crate) async fn output(
+    command: std::process::Command,
+) -> std::io::Result<std::process::Output> {
+    #[cfg(feature = "tokio-runtime")]
+    return tokio::process::Command::from(command)
+        .kill_on_drop(true)
+        .output()
+        .await;
+    #[cfg(feature = "async-dispatcher-runtime")]
+    return smol::process::Command::from(command)
+        .kill_on_drop(true)
+        .output()
+        .await;
+}
+
+/// `#[async_test]` runs an async test on whichever runtime is enabled.
+#[cfg(all(test, feature = "tokio-runtime"))]
+pub(crate) use tokio::test as async_test;
+
+#[cfg(all(test, feature = "async-dispatcher-runtime"))]
+pub(crate) use async_std::test as async_test;
@@ -0,0 +1,85 @@
+"""Regenerate the signed message fixtures in this directory.
+
+Each message is serialized and signed by `jupyter_client.session.Session`,
+and the version of jupyter_client that did it is recorded in the fixture.
+`json_dumps_*` fixtures use the session's default `json_packer`.
+`canonical_*` fixtures swap in a packer with sorted keys and compact
+separators, which is what runtimelib sends with canonical JSON enabled.
+
+    pip install jupyter_client
+    python3 generate.py
+"""
+
+import json
+from pathlib import Path
+
+import jupyter_client
+from jupyter_client.session import Session
+
+KEY = b"b0e8c1f2-9a3d-4c5e-8f71-6d2a0b9c3e4f"
+
+
+def canonical_packer(obj):
+    return json.dumps(
+        obj, sort_keys=True, separators=(",", ":"), ensure_ascii=False, allow_nan=False
+    ).encode("utf8")
+
+
+def serialize(msg, pack=None):
+    session = Session(key=KEY, signature_scheme="hmac-sha256")
+    if pack is not None:
+        session.pack = pack
+    return session.serialize(msg)
+
+
+def header(msg_id, msg_type):
+    return {
+        "msg_id": msg_id,
+        "username": "username",
+        "session": "5f1a2b3c-4d5e-6f70-8192-a3b4c5d6e7f8",
+        "date": "2024-05-04T12:30:45.123456Z",
+        "msg_type": msg_type,
+        "version": "5.3",
+    }
+
+
+execute_request = {
+    "header": header("0a1b2c3d-0000-4000-8000-000000000001", "execute_request"),
+    "parent_header": {},
+    "metadata": {"cellId": "c1", "deletedCells": [], "recordTiming": False},
+    "content": {
+        "code": "print('héllo ☃')\n",
+        "silent": False,
+        "store_history": True,
+        "user_expressions": {},
+        "allow_stdin": True,
+        "stop_on_error": True,
+    },
+}
+
+execute_result = {
+    "header": header("0a1b2c3d-0000-4000-8000-000000000002", "execute_result"),
+    "parent_header": execute_request["header"],
+    "metadata": {"timings": {"elapsed": 1.5e-05, "total": 1e16, "ratio": 0.1}},
+    "content": {
+        "execution_count": 3,
+        "data": {"text/plain": "'héllo ☃'"},
+        "metadata": {},
+    },
+}
+
+fixtures = {
+    "json_dumps_execute_request.json": serialize(execute_request),
+    "json_dumps_execute_result.json": serialize(execute_result),
+    "canonical_execute_request.json": serialize(execute_request, canonical_packer),
+    "canonical_execute_result.json": serialize(execute_result, canonical_packer),
+}
+
+here = Path(__file__).parent
+for name, parts in fixtures.items():
+    document = {
+        "jupyter_client": jupyter_client.__version__,
+        "key": KEY.decode("ascii"),
+        "parts": [part.decode("utf8") for part in parts],
+    }
+    (here / name).write_text(json.dumps(document, indent=1, ensure_ascii=False) + "\n")
@@ -0,0 +1,194 @@
+//! Rate limiting for messages on their way to the webview.
+//!
+//! Every `evaluate_script` call costs a trip through the webview, so a tight
+//! print loop sending thousands of stream messages a second freezes the UI.
+//! Messages are instead delivered in batches, at most a fixed number of times
+//! a second, with consecutive stream output merged. Message order is kept.
+use futures::{channel::mpsc, StreamExt};
+use jupyter_protocol::{JupyterMessage, JupyterMessageContent, Stdio};
+use smol::Timer;
+use std::time::{Duration, Instant};
+
+/// How many batches go to the webview per second unless `--max-updates` says otherwise.
+pub const DEFAULT_MAX_UPDATES_PER_SECOND: u32 = 30;
+
+/// Forward `messages` to `deliver` in coalesced batches, at most
+/// `max_updates_per_second` times a second.
+///
+/// A message arriving after a quiet period is delivered right away. Stops when
+/// `messages` ends or `deliver` returns `false`.
+pub async fn forward_batches(
+    mut messages: mpsc::UnboundedReceiver<JupyterMessage>,
+    max_updates_per_second: u32,
+    mut deliver: impl FnMut(Vec<JupyterMessage>) -> bool,
+) {
+    let interval = Duration::from_secs(1) / max_updates_per_second.max(1);
+    let mut next_delivery = Instant::now();
+
+    while let Some(message) = messages.next().await {
+        // Collect whatever else arrives before the next delivery is due
+        Timer::at(next_delivery).await;
+        let mut batch = vec![message];
+        while let Ok(Some(message)) = messages.try_next() {
+            batch.push(message);
+        }
+
+        next_delivery = Instant::now() + interval;
+        if !deliver(coalesce(batch)) {
+            break;
+        }
+    }
+}
+
+/// Merge runs of `stream` messages for the same stream and parent request
+/// into one message, keeping everything else as is.
+pub fn coalesce(messages: Vec<JupyterMessage>) -> Vec<JupyterMessage> {
+    let mut coalesced: Vec<JupyterMessage> = Vec::with_capacity(messages.len());
+    for message in messages {
+        if let Some(previous) = coalesced.last_mut() {
+            if same_parent(previous, &message) {
+                if let (
+                    JupyterMessageContent::StreamContent(previous),
+                    JupyterMessageContent::StreamContent(stream),
+                ) = (&mut previous.content, &message.content)
+                {
+                    if same_stream(&previous.name, &stream.name) {
+                        previous.text.push_str(&stream.text);
+                        continue;
+                    }
+                }
+            }
+        }
+        coalesced.push(message);
+    }
+    coalesced
+}
+
+fn same_parent(a: &JupyterMessage, b: &JupyterMessage) -> bool {
+    match (&a.parent_header, &b.parent_header) {
+        (Some(a), Some(b)) => a.msg_id == b.msg_id,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn same_stream(a: &Stdio, b: &Stdio) -> bool {
+    matches!(
+        (a, b),
+        (Stdio::Stdout, Stdio::Stdout) | (Stdio::Stderr, Stdio::Stderr)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jupyter_protocol::{ExecuteRequest, Status, StreamContent};
+
+    fn text(message: &JupyterMessage) -> &str {
+        match &message.content {
+            JupyterMessageContent::StreamContent(stream) => &stream.text,
+            other => panic!("expected a stream message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_merges_runs_of_one_stream() {
+        let request = JupyterMessage::new(ExecuteRequest::new("print()".to_string()), None);
+        let messages = vec![
+            JupyterMessage::new(StreamContent::stdout("a"), Some(&request)),
+            JupyterMessage::new(StreamContent::stdout("b\n"), Some(&request)),
+            JupyterMessage::new(StreamContent::stdout("c"), Some(&request)),
+        ];
+        let first_msg_id = messages[0].header.msg_id.clone();
+
+        let coalesced = coalesce(messages);
+        assert_eq!(coalesced.len(), 1);
+        assert_eq!(text(&coalesced[0]), "ab\nc");
+        // The merged message is the first one, with the rest's text
+        assert_eq!(coalesced[0].header.msg_id, first_msg_id);
+    }
+
+    #[test]
+    fn test_keeps_streams_parents_and_order_apart() {
+        let first = JupyterMessage::new(ExecuteRequest::new("1".to_string()), None);
+        let second = JupyterMessage::new(ExecuteRequest::new("2".to_string()), None);
+        let messages = vec![
+            JupyterMessage::new(StreamContent::stdout("out"), Some(&first)),
+            JupyterMessage::new(StreamContent::stderr("err"), Some(&first)),
+            JupyterMessage::new(StreamContent::stderr("err"), Some(&second)),
+            JupyterMessage::new(Status::idle(), Some(&second)),
+            JupyterMessage::new(StreamContent::stderr("after"), Some(&second)),
+            JupyterMessage::new(StreamContent::stderr("unparented"), None),
+        ];
+
+        let coalesced = coalesce(messages);
+        let types: Vec<_> = coalesced
+            .iter()
+            .map(|message| message.header.msg_type.as_str())
+            .collect();
+        assert_eq!(
+            types,
+            ["stream", "stream", "stream", "status", "stream", "stream"]
+        );
+        assert_eq!(text(&coalesced[4]), "after");
+    }
+
+    #[test]
+    fn test_keeps_carriage_returns() {
+        // Progress bars redraw their line with `\r`. The frontend handles that
+        // on the merged text, so it has to come through as sent, including a
+        // `\r\n` split across two messages.
+        let request = JupyterMessage::new(ExecuteRequest::new("tqdm()".to_string()), None);
+        let messages = vec![
+            JupyterMessage::new(StreamContent::stderr("\r 10%"), Some(&request)),
+            JupyterMessage::new(StreamContent::stderr("\r 50%"), Some(&request)),
+            JupyterMessage::new(StreamContent::stderr("\r100%\r"), Some(&request)),
+            JupyterMessage::new(StreamContent::stderr("\ndone"), Some(&request)),
+        ];
+
+        let coalesced = coalesce(messages);
+        assert_eq!(coalesced.len(), 1);
+        assert_eq!(text(&coalesced[0]), "\r 10%\r 50%\r100%\r\ndone");
+    }
+
+    #[test]
+    fn test_forward_batches() {
+        let (sender, receiver) = mpsc::unbounded();
+        let request = JupyterMessage::new(ExecuteRequest::new("print()".to_string()), None);
+        for text in ["a", "b", "c"] {
+            sender
+                .unbounded_send(JupyterMessage::new(
+                    StreamContent::stdout(text),
+                    Some(&request),
+                ))
+                .unwrap();
+        }
+        drop(sender);
+
+        let mut batches = Vec::new();
+        smol::block_on(forward_batches(receiver, 1000, |batch| {
+            batches.push(batch);
+            true
+        }));
+        // Everything queued before the first delivery goes out together
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 1);
+        assert_eq!(text(&batches[0][0]), "abc");
+    }
+
+    #[test]
+    fn test_forward_batches_stops_when_delivery_fails() {
+        let (sender, receiver) = mpsc::unbounded();
+        sender
+            .unbounded_send(JupyterMessage::new(StreamContent::stdout("a"), None))
+            .unwrap();
+
+        let mut deliveries = 0;
+        // The sender is still open, so this only returns because delivery failed
+        smol::block_on(forward_batches(receiver, 1000, |_| {
+            deliveries += 1;
+            false
+        }));
+        assert_eq!(deliveries, 1);
+    }
+}
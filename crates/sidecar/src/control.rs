@@ -0,0 +1,87 @@
+//! Kernel control actions for the toolbar: interrupt, restart and shutdown.
+//!
+//! Each action opens its own control connection, so a kernel that's stuck
+//! running code on shell can still be interrupted or shut down.
+use anyhow::Result;
+use jupyter_protocol::{
+    ConnectionInfo, InterruptRequest, JupyterMessageContent, Session, ShutdownRequest,
+};
+use runtimelib::{client::DEFAULT_CONTROL_TIMEOUT, ControlOutcome};
+use smol::{future, Timer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlAction {
+    Interrupt,
+    Restart,
+    Shutdown,
+}
+
+impl ControlAction {
+    /// The action for a `/control/<action>` request path.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.strip_prefix("/control/")? {
+            "interrupt" => Some(ControlAction::Interrupt),
+            "restart" => Some(ControlAction::Restart),
+            "shutdown" => Some(ControlAction::Shutdown),
+            _ => None,
+        }
+    }
+
+    fn request(self) -> JupyterMessageContent {
+        match self {
+            ControlAction::Interrupt => InterruptRequest {}.into(),
+            ControlAction::Restart => ShutdownRequest { restart: true }.into(),
+            ControlAction::Shutdown => ShutdownRequest { restart: false }.into(),
+        }
+    }
+}
+
+/// What's needed to reach the kernel's control channel.
+#[derive(Clone)]
+pub struct Controller {
+    pub connection_info: ConnectionInfo,
+    pub session: Session,
+}
+
+impl Controller {
+    /// Send `action` to the kernel and wait for its reply.
+    pub async fn send(&self, action: ControlAction) -> Result<ControlOutcome> {
+        let mut control =
+            runtimelib::create_client_control_connection(&self.connection_info, &self.session)
+                .await?;
+
+        let message = self.session.message(action.request());
+        let msg_id = message.header.msg_id.clone();
+        control.send(message).await?;
+
+        let reply = async {
+            loop {
+                let message = control.read().await?;
+                if message
+                    .parent_header
+                    .as_ref()
+                    .is_some_and(|parent| parent.msg_id == msg_id)
+                {
+                    return anyhow::Ok(Some(message));
+                }
+            }
+        };
+        let timeout = async {
+            Timer::after(DEFAULT_CONTROL_TIMEOUT).await;
+            Ok(None)
+        };
+
+        let Some(message) = future::or(reply, timeout).await? else {
+            return Ok(ControlOutcome::TimedOut);
+        };
+        Ok(match message.content {
+            JupyterMessageContent::InterruptReply(reply) => {
+                ControlOutcome::from_interrupt_reply(reply, &message.header.version)
+            }
+            JupyterMessageContent::ShutdownReply(reply) => {
+                ControlOutcome::from_reply(reply.status, reply.error)
+            }
+            _ => ControlOutcome::TimedOut,
+        })
+    }
+}
@@ -0,0 +1,161 @@
+//! A gallery of every plot the kernel has shown.
+//!
+//! Plots scroll away with the rest of the output. Each image or Plotly output
+//! is also kept here, with a small PNG thumbnail, so the window can show them
+//! side by side. `GET /gallery` lists the plots without their data, and
+//! `GET /gallery/<id>` returns one plot's full MIME bundle. New plots reach
+//! the window as `onGalleryUpdate` events.
+use base64::prelude::*;
+use jupyter_protocol::{JupyterMessage, JupyterMessageContent, Media, MediaType};
+use log::error;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+
+/// Thumbnails fit in a square this many pixels wide.
+pub const THUMBNAIL_SIZE: u32 = 160;
+
+/// Plots kept before the oldest ones are dropped.
+pub const MAX_GALLERY_ITEMS: usize = 500;
+
+/// One plot, as `GET /gallery` lists it.
+#[derive(Debug, Clone, Serialize)]
+pub struct GalleryItem {
+    pub id: usize,
+    pub msg_id: String,
+    pub execution_count: Option<usize>,
+    /// When the kernel sent the plot, in RFC 3339
+    pub date: String,
+    /// The MIME type the plot is shown as: an image type or Plotly
+    pub mime_type: String,
+    /// A data URL small enough to list, if one could be made
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Gallery {
+    items: VecDeque<(GalleryItem, Media)>,
+    next_id: usize,
+    /// Execution counts by the `msg_id` of their `execute_request`
+    execution_counts: HashMap<String, usize>,
+}
+
+impl Gallery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the plot in `message`, if it has one, returning its item.
+    pub fn record(&mut self, message: &JupyterMessage) -> Option<GalleryItem> {
+        let parent_msg_id = message
+            .parent_header
+            .as_ref()
+            .map(|parent| parent.msg_id.as_str());
+
+        let (data, execution_count) = match &message.content {
+            JupyterMessageContent::ExecuteInput(input) => {
+                if let Some(parent_msg_id) = parent_msg_id {
+                    self.execution_counts
+                        .insert(parent_msg_id.to_string(), input.execution_count.value());
+                }
+                return None;
+            }
+            JupyterMessageContent::ExecuteResult(result) => {
+                (&result.data, Some(result.execution_count.value()))
+            }
+            JupyterMessageContent::DisplayData(display_data) => (&display_data.data, None),
+            JupyterMessageContent::UpdateDisplayData(update) => (&update.data, None),
+            _ => return None,
+        };
+        let mime_type = plot_mime_type(data)?;
+        let execution_count = execution_count.or_else(|| {
+            parent_msg_id
+                .and_then(|parent_msg_id| self.execution_counts.get(parent_msg_id).copied())
+        });
+
+        let item = GalleryItem {
+            id: self.next_id,
+            msg_id: message.header.msg_id.clone(),
+            execution_count,
+            date: message.header.date.to_rfc3339(),
+            mime_type: mime_type.to_string(),
+            thumbnail: thumbnail(data),
+        };
+        self.next_id += 1;
+        if self.items.len() == MAX_GALLERY_ITEMS {
+            self.items.pop_front();
+        }
+        self.items.push_back((item.clone(), data.clone()));
+        Some(item)
+    }
+
+    /// Every plot, oldest first.
+    pub fn items(&self) -> Vec<&GalleryItem> {
+        self.items.iter().map(|(item, _)| item).collect()
+    }
+
+    /// The full MIME bundle of the plot with `id`.
+    pub fn data(&self, id: usize) -> Option<&Media> {
+        self.items
+            .iter()
+            .find(|(item, _)| item.id == id)
+            .map(|(_, data)| data)
+    }
+}
+
+/// The type a bundle's plot is shown as, preferring interactive Plotly over
+/// the static images that often come with it.
+fn plot_mime_type(data: &Media) -> Option<&'static str> {
+    let mut best = None;
+    for media_type in &data.content {
+        let (rank, mime_type) = match media_type {
+            MediaType::Plotly(_) => (0, "application/vnd.plotly.v1+json"),
+            MediaType::Png(_) => (1, "image/png"),
+            MediaType::Jpeg(_) => (2, "image/jpeg"),
+            MediaType::Gif(_) => (3, "image/gif"),
+            MediaType::Svg(_) => (4, "image/svg+xml"),
+            _ => continue,
+        };
+        if best.is_none_or(|(best_rank, _)| rank < best_rank) {
+            best = Some((rank, mime_type));
+        }
+    }
+    best.map(|(_, mime_type)| mime_type)
+}
+
+/// A thumbnail from the first image in `data`. Raster images are scaled
+/// down to a PNG, and SVGs are small enough to use as they are. Plotly
+/// outputs without an image don't get one.
+fn thumbnail(data: &Media) -> Option<String> {
+    data.content.iter().find_map(|media_type| match media_type {
+        MediaType::Png(encoded) | MediaType::Jpeg(encoded) | MediaType::Gif(encoded) => {
+            match scaled_png(encoded) {
+                Ok(png) => Some(format!(
+                    "data:image/png;base64,{}",
+                    BASE64_STANDARD.encode(png)
+                )),
+                Err(e) => {
+                    error!("Failed to make a thumbnail: {}", e);
+                    None
+                }
+            }
+        }
+        MediaType::Svg(svg) => Some(format!(
+            "data:image/svg+xml;base64,{}",
+            BASE64_STANDARD.encode(svg)
+        )),
+        _ => None,
+    })
+}
+
+/// Decode a base64 image and scale it to fit in [`THUMBNAIL_SIZE`], as a PNG.
+fn scaled_png(encoded: &str) -> anyhow::Result<Vec<u8>> {
+    // Notebooks often wrap base64 across lines
+    let encoded: String = encoded.split_whitespace().collect();
+    let image = image::load_from_memory(&BASE64_STANDARD.decode(encoded)?)?;
+    let mut png = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+    Ok(png)
+}
@@ -0,0 +1,109 @@
+//! Headless mode: bridge kernel messages over a local WebSocket instead of a webview.
+//!
+//! Every iopub message is broadcast to all connected clients as JSON, in the same
+//! shape the webview receives. Messages sent by clients are forwarded to the
+//! kernel's shell channel.
+use anyhow::Result;
+use async_tungstenite::tungstenite::Message;
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use jupyter_protocol::JupyterMessage;
+use log::{debug, error, info};
+use runtimelib::ClientIoPubConnection;
+use smol::{
+    lock::Mutex,
+    net::{TcpListener, TcpStream},
+};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::WryJupyterMessage;
+
+type Clients = Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>;
+
+pub async fn run(
+    mut iopub: ClientIoPubConnection,
+    shell_tx: mpsc::Sender<JupyterMessage>,
+    addr: SocketAddr,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Headless sidecar listening on ws://{}", local_addr);
+    // Always print the address so scripts can pick up an ephemeral port
+    println!("ws://{}", local_addr);
+
+    let clients: Clients = Arc::default();
+
+    let accept_clients = clients.clone();
+    smol::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let clients = accept_clients.clone();
+                    let shell_tx = shell_tx.clone();
+                    smol::spawn(async move {
+                        if let Err(e) = handle_client(stream, peer, clients, shell_tx).await {
+                            error!("WebSocket client {} failed: {}", peer, e);
+                        }
+                    })
+                    .detach();
+                }
+                Err(e) => error!("Failed to accept connection: {}", e),
+            }
+        }
+    })
+    .detach();
+
+    while let Ok(message) = iopub.read().await {
+        debug!("Received message from iopub: {:?}", message);
+        let serialized: WryJupyterMessage = message.into();
+        match serde_json::to_string(&serialized) {
+            Ok(serialized_message) => {
+                // Drop clients whose connection has gone away
+                clients
+                    .lock()
+                    .await
+                    .retain(|client| client.unbounded_send(serialized_message.clone()).is_ok());
+            }
+            Err(e) => error!("Failed to serialize message: {}", e),
+        }
+    }
+
+    info!("iopub connection closed, shutting down");
+    Ok(())
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    clients: Clients,
+    mut shell_tx: mpsc::Sender<JupyterMessage>,
+) -> Result<()> {
+    let websocket = async_tungstenite::accept_async(stream).await?;
+    info!("WebSocket client connected: {}", peer);
+
+    let (mut sink, mut stream) = websocket.split();
+    let (tx, mut rx) = mpsc::unbounded::<String>();
+    clients.lock().await.push(tx);
+
+    let forward = smol::spawn(async move {
+        while let Some(text) = rx.next().await {
+            if sink.send(Message::text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(message) = stream.next().await {
+        match message? {
+            Message::Text(text) => match serde_json::from_str::<WryJupyterMessage>(&text) {
+                Ok(wry_message) => shell_tx.send(wry_message.into()).await?,
+                Err(e) => error!("Failed to deserialize message from {}: {}", peer, e),
+            },
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    forward.cancel().await;
+    info!("WebSocket client disconnected: {}", peer);
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! A searchable index of the messages the sidecar has received.
+//!
+//! Long-running sessions scroll far past the error you're looking for. Each
+//! iopub message is indexed by its type, the execution count of the cell that
+//! produced it and its text, and the webview queries the index with
+//! `GET /search?q=...&types=stream,error&execution_count=3`, parsed and
+//! matched by [`crate::search`].
+//!
+//! With `--index-file`, entries are also appended to a JSON lines file and
+//! read back on the next start, so search covers earlier sidecar sessions too.
+use crate::search::{search, SearchQuery};
+use anyhow::Result;
+use jupyter_protocol::{JupyterMessage, JupyterMessageContent, Media, MediaType};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// One indexed message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub msg_id: String,
+    pub msg_type: String,
+    /// The request that produced the message, usually an `execute_request`
+    pub parent_msg_id: Option<String>,
+    pub execution_count: Option<usize>,
+    /// When the kernel sent the message, in RFC 3339
+    pub date: String,
+    /// Text the message shows, for matching queries against
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct MessageIndex {
+    entries: Vec<IndexEntry>,
+    /// Execution counts by the `msg_id` of their `execute_request`
+    execution_counts: HashMap<String, usize>,
+    file: Option<File>,
+}
+
+impl MessageIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An index that persists to `path`, starting with the entries already there.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut index = Self::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                match serde_json::from_str::<IndexEntry>(&line?) {
+                    Ok(entry) => index.entries.push(entry),
+                    Err(e) => error!("Skipping unreadable index entry: {}", e),
+                }
+            }
+        }
+        index.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(index)
+    }
+
+    pub fn record(&mut self, message: &JupyterMessage) {
+        if let JupyterMessageContent::Status(_) = message.content {
+            return;
+        }
+        let parent_msg_id = message
+            .parent_header
+            .as_ref()
+            .map(|parent| parent.msg_id.clone());
+
+        let execution_count = match &message.content {
+            JupyterMessageContent::ExecuteInput(input) => Some(input.execution_count.value()),
+            JupyterMessageContent::ExecuteResult(result) => Some(result.execution_count.value()),
+            _ => None,
+        };
+        let execution_count = match (execution_count, &parent_msg_id) {
+            (Some(count), Some(parent_msg_id)) => {
+                self.execution_counts.insert(parent_msg_id.clone(), count);
+                Some(count)
+            }
+            (Some(count), None) => Some(count),
+            (None, Some(parent_msg_id)) => self.execution_counts.get(parent_msg_id).copied(),
+            (None, None) => None,
+        };
+
+        let entry = IndexEntry {
+            msg_id: message.header.msg_id.clone(),
+            msg_type: message.message_type().to_string(),
+            parent_msg_id,
+            execution_count,
+            date: message.header.date.to_rfc3339(),
+            text: searchable_text(&message.content),
+        };
+        if let Some(file) = &mut self.file {
+            let written = serde_json::to_string(&entry)
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(writeln!(file, "{}", line)?));
+            if let Err(e) = written {
+                error!("Failed to write to the message index: {}", e);
+            }
+        }
+        self.entries.push(entry);
+    }
+
+    /// Entries matching `query`, most recent first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<&IndexEntry> {
+        search(&self.entries, query)
+    }
+}
+
+/// The text a message shows: stream output, tracebacks, the code of an
+/// `execute_input` and the textual parts of rich outputs.
+fn searchable_text(content: &JupyterMessageContent) -> String {
+    match content {
+        JupyterMessageContent::StreamContent(stream) => stream.text.clone(),
+        JupyterMessageContent::ErrorOutput(error) => {
+            format!(
+                "{}: {}\n{}",
+                error.ename,
+                error.evalue,
+                error.traceback_plain()
+            )
+        }
+        JupyterMessageContent::ExecuteInput(input) => input.code.clone(),
+        JupyterMessageContent::DisplayData(display_data) => media_text(&display_data.data),
+        JupyterMessageContent::ExecuteResult(result) => media_text(&result.data),
+        JupyterMessageContent::UpdateDisplayData(update) => media_text(&update.data),
+        _ => String::new(),
+    }
+}
+
+fn media_text(media: &Media) -> String {
+    media
+        .content
+        .iter()
+        .filter_map(|media_type| match media_type {
+            MediaType::Plain(text)
+            | MediaType::Markdown(text)
+            | MediaType::Latex(text)
+            | MediaType::Html(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -6,16 +6,23 @@ use env_logger;
 use futures::StreamExt;
 use log::{debug, error, info};
 
-use jupyter_protocol::{Channel, ConnectionInfo, Header, JupyterMessage, JupyterMessageContent};
+use jupyter_protocol::media::sanitize::SanitizePolicy;
+use jupyter_protocol::media::TabularDataResource;
+use jupyter_protocol::{
+    Channel, ConnectionInfo, DisplayData, ExecuteResult, Header, JupyterMessage,
+    JupyterMessageContent, KernelInfoCard, UpdateDisplayData,
+};
 
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 use smol::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use tao::{
     dpi::Size,
     event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop, EventLoopBuilder},
+    event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy},
     window::{Window, WindowBuilder},
 };
 use wry::{
@@ -23,6 +30,21 @@ use wry::{
     WebViewBuilder,
 };
 
+mod coalesce;
+mod control;
+mod gallery;
+mod headless;
+mod index;
+mod search;
+mod ui_state;
+mod variables;
+
+use control::{ControlAction, Controller};
+use gallery::{Gallery, GalleryItem};
+use index::MessageIndex;
+use search::SearchQuery;
+use ui_state::{UiChange, UiState, UiStore};
+
 #[derive(Parser)]
 #[clap(name = "sidecar", version = "0.1.0", author = "Kyle Kelley")]
 struct Cli {
@@ -32,6 +54,38 @@ struct Cli {
     /// Suppress output
     #[clap(short, long)]
     quiet: bool,
+
+    /// Run without a window, bridging messages over a local WebSocket instead
+    #[clap(long)]
+    headless: bool,
+
+    /// Port for the headless WebSocket server. Defaults to an open port.
+    #[clap(long, default_value_t = 0, requires = "headless")]
+    port: u16,
+
+    /// Most times per second to send new output to the window
+    #[clap(long, default_value_t = coalesce::DEFAULT_MAX_UPDATES_PER_SECOND)]
+    max_updates: u32,
+
+    /// Keep the searchable message index in this file, so it lasts across sessions
+    #[clap(long)]
+    index_file: Option<PathBuf>,
+
+    /// Keep collapsed and pinned outputs and the scroll lock in this file
+    #[clap(long)]
+    ui_state_file: Option<PathBuf>,
+}
+
+/// What the background tasks hand to the window's event loop.
+enum SidecarEvent {
+    /// iopub messages, coalesced
+    Messages(Vec<JupyterMessage>),
+    /// The kernel's variables, after an execution finished
+    Variables(TabularDataResource),
+    /// How the window is arranged, after it changed
+    UiState(UiState),
+    /// A plot was added to the gallery
+    Gallery(GalleryItem),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,11 +105,22 @@ struct WryJupyterMessage {
 
 impl From<JupyterMessage> for WryJupyterMessage {
     fn from(msg: JupyterMessage) -> Self {
+        let mut content = msg.content;
+        // The window only shows HTML, so Markdown-only outputs (like the Ollama
+        // kernel's answers) get HTML rendered the same way `runt export` does
+        match &mut content {
+            JupyterMessageContent::DisplayData(DisplayData { data, .. })
+            | JupyterMessageContent::UpdateDisplayData(UpdateDisplayData { data, .. })
+            | JupyterMessageContent::ExecuteResult(ExecuteResult { data, .. }) => {
+                data.render_markdown(SanitizePolicy::AllowStyles)
+            }
+            _ => {}
+        }
         WryJupyterMessage {
             header: msg.header,
             parent_header: msg.parent_header,
             metadata: msg.metadata,
-            content: msg.content,
+            content,
             buffers: msg.buffers,
             channel: msg.channel,
         }
@@ -104,23 +169,23 @@ where
         .collect()
 }
 
-async fn run(
+/// Connect to the kernel, returning the iopub connection, a sender for shell messages,
+/// and a [`Controller`] for control requests
+async fn connect(
     connection_file_path: &PathBuf,
-    event_loop: EventLoop<JupyterMessage>,
-    window: Window,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<(
+    runtimelib::ClientIoPubConnection,
+    futures::channel::mpsc::Sender<JupyterMessage>,
+    Controller,
+)> {
     let content = fs::read_to_string(&connection_file_path).await?;
     let connection_info = serde_json::from_str::<ConnectionInfo>(&content)?;
 
-    let mut iopub = runtimelib::create_client_iopub_connection(
-        &connection_info,
-        "",
-        &format!("sidecar-{}", uuid::Uuid::new_v4()),
-    )
-    .await?;
+    let session = runtimelib::Session::new().with_id(format!("sidecar-{}", uuid::Uuid::new_v4()));
+
+    let iopub = runtimelib::create_client_iopub_connection(&connection_info, "", &session).await?;
 
-    let mut shell =
-        runtimelib::create_client_shell_connection(&connection_info, &iopub.session_id).await?;
+    let mut shell = runtimelib::create_client_shell_connection(&connection_info, &session).await?;
 
     let (tx, mut rx) = futures::channel::mpsc::channel::<JupyterMessage>(100);
 
@@ -134,9 +199,119 @@ async fn run(
     })
     .detach();
 
+    let controller = Controller {
+        connection_info,
+        session,
+    };
+
+    Ok((iopub, tx, controller))
+}
+
+async fn run(
+    connection_file_path: &PathBuf,
+    event_loop: EventLoop<SidecarEvent>,
+    window: Window,
+    max_updates: u32,
+    index: MessageIndex,
+    ui_store: UiStore,
+) -> anyhow::Result<()> {
+    let (mut iopub, tx, controller) = connect(connection_file_path).await?;
+    let index = Arc::new(Mutex::new(index));
+    let search_index = index.clone();
+    let gallery = Arc::new(Mutex::new(Gallery::new()));
+    let gallery_index = gallery.clone();
+    let gallery_proxy = event_loop.create_proxy();
+    let ui_store = Arc::new(Mutex::new(ui_store));
+    let ui_proxy = event_loop.create_proxy();
+
+    let variables_session =
+        runtimelib::Session::new().with_id(format!("sidecar-variables-{}", uuid::Uuid::new_v4()));
+    let (refresh_tx, refresh_rx) = futures::channel::mpsc::unbounded();
+    let variables_proxy = event_loop.create_proxy();
+    smol::spawn({
+        let connection_info = controller.connection_info.clone();
+        let session = variables_session.clone();
+        async move {
+            let watched = variables::watch(connection_info, session, refresh_rx, |table| {
+                variables_proxy
+                    .send_event(SidecarEvent::Variables(table))
+                    .is_ok()
+            })
+            .await;
+            if let Err(e) = watched {
+                error!("Variable explorer stopped: {}", e);
+            }
+        }
+    })
+    .detach();
+
+    // Shared by every `/kernel-info` request, rather than dialing shell each time
+    let kernel_info_pool =
+        runtimelib::ShellPool::new(&controller.connection_info, controller.session.clone());
     let webview = WebViewBuilder::new()
         .with_devtools(true)
         .with_asynchronous_custom_protocol("sidecar".into(), move |_webview_id, req, responder| {
+            if let (&Method::POST, Some(action)) =
+                (req.method(), ControlAction::from_path(req.uri().path()))
+            {
+                let controller = controller.clone();
+                smol::spawn(async move {
+                    let response = match controller.send(action).await {
+                        Ok(outcome) => {
+                            info!("{:?} request: {:?}", action, outcome);
+                            Response::builder()
+                                .header("Content-Type", "application/json")
+                                .status(200)
+                                .body(serde_json::to_vec(&outcome).unwrap_or_default())
+                                .unwrap()
+                        }
+                        Err(e) => {
+                            error!("Failed to send {:?} request: {}", action, e);
+                            Response::builder()
+                                .status(502)
+                                .body(e.to_string().into_bytes())
+                                .unwrap()
+                        }
+                    };
+                    responder.respond(response);
+                })
+                .detach();
+                return;
+            }
+            if let (&Method::GET, "/search") = (req.method(), req.uri().path()) {
+                let query = SearchQuery::from_query_string(req.uri().query().unwrap_or_default());
+                let body = match search_index.lock() {
+                    Ok(index) => serde_json::to_vec(&index.search(&query)).unwrap_or_default(),
+                    Err(e) => {
+                        error!("Message index is unavailable: {}", e);
+                        b"[]".to_vec()
+                    }
+                };
+                responder.respond(
+                    Response::builder()
+                        .header("Content-Type", "application/json")
+                        .status(200)
+                        .body(body)
+                        .unwrap(),
+                );
+                return;
+            }
+            if *req.method() == Method::GET && req.uri().path().starts_with("/gallery") {
+                responder.respond(gallery_response(req.uri().path(), &gallery_index));
+                return;
+            }
+            if let (&Method::GET, "/kernel-info") = (req.method(), req.uri().path()) {
+                let pool = kernel_info_pool.clone();
+                smol::spawn(async move {
+                    responder.respond(kernel_info_response(&pool).await);
+                })
+                .detach();
+                return;
+            }
+            if req.uri().path() == "/ui-state" {
+                responder.respond(ui_state_response(&req, &ui_store, &ui_proxy));
+                return;
+            }
             if let (&Method::POST, "/message") = (req.method(), req.uri().path()) {
                 match serde_json::from_slice::<WryJupyterMessage>(req.body()) {
                     Ok(wry_message) => {
@@ -183,23 +358,49 @@ async fn run(
         .build(&window)?;
 
     let event_loop_proxy = event_loop.create_proxy();
+    let (messages_tx, messages_rx) = futures::channel::mpsc::unbounded();
 
     smol::spawn(async move {
         while let Ok(message) = iopub.read().await {
             debug!("Received message from iopub: {:?}", message);
-            match event_loop_proxy.send_event(message) {
-                Ok(_) => {
-                    debug!("Sent message to event loop");
-                }
-                Err(e) => {
-                    error!("Failed to send message to event loop: {:?}", e);
-                    break;
+            if let Ok(mut index) = index.lock() {
+                index.record(&message);
+            }
+            let plot = gallery
+                .lock()
+                .ok()
+                .and_then(|mut gallery| gallery.record(&message));
+            if let Some(item) = plot {
+                if let Err(e) = gallery_proxy.send_event(SidecarEvent::Gallery(item)) {
+                    error!("Failed to send gallery update to event loop: {:?}", e);
                 }
-            };
+            }
+            if variables::finishes_execution(&message, &variables_session) {
+                let _ = refresh_tx.unbounded_send(());
+            }
+            if messages_tx.unbounded_send(message).is_err() {
+                break;
+            }
         }
     })
     .detach();
 
+    smol::spawn(coalesce::forward_batches(
+        messages_rx,
+        max_updates,
+        move |batch| match event_loop_proxy.send_event(SidecarEvent::Messages(batch)) {
+            Ok(_) => {
+                debug!("Sent messages to event loop");
+                true
+            }
+            Err(e) => {
+                error!("Failed to send messages to event loop: {:?}", e);
+                false
+            }
+        },
+    ))
+    .detach();
+
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
 
@@ -210,22 +411,43 @@ async fn run(
             } => {
                 *control_flow = ControlFlow::Exit;
             }
-            Event::UserEvent(data) => {
-                debug!("Received UserEvent: {:?}", data);
-                let serialized: WryJupyterMessage = data.into();
+            Event::UserEvent(SidecarEvent::Messages(batch)) => {
+                debug!("Received UserEvent with {} messages", batch.len());
+                let serialized: Vec<WryJupyterMessage> =
+                    batch.into_iter().map(WryJupyterMessage::from).collect();
                 match serde_json::to_string(&serialized) {
-                    Ok(serialized_message) => {
-                        debug!("Serialized message: {}", serialized_message);
+                    Ok(serialized_messages) => {
+                        debug!("Serialized messages: {}", serialized_messages);
+                        // One script per batch, handing messages over in order
                         webview
                             .evaluate_script(&format!(
-                                r#"globalThis.onMessage({})"#,
-                                serialized_message
+                                r#"{}.forEach((message) => globalThis.onMessage(message))"#,
+                                serialized_messages
                             ))
                             .unwrap_or_else(|e| error!("Failed to evaluate script: {:?}", e));
                     }
-                    Err(e) => error!("Failed to serialize message: {}", e),
+                    Err(e) => error!("Failed to serialize messages: {}", e),
                 }
             }
+            Event::UserEvent(SidecarEvent::Variables(table)) => match serde_json::to_string(&table)
+            {
+                Ok(serialized_table) => webview
+                    .evaluate_script(&format!("globalThis.onVariables({})", serialized_table))
+                    .unwrap_or_else(|e| error!("Failed to evaluate script: {:?}", e)),
+                Err(e) => error!("Failed to serialize variables: {}", e),
+            },
+            Event::UserEvent(SidecarEvent::UiState(state)) => match serde_json::to_string(&state) {
+                Ok(serialized_state) => webview
+                    .evaluate_script(&format!("globalThis.onUiState({})", serialized_state))
+                    .unwrap_or_else(|e| error!("Failed to evaluate script: {:?}", e)),
+                Err(e) => error!("Failed to serialize UI state: {}", e),
+            },
+            Event::UserEvent(SidecarEvent::Gallery(item)) => match serde_json::to_string(&item) {
+                Ok(serialized_item) => webview
+                    .evaluate_script(&format!("globalThis.onGalleryUpdate({})", serialized_item))
+                    .unwrap_or_else(|e| error!("Failed to evaluate script: {:?}", e)),
+                Err(e) => error!("Failed to serialize gallery item: {}", e),
+            },
             _ => {}
         }
     });
@@ -244,7 +466,15 @@ fn main() -> Result<()> {
     }
     let connection_file = args.file;
 
-    let event_loop: EventLoop<JupyterMessage> = EventLoopBuilder::with_user_event().build();
+    if args.headless {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), args.port);
+        return smol::block_on(async {
+            let (iopub, shell_tx, _controller) = connect(&connection_file).await?;
+            headless::run(iopub, shell_tx, addr).await
+        });
+    }
+
+    let event_loop: EventLoop<SidecarEvent> = EventLoopBuilder::with_user_event().build();
 
     let window = WindowBuilder::new()
         .with_title("kernel sidecar")
@@ -252,7 +482,125 @@ fn main() -> Result<()> {
         .build(&event_loop)
         .unwrap();
 
-    smol::block_on(run(&connection_file, event_loop, window))
+    let index = match &args.index_file {
+        Some(path) => MessageIndex::open(path)?,
+        None => MessageIndex::new(),
+    };
+    let ui_store = match &args.ui_state_file {
+        Some(path) => UiStore::open(path)?,
+        None => UiStore::new(),
+    };
+
+    smol::block_on(run(
+        &connection_file,
+        event_loop,
+        window,
+        args.max_updates,
+        index,
+        ui_store,
+    ))
+}
+
+/// Answer `GET /kernel-info` with the kernel's [`KernelInfoCard`], and the
+/// card as HTML for the window's header.
+async fn kernel_info_response(pool: &runtimelib::ShellPool) -> Response<Vec<u8>> {
+    match pool.kernel_info().await {
+        Ok(reply) => {
+            let card = KernelInfoCard::from(&reply);
+            let body = serde_json::json!({ "html": card.to_html(), "card": card });
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .status(200)
+                .body(serde_json::to_vec(&body).unwrap_or_default())
+                .unwrap()
+        }
+        Err(e) => {
+            error!("Failed to get kernel info: {}", e);
+            Response::builder()
+                .status(502)
+                .body(e.to_string().into_bytes())
+                .unwrap()
+        }
+    }
+}
+
+/// Answer `GET /gallery` with every plot's [`GalleryItem`], and
+/// `GET /gallery/<id>` with one plot's MIME bundle.
+fn gallery_response(path: &str, gallery: &Mutex<Gallery>) -> Response<Vec<u8>> {
+    let Ok(gallery) = gallery.lock() else {
+        error!("Plot gallery is unavailable");
+        return Response::builder()
+            .status(500)
+            .body("Internal Server Error".as_bytes().to_vec())
+            .unwrap();
+    };
+    let body = match path.trim_end_matches('/') {
+        "/gallery" => serde_json::to_vec(&gallery.items()),
+        path => match path
+            .strip_prefix("/gallery/")
+            .and_then(|id| id.parse().ok())
+            .and_then(|id| gallery.data(id))
+        {
+            Some(data) => serde_json::to_vec(data),
+            None => {
+                return Response::builder()
+                    .status(404)
+                    .body("Not Found".as_bytes().to_vec())
+                    .unwrap()
+            }
+        },
+    };
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .status(200)
+        .body(body.unwrap_or_default())
+        .unwrap()
+}
+
+/// Answer `GET /ui-state` with the current state, and `POST /ui-state` by
+/// applying the [`UiChange`] in the body and telling the window about it.
+fn ui_state_response(
+    request: &Request<Vec<u8>>,
+    store: &Mutex<UiStore>,
+    proxy: &EventLoopProxy<SidecarEvent>,
+) -> Response<Vec<u8>> {
+    let Ok(mut store) = store.lock() else {
+        error!("UI state is unavailable");
+        return Response::builder()
+            .status(500)
+            .body("Internal Server Error".as_bytes().to_vec())
+            .unwrap();
+    };
+    match *request.method() {
+        Method::GET => {}
+        Method::POST => match serde_json::from_slice::<UiChange>(request.body()) {
+            Ok(change) => {
+                if store.apply(change) {
+                    if let Err(e) = proxy.send_event(SidecarEvent::UiState(store.state().clone())) {
+                        error!("Failed to send UI state to event loop: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to deserialize UI change: {}", e);
+                return Response::builder()
+                    .status(400)
+                    .body("Bad Request".as_bytes().to_vec())
+                    .unwrap();
+            }
+        },
+        _ => {
+            return Response::builder()
+                .status(405)
+                .body("Method Not Allowed".as_bytes().to_vec())
+                .unwrap()
+        }
+    }
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .status(200)
+        .body(serde_json::to_vec(store.state()).unwrap_or_default())
+        .unwrap()
 }
 
 fn get_response(request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
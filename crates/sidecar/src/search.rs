@@ -0,0 +1,205 @@
+//! Searching the message index: parsing `/search` query strings and matching
+//! them against [`IndexEntry`]s. Nothing here touches the webview or the
+//! index file, so it's all tested on its own.
+use crate::index::IndexEntry;
+
+/// Most results a search returns unless it asks for fewer.
+pub const DEFAULT_SEARCH_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Case insensitive text to look for
+    pub text: Option<String>,
+    /// Message types to include. Empty for all of them
+    pub types: Vec<String>,
+    pub execution_count: Option<usize>,
+    pub limit: usize,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self {
+            text: None,
+            types: Vec::new(),
+            execution_count: None,
+            limit: DEFAULT_SEARCH_LIMIT,
+        }
+    }
+}
+
+impl SearchQuery {
+    /// Parse the query string of a `/search` request. Unknown and malformed
+    /// parameters are ignored.
+    pub fn from_query_string(query: &str) -> Self {
+        let mut search = SearchQuery::default();
+        for (key, value) in querystring::querify(query) {
+            let value = decode_component(value);
+            match key {
+                "q" if !value.is_empty() => search.text = Some(value),
+                "types" => {
+                    search.types = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|msg_type| !msg_type.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                "execution_count" => search.execution_count = value.parse().ok(),
+                "limit" => search.limit = value.parse().unwrap_or(DEFAULT_SEARCH_LIMIT),
+                _ => {}
+            }
+        }
+        search
+    }
+
+    /// Whether `entry` matches every part of the query but the limit.
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        (self.types.is_empty() || self.types.contains(&entry.msg_type))
+            && (self.execution_count.is_none() || entry.execution_count == self.execution_count)
+            && self
+                .text
+                .as_ref()
+                .is_none_or(|text| entry.text.to_lowercase().contains(&text.to_lowercase()))
+    }
+}
+
+/// The entries matching `query`, most recent first, where `entries` are in
+/// the order they were indexed.
+pub fn search<'a>(entries: &'a [IndexEntry], query: &SearchQuery) -> Vec<&'a IndexEntry> {
+    entries
+        .iter()
+        .rev()
+        .filter(|entry| query.matches(entry))
+        .take(query.limit)
+        .collect()
+}
+
+/// Undo URL encoding in a query string value, where `+` is a space. A `%`
+/// that isn't followed by two hex digits is kept as it is, and bytes that
+/// don't decode to UTF-8 become replacement characters.
+pub fn decode_component(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match bytes.get(i + 1..i + 3).and_then(hex_byte) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The byte two hex digits spell. `u8::from_str_radix` alone would also take
+/// a sign, like `+1`.
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(msg_type: &str, execution_count: Option<usize>, text: &str) -> IndexEntry {
+        IndexEntry {
+            msg_id: format!("{}-{}", msg_type, text),
+            msg_type: msg_type.to_string(),
+            parent_msg_id: None,
+            execution_count,
+            date: "2024-01-01T00:00:00+00:00".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_decode_component() {
+        assert_eq!(decode_component("caf%C3%A9+au+lait"), "café au lait");
+        assert_eq!(decode_component("%2Fpath%2f"), "/path/");
+        assert_eq!(decode_component("plain"), "plain");
+    }
+
+    #[test]
+    fn test_decode_malformed_component() {
+        // Cut short, at the end and in the middle
+        assert_eq!(decode_component("100%"), "100%");
+        assert_eq!(decode_component("%4"), "%4");
+        assert_eq!(decode_component("a%4g"), "a%4g");
+        // Not hex, or hex with a sign. The `+` is still a space
+        assert_eq!(decode_component("%zz%-1"), "%zz%-1");
+        assert_eq!(decode_component("%+1"), "% 1");
+        // A percent before a multibyte character
+        assert_eq!(decode_component("%é"), "%é");
+        // Bytes that aren't UTF-8
+        assert_eq!(decode_component("%FFok"), "\u{FFFD}ok");
+        assert_eq!(decode_component("%%41"), "%A");
+    }
+
+    #[test]
+    fn test_parse_query_string() {
+        let query = SearchQuery::from_query_string(
+            "q=Key%20Error&types=stream,+error,,&execution_count=3&limit=5&other=1",
+        );
+        assert_eq!(
+            query,
+            SearchQuery {
+                text: Some("Key Error".to_string()),
+                types: vec!["stream".to_string(), "error".to_string()],
+                execution_count: Some(3),
+                limit: 5,
+            }
+        );
+
+        // Empty and malformed values fall back to the defaults
+        let query = SearchQuery::from_query_string("q=&execution_count=three&limit=-1");
+        assert_eq!(query, SearchQuery::default());
+        assert_eq!(SearchQuery::from_query_string(""), SearchQuery::default());
+    }
+
+    #[test]
+    fn test_search() {
+        let entries = vec![
+            entry("execute_input", Some(1), "print('hi')"),
+            entry("stream", Some(1), "hi\n"),
+            entry("error", Some(2), "KeyError: 'x'"),
+            entry("stream", Some(2), "HI again\n"),
+        ];
+        let msg_ids = |query: &SearchQuery| -> Vec<String> {
+            search(&entries, query)
+                .into_iter()
+                .map(|entry| entry.msg_id.clone())
+                .collect()
+        };
+
+        // Case insensitive, most recent first
+        let query = SearchQuery::from_query_string("q=hi");
+        assert_eq!(
+            msg_ids(&query),
+            [
+                "stream-HI again\n",
+                "stream-hi\n",
+                "execute_input-print('hi')"
+            ]
+        );
+
+        let query = SearchQuery::from_query_string("q=hi&types=stream&limit=1");
+        assert_eq!(msg_ids(&query), ["stream-HI again\n"]);
+
+        let query = SearchQuery::from_query_string("execution_count=2&types=error");
+        assert_eq!(msg_ids(&query), ["error-KeyError: 'x'"]);
+
+        let query = SearchQuery::from_query_string("q=nothing");
+        assert!(msg_ids(&query).is_empty());
+        assert!(search(&[], &SearchQuery::default()).is_empty());
+    }
+}
@@ -5,18 +5,143 @@ function log(level, ...args) {
 }
 
 /**
+ * How the window is arranged. The sidecar keeps it, so it survives reloads;
+ * this copy is what it last sent.
+ *
+ * @type {t.UiState}
+ */
+let uiState = { collapsed: [], pinned: [], scroll_lock: false };
+
+/**
+ * @param {string} msgId
  * @param {number | undefined} executionCount
+ * @returns {HTMLElement} where the output goes
  */
-function createOutputCell(executionCount) {
+function createOutputCell(msgId, executionCount) {
   const cell = document.createElement("div");
   cell.className = "cell";
+  cell.dataset.msgId = msgId;
   if (executionCount !== undefined) {
     cell.dataset.n = executionCount.toString();
   }
+
+  const controls = document.createElement("div");
+  controls.className = "cellControls";
+  for (const [change, label] of [
+    ["collapse", "Collapse"],
+    ["pin", "Pin"],
+  ]) {
+    const button = document.createElement("button");
+    button.textContent = label;
+    button.dataset.change = change;
+    button.addEventListener("click", () => {
+      const on = !cell.classList.contains(
+        change === "collapse" ? "collapsed" : "pinned",
+      );
+      changeUiState(
+        change === "collapse"
+          ? { change, msg_id: msgId, collapsed: on }
+          : { change, msg_id: msgId, pinned: on },
+      );
+    });
+    controls.appendChild(button);
+  }
+  const output = document.createElement("div");
+  output.className = "output";
+  cell.append(controls, output);
+  applyUiState(cell);
+
   const outputArea = document.querySelector("#outputArea");
   assert(outputArea, "outputArea not found");
   outputArea.appendChild(cell);
-  return cell;
+  if (!uiState.scroll_lock) {
+    cell.scrollIntoView({ behavior: "smooth", block: "end" });
+  }
+  return output;
+}
+
+/** @param {HTMLElement} cell */
+function applyUiState(cell) {
+  const msgId = cell.dataset.msgId ?? "";
+  const collapsed = uiState.collapsed.includes(msgId);
+  const pinned = uiState.pinned.includes(msgId);
+  cell.classList.toggle("collapsed", collapsed);
+  cell.classList.toggle("pinned", pinned);
+  for (const button of cell.querySelectorAll(".cellControls button")) {
+    if (button instanceof HTMLElement && button.dataset.change === "collapse") {
+      button.textContent = collapsed ? "Expand" : "Collapse";
+    } else {
+      button.textContent = pinned ? "Unpin" : "Pin";
+    }
+  }
+}
+
+/**
+ * Ask the sidecar to change the UI state. The window updates when the change
+ * comes back as a `ui-state` event, like it does for every other window.
+ *
+ * @param {t.UiChange} change
+ */
+export async function changeUiState(change) {
+  try {
+    const response = await fetch("/ui-state", {
+      method: "POST",
+      body: JSON.stringify(change),
+    });
+    if (!response.ok) {
+      log("error", "UI state change rejected:", await response.text());
+    }
+  } catch (error) {
+    log("error", "Error changing UI state:", error);
+  }
+}
+
+/**
+ * Arrange the window as the sidecar says: collapse and pin outputs and set
+ * the scroll lock.
+ *
+ * @param {t.UiState} state
+ */
+export function onUiState(state) {
+  log("info", "Received UI state:", state);
+  uiState = state;
+  for (const cell of document.querySelectorAll(".cell")) {
+    if (cell instanceof HTMLElement) {
+      applyUiState(cell);
+    }
+  }
+  const scrollLock = document.querySelector("#scrollLock");
+  if (scrollLock instanceof HTMLInputElement) {
+    scrollLock.checked = state.scroll_lock;
+  }
+}
+
+/** Pick up the UI state from before the window (re)loaded. */
+export async function loadUiState() {
+  try {
+    const response = await fetch("/ui-state");
+    onUiState(await response.json());
+  } catch (error) {
+    log("error", "Error loading UI state:", error);
+  }
+}
+
+/** Show which kernel this is above the toolbar. */
+export async function loadKernelInfo() {
+  try {
+    const response = await fetch("/kernel-info");
+    if (!response.ok) {
+      throw new Error(await response.text());
+    }
+    /** @type {t.KernelInfo} */
+    const kernelInfo = await response.json();
+    const header = document.querySelector("#kernelInfo");
+    assert(header, "kernelInfo not found");
+    header.innerHTML = kernelInfo.html;
+    header.hidden = false;
+  } catch (error) {
+    log("error", "Error loading kernel info:", error);
+  }
 }
 
 /**
@@ -49,7 +174,7 @@ export async function onMessage(message) {
   if (isDisplayDataOrExecuteResult(message)) {
     log("info", "Handling display data or execute result");
     const { data, execution_count } = message.content;
-    const output = createOutputCell(execution_count);
+    const output = createOutputCell(message.header.msg_id, execution_count);
     if (data["application/vnd.jupyter.widget-view+json"]) {
       log("debug", "Creating widget view");
       const { model_id } = data["application/vnd.jupyter.widget-view+json"];
@@ -120,6 +245,255 @@ export async function onMessage(message) {
   }
 }
 
+/**
+ * Send a control request to the kernel and show how it went in the toolbar.
+ *
+ * @param {"interrupt" | "restart" | "shutdown"} action
+ */
+export async function controlKernel(action) {
+  const status = document.querySelector("#controlStatus");
+  assert(status, "controlStatus not found");
+  if (action === "shutdown" && !confirm("Shut down the kernel?")) {
+    return;
+  }
+
+  status.textContent = `Sending ${action}...`;
+  try {
+    const response = await fetch(`/control/${action}`, { method: "POST" });
+    if (!response.ok) {
+      status.textContent = `${action} failed: ${await response.text()}`;
+      return;
+    }
+    /** @type {t.ControlOutcome} */
+    const outcome = await response.json();
+    log("info", `${action} outcome:`, outcome);
+    switch (outcome.outcome) {
+      case "accepted":
+        status.textContent = `${action}: done`;
+        break;
+      case "rejected":
+        status.textContent = `${action} rejected${
+          outcome.error ? `: ${outcome.error.ename}: ${outcome.error.evalue}` : ""
+        }`;
+        break;
+      case "unconfirmed":
+        status.textContent = `${action}: sent, but the kernel didn't say whether it worked`;
+        break;
+      case "timed_out":
+        status.textContent = `${action}: no reply from the kernel`;
+        break;
+      case "not_supported":
+        status.textContent = `${action} isn't supported by this kernel`;
+        break;
+    }
+  } catch (error) {
+    log("error", `Error sending ${action}:`, error);
+    status.textContent = `${action} failed`;
+  }
+}
+
+/**
+ * Search the messages received so far and list the matches under the toolbar.
+ * `type:error` and `in:3` terms narrow the search to a message type or an
+ * execution count; the rest is matched against the text of each message.
+ *
+ * @param {string} input
+ */
+export async function searchMessages(input) {
+  const results = document.querySelector("#searchResults");
+  assert(results, "searchResults not found");
+  results.replaceChildren();
+
+  const params = new URLSearchParams();
+  const types = [];
+  const words = [];
+  for (const term of input.trim().split(/\s+/)) {
+    if (term.startsWith("type:")) {
+      types.push(term.slice("type:".length));
+    } else if (term.startsWith("in:")) {
+      params.set("execution_count", term.slice("in:".length));
+    } else if (term) {
+      words.push(term);
+    }
+  }
+  if (
+    words.length === 0 &&
+    types.length === 0 &&
+    !params.has("execution_count")
+  ) {
+    return;
+  }
+  params.set("q", words.join(" "));
+  params.set("types", types.join(","));
+
+  try {
+    const response = await fetch(`/search?${params}`);
+    /** @type {t.SearchResult[]} */
+    const matches = await response.json();
+    log("info", `${matches.length} search results for`, input);
+    if (matches.length === 0) {
+      const item = document.createElement("li");
+      item.textContent = "No matches";
+      results.appendChild(item);
+      return;
+    }
+    for (const match of matches) {
+      const item = document.createElement("li");
+      const prompt =
+        match.execution_count === null ? "" : `[${match.execution_count}] `;
+      const firstLine =
+        match.text.split("\n").find((line) => line.trim()) ?? "";
+      item.textContent = `${prompt}${match.msg_type}: ${firstLine}`;
+      item.title = match.text;
+      if (match.execution_count !== null) {
+        item.addEventListener("click", () => {
+          document
+            .querySelector(`.cell[data-n="${match.execution_count}"]`)
+            ?.scrollIntoView({ behavior: "smooth" });
+        });
+      }
+      results.appendChild(item);
+    }
+  } catch (error) {
+    log("error", "Error searching messages:", error);
+  }
+}
+
+/**
+ * Show the kernel's variables, replacing the previous table. The table stays
+ * hidden until the first one arrives, so kernels without a variable explorer
+ * don't get an empty one.
+ *
+ * @param {t.VariableTable} table
+ */
+export function onVariables(table) {
+  log("info", `Received ${table.data.length} variables`);
+  const variables = document.querySelector("#variables");
+  assert(variables, "variables not found");
+  const head = variables.querySelector("thead");
+  const body = variables.querySelector("tbody");
+  assert(head && body, "variables table not found");
+
+  const columns = table.schema.fields.map((field) => field.name);
+  const headerRow = document.createElement("tr");
+  for (const column of columns) {
+    const cell = document.createElement("th");
+    cell.textContent = column;
+    headerRow.appendChild(cell);
+  }
+  head.replaceChildren(headerRow);
+
+  body.replaceChildren(
+    ...table.data.map((variable) => {
+      const row = document.createElement("tr");
+      for (const column of columns) {
+        const cell = document.createElement("td");
+        const value = variable[column];
+        cell.textContent =
+          typeof value === "string" ? value : JSON.stringify(value ?? "");
+        cell.title = cell.textContent;
+        row.appendChild(cell);
+      }
+      return row;
+    }),
+  );
+  variables.hidden = false;
+}
+
+/**
+ * Add a plot to the gallery. The gallery stays hidden until the first plot
+ * arrives.
+ *
+ * @param {t.GalleryItem} item
+ */
+export function onGalleryUpdate(item) {
+  log("info", "Received gallery item:", item);
+  const gallery = document.querySelector("#gallery");
+  const thumbnails = document.querySelector("#galleryThumbnails");
+  assert(gallery && thumbnails, "gallery not found");
+
+  const figure = document.createElement("figure");
+  figure.title = `${item.mime_type}, ${new Date(item.date).toLocaleString()}`;
+  if (item.thumbnail) {
+    const image = document.createElement("img");
+    image.src = item.thumbnail;
+    image.alt = item.mime_type;
+    figure.appendChild(image);
+  } else {
+    const placeholder = document.createElement("div");
+    placeholder.className = "galleryPlaceholder";
+    placeholder.textContent = item.mime_type.includes("plotly")
+      ? "Plotly"
+      : item.mime_type;
+    figure.appendChild(placeholder);
+  }
+  const caption = document.createElement("figcaption");
+  caption.textContent =
+    item.execution_count === null ? "" : `[${item.execution_count}]`;
+  figure.appendChild(caption);
+  figure.addEventListener("click", () => showPlot(item));
+  thumbnails.appendChild(figure);
+  gallery.hidden = false;
+}
+
+/** Fill the gallery with the plots from before the window (re)loaded. */
+export async function loadGallery() {
+  try {
+    const response = await fetch("/gallery");
+    /** @type {t.GalleryItem[]} */
+    const items = await response.json();
+    document.querySelector("#galleryThumbnails")?.replaceChildren();
+    items.forEach(onGalleryUpdate);
+  } catch (error) {
+    log("error", "Error loading the gallery:", error);
+  }
+}
+
+/**
+ * Scroll to a plot's output, or show it full size if it has scrolled out of
+ * the window since it was drawn.
+ *
+ * @param {t.GalleryItem} item
+ */
+async function showPlot(item) {
+  const cell = document.querySelector(`.cell[data-msg-id="${item.msg_id}"]`);
+  if (cell) {
+    cell.scrollIntoView({ behavior: "smooth" });
+    return;
+  }
+  const viewer = document.querySelector("#galleryViewer");
+  assert(viewer, "galleryViewer not found");
+  try {
+    const response = await fetch(`/gallery/${item.id}`);
+    if (!response.ok) {
+      throw new Error(await response.text());
+    }
+    /** @type {Record<string, string>} */
+    const data = await response.json();
+    const image = document.createElement("img");
+    if (data["image/png"] || data["image/jpeg"] || data["image/gif"]) {
+      const mimeType = ["image/png", "image/jpeg", "image/gif"].find(
+        (mimeType) => data[mimeType],
+      );
+      image.src = `data:${mimeType};base64,${data[mimeType ?? ""]}`;
+    } else if (data["image/svg+xml"]) {
+      image.src = `data:image/svg+xml;charset=utf-8,${encodeURIComponent(
+        data["image/svg+xml"],
+      )}`;
+    } else {
+      const pre = document.createElement("pre");
+      pre.textContent = data["text/plain"] ?? item.mime_type;
+      viewer.replaceChildren(pre);
+      viewer.hidden = false;
+      return;
+    }
+    viewer.replaceChildren(image);
+    viewer.hidden = false;
+  } catch (error) {
+    log("error", "Error loading plot:", error);
+  }
+}
+
 // This class is a striped down version of Comm from @jupyter-widgets/base
 export class Comm {
   /** @type {string} */
@@ -1,4 +1,5 @@
 type Header<MsgType> = {
+  msg_id: string;
   msg_type: MsgType;
 };
 
@@ -45,6 +46,68 @@ export type CommOpen = {
   buffers: ArrayBuffer[];
 };
 
+export type ControlOutcome =
+  | { outcome: "accepted" }
+  | {
+    outcome: "rejected";
+    error: { ename: string; evalue: string; traceback: string[] } | null;
+  }
+  | { outcome: "unconfirmed" }
+  | { outcome: "timed_out" }
+  | { outcome: "not_supported" };
+
+export type SearchResult = {
+  msg_id: string;
+  msg_type: string;
+  parent_msg_id: string | null;
+  execution_count: number | null;
+  date: string;
+  text: string;
+};
+
+/** The kernel's variables, one row per variable. */
+export type VariableTable = {
+  schema: { fields: { name: string; type: string }[] };
+  data: Record<string, JsonValue>[];
+};
+
+/** Which kernel this is, from `GET /kernel-info`. */
+export type KernelInfo = {
+  card: {
+    implementation: string;
+    implementation_version: string;
+    language: string;
+    language_version: string;
+    banner: string;
+    help_links: { text: string; url: string }[];
+  };
+  /** The card rendered by the sidecar, with its text escaped */
+  html: string;
+};
+
+/** A plot in the gallery, from `GET /gallery` or a gallery update. */
+export type GalleryItem = {
+  id: number;
+  msg_id: string;
+  execution_count: number | null;
+  date: string;
+  mime_type: string;
+  /** A data URL, missing for plots without an image to shrink */
+  thumbnail: string | null;
+};
+
+/** How the window is arranged, as the sidecar keeps it. */
+export type UiState = {
+  collapsed: string[];
+  pinned: string[];
+  scroll_lock: boolean;
+};
+
+export type UiChange =
+  | { change: "collapse"; msg_id: string; collapsed: boolean }
+  | { change: "pin"; msg_id: string; pinned: boolean }
+  | { change: "scroll_lock"; locked: boolean };
+
 export type JupyterMessage = DisplayData | ExecuteResult | CommOpen;
 
 export type JsonValue = string | number | boolean | null | Array<JsonValue> | {
@@ -0,0 +1,110 @@
+//! How the window is arranged: collapsed and pinned outputs, and the scroll
+//! lock.
+//!
+//! The state lives here rather than in the page so it survives the webview
+//! reloading, and with `--ui-state-file` it's saved for the next sidecar
+//! started with the same file. The window changes it with `POST /ui-state`
+//! and a [`UiChange`], reads it with `GET /ui-state`, and gets every change
+//! back as a `ui-state` event, so all the windows on a store stay in sync.
+use anyhow::Result;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiState {
+    /// `msg_id`s of the outputs shown collapsed
+    pub collapsed: BTreeSet<String>,
+    /// `msg_id`s of the outputs kept at the top of the window
+    pub pinned: BTreeSet<String>,
+    /// Keep the window where it is instead of following new output
+    pub scroll_lock: bool,
+}
+
+/// One change to the [`UiState`], as the window sends it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum UiChange {
+    Collapse { msg_id: String, collapsed: bool },
+    Pin { msg_id: String, pinned: bool },
+    ScrollLock { locked: bool },
+}
+
+#[derive(Default)]
+pub struct UiStore {
+    state: UiState,
+    file: Option<PathBuf>,
+}
+
+impl UiStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store that saves to `path`, starting with the state already there.
+    pub fn open(path: &Path) -> Result<Self> {
+        let state = if path.exists() {
+            match serde_json::from_slice(&fs::read(path)?) {
+                Ok(state) => state,
+                Err(e) => {
+                    error!(
+                        "Starting with fresh UI state, the saved one is unreadable: {}",
+                        e
+                    );
+                    UiState::default()
+                }
+            }
+        } else {
+            UiState::default()
+        };
+        Ok(Self {
+            state,
+            file: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn state(&self) -> &UiState {
+        &self.state
+    }
+
+    /// Apply `change`, returning whether the state changed.
+    pub fn apply(&mut self, change: UiChange) -> bool {
+        let changed = match change {
+            UiChange::Collapse { msg_id, collapsed } => {
+                toggle(&mut self.state.collapsed, msg_id, collapsed)
+            }
+            UiChange::Pin { msg_id, pinned } => toggle(&mut self.state.pinned, msg_id, pinned),
+            UiChange::ScrollLock { locked } => {
+                std::mem::replace(&mut self.state.scroll_lock, locked) != locked
+            }
+        };
+        if changed {
+            self.save();
+        }
+        changed
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let written = serde_json::to_vec(&self.state)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(path, json)?));
+        if let Err(e) = written {
+            error!("Failed to save the UI state: {}", e);
+        }
+    }
+}
+
+/// Add `msg_id` to `set` or take it out, returning whether that changed it.
+fn toggle(set: &mut BTreeSet<String>, msg_id: String, present: bool) -> bool {
+    if present {
+        set.insert(msg_id)
+    } else {
+        set.remove(&msg_id)
+    }
+}
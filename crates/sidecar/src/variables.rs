@@ -0,0 +1,160 @@
+//! The variable explorer: what's defined in the kernel, refreshed whenever an
+//! execution finishes.
+//!
+//! The sidecar asks the kernel for its variables with a user expression
+//! probe on a session of its own, so the question never shows up as output or
+//! in the kernel's history. Answers are tables in the
+//! `application/vnd.dataresource+json` format, with a row per variable, and go
+//! to the window as `variables` events.
+//!
+//! How to ask depends on the kernel's language. Each language implements
+//! [`VariableInspector`] and is registered in [`inspector_for`].
+use anyhow::{anyhow, bail, Result};
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::StreamExt;
+use jupyter_protocol::media::TabularDataResource;
+use jupyter_protocol::{
+    ConnectionInfo, ExecutionState, ExpressionResult, JupyterMessage, JupyterMessageContent,
+    MediaType, Session,
+};
+use log::{error, info};
+use runtimelib::KernelClient;
+use std::collections::HashMap;
+
+/// Lists a kernel's variables for one language.
+pub trait VariableInspector: Send + Sync {
+    /// User expressions whose results list the variables.
+    fn expressions(&self) -> HashMap<String, String>;
+
+    /// The variables from the results of [`expressions`](Self::expressions).
+    fn variables(&self, results: &HashMap<String, ExpressionResult>)
+        -> Result<TabularDataResource>;
+}
+
+/// The inspector for kernels whose `language_info.name` is `language`.
+pub fn inspector_for(language: &str) -> Option<Box<dyn VariableInspector>> {
+    match language.to_lowercase().as_str() {
+        "python" => Some(Box::new(PythonInspector)),
+        _ => None,
+    }
+}
+
+/// Asks IPython kernels for their variables with a user expression, laid out
+/// like jupyterlab-variableinspector's table.
+///
+/// The expression evaluates to an object whose `_repr_mimebundle_` is the
+/// table, so IPython sends it back as a data resource rather than a `repr`.
+pub struct PythonInspector;
+
+const VARIABLES_EXPRESSION: &str = "sidecar_variables";
+
+/// Everything in the user's namespace except private names, IPython's own
+/// globals, modules, functions and classes.
+const PYTHON_VARIABLES: &str = r#"(lambda namespace: type("VariableTable", (), {
+    "_repr_mimebundle_": lambda self, include=None, exclude=None: {
+        "application/vnd.dataresource+json": {
+            "schema": {
+                "fields": [
+                    {"name": "name", "type": "string"},
+                    {"name": "type", "type": "string"},
+                    {"name": "size", "type": "string"},
+                    {"name": "value", "type": "string"},
+                ],
+                "primaryKey": ["name"],
+            },
+            "data": [
+                {
+                    "name": name,
+                    "type": type(value).__name__,
+                    "size": str(getattr(value, "shape", None)
+                        or (len(value) if hasattr(value, "__len__") else "")),
+                    "value": repr(value)[:200],
+                }
+                for name, value in sorted(namespace.items())
+                if not name.startswith("_")
+                and name not in ("In", "Out", "get_ipython", "exit", "quit", "open")
+                and not callable(value)
+                and type(value).__name__ != "module"
+            ],
+        }
+    }
+})())(globals())"#;
+
+impl VariableInspector for PythonInspector {
+    fn expressions(&self) -> HashMap<String, String> {
+        HashMap::from([(
+            VARIABLES_EXPRESSION.to_string(),
+            PYTHON_VARIABLES.to_string(),
+        )])
+    }
+
+    fn variables(
+        &self,
+        results: &HashMap<String, ExpressionResult>,
+    ) -> Result<TabularDataResource> {
+        match results.get(VARIABLES_EXPRESSION) {
+            Some(ExpressionResult::Ok { data, .. }) => data
+                .content
+                .iter()
+                .find_map(|media_type| match media_type {
+                    MediaType::DataTable(table) => Some(table.as_ref().clone()),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("The kernel didn't send the variables as a table")),
+            Some(ExpressionResult::Error(error)) => {
+                bail!("Listing variables raised {}: {}", error.ename, error.evalue)
+            }
+            None => bail!("The kernel didn't evaluate the variables expression"),
+        }
+    }
+}
+
+/// Whether `message` is the kernel going idle after someone else's
+/// `execute_request`, when the variables may have changed. Requests from the
+/// explorer's own `session` don't count, or it would refresh forever.
+pub fn finishes_execution(message: &JupyterMessage, session: &Session) -> bool {
+    let JupyterMessageContent::Status(status) = &message.content else {
+        return false;
+    };
+    status.execution_state == ExecutionState::Idle
+        && message.parent_header.as_ref().is_some_and(|parent| {
+            parent.msg_type == "execute_request" && parent.session != session.id()
+        })
+}
+
+/// Send the kernel's variables to `on_variables` now and each time `refresh`
+/// fires, until either of them stops. Kernels in languages without an
+/// inspector are left alone.
+pub async fn watch(
+    connection_info: ConnectionInfo,
+    session: Session,
+    mut refresh: UnboundedReceiver<()>,
+    on_variables: impl Fn(TabularDataResource) -> bool,
+) -> Result<()> {
+    let mut client = KernelClient::connect_with_session(&connection_info, session).await?;
+    let language = client.kernel_info().await?.language_info.name;
+    let Some(inspector) = inspector_for(&language) else {
+        info!("No variable explorer for {} kernels", language);
+        return Ok(());
+    };
+
+    loop {
+        let results = client
+            .probe_user_expressions(inspector.expressions())
+            .await?;
+        match inspector.variables(&results) {
+            Ok(table) => {
+                if !on_variables(table) {
+                    return Ok(());
+                }
+            }
+            Err(e) => error!("Failed to list variables: {}", e),
+        }
+
+        if refresh.next().await.is_none() {
+            return Ok(());
+        }
+        // Executions that finished while this refresh ran only need one more
+        while refresh.try_recv().is_ok() {}
+    }
+}